@@ -0,0 +1,169 @@
+use std::error::Error;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::colour::Colour;
+use crate::image::FloatImage;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GradientDirection {
+    Vertical,
+    Horizontal,
+}
+
+fn default_direction() -> GradientDirection {
+    GradientDirection::Vertical
+}
+
+fn default_radius() -> f64 {
+    1.
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct GradientSpec {
+    from: Colour, // top or left
+    to: Colour,   // bottom or right
+    #[serde(default = "default_direction")]
+    direction: GradientDirection,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct RadialSpec {
+    centre: Colour,
+    edge: Colour,
+    // Distance from the middle of the screen at which `edge` is reached, where the corners are at 1
+    #[serde(default = "default_radius")]
+    radius: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct ImageSpec {
+    image: String,
+}
+
+// What the camera sees where a ray leaves the scene, when there's no environment to light it.
+// Looked up in screen space, so it doesn't move with the view direction.
+pub enum Background {
+    Colour(Colour),
+    Gradient {
+        from: Colour,
+        to: Colour,
+        direction: GradientDirection,
+    },
+    Radial {
+        centre: Colour,
+        edge: Colour,
+        radius: f64,
+    },
+    Image(FloatImage), // stretched over the whole screen
+}
+
+impl Background {
+    // Parses a plain colour or a table with a `type` of "gradient", "radial" or "image"
+    pub fn from_toml(value: &toml::Value, scene_dir: &Path) -> Result<Self, Box<dyn Error>> {
+        let Some(toml::Value::String(s)) = value.get("type") else {
+            let colour = toml::Value::try_into::<Colour>(value.clone())?;
+            return Ok(Background::Colour(colour));
+        };
+
+        match s.to_lowercase().as_str() {
+            "gradient" => {
+                let spec = toml::Value::try_into::<GradientSpec>(value.clone())?;
+                Ok(Background::Gradient {
+                    from: spec.from,
+                    to: spec.to,
+                    direction: spec.direction,
+                })
+            }
+            "radial" => {
+                let spec = toml::Value::try_into::<RadialSpec>(value.clone())?;
+                Ok(Background::Radial {
+                    centre: spec.centre,
+                    edge: spec.edge,
+                    radius: spec.radius,
+                })
+            }
+            "image" => {
+                let spec = toml::Value::try_into::<ImageSpec>(value.clone())?;
+                let image = FloatImage::load(&scene_dir.join(&spec.image))?;
+                Ok(Background::Image(image))
+            }
+            other => Err(format!("unknown background type '{}'", other).into()),
+        }
+    }
+
+    // `screen_uv` runs from (0, 0) at the top left of the image to (1, 1) at the bottom right
+    pub fn colour_at(&self, screen_uv: (f64, f64)) -> Colour {
+        let (u, v) = screen_uv;
+        match self {
+            Background::Colour(colour) => *colour,
+            Background::Gradient {
+                from,
+                to,
+                direction,
+            } => {
+                let t = match direction {
+                    GradientDirection::Vertical => v,
+                    GradientDirection::Horizontal => u,
+                };
+                lerp(*from, *to, t)
+            }
+            Background::Radial {
+                centre,
+                edge,
+                radius,
+            } => {
+                // Normalised so the corners are at distance 1
+                let (x, y) = (2. * u - 1., 2. * v - 1.);
+                let distance = (x * x + y * y).sqrt() / 2_f64.sqrt();
+                lerp(*centre, *edge, distance / radius.max(f64::EPSILON))
+            }
+            Background::Image(image) => image.bilinear(u, v),
+        }
+    }
+}
+
+fn lerp(a: Colour, b: Colour, t: f64) -> Colour {
+    let t = t.clamp(0., 1.) as f32;
+    a * (1. - t) + b * t
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Background {
+        let value = s.parse::<toml::Table>().unwrap()["background"].clone();
+        Background::from_toml(&value, Path::new("")).unwrap()
+    }
+
+    #[test]
+    fn vertical_gradient_runs_top_to_bottom() {
+        let background =
+            parse(r#"background = {type = "gradient", from = [1, 1, 1], to = [0, 0, 0]}"#);
+        assert_eq!(background.colour_at((0.3, 0.)), Colour::white());
+        assert_eq!(background.colour_at((0.3, 1.)), Colour::black());
+        assert_eq!(background.colour_at((0.9, 0.5)), Colour::new(0.5, 0.5, 0.5));
+    }
+
+    #[test]
+    fn radial_reaches_edge_colour_at_radius() {
+        let background = parse(
+            r#"background = {type = "radial", centre = [1, 1, 1], edge = [0, 0, 0], radius = 0.5}"#,
+        );
+        assert_eq!(background.colour_at((0.5, 0.5)), Colour::white());
+        assert_eq!(background.colour_at((0., 0.)), Colour::black());
+        assert_eq!(background.colour_at((0.75, 0.75)), Colour::black());
+    }
+
+    #[test]
+    fn unknown_type_fails() {
+        let value = "background = {type = \"plaid\"}"
+            .parse::<toml::Table>()
+            .unwrap()["background"]
+            .clone();
+        assert!(Background::from_toml(&value, Path::new("")).is_err());
+    }
+}
@@ -0,0 +1,414 @@
+// Resources:
+// Bézier surfaces: https://en.wikipedia.org/wiki/B%C3%A9zier_surface
+// Ray tracing parametric patches with Newton's method: Toth 1985, "On ray tracing parametric surfaces"
+// The .bpt patch format, as used for the Utah teapot: https://www.realtimerendering.com/resources/RTNews/html/rtnv8n1.html#art3
+
+use crate::bvh::{Aabb, Bvh};
+use crate::colour::Colour;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::vector::Vector;
+use crate::world::{Entity, Hit, IntersectionResult, SurfaceInteraction};
+use serde::Deserialize;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+// Deepest a patch is split into quarters before intersecting the pieces
+const MAX_DEPTH: usize = 6;
+// How far a piece's control points may stray from the plane of its corners, relative to its
+// size, for Newton's method to reliably find hits from its centre
+const FLATNESS: f64 = 0.05;
+const NEWTON_ITERATIONS: usize = 16;
+// How far outside its piece of the patch a hit may be found, to close gaps between pieces
+const DOMAIN_EPSILON: f64 = 1e-7;
+
+// A `type = "bezier"` entity, read from a file of patches
+#[derive(Debug, Clone, Deserialize)]
+pub struct BezierSpec {
+    pub file: String, // relative to the scene file
+    pub material: Material,
+}
+
+// Cubic Bernstein polynomials and their derivatives at t
+pub fn bernstein(t: f64) -> ([f64; 4], [f64; 4]) {
+    let s = 1. - t;
+    (
+        [s * s * s, 3. * t * s * s, 3. * t * t * s, t * t * t],
+        [
+            -3. * s * s,
+            3. * s * s - 6. * t * s,
+            6. * t * s - 3. * t * t,
+            3. * t * t,
+        ],
+    )
+}
+
+// Control points of the part of a cubic curve between a and b
+pub fn restrict(curve: [Vector; 4], a: f64, b: f64) -> [Vector; 4] {
+    // Split with de Casteljau's algorithm at t, keeping the part before or after it
+    let split = |c: [Vector; 4], t: f64, before: bool| {
+        let lerp = |p: Vector, q: Vector| p + (q - p) * t;
+        let (p01, p12, p23) = (lerp(c[0], c[1]), lerp(c[1], c[2]), lerp(c[2], c[3]));
+        let (p012, p123) = (lerp(p01, p12), lerp(p12, p23));
+        let middle = lerp(p012, p123);
+        if before {
+            [c[0], p01, p012, middle]
+        } else {
+            [middle, p123, p23, c[3]]
+        }
+    };
+    if b == 0. {
+        return [curve[0]; 4];
+    }
+    split(split(curve, b, true), a / b, false)
+}
+
+// Bicubic patch, with `points[i][j]` the control point j along u in row i along v
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Patch {
+    pub points: [[Vector; 4]; 4],
+}
+
+impl Patch {
+    // Position and its derivatives with respect to u and v
+    pub fn evaluate(&self, u: f64, v: f64) -> (Vector, Vector, Vector) {
+        let ((bu, dbu), (bv, dbv)) = (bernstein(u), bernstein(v));
+        let mut p = Vector::zero();
+        let mut dpdu = Vector::zero();
+        let mut dpdv = Vector::zero();
+        for i in 0..4 {
+            for j in 0..4 {
+                let point = self.points[i][j];
+                p += point * (bv[i] * bu[j]);
+                dpdu += point * (bv[i] * dbu[j]);
+                dpdv += point * (dbv[i] * bu[j]);
+            }
+        }
+        (p, dpdu, dpdv)
+    }
+
+    // The patch over part of its domain, as a patch of its own
+    fn restrict(&self, u: (f64, f64), v: (f64, f64)) -> Patch {
+        let rows = self.points.map(|row| restrict(row, u.0, u.1));
+        let mut points = rows;
+        for j in 0..4 {
+            let column = restrict([rows[0][j], rows[1][j], rows[2][j], rows[3][j]], v.0, v.1);
+            for i in 0..4 {
+                points[i][j] = column[i];
+            }
+        }
+        Patch { points }
+    }
+
+    // Greatest distance of a control point from the bilinear patch through the corners
+    fn flatness(&self) -> f64 {
+        let p = &self.points;
+        let mut furthest: f64 = 0.;
+        for i in 0..4 {
+            for j in 0..4 {
+                let (s, t) = (j as f64 / 3., i as f64 / 3.);
+                let top = p[0][0] * (1. - s) + p[0][3] * s;
+                let bottom = p[3][0] * (1. - s) + p[3][3] * s;
+                let bilinear = top * (1. - t) + bottom * t;
+                furthest = furthest.max((p[i][j] - bilinear).length());
+            }
+        }
+        furthest
+    }
+
+    fn bounds(&self) -> Aabb {
+        // Bézier patches lie within the hull of their control points
+        Aabb::from_points(self.points.as_flattened())
+    }
+}
+
+// Read patches in the .bpt format: the number of patches, then for each its degree in u and v
+// (which must be 3 3) followed by its 16 control points, row by row
+pub fn parse_bpt(text: &str) -> Result<Vec<Patch>, Box<dyn Error>> {
+    let mut numbers = text.split_whitespace().map(|word| {
+        word.parse::<f64>()
+            .map_err(|_| format!("expected a number, found '{}'", word))
+    });
+    let mut next = || numbers.next().ok_or("unexpected end of patch file")?;
+
+    let count = next()?;
+    let mut patches = Vec::new();
+    for _ in 0..count as usize {
+        let degree = (next()?, next()?);
+        if degree != (3., 3.) {
+            return Err(format!("only bicubic patches are supported, not {:?}", degree).into());
+        }
+        let mut points = [[Vector::zero(); 4]; 4];
+        for row in &mut points {
+            for point in row {
+                *point = Vector::new(next()?, next()?, next()?);
+            }
+        }
+        patches.push(Patch { points });
+    }
+    Ok(patches)
+}
+
+pub fn load_bpt(path: &Path) -> Result<Vec<Patch>, Box<dyn Error>> {
+    parse_bpt(&fs::read_to_string(path)?)
+}
+
+// Part of a patch small and flat enough to intersect directly
+#[derive(Debug, Clone, Copy)]
+struct Piece {
+    patch: usize,
+    u: (f64, f64),
+    v: (f64, f64),
+}
+
+// Hit on a piece: distance along the ray and where on the patch
+struct PatchHit {
+    t: f64,
+    u: f64,
+    v: f64,
+}
+
+// Surface made of Bézier patches, intersected exactly. Each patch is split until its pieces are
+// nearly flat, and Newton's method then finds the hit on a piece the ray enters the bounds of.
+pub struct BezierSurface {
+    patches: Vec<Patch>,
+    pieces: Vec<Piece>,
+    bvh: Bvh,
+    material: Material,
+}
+
+impl BezierSurface {
+    pub fn new(patches: Vec<Patch>, material: Material) -> Result<Self, String> {
+        if patches.is_empty() {
+            return Err("the surface has no patches".to_string());
+        }
+        let mut pieces = Vec::new();
+        let mut bounds = Vec::new();
+        for (index, patch) in patches.iter().enumerate() {
+            split(
+                patch,
+                index,
+                (0., 1.),
+                (0., 1.),
+                0,
+                &mut pieces,
+                &mut bounds,
+            );
+        }
+        Ok(BezierSurface {
+            patches,
+            pieces,
+            bvh: Bvh::new(&bounds),
+            material,
+        })
+    }
+
+    // Solve S(u, v) = o + t d, starting from the middle of the piece
+    fn intersect_piece(&self, ray: &Ray, piece: &Piece) -> Option<PatchHit> {
+        let patch = &self.patches[piece.patch];
+        let mut u = (piece.u.0 + piece.u.1) / 2.;
+        let mut v = (piece.v.0 + piece.v.1) / 2.;
+        let (start, _, _) = patch.evaluate(u, v);
+        let mut t = (start - ray.origin).dot(&ray.direction) / ray.direction.abs_squared();
+
+        let bounds = patch.bounds();
+        let tolerance = 1e-10 * (1. + (bounds.max - bounds.min).length());
+        for _ in 0..NEWTON_ITERATIONS {
+            let (p, dpdu, dpdv) = patch.evaluate(u, v);
+            let error = p - ray.at(t);
+            if error.length() < tolerance {
+                let inside =
+                    |x: f64, (a, b): (f64, f64)| x >= a - DOMAIN_EPSILON && x <= b + DOMAIN_EPSILON;
+                if t > 0. && inside(u, piece.u) && inside(v, piece.v) {
+                    return Some(PatchHit {
+                        t,
+                        u: u.clamp(0., 1.),
+                        v: v.clamp(0., 1.),
+                    });
+                }
+                return None;
+            }
+            // Jacobian columns dS/du, dS/dv and -d; solve J x = error by Cramer's rule
+            let d = -ray.direction;
+            let determinant = dpdu.dot(&dpdv.cross(&d));
+            if determinant.abs() < 1e-300 {
+                return None;
+            }
+            u -= error.dot(&dpdv.cross(&d)) / determinant;
+            v -= dpdu.dot(&error.cross(&d)) / determinant;
+            t -= dpdu.dot(&dpdv.cross(&error)) / determinant;
+            if !(u.is_finite() && v.is_finite() && t.is_finite()) {
+                return None;
+            }
+        }
+        None
+    }
+}
+
+// Split a patch into pieces flat enough to intersect, with their bounds
+fn split(
+    patch: &Patch,
+    index: usize,
+    u: (f64, f64),
+    v: (f64, f64),
+    depth: usize,
+    pieces: &mut Vec<Piece>,
+    bounds: &mut Vec<Aabb>,
+) {
+    let piece = patch.restrict(u, v);
+    let piece_bounds = piece.bounds();
+    let size = (piece_bounds.max - piece_bounds.min).length();
+    if depth == MAX_DEPTH || piece.flatness() <= FLATNESS * size {
+        pieces.push(Piece { patch: index, u, v });
+        bounds.push(piece_bounds);
+        return;
+    }
+    let (u_middle, v_middle) = ((u.0 + u.1) / 2., (v.0 + v.1) / 2.);
+    for u in [(u.0, u_middle), (u_middle, u.1)] {
+        for v in [(v.0, v_middle), (v_middle, v.1)] {
+            split(patch, index, u, v, depth + 1, pieces, bounds);
+        }
+    }
+}
+
+impl Entity for BezierSurface {
+    fn intersection(&self, ray: &Ray) -> IntersectionResult {
+        match self.hit(ray) {
+            Some(hit) => IntersectionResult::One(hit.t),
+            None => IntersectionResult::No,
+        }
+    }
+
+    // The part hit is the piece
+    fn hit(&self, ray: &Ray) -> Option<Hit> {
+        let (piece, hit) = self.bvh.nearest(ray, |i| {
+            self.intersect_piece(ray, &self.pieces[i])
+                .map(|hit| Hit::new(hit.t))
+        })?;
+        Some(Hit { part: piece, ..hit })
+    }
+
+    fn surface(&self, ray: &Ray, hit: Hit) -> SurfaceInteraction<'_> {
+        // Test the piece again for where on its patch the hit is. It is the test that found the
+        // hit, so it can't miss.
+        let piece = &self.pieces[hit.part];
+        let (u, v) = self.intersect_piece(ray, piece).map_or(
+            ((piece.u.0 + piece.u.1) / 2., (piece.v.0 + piece.v.1) / 2.),
+            |hit| (hit.u, hit.v),
+        );
+        let patch = &self.patches[piece.patch];
+        let (_, dpdu, dpdv) = patch.evaluate(u, v);
+        let mut normal = dpdu.cross(&dpdv);
+        if normal.abs_squared() < 1e-20 {
+            // A corner collapsed to a point, as at the top of the teapot's lid. Step towards the
+            // middle of the patch, where the normal is well defined.
+            let (_, dpdu, dpdv) = patch.evaluate(u + (0.5 - u) * 1e-4, v + (0.5 - v) * 1e-4);
+            normal = dpdu.cross(&dpdv);
+        }
+        SurfaceInteraction {
+            position: ray.at(hit.t),
+            normal: normal.normalised(),
+            uv: (u, v),
+            dpdu,
+            dpdv,
+            object_position: ray.at(hit.t),
+            vertex_colour: None,
+            material: &self.material,
+        }
+    }
+
+    fn bounds(&self) -> Aabb {
+        self.bvh.bounds()
+    }
+
+    fn position(&self) -> Vector {
+        self.bounds().centre()
+    }
+
+    fn emission(&self) -> Colour {
+        self.material.emission
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::Rng;
+
+    // Over [0, 3] x [0, 3] in x and y, raised in the middle
+    fn dome() -> Patch {
+        let mut points = [[Vector::zero(); 4]; 4];
+        for (i, row) in points.iter_mut().enumerate() {
+            for (j, point) in row.iter_mut().enumerate() {
+                let raised = (1..=2).contains(&i) && (1..=2).contains(&j);
+                *point = Vector::new(j as f64, i as f64, if raised { 2. } else { 0. });
+            }
+        }
+        Patch { points }
+    }
+
+    #[test]
+    fn evaluation_and_restriction() {
+        let patch = dome();
+        let (p, dpdu, dpdv) = patch.evaluate(0.5, 0.5);
+        // The Bernstein weights of the raised middle four points sum to (3/4)^2
+        assert!((p - Vector::new(1.5, 1.5, 2. * 0.75 * 0.75)).length() < 1e-12);
+        assert!(dpdu.z.abs() < 1e-12 && dpdv.z.abs() < 1e-12);
+        // A piece of the patch traces the same surface
+        let piece = patch.restrict((0.25, 0.75), (0.5, 1.));
+        let (q, _, _) = piece.evaluate(0.5, 0.);
+        assert!((q - p).length() < 1e-12);
+    }
+
+    #[test]
+    fn newton_finds_hits_on_the_surface() {
+        assert!(BezierSurface::new(Vec::new(), Material::default()).is_err());
+        let surface = BezierSurface::new(vec![dome()], Material::default()).unwrap();
+        assert!(surface.pieces.len() > 1);
+        let mut rng = Rng::new(3);
+        for _ in 0..200 {
+            let (a, b) = rng.next_2d();
+            let (c, d) = rng.next_2d();
+            let origin = Vector::new(a * 5. - 1., b * 5. - 1., 5.);
+            let ray = Ray::new(origin, Vector::new(c * 3., d * 3., 0.) - origin);
+            let Some(hit) = surface.hit(&ray) else {
+                continue;
+            };
+            let interaction = surface.surface(&ray, hit);
+            let (p, _, _) = dome().evaluate(interaction.uv.0, interaction.uv.1);
+            assert!((p - ray.at(hit.t)).length() < 1e-8);
+            // Straight down onto the dome's top is the nearest hit
+            assert!(interaction.normal.z > 0.);
+        }
+        let down = Ray::new(Vector::new(1.5, 1.5, 5.), Vector::new(0., 0., -1.));
+        let hit = surface.hit(&down).unwrap();
+        assert!((hit.t - (5. - 1.125)).abs() < 1e-9);
+        assert!((surface.surface(&down, hit).normal - Vector::new(0., 0., 1.)).length() < 1e-9);
+        let beside = Ray::new(Vector::new(3.5, 1.5, 5.), Vector::new(0., 0., -1.));
+        assert_eq!(surface.intersection(&beside), IntersectionResult::No);
+
+        // Seen by paths that hit it, but not sampled as a light
+        let material = Material {
+            emission: Colour::white(),
+            ..Material::default()
+        };
+        let glowing = BezierSurface::new(vec![dome()], material).unwrap();
+        assert!(glowing.emission() == Colour::white() && !glowing.can_sample());
+    }
+
+    #[test]
+    fn reads_bpt_files() {
+        let mut text = String::from("1\n3 3\n");
+        for i in 0..4 {
+            for j in 0..4 {
+                text += &format!("{} {} 0\n", j, i);
+            }
+        }
+        let patches = parse_bpt(&text).unwrap();
+        assert_eq!(patches.len(), 1);
+        assert_eq!(patches[0].points[2][1], Vector::new(1., 2., 0.));
+        assert!(parse_bpt("1\n2 2\n").is_err());
+        assert!(parse_bpt(&text[..text.len() - 6]).is_err());
+    }
+}
@@ -0,0 +1,511 @@
+// Resources:
+// Microfacet models for refraction through rough surfaces (Walter et al. 2007): https://www.cs.cornell.edu/~srm/publications/EGSR07-btdf.pdf
+// Sampling the GGX distribution of visible normals (Heitz 2018): https://jcgt.org/published/0007/04/01/
+// Fresnel equations and conductor IOR: https://pbr-book.org/4ed/Reflection_Models/Specular_Reflection_and_Transmission
+// Measured metal IORs: https://refractiveindex.info
+
+use core::f64;
+
+use crate::colour::Colour;
+use crate::sampling;
+use crate::vector::Vector;
+
+// Below this the GGX distribution is numerically a mirror, which the integrator can't sample
+// as a delta, so roughness is clamped to stay just above it
+const MIN_ALPHA: f64 = 1e-3;
+
+// All directions are in the local shading frame, where the normal is +z. Both point away from
+// the surface.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BsdfSample {
+    pub wi: Vector,
+    pub f: Colour,
+    pub pdf: f64, // solid angle
+}
+
+fn same_hemisphere(a: Vector, b: Vector) -> bool {
+    a.z * b.z > 0.
+}
+
+pub fn reflect(wo: Vector, n: Vector) -> Vector {
+    n * (2. * wo.dot(&n)) - wo
+}
+
+// Direction refracted through a surface with normal `n` (on either side of `wo`) and relative
+// IOR `eta` (inside over outside), or None for total internal reflection
+fn refract(wo: Vector, n: Vector, eta: f64) -> Option<Vector> {
+    let mut cos_i = n.dot(&wo);
+    let (mut n, mut eta) = (n, eta);
+    if cos_i < 0. {
+        eta = 1. / eta;
+        cos_i = -cos_i;
+        n = -n;
+    }
+    let sin2_i = (1. - cos_i * cos_i).max(0.);
+    let sin2_t = sin2_i / (eta * eta);
+    if sin2_t >= 1. {
+        return None;
+    }
+    let cos_t = (1. - sin2_t).sqrt();
+    Some(-wo / eta + n * (cos_i / eta - cos_t))
+}
+
+// Unpolarised reflectance of a dielectric boundary; `cos_i` is negative from inside
+pub fn fresnel_dielectric(cos_i: f64, eta: f64) -> f64 {
+    let (cos_i, eta) = if cos_i < 0. {
+        (-cos_i, 1. / eta)
+    } else {
+        (cos_i, eta)
+    };
+    let sin2_t = (1. - cos_i * cos_i).max(0.) / (eta * eta);
+    if sin2_t >= 1. {
+        return 1.;
+    }
+    let cos_t = (1. - sin2_t).sqrt();
+    let r_parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    (r_parallel * r_parallel + r_perpendicular * r_perpendicular) / 2.
+}
+
+// Reflectance of a conductor with complex IOR eta + ik, for one wavelength
+fn fresnel_conductor_channel(cos_i: f64, eta: f64, k: f64) -> f64 {
+    let cos2 = cos_i.clamp(0., 1.).powi(2);
+    let sin2 = 1. - cos2;
+    let (eta2, k2) = (eta * eta, k * k);
+
+    let t0 = eta2 - k2 - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4. * eta2 * k2).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.).sqrt();
+    let t2 = 2. * cos_i * a;
+    let r_s = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let r_p = r_s * (t3 - t4) / (t3 + t4);
+    (r_p + r_s) / 2.
+}
+
+pub fn fresnel_conductor(cos_i: f64, eta: Colour, k: Colour) -> Colour {
+    let f = |eta: f32, k: f32| fresnel_conductor_channel(cos_i, eta as f64, k as f64) as f32;
+    Colour::new(f(eta.r, k.r), f(eta.g, k.g), f(eta.b, k.b))
+}
+
+// Complex IORs of common metals at roughly the red, green and blue wavelengths (650, 550 and
+// 450nm), as (eta, k)
+pub fn metal_ior(name: &str) -> Option<(Colour, Colour)> {
+    match name.to_lowercase().as_str() {
+        "gold" => Some((
+            Colour::new(0.143, 0.374, 1.442),
+            Colour::new(3.983, 2.385, 1.603),
+        )),
+        "copper" => Some((
+            Colour::new(0.200, 0.924, 1.102),
+            Colour::new(3.912, 2.452, 2.142),
+        )),
+        "aluminium" | "aluminum" => Some((
+            Colour::new(1.657, 0.880, 0.521),
+            Colour::new(9.224, 6.270, 4.837),
+        )),
+        "silver" => Some((
+            Colour::new(0.155, 0.117, 0.138),
+            Colour::new(4.828, 3.122, 2.147),
+        )),
+        _ => None,
+    }
+}
+
+// Trowbridge-Reitz (GGX) distribution of microfacet normals with Smith height-correlated
+// masking-shadowing
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrowbridgeReitz {
+    alpha: f64,
+}
+
+impl TrowbridgeReitz {
+    // Perceptual roughness in [0, 1], squared as in the Disney model
+    pub fn from_roughness(roughness: f64) -> Self {
+        TrowbridgeReitz {
+            alpha: (roughness * roughness).clamp(MIN_ALPHA, 1.),
+        }
+    }
+
+    pub fn d(&self, wm: Vector) -> f64 {
+        let cos2 = wm.z * wm.z;
+        if cos2 == 0. {
+            return 0.;
+        }
+        let a2 = self.alpha * self.alpha;
+        let denominator = cos2 * (a2 - 1.) + 1.;
+        a2 / (f64::consts::PI * denominator * denominator)
+    }
+
+    fn lambda(&self, w: Vector) -> f64 {
+        let cos2 = w.z * w.z;
+        if cos2 == 0. {
+            return f64::INFINITY;
+        }
+        let tan2 = (1. - cos2).max(0.) / cos2;
+        ((1. + self.alpha * self.alpha * tan2).sqrt() - 1.) / 2.
+    }
+
+    pub fn g1(&self, w: Vector) -> f64 {
+        1. / (1. + self.lambda(w))
+    }
+
+    pub fn g(&self, wo: Vector, wi: Vector) -> f64 {
+        1. / (1. + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // Density of visible normals from `w`, with respect to solid angle around wm
+    pub fn pdf(&self, w: Vector, wm: Vector) -> f64 {
+        if w.z == 0. {
+            return 0.;
+        }
+        self.g1(w) / w.z.abs() * self.d(wm) * w.dot(&wm).abs()
+    }
+
+    // Microfacet normal (in the upper hemisphere) sampled proportionally to its visibility from w
+    pub fn sample_wm(&self, w: Vector, u: (f64, f64)) -> Vector {
+        // Stretch the view direction to the hemisphere configuration
+        let mut wh = Vector::new(self.alpha * w.x, self.alpha * w.y, w.z).normalised();
+        if wh.z < 0. {
+            wh = -wh;
+        }
+        let (t1, t2) = if wh.z < 0.99999 {
+            let t1 = Vector::new(0., 0., 1.).cross(&wh).normalised();
+            (t1, wh.cross(&t1))
+        } else {
+            (Vector::new(1., 0., 0.), Vector::new(0., 1., 0.))
+        };
+
+        // Uniform point on a disk, warped to the projected visible hemisphere
+        let r = u.0.sqrt();
+        let phi = 2. * f64::consts::PI * u.1;
+        let p1 = r * phi.cos();
+        let p2 = r * phi.sin();
+        let h = (1. - p1 * p1).max(0.).sqrt();
+        let s = (1. + wh.z) / 2.;
+        let p2 = (1. - s) * h + s * p2;
+        let pz = (1. - p1 * p1 - p2 * p2).max(0.).sqrt();
+
+        let nh = t1 * p1 + t2 * p2 + wh * pz;
+        // Unstretch
+        Vector::new(self.alpha * nh.x, self.alpha * nh.y, nh.z.max(1e-6)).normalised()
+    }
+}
+
+// How a surface scatters light at one point. The integrator only sees this trait, so a new
+// material model just needs to return its own implementation.
+pub trait Bsdf {
+    // BSDF value for light arriving from wi and leaving towards wo
+    fn evaluate(&self, wo: Vector, wi: Vector) -> Colour;
+
+    // Choose wi given wo; `u_lobe` picks between lobes and `u` the direction within one
+    fn sample(&self, wo: Vector, u_lobe: f64, u: (f64, f64)) -> Option<BsdfSample>;
+
+    // Solid angle density of `sample` choosing wi
+    fn pdf(&self, wo: Vector, wi: Vector) -> f64;
+}
+
+// Fill in a sample for a direction that has already been chosen, using the BSDF's own evaluate
+// and pdf
+pub fn sample_towards<B: Bsdf + ?Sized>(bsdf: &B, wo: Vector, wi: Vector) -> Option<BsdfSample> {
+    let pdf = bsdf.pdf(wo, wi);
+    if pdf <= 0. {
+        return None;
+    }
+    Some(BsdfSample {
+        wi,
+        f: bsdf.evaluate(wo, wi),
+        pdf,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Diffuse {
+    pub albedo: Colour,
+}
+
+impl Bsdf for Diffuse {
+    fn evaluate(&self, wo: Vector, wi: Vector) -> Colour {
+        if !same_hemisphere(wo, wi) {
+            return Colour::black();
+        }
+        self.albedo / f64::consts::PI as f32
+    }
+
+    fn sample(&self, wo: Vector, _u_lobe: f64, u: (f64, f64)) -> Option<BsdfSample> {
+        if wo.z == 0. {
+            return None;
+        }
+        let wi = sampling::cosine_hemisphere(Vector::new(0., 0., 1.), u);
+        let wi = if wo.z < 0. {
+            Vector::new(wi.x, wi.y, -wi.z)
+        } else {
+            wi
+        };
+        sample_towards(self, wo, wi)
+    }
+
+    fn pdf(&self, wo: Vector, wi: Vector) -> f64 {
+        if !same_hemisphere(wo, wi) {
+            return 0.;
+        }
+        sampling::cosine_hemisphere_pdf(wi.z.abs())
+    }
+}
+
+// Rough metal with a complex index of refraction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Conductor {
+    pub eta: Colour,
+    pub k: Colour,
+    pub distribution: TrowbridgeReitz,
+}
+
+impl Bsdf for Conductor {
+    fn evaluate(&self, wo: Vector, wi: Vector) -> Colour {
+        let (cos_o, cos_i) = (wo.z.abs(), wi.z.abs());
+        if !same_hemisphere(wo, wi) || cos_o == 0. || cos_i == 0. {
+            return Colour::black();
+        }
+        let wm = face_up((wo + wi).normalised());
+        let fresnel = fresnel_conductor(wo.dot(&wm).abs(), self.eta, self.k);
+        let value = self.distribution.d(wm) * self.distribution.g(wo, wi) / (4. * cos_o * cos_i);
+        fresnel * value as f32
+    }
+
+    fn sample(&self, wo: Vector, _u_lobe: f64, u: (f64, f64)) -> Option<BsdfSample> {
+        if wo.z == 0. {
+            return None;
+        }
+        let wm = self.distribution.sample_wm(wo, u);
+        sample_towards(self, wo, reflect(wo, wm))
+    }
+
+    fn pdf(&self, wo: Vector, wi: Vector) -> f64 {
+        if !same_hemisphere(wo, wi) {
+            return 0.;
+        }
+        let wm = face_up((wo + wi).normalised());
+        self.distribution.pdf(wo, wm) / (4. * wo.dot(&wm).abs())
+    }
+}
+
+// Rough glass, with eta the IOR inside over outside (the normal points outside)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Dielectric {
+    pub eta: f64,
+    pub distribution: TrowbridgeReitz,
+}
+
+impl Bsdf for Dielectric {
+    fn evaluate(&self, wo: Vector, wi: Vector) -> Colour {
+        let Some((wm, eta_path)) = dielectric_half_vector(wo, wi, self.eta) else {
+            return Colour::black();
+        };
+        let (cos_o, cos_i) = (wo.z, wi.z);
+        let fresnel = fresnel_dielectric(wo.dot(&wm), self.eta);
+        let d_g = self.distribution.d(wm) * self.distribution.g(wo, wi);
+        let value = if same_hemisphere(wo, wi) {
+            d_g * fresnel / (4. * cos_o * cos_i).abs()
+        } else {
+            let denominator = (wi.dot(&wm) + wo.dot(&wm) / eta_path).powi(2);
+            // Radiance is compressed into a smaller solid angle on entering a denser medium
+            d_g * (1. - fresnel) * (wi.dot(&wm) * wo.dot(&wm) / (cos_i * cos_o * denominator)).abs()
+                / (eta_path * eta_path)
+        };
+        Colour::white() * value as f32
+    }
+
+    fn sample(&self, wo: Vector, u_lobe: f64, u: (f64, f64)) -> Option<BsdfSample> {
+        if wo.z == 0. {
+            return None;
+        }
+        let wm = self.distribution.sample_wm(wo, u);
+        // Choose reflection or transmission in proportion to the Fresnel term
+        let reflectance = fresnel_dielectric(wo.dot(&wm), self.eta);
+        let wi = if u_lobe < reflectance {
+            reflect(wo, wm)
+        } else {
+            refract(wo, wm, self.eta)?
+        };
+        sample_towards(self, wo, wi)
+    }
+
+    fn pdf(&self, wo: Vector, wi: Vector) -> f64 {
+        let Some((wm, eta_path)) = dielectric_half_vector(wo, wi, self.eta) else {
+            return 0.;
+        };
+        let reflectance = fresnel_dielectric(wo.dot(&wm), self.eta);
+        if same_hemisphere(wo, wi) {
+            self.distribution.pdf(wo, wm) / (4. * wo.dot(&wm).abs()) * reflectance
+        } else {
+            let denominator = (wi.dot(&wm) + wo.dot(&wm) / eta_path).powi(2);
+            let dwm_dwi = wi.dot(&wm).abs() / denominator;
+            self.distribution.pdf(wo, wm) * dwm_dwi * (1. - reflectance)
+        }
+    }
+}
+
+// Flip a microfacet normal into the upper hemisphere
+fn face_up(wm: Vector) -> Vector {
+    if wm.z < 0. {
+        -wm
+    } else {
+        wm
+    }
+}
+
+// Microfacet normal (in the upper hemisphere) that reflects or refracts wo into wi, along with
+// the relative IOR along the path, or None if no microfacet facing both directions does
+fn dielectric_half_vector(wo: Vector, wi: Vector, eta: f64) -> Option<(Vector, f64)> {
+    let (cos_o, cos_i) = (wo.z, wi.z);
+    if cos_o == 0. || cos_i == 0. {
+        return None;
+    }
+    let eta_path = if same_hemisphere(wo, wi) {
+        1.
+    } else if cos_o > 0. {
+        eta
+    } else {
+        1. / eta
+    };
+    let wm = wi * eta_path + wo;
+    if wm.abs_squared() == 0. {
+        return None;
+    }
+    let wm = face_up(wm.normalised());
+    // Discard back-facing microfacets
+    if wm.dot(&wi) * cos_i < 0. || wm.dot(&wo) * cos_o < 0. {
+        return None;
+    }
+    Some((wm, eta_path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::Rng;
+
+    fn direction(theta: f64, phi: f64) -> Vector {
+        Vector::new(
+            theta.sin() * phi.cos(),
+            theta.sin() * phi.sin(),
+            theta.cos(),
+        )
+    }
+
+    #[test]
+    fn fresnel_limits() {
+        // Normal incidence on glass reflects 4%
+        assert!((fresnel_dielectric(1., 1.5) - 0.04).abs() < 1e-9);
+        // Total internal reflection from inside at a grazing angle
+        assert_eq!(fresnel_dielectric(-0.1, 1.5), 1.);
+        // A conductor with no absorption behaves like a dielectric
+        let c = fresnel_conductor(1., Colour::new(1.5, 1.5, 1.5), Colour::black());
+        assert!((c.r - 0.04).abs() < 1e-6);
+        // Gold reflects more red than blue
+        let (eta, k) = metal_ior("gold").unwrap();
+        let gold = fresnel_conductor(0.8, eta, k);
+        assert!(gold.r > gold.b);
+    }
+
+    #[test]
+    fn visible_normals_integrate_to_one() {
+        // The distribution of visible normals is normalised for every view direction
+        let distribution = TrowbridgeReitz::from_roughness(0.6);
+        let wo = direction(1.0, 0.3);
+        let mut rng = Rng::new(3);
+        let n = 200000;
+        let mut total = 0.;
+        for _ in 0..n {
+            // Uniform over the hemisphere, pdf 1 / 2π
+            let w = sampling::uniform_sphere(rng.next_2d());
+            let wm = Vector::new(w.x, w.y, w.z.abs());
+            if wm.dot(&wo) > 0. {
+                total += distribution.pdf(wo, wm) * 2. * f64::consts::PI;
+            }
+        }
+        assert!((total / n as f64 - 1.).abs() < 0.02, "{}", total / n as f64);
+    }
+
+    fn assert_sampling_matches_pdf(bsdf: &dyn Bsdf, wo: Vector) {
+        let mut rng = Rng::new(9);
+        for _ in 0..1000 {
+            if let Some(sample) = bsdf.sample(wo, rng.next_f64(), rng.next_2d()) {
+                let pdf = bsdf.pdf(wo, sample.wi);
+                assert!(
+                    (pdf - sample.pdf).abs() <= 1e-6 * pdf,
+                    "{} {}",
+                    pdf,
+                    sample.pdf
+                );
+                assert!((sample.wi.length() - 1.).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn samples_agree_with_pdfs() {
+        let distribution = TrowbridgeReitz::from_roughness(0.4);
+        let (eta, k) = metal_ior("copper").unwrap();
+        let bsdfs: [&dyn Bsdf; 3] = [
+            &Diffuse {
+                albedo: Colour::white(),
+            },
+            &Conductor {
+                eta,
+                k,
+                distribution,
+            },
+            &Dielectric {
+                eta: 1.5,
+                distribution,
+            },
+        ];
+        for bsdf in bsdfs {
+            assert_sampling_matches_pdf(bsdf, direction(0.7, 1.));
+            assert_sampling_matches_pdf(bsdf, direction(2.5, -0.4));
+        }
+    }
+
+    // Monte Carlo estimate of the fraction of light leaving towards wo that is reflected or
+    // transmitted, using the BSDF's own sampling
+    fn albedo(bsdf: &dyn Bsdf, wo: Vector) -> f64 {
+        let mut rng = Rng::new(17);
+        let n = 100000;
+        let mut total = 0.;
+        for _ in 0..n {
+            if let Some(sample) = bsdf.sample(wo, rng.next_f64(), rng.next_2d()) {
+                total += sample.f.r as f64 * sample.wi.z.abs() / sample.pdf;
+            }
+        }
+        total / n as f64
+    }
+
+    #[test]
+    fn energy_is_conserved() {
+        let distribution = TrowbridgeReitz::from_roughness(0.3);
+        let mirror = Conductor {
+            eta: Colour::new(0., 0., 0.),
+            k: Colour::new(1e3, 1e3, 1e3),
+            distribution,
+        };
+        // A perfect reflector loses energy only to single-scattering masking
+        let a = albedo(&mirror, direction(0.5, 0.));
+        assert!(a > 0.9 && a <= 1.001, "{}", a);
+
+        // From outside glass reflects and refracts everything between them; the refracted part
+        // is compressed by 1 / eta^2
+        let glass = Dielectric {
+            eta: 1.5,
+            distribution: TrowbridgeReitz::from_roughness(0.),
+        };
+        let a = albedo(&glass, direction(0.3, 0.));
+        let r = fresnel_dielectric(0.3_f64.cos(), 1.5);
+        let expected = r + (1. - r) / (1.5 * 1.5);
+        assert!((a - expected).abs() < 0.01, "{} {}", a, expected);
+    }
+}
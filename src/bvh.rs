@@ -0,0 +1,348 @@
+// Resources:
+// Bounding volume hierarchies: https://pbr-book.org/4ed/Primitives_and_Intersection_Acceleration/Bounding_Volume_Hierarchies
+// Ray/box slab test: https://tavianator.com/2011/ray_box.html
+
+use crate::ray::Ray;
+use crate::vector::{Transform, Vector};
+use crate::world::Hit;
+
+// Most items a leaf holds before it is split
+const MAX_LEAF_SIZE: usize = 2;
+
+// Axis-aligned bounding box
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vector,
+    pub max: Vector,
+}
+
+impl Aabb {
+    pub fn new(min: Vector, max: Vector) -> Self {
+        Aabb { min, max }
+    }
+
+    // Contains nothing; the identity for `union`
+    pub fn empty() -> Self {
+        Aabb {
+            min: Vector::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+            max: Vector::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+        }
+    }
+
+    pub fn from_points(points: &[Vector]) -> Self {
+        points
+            .iter()
+            .fold(Aabb::empty(), |bounds, &p| bounds.union(&Aabb::new(p, p)))
+    }
+
+    pub fn union(&self, other: &Aabb) -> Self {
+        Aabb {
+            min: Vector::new(
+                self.min.x.min(other.min.x),
+                self.min.y.min(other.min.y),
+                self.min.z.min(other.min.z),
+            ),
+            max: Vector::new(
+                self.max.x.max(other.max.x),
+                self.max.y.max(other.max.y),
+                self.max.z.max(other.max.z),
+            ),
+        }
+    }
+
+    pub fn centre(&self) -> Vector {
+        (self.min + self.max) / 2.
+    }
+
+    fn extent(&self) -> Vector {
+        self.max - self.min
+    }
+
+    fn corners(&self) -> [Vector; 8] {
+        let (a, b) = (self.min, self.max);
+        [
+            Vector::new(a.x, a.y, a.z),
+            Vector::new(b.x, a.y, a.z),
+            Vector::new(a.x, b.y, a.z),
+            Vector::new(b.x, b.y, a.z),
+            Vector::new(a.x, a.y, b.z),
+            Vector::new(b.x, a.y, b.z),
+            Vector::new(a.x, b.y, b.z),
+            Vector::new(b.x, b.y, b.z),
+        ]
+    }
+
+    // Bounds of the box's corners after transforming them into another space
+    pub fn transformed(&self, transform: &Transform) -> Self {
+        Aabb::from_points(&self.corners().map(|p| transform.point(p)))
+    }
+
+    // Distances along the whole line of the ray (behind its origin too) where it enters and
+    // leaves the box, if it passes through it
+    pub fn interval(&self, ray: &Ray) -> Option<(f64, f64)> {
+        let mut t_min = f64::NEG_INFINITY;
+        let mut t_max = f64::INFINITY;
+        let axes = [
+            (ray.origin.x, ray.direction.x, self.min.x, self.max.x),
+            (ray.origin.y, ray.direction.y, self.min.y, self.max.y),
+            (ray.origin.z, ray.direction.z, self.min.z, self.max.z),
+        ];
+        for (origin, direction, min, max) in axes {
+            let inverse = 1. / direction;
+            let (mut t0, mut t1) = ((min - origin) * inverse, (max - origin) * inverse);
+            if t0 > t1 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            // Written so NaNs (a ray lying in a slab's plane) leave the interval alone
+            if t0 > t_min {
+                t_min = t0;
+            }
+            if t1 < t_max {
+                t_max = t1;
+            }
+            if t_min > t_max {
+                return None;
+            }
+        }
+        Some((t_min, t_max))
+    }
+
+    // Distance along the ray to where it enters the box (zero if it starts inside), if it does
+    // so before `max_distance`
+    pub fn hit(&self, ray: &Ray, max_distance: f64) -> Option<f64> {
+        let (t_min, t_max) = self.interval(ray)?;
+        let entry = t_min.max(0.);
+        if t_max < 0. || entry > max_distance {
+            return None;
+        }
+        Some(entry)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Leaf {
+        bounds: Aabb,
+        start: usize, // into `Bvh::items`
+        count: usize,
+    },
+    Interior {
+        bounds: Aabb,
+        right: usize, // the left child follows its parent
+    },
+}
+
+impl Node {
+    fn bounds(&self) -> &Aabb {
+        match self {
+            Node::Leaf { bounds, .. } | Node::Interior { bounds, .. } => bounds,
+        }
+    }
+}
+
+// Hierarchy of boxes over a list of items, which are referred to by their index in that list
+#[derive(Debug, Clone, Default)]
+pub struct Bvh {
+    nodes: Vec<Node>,
+    items: Vec<usize>,
+}
+
+impl Bvh {
+    pub fn new(bounds: &[Aabb]) -> Self {
+        let mut bvh = Bvh {
+            nodes: Vec::new(),
+            items: (0..bounds.len()).collect(),
+        };
+        if !bounds.is_empty() {
+            bvh.build(bounds, 0, bounds.len());
+        }
+        bvh
+    }
+
+    // Split items[start..end] at the median centroid along the axis they are most spread out on
+    fn build(&mut self, bounds: &[Aabb], start: usize, end: usize) {
+        let items = &mut self.items[start..end];
+        let node_bounds = items
+            .iter()
+            .fold(Aabb::empty(), |b, &i| b.union(&bounds[i]));
+        if items.len() <= MAX_LEAF_SIZE {
+            self.nodes.push(Node::Leaf {
+                bounds: node_bounds,
+                start,
+                count: end - start,
+            });
+            return;
+        }
+
+        let centroids = Aabb::from_points(
+            &items
+                .iter()
+                .map(|&i| bounds[i].centre())
+                .collect::<Vec<_>>(),
+        );
+        let extent = centroids.extent();
+        let axis = |v: Vector| {
+            if extent.x >= extent.y && extent.x >= extent.z {
+                v.x
+            } else if extent.y >= extent.z {
+                v.y
+            } else {
+                v.z
+            }
+        };
+        let middle = items.len() / 2;
+        items.select_nth_unstable_by(middle, |&a, &b| {
+            axis(bounds[a].centre()).total_cmp(&axis(bounds[b].centre()))
+        });
+
+        let index = self.nodes.len();
+        self.nodes.push(Node::Interior {
+            bounds: node_bounds,
+            right: 0,
+        });
+        self.build(bounds, start, start + middle);
+        let right_index = self.nodes.len();
+        self.build(bounds, start + middle, end);
+        if let Node::Interior { right, .. } = &mut self.nodes[index] {
+            *right = right_index;
+        }
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        self.nodes
+            .first()
+            .map_or(Aabb::empty(), |node| *node.bounds())
+    }
+
+    // The item hit nearest along the ray and where, given by `hit` for an item if the ray hits it
+    pub fn nearest(
+        &self,
+        ray: &Ray,
+        mut hit: impl FnMut(usize) -> Option<Hit>,
+    ) -> Option<(usize, Hit)> {
+        let mut nearest: Option<(usize, Hit)> = None;
+        self.traverse(ray, |item, distance| {
+            if let Some(hit) = hit(item) {
+                if hit.t < distance {
+                    nearest = Some((item, hit));
+                    return Some(hit.t);
+                }
+            }
+            None
+        });
+        nearest
+    }
+
+    // Whether `hit` is true for any item whose box the ray enters before `max_distance`
+    pub fn any(&self, ray: &Ray, max_distance: f64, mut hit: impl FnMut(usize) -> bool) -> bool {
+        let mut found = false;
+        self.traverse_within(ray, max_distance, |item, _| {
+            if hit(item) {
+                found = true;
+                return Some(0.);
+            }
+            None
+        });
+        found
+    }
+
+    fn traverse(&self, ray: &Ray, visit: impl FnMut(usize, f64) -> Option<f64>) {
+        self.traverse_within(ray, f64::INFINITY, visit);
+    }
+
+    // Visit the items in every box the ray enters before the current distance, which `visit`
+    // shortens by returning a closer one
+    fn traverse_within(
+        &self,
+        ray: &Ray,
+        max_distance: f64,
+        mut visit: impl FnMut(usize, f64) -> Option<f64>,
+    ) {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let mut distance = max_distance;
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if node.bounds().hit(ray, distance).is_none() {
+                continue;
+            }
+            match *node {
+                Node::Leaf { start, count, .. } => {
+                    for &item in &self.items[start..start + count] {
+                        if let Some(t) = visit(item, distance) {
+                            distance = t;
+                        }
+                    }
+                    // Nothing can be hit closer than the ray origin
+                    if distance <= 0. {
+                        return;
+                    }
+                }
+                Node::Interior { right, .. } => {
+                    // Visit the nearer child first so it can cut the other short
+                    let left = index + 1;
+                    let near_left = self.nodes[left].bounds().hit(ray, distance);
+                    let near_right = self.nodes[right].bounds().hit(ray, distance);
+                    match (near_left, near_right) {
+                        (Some(l), Some(r)) if r < l => stack.extend([left, right]),
+                        _ => stack.extend([right, left]),
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::Rng;
+
+    #[test]
+    fn ray_box_slab_test() {
+        let b = Aabb::new(Vector::new(-1., -1., 2.), Vector::new(1., 1., 4.));
+        let ray = Ray::new(Vector::zero(), Vector::new(0., 0., 1.));
+        assert_eq!(b.hit(&ray, f64::INFINITY), Some(2.));
+        assert_eq!(b.hit(&ray, 1.), None);
+        // Starting inside
+        let inside = Ray::new(Vector::new(0., 0., 3.), Vector::new(1., 0., 0.));
+        assert_eq!(b.hit(&inside, f64::INFINITY), Some(0.));
+        let miss = Ray::new(Vector::new(2., 0., 0.), Vector::new(0., 0., 1.));
+        assert_eq!(b.hit(&miss, f64::INFINITY), None);
+        let behind = Ray::new(Vector::new(0., 0., 5.), Vector::new(0., 0., 1.));
+        assert_eq!(b.hit(&behind, f64::INFINITY), None);
+    }
+
+    #[test]
+    fn nearest_matches_brute_force() {
+        // Unit boxes scattered about, each "hit" at its entry distance
+        let mut rng = Rng::new(11);
+        let boxes: Vec<Aabb> = (0..200)
+            .map(|_| {
+                let (a, b) = (rng.next_2d(), rng.next_2d());
+                let min = Vector::new(a.0 * 20. - 10., a.1 * 20. - 10., b.0 * 20. + 5.);
+                Aabb::new(min, min + Vector::new(1., 1., b.1 + 0.5))
+            })
+            .collect();
+        let bvh = Bvh::new(&boxes);
+        for _ in 0..200 {
+            let (u, v) = rng.next_2d();
+            let direction = Vector::new(u - 0.5, v - 0.5, 1.).normalised();
+            let ray = Ray::new(Vector::zero(), direction);
+            let hit = |i: usize| boxes[i].hit(&ray, f64::INFINITY).map(Hit::new);
+            let expected = (0..boxes.len())
+                .filter_map(|i| hit(i).map(|hit| (i, hit)))
+                .min_by(|a, b| a.1.t.total_cmp(&b.1.t));
+            assert_eq!(bvh.nearest(&ray, hit), expected);
+            assert_eq!(bvh.any(&ray, 1e3, |i| hit(i).is_some()), expected.is_some());
+        }
+        assert!(Bvh::new(&[])
+            .nearest(
+                &Ray::new(Vector::zero(), Vector::new(0., 0., 1.)),
+                |_| Some(Hit::new(1.))
+            )
+            .is_none());
+    }
+}
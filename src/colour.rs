@@ -100,6 +100,18 @@ impl Colour {
         Self::new(1., 1., 1.)
     }
 
+    pub fn black() -> Self {
+        Self::new(0., 0., 0.)
+    }
+
+    pub fn is_black(&self) -> bool {
+        self.r <= 0. && self.g <= 0. && self.b <= 0.
+    }
+
+    pub fn max_component(&self) -> f32 {
+        self.r.max(self.g).max(self.b)
+    }
+
     pub fn as_rgb24(&self) -> u32 {
         let r = f32_0_1_to_u8_0_255(self.r) as u32;
         let g = f32_0_1_to_u8_0_255(self.g) as u32;
@@ -130,6 +142,23 @@ impl AddAssign for Colour {
     }
 }
 
+impl Mul for Colour {
+    type Output = Colour;
+
+    fn mul(mut self, rhs: Colour) -> Self::Output {
+        self *= rhs;
+        return self;
+    }
+}
+
+impl MulAssign for Colour {
+    fn mul_assign(&mut self, rhs: Colour) {
+        self.r *= rhs.r;
+        self.g *= rhs.g;
+        self.b *= rhs.b;
+    }
+}
+
 impl Mul<f32> for Colour {
     type Output = Colour;
 
@@ -181,6 +210,17 @@ mod tests {
         assert_eq!(red, Colour::new(1., 1., 0.));
     }
 
+    #[test]
+    fn multiplying_colours() {
+        let mut a = Colour::new(0.5, 1., 0.);
+        let b = Colour::new(0.5, 0.5, 1.);
+        assert_eq!(a * b, Colour::new(0.25, 0.5, 0.));
+        a *= b;
+        assert_eq!(a, Colour::new(0.25, 0.5, 0.));
+        assert!(Colour::black().is_black());
+        assert_eq!(a.max_component(), 0.5);
+    }
+
     #[test]
     fn colour_to_rgb24() {
         let red = Colour::new(1., 0., 0.);
@@ -0,0 +1,289 @@
+// Resources:
+// Constructive solid geometry by combining ray intervals: https://www.cs.jhu.edu/~cohen/RendTech99/Lectures/Ray_Casting.bw.pdf
+// Roth 1982, "Ray casting for modeling solids"
+
+use crate::bvh::Aabb;
+use crate::colour::Colour;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::vector::Vector;
+use crate::world::{Entity, Hit, IntersectionResult, SurfaceInteraction};
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    Union,
+    Intersection,
+    Difference, // the right child cut out of the left
+}
+
+impl Operation {
+    fn inside(&self, left: bool, right: bool) -> bool {
+        match self {
+            Operation::Union => left || right,
+            Operation::Intersection => left && right,
+            Operation::Difference => left && !right,
+        }
+    }
+}
+
+// A `type = "csg"` entity. `left` and `right` are entity tables like those under `[[entities]]`,
+// and its material, if given, replaces theirs.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CsgSpec {
+    pub operation: Operation,
+    pub left: toml::Value,
+    pub right: toml::Value,
+    pub material: Option<Material>,
+}
+
+// Two closed entities combined into one solid
+pub struct Csg {
+    operation: Operation,
+    left: Box<dyn Entity>,
+    right: Box<dyn Entity>,
+    material: Option<Material>,
+}
+
+impl Csg {
+    pub fn new(
+        operation: Operation,
+        left: Box<dyn Entity>,
+        right: Box<dyn Entity>,
+        material: Option<Material>,
+    ) -> Result<Self, String> {
+        if !left.is_closed() || !right.is_closed() {
+            return Err("csg can only combine closed entities".to_string());
+        }
+        Ok(Csg {
+            operation,
+            left,
+            right,
+            material,
+        })
+    }
+}
+
+// Merge two sorted lists of spans by sweeping along the ray, keeping where the operation says
+// the ray is inside. The ends of the result are the children's hits, marked with which child
+// they are on.
+fn combine(operation: Operation, left: &[(Hit, Hit)], right: &[(Hit, Hit)]) -> Vec<(Hit, Hit)> {
+    // Every span boundary, marked with which side it belongs to
+    let mut events: Vec<(Hit, bool)> = left
+        .iter()
+        .flat_map(|&(t1, t2)| [(t1.within(0, 2), true), (t2.within(0, 2), true)])
+        .chain(
+            right
+                .iter()
+                .flat_map(|&(t1, t2)| [(t1.within(1, 2), false), (t2.within(1, 2), false)]),
+        )
+        .collect();
+    events.sort_by(|a, b| a.0.t.total_cmp(&b.0.t));
+
+    let mut combined = Vec::new();
+    let (mut in_left, mut in_right) = (false, false);
+    let mut start: Option<Hit> = None;
+    for (hit, is_left) in events {
+        if is_left {
+            in_left = !in_left;
+        } else {
+            in_right = !in_right;
+        }
+        match (operation.inside(in_left, in_right), start) {
+            (true, None) => start = Some(hit),
+            (false, Some(first)) => {
+                if hit.t > first.t {
+                    combined.push((first, hit));
+                }
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    combined
+}
+
+impl Entity for Csg {
+    fn intersection(&self, ray: &Ray) -> IntersectionResult {
+        let hits = self
+            .intervals(ray)
+            .iter()
+            .flat_map(|&(start, end)| [start.t, end.t])
+            .collect();
+        IntersectionResult::from_distances(hits)
+    }
+
+    fn hit(&self, ray: &Ray) -> Option<Hit> {
+        self.intervals(ray)
+            .iter()
+            .flat_map(|&(start, end)| [start, end])
+            .find(|hit| hit.t > 0.)
+    }
+
+    fn surface(&self, ray: &Ray, hit: Hit) -> SurfaceInteraction<'_> {
+        let (side, hit) = hit.child(2);
+        let hit_right = side == 1;
+        let surface = if hit_right {
+            self.right.surface(ray, hit)
+        } else {
+            self.left.surface(ray, hit)
+        };
+        // Where the right child is cut out of the left, its surface faces into it
+        let normal = if hit_right && self.operation == Operation::Difference {
+            -surface.normal
+        } else {
+            surface.normal
+        };
+        SurfaceInteraction {
+            normal,
+            material: self.material.as_ref().unwrap_or(surface.material),
+            ..surface
+        }
+    }
+
+    fn bounds(&self) -> Aabb {
+        let (left, right) = (self.left.bounds(), self.right.bounds());
+        match self.operation {
+            Operation::Union => left.union(&right),
+            Operation::Intersection => Aabb::new(
+                Vector::new(
+                    left.min.x.max(right.min.x),
+                    left.min.y.max(right.min.y),
+                    left.min.z.max(right.min.z),
+                ),
+                Vector::new(
+                    left.max.x.min(right.max.x),
+                    left.max.y.min(right.max.y),
+                    left.max.z.min(right.max.z),
+                ),
+            ),
+            Operation::Difference => left,
+        }
+    }
+
+    fn position(&self) -> Vector {
+        self.bounds().centre()
+    }
+
+    // The material's light, or else that of whichever child gives any off
+    fn emission(&self) -> Colour {
+        match &self.material {
+            Some(material) => material.emission,
+            None if !self.left.emission().is_black() => self.left.emission(),
+            None => self.right.emission(),
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        true
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<(Hit, Hit)> {
+        combine(
+            self.operation,
+            &self.left.intervals(ray),
+            &self.right.intervals(ray),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{hit_spans, World};
+
+    #[test]
+    fn combining_intervals() {
+        let combined = |operation, left: &[(f64, f64)], right: &[(f64, f64)]| {
+            let spans = combine(
+                operation,
+                &hit_spans(left.to_vec()),
+                &hit_spans(right.to_vec()),
+            );
+            spans
+                .iter()
+                .map(|(start, end)| (start.t, end.t))
+                .collect::<Vec<_>>()
+        };
+        let left = [(1., 4.), (6., 8.)];
+        let right = [(3., 7.)];
+        assert_eq!(combined(Operation::Union, &left, &right), vec![(1., 8.)]);
+        assert_eq!(
+            combined(Operation::Intersection, &left, &right),
+            vec![(3., 4.), (6., 7.)]
+        );
+        assert_eq!(
+            combined(Operation::Difference, &left, &right),
+            vec![(1., 3.), (7., 8.)]
+        );
+        // Starting inside both
+        assert_eq!(
+            combined(Operation::Difference, &[(0., 5.)], &[(0., 2.)]),
+            vec![(2., 5.)]
+        );
+        // Each end remembers the child it is on
+        let spans = combine(
+            Operation::Difference,
+            &hit_spans(left.to_vec()),
+            &hit_spans(right.to_vec()),
+        );
+        let sides: Vec<_> = spans
+            .iter()
+            .flat_map(|&(start, end)| [start.child(2).0, end.child(2).0])
+            .collect();
+        assert_eq!(sides, vec![0, 1, 1, 0]);
+    }
+
+    #[test]
+    fn csg_entities() {
+        let toml_string = r#"
+        [materials.red]
+        colour = [1, 0, 0]
+
+        # A sphere with a bite taken out of its near side
+        [[entities]]
+        type = "csg"
+        operation = "difference"
+        left = {type = "sphere", position = [0, 0, 5], radius = 1, material = {colour = [1, 1, 1]}}
+        right = {type = "sphere", position = [0, 0, 4], radius = 0.5, material = "red"}
+
+        # The lens shaped overlap of two spheres
+        [[entities]]
+        type = "csg"
+        operation = "intersection"
+        left = {type = "sphere", position = [-0.5, 5, 5], radius = 1, material = {colour = [1, 1, 1]}}
+        right = {type = "sphere", position = [0.5, 5, 5], radius = 1, material = {colour = [1, 1, 1]}}
+
+        [[entities]]
+        type = "csg"
+        operation = "union"
+        left = {type = "sphere", position = [0, 0, 5], radius = 1, material = {colour = [1, 1, 1]}}
+        right = {type = "triangle", vertices = [[0, 0, 0], [1, 0, 0], [0, 1, 0]], material = {colour = [1, 1, 1]}}
+        "#;
+        let world = World::from_toml(&toml_string.parse::<toml::Table>().unwrap());
+        // Triangles don't enclose anything
+        assert_eq!(world.entities.len(), 2);
+
+        let forward = Vector::new(0., 0., 1.);
+        // Through the bite, whose surface faces out of the hole and keeps its own material
+        let result = world.find_nearest(&Ray::new(Vector::zero(), forward));
+        assert!((result.distance - 4.5).abs() < 1e-9);
+        assert!((result.normal - Vector::new(0., 0., -1.)).length() < 1e-9);
+        let colour = result
+            .material
+            .bsdf(&result.texture_coords())
+            .evaluate(forward, forward);
+        assert!(colour.r > 0. && colour.g == 0.);
+        // Beside the bite, the sphere itself
+        let result = world.find_nearest(&Ray::new(Vector::new(0.8, 0., 0.), forward));
+        let expected = 5. - (1f64 - 0.64).sqrt();
+        assert!((result.distance - expected).abs() < 1e-9);
+
+        // The lens is only as wide as the spheres' overlap
+        let across = Vector::new(1., 0., 0.);
+        let result = world.find_nearest(&Ray::new(Vector::new(-5., 5., 5.), across));
+        assert!((result.distance - 4.5).abs() < 1e-9);
+        assert!((result.normal - Vector::new(-1., 0., 0.)).length() < 1e-9);
+    }
+}
@@ -0,0 +1,317 @@
+// Resources:
+// Ray/box slab test: https://tavianator.com/2011/ray_box.html
+
+use crate::bvh::Aabb;
+use crate::colour::Colour;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::sampling;
+use crate::vector::{Matrix, Rotation, Transform, Vector};
+use crate::world::{
+    Entity, Hit, IntersectionResult, SurfaceInteraction, SurfaceSample, Transformed,
+};
+use serde::Deserialize;
+
+// A `type = "box"` entity, given either by its `min` and `max` corners or by its `centre` and
+// `size` with an optional `rotation` about the centre
+#[derive(Debug, Clone, Deserialize)]
+pub struct CuboidSpec {
+    pub min: Option<Vector>,
+    pub max: Option<Vector>,
+    pub centre: Option<Vector>,
+    pub size: Option<Vector>,
+    pub rotation: Option<Rotation>,
+    pub material: Material,
+}
+
+impl CuboidSpec {
+    pub fn build(self) -> Result<Box<dyn Entity>, String> {
+        let (min, max, centre) = match (self.min, self.max, self.centre, self.size) {
+            (Some(min), Some(max), None, None) => (min, max, (min + max) / 2.),
+            (None, None, Some(centre), Some(size)) => {
+                (centre - size / 2., centre + size / 2., centre)
+            }
+            _ => return Err("a box needs min and max, or centre and size".to_string()),
+        };
+        if min.x > max.x || min.y > max.y || min.z > max.z {
+            return Err("a box's min corner must be below its max".to_string());
+        }
+        let cuboid = Cuboid {
+            min,
+            max,
+            material: self.material,
+        };
+        let Some(rotation) = self.rotation else {
+            return Ok(Box::new(cuboid));
+        };
+        let matrix = Matrix::translation(centre) * rotation.matrix() * Matrix::translation(-centre);
+        let transform = Transform::new(matrix).ok_or("a box's rotation must be invertible")?;
+        Ok(Box::new(Transformed::new(Box::new(cuboid), transform)))
+    }
+}
+
+// Axis-aligned box
+#[derive(Debug, Clone)]
+pub struct Cuboid {
+    min: Vector,
+    max: Vector,
+    material: Material,
+}
+
+// One side of a box: its outward normal and the directions u and v run along. Looking at a face
+// from outside, u runs to the right and v downwards, as in an image.
+struct Face {
+    normal: Vector,
+    u: Vector,
+    v: Vector,
+}
+
+#[rustfmt::skip]
+const FACES: [Face; 6] = [
+    Face { normal: Vector { x: -1., y: 0., z: 0. }, u: Vector { x: 0., y: 0., z: -1. }, v: Vector { x: 0., y: 1., z: 0. } },
+    Face { normal: Vector { x: 1., y: 0., z: 0. }, u: Vector { x: 0., y: 0., z: 1. }, v: Vector { x: 0., y: 1., z: 0. } },
+    Face { normal: Vector { x: 0., y: -1., z: 0. }, u: Vector { x: 1., y: 0., z: 0. }, v: Vector { x: 0., y: 0., z: -1. } },
+    Face { normal: Vector { x: 0., y: 1., z: 0. }, u: Vector { x: 1., y: 0., z: 0. }, v: Vector { x: 0., y: 0., z: 1. } },
+    Face { normal: Vector { x: 0., y: 0., z: -1. }, u: Vector { x: 1., y: 0., z: 0. }, v: Vector { x: 0., y: 1., z: 0. } },
+    Face { normal: Vector { x: 0., y: 0., z: 1. }, u: Vector { x: -1., y: 0., z: 0. }, v: Vector { x: 0., y: 1., z: 0. } },
+];
+
+impl Cuboid {
+    fn size(&self) -> Vector {
+        self.max - self.min
+    }
+
+    // Length of the box along a face direction
+    fn extent(&self, direction: Vector) -> f64 {
+        let size = self.size();
+        (direction.x * size.x + direction.y * size.y + direction.z * size.z).abs()
+    }
+
+    fn face_area(&self, face: &Face) -> f64 {
+        self.extent(face.u) * self.extent(face.v)
+    }
+
+    // The face a point on the surface lies on: the one it is furthest out towards, relative to
+    // the box's size
+    fn face(&self, at: Vector) -> &'static Face {
+        let centre = (self.min + self.max) / 2.;
+        let half = self.size() / 2.;
+        let d = at - centre;
+        let relative = |offset: f64, half: f64| {
+            if half > 0. {
+                offset.abs() / half
+            } else {
+                f64::INFINITY
+            }
+        };
+        let (x, y, z) = (
+            relative(d.x, half.x),
+            relative(d.y, half.y),
+            relative(d.z, half.z),
+        );
+        let index = if x >= y && x >= z {
+            (d.x > 0.) as usize
+        } else if y >= z {
+            2 + (d.y > 0.) as usize
+        } else {
+            4 + (d.z > 0.) as usize
+        };
+        &FACES[index]
+    }
+
+    // Where along a direction a point is, from 0 at the box's near side to 1 at its far side
+    fn fraction(&self, at: Vector, direction: Vector) -> f64 {
+        let start = if direction.x + direction.y + direction.z > 0. {
+            self.min
+        } else {
+            self.max
+        };
+        (at - start).dot(&direction) / self.extent(direction)
+    }
+}
+
+impl Entity for Cuboid {
+    fn intersection(&self, ray: &Ray) -> IntersectionResult {
+        let Some((t_near, t_far)) = Aabb::new(self.min, self.max).interval(ray) else {
+            return IntersectionResult::No;
+        };
+        if t_near > 0. {
+            IntersectionResult::Two(t_near, t_far)
+        } else if t_far > 0. {
+            // Inside the box
+            IntersectionResult::One(t_far)
+        } else {
+            IntersectionResult::No
+        }
+    }
+
+    fn surface(&self, ray: &Ray, hit: Hit) -> SurfaceInteraction<'_> {
+        let position = ray.at(hit.t);
+        let face = self.face(position);
+        SurfaceInteraction {
+            position,
+            normal: face.normal,
+            uv: (
+                self.fraction(position, face.u),
+                self.fraction(position, face.v),
+            ),
+            dpdu: face.u * self.extent(face.u),
+            dpdv: face.v * self.extent(face.v),
+            object_position: position - (self.min + self.max) / 2.,
+            vertex_colour: None,
+            material: &self.material,
+        }
+    }
+
+    fn bounds(&self) -> Aabb {
+        Aabb::new(self.min, self.max)
+    }
+
+    fn is_closed(&self) -> bool {
+        true
+    }
+
+    fn position(&self) -> Vector {
+        (self.min + self.max) / 2.
+    }
+
+    fn emission(&self) -> Colour {
+        self.material.emission
+    }
+
+    fn can_sample(&self) -> bool {
+        true
+    }
+
+    fn area(&self) -> f64 {
+        FACES.iter().map(|face| self.face_area(face)).sum()
+    }
+
+    // Uniform over the surface area, choosing a face in proportion to its area
+    fn sample(&self, reference: Vector, u: (f64, f64)) -> Option<SurfaceSample> {
+        let mut pick = u.0 * self.area();
+        let mut chosen = &FACES[5];
+        for face in &FACES[..5] {
+            let area = self.face_area(face);
+            if pick < area {
+                chosen = face;
+                break;
+            }
+            pick -= area;
+        }
+        let face = chosen;
+        let along = (pick / self.face_area(face)).clamp(0., 1.);
+
+        let centre = (self.min + self.max) / 2.;
+        let position = centre
+            + face.normal * (self.extent(face.normal) / 2.)
+            + face.u * ((along - 0.5) * self.extent(face.u))
+            + face.v * ((u.1 - 0.5) * self.extent(face.v));
+        let pdf = self.pdf(reference, position, face.normal);
+        if pdf == 0. {
+            return None;
+        }
+        Some(SurfaceSample {
+            position,
+            normal: face.normal,
+            pdf,
+            emission: self.emission(),
+        })
+    }
+
+    fn pdf(&self, reference: Vector, point: Vector, normal: Vector) -> f64 {
+        sampling::area_to_solid_angle_pdf(1. / self.area(), reference, point, normal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::Rng;
+
+    fn unit_box() -> Cuboid {
+        Cuboid {
+            min: Vector::new(-1., -1., 4.),
+            max: Vector::new(1., 1., 6.),
+            material: Material::default(),
+        }
+    }
+
+    #[test]
+    fn slab_intersection() {
+        let cuboid = unit_box();
+        let ray = Ray::new(Vector::zero(), Vector::new(0., 0., 1.));
+        assert_eq!(cuboid.intersection(&ray), IntersectionResult::Two(4., 6.));
+        let inside = Ray::new(Vector::new(0.5, 0., 5.), Vector::new(1., 0., 0.));
+        let IntersectionResult::One(t) = cuboid.intersection(&inside) else {
+            panic!("expected to leave the box");
+        };
+        assert!((t - 0.5).abs() < 1e-9);
+        let miss = Ray::new(Vector::zero(), Vector::new(0., 1., 0.));
+        assert_eq!(cuboid.intersection(&miss), IntersectionResult::No);
+    }
+
+    #[test]
+    fn faces_have_outward_normals_and_unit_uvs() {
+        let cuboid = unit_box();
+        let centre = Vector::new(0., 0., 5.);
+        for face in &FACES {
+            // Aim at a point a quarter of the way across the face
+            let target = centre + face.normal + face.u * -0.5 + face.v * -0.5;
+            let ray = Ray::new(target + face.normal * 3., -face.normal);
+            let t = cuboid.intersection(&ray).nearest().unwrap();
+            let surface = cuboid.surface(&ray, Hit::new(t));
+            assert_eq!(surface.normal, face.normal);
+            assert!((surface.uv.0 - 0.25).abs() < 1e-9 && (surface.uv.1 - 0.25).abs() < 1e-9);
+            // dpdu and dpdv follow the uvs, and image down crossed with right points inwards
+            assert!((surface.dpdu.length() - 2.).abs() < 1e-9);
+            assert!(surface.dpdu.cross(&surface.dpdv).dot(&face.normal) < 0.);
+        }
+    }
+
+    #[test]
+    fn samples_lie_on_the_surface() {
+        let cuboid = Cuboid {
+            min: Vector::new(-1., -2., 4.),
+            max: Vector::new(1., 1., 7.),
+            material: Material::default(),
+        };
+        let mut rng = Rng::new(2);
+        let reference = Vector::zero();
+        for _ in 0..100 {
+            let sample = cuboid.sample(reference, rng.next_2d()).unwrap();
+            let face = cuboid.face(sample.position);
+            assert_eq!(face.normal, sample.normal);
+            let p = sample.position;
+            let on = |v: f64, a: f64, b: f64| v >= a - 1e-9 && v <= b + 1e-9;
+            assert!(on(p.x, -1., 1.) && on(p.y, -2., 1.) && on(p.z, 4., 7.));
+        }
+    }
+
+    #[test]
+    fn box_specs() {
+        let table: toml::Table = r#"
+        corners = {min = [0, 0, 0], max = [1, 2, 3], material = {colour = [1, 1, 1]}}
+        rotated = {centre = [0, 0, 5], size = [2, 2, 2], rotation = [0, 45, 0], material = {colour = [1, 1, 1]}}
+        both = {min = [0, 0, 0], size = [1, 1, 1], material = {colour = [1, 1, 1]}}
+        inverted = {min = [1, 0, 0], max = [0, 1, 1], material = {colour = [1, 1, 1]}}
+        "#
+        .parse()
+        .unwrap();
+        let build = |name: &str| {
+            CuboidSpec::deserialize(table[name].clone())
+                .unwrap()
+                .build()
+        };
+
+        let corners = build("corners").unwrap();
+        assert_eq!(corners.position(), Vector::new(0.5, 1., 1.5));
+        // Turned 45 degrees about y, the nearest point is an edge at the half diagonal
+        let rotated = build("rotated").unwrap();
+        let ray = Ray::new(Vector::zero(), Vector::new(0., 0., 1.));
+        let t = rotated.intersection(&ray).nearest().unwrap();
+        assert!((t - (5. - 2f64.sqrt())).abs() < 1e-9);
+        assert!(build("both").is_err());
+        assert!(build("inverted").is_err());
+    }
+}
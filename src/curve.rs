@@ -0,0 +1,398 @@
+// Resources:
+// Nakamaru and Ohno 2002, "Ray tracing for curves primitive": https://jcgt.org/published/0009/03/03/
+// Intersecting curves by recursive subdivision in ray space: https://pbr-book.org/3ed-2018/Shapes/Curves
+
+use crate::bezier;
+use crate::bvh::{Aabb, Bvh};
+use crate::colour::Colour;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::sampling;
+use crate::vector::Vector;
+use crate::world::{Entity, Hit, IntersectionResult, SurfaceInteraction};
+use serde::Deserialize;
+
+// Most times a strand is halved while looking for a hit
+const MAX_DEPTH: u32 = 10;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CurveShape {
+    Flat, // a ribbon that always faces the ray, for grass blades seen from afar
+    #[default]
+    Cylinder, // shaded as a tube, for hair and fur
+}
+
+// Either one width along the whole strand or its width at the root and the tip
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum Width {
+    Constant(f64),
+    Tapered([f64; 2]),
+}
+
+// A `type = "curves"` entity: strands that are each a cubic Bézier curve given by four control
+// points, from root to tip
+#[derive(Debug, Clone, Deserialize)]
+pub struct CurvesSpec {
+    pub strands: Vec<[Vector; 4]>,
+    pub width: Width,
+    #[serde(default)]
+    pub shape: CurveShape,
+    pub material: Material,
+}
+
+impl CurvesSpec {
+    pub fn build(self) -> Result<Box<dyn Entity>, String> {
+        let width = match self.width {
+            Width::Constant(width) => (width, width),
+            Width::Tapered([root, tip]) => (root, tip),
+        };
+        if width.0 < 0. || width.1 < 0. || width.0.max(width.1) <= 0. {
+            return Err("width must be positive".to_string());
+        }
+        if self.strands.is_empty() {
+            return Err("there must be at least one strand".to_string());
+        }
+        Ok(Box::new(Curves::new(
+            self.strands,
+            width,
+            self.shape,
+            self.material,
+        )))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Strand {
+    points: [Vector; 4],
+    depth: u32, // how often to halve it to make its pieces close enough to straight
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct CurveHit {
+    t: f64,
+    u: f64, // along the strand
+}
+
+// Thin strands for hair, fur and grass. Nothing is tessellated: a strand is only split up, in the
+// frame of each ray that reaches its bounds, until its pieces are straight enough to treat as
+// lines, so millions of strands cost little more than their control points.
+pub struct Curves {
+    strands: Vec<Strand>,
+    width: (f64, f64),
+    shape: CurveShape,
+    bvh: Bvh,
+    material: Material,
+}
+
+impl Curves {
+    pub fn new(
+        points: Vec<[Vector; 4]>,
+        width: (f64, f64),
+        shape: CurveShape,
+        material: Material,
+    ) -> Self {
+        let half_width = width.0.max(width.1) / 2.;
+        let r = Vector::new(half_width, half_width, half_width);
+        let bounds: Vec<Aabb> = points
+            .iter()
+            .map(|p| {
+                let bounds = Aabb::from_points(p);
+                Aabb::new(bounds.min - r, bounds.max + r)
+            })
+            .collect();
+        let strands = points
+            .into_iter()
+            .map(|points| Strand {
+                points,
+                depth: subdivisions(&points, half_width * 2.),
+            })
+            .collect();
+        Curves {
+            strands,
+            width,
+            shape,
+            bvh: Bvh::new(&bounds),
+            material,
+        }
+    }
+
+    fn width_at(&self, u: f64) -> f64 {
+        self.width.0 + (self.width.1 - self.width.0) * u
+    }
+
+    fn intersect_strand(&self, ray: &Ray, strand: &Strand) -> Option<CurveHit> {
+        // Move to a frame with the ray starting at the origin and running along +z, where a hit
+        // is a piece of the strand passing within half its width of the z axis
+        let length = ray.direction.length();
+        let z = ray.direction / length;
+        let (x, y) = sampling::orthonormal_basis(z);
+        let points = strand.points.map(|p| {
+            let p = p - ray.origin;
+            Vector::new(p.dot(&x), p.dot(&y), p.dot(&z))
+        });
+        let (distance, u) = self.subdivide(points, (0., 1.), strand.depth)?;
+        let t = match self.shape {
+            CurveShape::Flat => distance,
+            // Back out of the middle of the tube to its surface
+            CurveShape::Cylinder => {
+                let half_width = self.width_at(u) / 2.;
+                let (centre, _) = evaluate(&points, u);
+                let offset2 = centre.x * centre.x + centre.y * centre.y;
+                distance - (half_width * half_width - offset2).max(0.).sqrt()
+            }
+        };
+        Some(CurveHit { t: t / length, u })
+    }
+
+    // Distance along the ray to where the piece of the strand between `u` passes closest to it,
+    // and where along the strand that is
+    fn subdivide(&self, points: [Vector; 4], u: (f64, f64), depth: u32) -> Option<(f64, f64)> {
+        let half_width = self.width_at(u.0).max(self.width_at(u.1)) / 2.;
+        let bounds = Aabb::from_points(&points);
+        if bounds.min.x > half_width
+            || bounds.max.x < -half_width
+            || bounds.min.y > half_width
+            || bounds.max.y < -half_width
+            || bounds.max.z < -half_width
+        {
+            return None;
+        }
+
+        if depth > 0 {
+            let middle = (u.0 + u.1) / 2.;
+            let before =
+                self.subdivide(bezier::restrict(points, 0., 0.5), (u.0, middle), depth - 1);
+            let after = self.subdivide(bezier::restrict(points, 0.5, 1.), (middle, u.1), depth - 1);
+            return match (before, after) {
+                (Some(a), Some(b)) => Some(if a.0 <= b.0 { a } else { b }),
+                (a, b) => a.or(b),
+            };
+        }
+
+        // Close enough to straight. Only take hits level with this piece, past the lines through
+        // its ends at right angles to it, so neighbouring pieces don't both claim them.
+        let [p0, p1, p2, p3] = points;
+        if (p1.y - p0.y) * -p0.y + p0.x * (p0.x - p1.x) < 0.
+            || (p2.y - p3.y) * -p3.y + p3.x * (p3.x - p2.x) < 0.
+        {
+            return None;
+        }
+        // Nearest point to the ray along the line between the ends
+        let (dx, dy) = (p3.x - p0.x, p3.y - p0.y);
+        let length2 = dx * dx + dy * dy;
+        if length2 == 0. {
+            return None;
+        }
+        let w = ((-p0.x * dx - p0.y * dy) / length2).clamp(0., 1.);
+        let hit_u = u.0 + (u.1 - u.0) * w;
+        let half_width = self.width_at(hit_u) / 2.;
+        let (centre, _) = evaluate(&points, w);
+        if centre.x * centre.x + centre.y * centre.y > half_width * half_width {
+            return None;
+        }
+        // A path leaving a strand would otherwise find it again straight away
+        if centre.z < 2. * half_width {
+            return None;
+        }
+        Some((centre.z, hit_u))
+    }
+}
+
+// Point on a cubic Bézier curve and its derivative
+fn evaluate(points: &[Vector; 4], t: f64) -> (Vector, Vector) {
+    let (weights, derivatives) = bezier::bernstein(t);
+    let mut point = Vector::zero();
+    let mut tangent = Vector::zero();
+    for i in 0..4 {
+        point += points[i] * weights[i];
+        tangent += points[i] * derivatives[i];
+    }
+    (point, tangent)
+}
+
+// Halvings needed to bring a curve within a small fraction of `width` of the lines between the
+// ends of its pieces
+fn subdivisions(points: &[Vector; 4], width: f64) -> u32 {
+    let second_difference = |i: usize| {
+        let d = points[i] - points[i + 1] * 2. + points[i + 2];
+        d.x.abs().max(d.y.abs()).max(d.z.abs())
+    };
+    let curvature = second_difference(0).max(second_difference(1));
+    let epsilon = width * 0.05;
+    let depth = (2f64.sqrt() * 6. * curvature / (8. * epsilon)).log2() / 2.;
+    depth.clamp(0., MAX_DEPTH as f64) as u32
+}
+
+impl Entity for Curves {
+    fn intersection(&self, ray: &Ray) -> IntersectionResult {
+        match self.hit(ray) {
+            Some(hit) => IntersectionResult::One(hit.t),
+            None => IntersectionResult::No,
+        }
+    }
+
+    // The part hit is the strand
+    fn hit(&self, ray: &Ray) -> Option<Hit> {
+        let (strand, hit) = self.bvh.nearest(ray, |i| {
+            self.intersect_strand(ray, &self.strands[i])
+                .map(|hit| Hit::new(hit.t))
+        })?;
+        Some(Hit {
+            part: strand,
+            ..hit
+        })
+    }
+
+    fn surface(&self, ray: &Ray, hit: Hit) -> SurfaceInteraction<'_> {
+        // Test the strand again for how far along it the hit is. It is the test that found the
+        // hit, so it can't miss.
+        let strand = &self.strands[hit.part];
+        let u = self.intersect_strand(ray, strand).map_or(0.5, |hit| hit.u);
+        let (centre, dpdu) = evaluate(&strand.points, u);
+        let position = ray.at(hit.t);
+        let tangent = dpdu.normalised();
+
+        // Facing back along the ray, across the strand
+        let towards = -ray.direction;
+        let mut facing = towards - tangent * towards.dot(&tangent);
+        if facing.abs_squared() < 1e-20 * towards.abs_squared() {
+            facing = sampling::orthonormal_basis(tangent).0;
+        }
+        let facing = facing.normalised();
+        let side = tangent.cross(&facing);
+        let width = self.width_at(u);
+        let across = ((position - centre).dot(&side) / (width / 2.)).clamp(-1., 1.);
+        let normal = match self.shape {
+            CurveShape::Flat => facing,
+            CurveShape::Cylinder => {
+                (facing * (1. - across * across).max(0.).sqrt() + side * across).normalised()
+            }
+        };
+        SurfaceInteraction {
+            position,
+            normal,
+            uv: (u, (1. + across) / 2.),
+            dpdu,
+            dpdv: side * width,
+            object_position: position,
+            vertex_colour: None,
+            material: &self.material,
+        }
+    }
+
+    fn bounds(&self) -> Aabb {
+        self.bvh.bounds()
+    }
+
+    fn position(&self) -> Vector {
+        self.bounds().centre()
+    }
+
+    fn emission(&self) -> Colour {
+        self.material.emission
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A single strand along x at depth 5, bowed towards the camera in the middle
+    fn strand(width: (f64, f64), shape: CurveShape) -> Curves {
+        Curves::new(
+            vec![[
+                Vector::new(-2., 0., 5.),
+                Vector::new(-1., 0., 4.),
+                Vector::new(1., 0., 4.),
+                Vector::new(2., 0., 5.),
+            ]],
+            width,
+            shape,
+            Material::default(),
+        )
+    }
+
+    #[test]
+    fn hits_within_the_width() {
+        let flat = strand((0.2, 0.2), CurveShape::Flat);
+        let forward = Vector::new(0., 0., 1.);
+        // The middle of the strand is at 5 - 0.75
+        let t = flat
+            .intersection(&Ray::new(Vector::zero(), forward))
+            .nearest()
+            .unwrap();
+        assert!((t - 4.25).abs() < 1e-3, "{}", t);
+        assert!(flat
+            .intersection(&Ray::new(Vector::new(0., 0.09, 0.), forward))
+            .nearest()
+            .is_some());
+        assert_eq!(
+            flat.intersection(&Ray::new(Vector::new(0., 0.11, 0.), forward)),
+            IntersectionResult::No
+        );
+        // Past either end
+        assert_eq!(
+            flat.intersection(&Ray::new(Vector::new(2.05, 0., 0.), forward)),
+            IntersectionResult::No
+        );
+
+        // Tapering to nothing at the tip
+        let tapered = strand((0.2, 0.), CurveShape::Flat);
+        let near_root = Ray::new(Vector::new(-1.5, 0.08, 0.), forward);
+        let near_tip = Ray::new(Vector::new(1.5, 0.08, 0.), forward);
+        assert!(tapered.intersection(&near_root).nearest().is_some());
+        assert_eq!(tapered.intersection(&near_tip), IntersectionResult::No);
+    }
+
+    #[test]
+    fn tubes_are_rounded() {
+        let tube = strand((0.2, 0.2), CurveShape::Cylinder);
+        let forward = Vector::new(0., 0., 1.);
+        let ray = Ray::new(Vector::new(0., 0.05, 0.), forward);
+        let hit = tube.hit(&ray).unwrap();
+        let t = hit.t;
+        // In front of the middle by the depth of the tube there
+        assert!(
+            (t - (4.25 - (0.01f64 - 0.0025).sqrt())).abs() < 1e-3,
+            "{}",
+            t
+        );
+        let surface = tube.surface(&ray, hit);
+        // Leaning 30° towards the edge, across the strand, which runs along dpdu
+        assert!((surface.normal - Vector::new(0., 0.5, -(0.75f64.sqrt()))).length() < 1e-3);
+        assert!((surface.dpdu.normalised() - Vector::new(1., 0., 0.)).length() < 1e-6);
+        assert!((surface.uv.0 - 0.5).abs() < 1e-3 && (surface.uv.1 - 0.75).abs() < 1e-3);
+
+        // A ribbon faces the ray wherever it is hit
+        let ribbon = strand((0.2, 0.2), CurveShape::Flat);
+        let hit = ribbon.hit(&ray).unwrap();
+        assert!((ribbon.surface(&ray, hit).normal - -forward).length() < 1e-6);
+    }
+
+    #[test]
+    fn spec_checks() {
+        let table: toml::Table = r#"
+        fur = {strands = [[[0, 0, 0], [0, -1, 0], [0, -2, 0], [0, -3, 0]]], width = [0.1, 0.01], material = {type = "hair"}}
+        grass = {strands = [[[0, 0, 0], [0, -1, 0], [0, -2, 0], [0, -3, 0]]], width = 0.1, shape = "flat", material = {colour = [0, 1, 0]}}
+        bald = {strands = [], width = 0.1, material = {type = "hair"}}
+        invisible = {strands = [[[0, 0, 0], [0, -1, 0], [0, -2, 0], [0, -3, 0]]], width = 0, material = {type = "hair"}}
+        glowing = {strands = [[[0, 0, 0], [0, -1, 0], [0, -2, 0], [0, -3, 0]]], width = 0.1, material = {emission = [1, 1, 1]}}
+        "#
+        .parse()
+        .unwrap();
+        let build = |name: &str| {
+            CurvesSpec::deserialize(table[name].clone())
+                .unwrap()
+                .build()
+        };
+        assert!(build("fur").is_ok());
+        assert!(build("grass").is_ok());
+        assert!(build("bald").is_err());
+        assert!(build("invisible").is_err());
+        // Seen by paths that hit them, but not sampled as a light
+        let glowing = build("glowing").unwrap();
+        assert!(glowing.emission() == Colour::white() && !glowing.can_sample());
+    }
+}
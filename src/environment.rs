@@ -0,0 +1,209 @@
+// Resources:
+// Importance sampling infinite area lights: https://pbr-book.org/3ed-2018/Light_Transport_I_Surface_Reflection/Sampling_Light_Sources#InfiniteAreaLights
+
+use core::f64;
+use std::error::Error;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::colour::Colour;
+use crate::image::FloatImage;
+use crate::sampling::Distribution2D;
+use crate::sky::Sky;
+use crate::vector::Vector;
+
+// Resolution of the grid an analytic sky is importance sampled over
+const SKY_DISTRIBUTION_SIZE: (usize, usize) = (128, 64);
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct EnvironmentSpec {
+    pub image: String,
+    #[serde(default = "default_intensity")]
+    pub intensity: f64,
+    #[serde(default)]
+    pub rotation: f64, // degrees about the vertical axis
+}
+
+fn default_intensity() -> f64 {
+    1.
+}
+
+enum Source {
+    Image(FloatImage),
+    Sky(Sky),
+}
+
+// Infinitely distant light surrounding the scene, parameterised in lat-long. The top of the map is
+// straight up, which is -y (screen up), and its centre is straight ahead (+z).
+pub struct Environment {
+    source: Source,
+    intensity: f64,
+    rotation: f64, // radians
+    distribution: Distribution2D,
+}
+
+impl Environment {
+    pub fn load(spec: &EnvironmentSpec, scene_dir: &Path) -> Result<Self, Box<dyn Error>> {
+        let image = FloatImage::load(&scene_dir.join(&spec.image))?;
+        Ok(Self::new(image, spec.intensity, spec.rotation))
+    }
+
+    pub fn new(image: FloatImage, intensity: f64, rotation_degrees: f64) -> Self {
+        let (width, height) = (image.width, image.height);
+        let mut environment = Environment {
+            source: Source::Image(image),
+            intensity,
+            rotation: rotation_degrees.to_radians(),
+            distribution: Distribution2D::new(&[1.], 1, 1),
+        };
+        environment.build_distribution(width, height);
+        environment
+    }
+
+    pub fn from_sky(sky: Sky) -> Self {
+        let mut environment = Environment {
+            source: Source::Sky(sky),
+            intensity: 1.,
+            rotation: 0.,
+            distribution: Distribution2D::new(&[1.], 1, 1),
+        };
+        environment.build_distribution(SKY_DISTRIBUTION_SIZE.0, SKY_DISTRIBUTION_SIZE.1);
+        environment
+    }
+
+    fn build_distribution(&mut self, width: usize, height: usize) {
+        // Weight by sin(theta) as rows near the poles cover less solid angle
+        let mut values = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let v = (y as f64 + 0.5) / height as f64;
+            let sin_theta = (f64::consts::PI * v).sin();
+            for x in 0..width {
+                let u = (x as f64 + 0.5) / width as f64;
+                values.push(luminance(self.lookup((u, v))) * sin_theta);
+            }
+        }
+        self.distribution = Distribution2D::new(&values, width, height);
+    }
+
+    fn direction_to_uv(&self, direction: Vector) -> (f64, f64) {
+        let d = direction.normalised();
+        let theta = (-d.y).clamp(-1., 1.).acos();
+        let phi = d.x.atan2(d.z) - self.rotation;
+        let u = (0.5 + phi / (2. * f64::consts::PI)).rem_euclid(1.);
+        (u, theta / f64::consts::PI)
+    }
+
+    fn uv_to_direction(&self, uv: (f64, f64)) -> Vector {
+        let theta = uv.1 * f64::consts::PI;
+        let phi = (uv.0 - 0.5) * 2. * f64::consts::PI + self.rotation;
+        let sin_theta = theta.sin();
+        Vector::new(sin_theta * phi.sin(), -theta.cos(), sin_theta * phi.cos())
+    }
+
+    fn lookup(&self, uv: (f64, f64)) -> Colour {
+        let colour = match &self.source {
+            Source::Image(image) => {
+                let x = ((uv.0 * image.width as f64) as usize).min(image.width - 1);
+                let y = ((uv.1 * image.height as f64) as usize).min(image.height - 1);
+                image.pixel(x, y)
+            }
+            Source::Sky(sky) => sky.radiance(self.uv_to_direction(uv)),
+        };
+        colour * self.intensity as f32
+    }
+
+    // Radiance arriving from `direction`
+    pub fn radiance(&self, direction: Vector) -> Colour {
+        self.lookup(self.direction_to_uv(direction))
+    }
+
+    // Direction towards the environment chosen by luminance, its radiance and solid angle density
+    pub fn sample(&self, u: (f64, f64)) -> Option<(Vector, Colour, f64)> {
+        let (uv, pdf_uv) = self.distribution.sample(u);
+        let pdf = uv_pdf_to_solid_angle(pdf_uv, uv.1);
+        if pdf <= 0. {
+            return None;
+        }
+        Some((self.uv_to_direction(uv), self.lookup(uv), pdf))
+    }
+
+    pub fn pdf(&self, direction: Vector) -> f64 {
+        let uv = self.direction_to_uv(direction);
+        uv_pdf_to_solid_angle(self.distribution.pdf(uv), uv.1)
+    }
+}
+
+// The lat-long mapping stretches (u, v) over 2π by π radians, scaled by sin(theta)
+fn uv_pdf_to_solid_angle(pdf_uv: f64, v: f64) -> f64 {
+    let sin_theta = (v * f64::consts::PI).sin();
+    if sin_theta <= 0. {
+        return 0.;
+    }
+    pdf_uv / (2. * f64::consts::PI * f64::consts::PI * sin_theta)
+}
+
+fn luminance(c: Colour) -> f64 {
+    (0.2126 * c.r + 0.7152 * c.g + 0.0722 * c.b) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::Rng;
+
+    fn environment(rotation: f64) -> Environment {
+        // Bright spot in the upper middle of an otherwise dim 8x4 map
+        let mut pixels = vec![Colour::new(0.1, 0.1, 0.1); 32];
+        pixels[8 + 4] = Colour::new(100., 100., 100.);
+        Environment::new(FloatImage::new(8, 4, pixels), 2., rotation)
+    }
+
+    #[test]
+    fn uv_direction_round_trip() {
+        let env = environment(30.);
+        let uv = (0.3, 0.6);
+        let (u, v) = env.direction_to_uv(env.uv_to_direction(uv));
+        assert!((u - uv.0).abs() < 1e-9 && (v - uv.1).abs() < 1e-9);
+    }
+
+    #[test]
+    fn centre_of_image_is_straight_ahead_and_top_is_up() {
+        let env = environment(0.);
+        let ahead = env.uv_to_direction((0.5, 0.5));
+        assert!((ahead - Vector::new(0., 0., 1.)).length() < 1e-9);
+        let up = env.uv_to_direction((0.5, 0.));
+        assert!((up - Vector::new(0., -1., 0.)).length() < 1e-9);
+    }
+
+    #[test]
+    fn sky_environment_favours_the_sun() {
+        let spec: crate::sky::SkySpec = toml::from_str("sun_elevation = 40").unwrap();
+        let sky = Sky::new(&spec);
+        let env = Environment::from_sky(sky);
+        let mut rng = Rng::new(2);
+        let mut towards_sun = 0.;
+        for _ in 0..1000 {
+            let (direction, _, pdf) = env.sample(rng.next_2d()).unwrap();
+            assert!((env.pdf(direction) - pdf).abs() / pdf < 1e-6);
+            towards_sun += direction.dot(&sky.sun_direction());
+        }
+        assert!(towards_sun > 0.);
+    }
+
+    #[test]
+    fn samples_favour_bright_texels_and_pdfs_agree() {
+        let env = environment(0.);
+        let mut rng = Rng::new(11);
+        let mut bright = 0;
+        for _ in 0..1000 {
+            let (direction, radiance, pdf) = env.sample(rng.next_2d()).unwrap();
+            assert!((env.pdf(direction) - pdf).abs() / pdf < 1e-6);
+            assert_eq!(radiance, env.radiance(direction));
+            if radiance.r > 10. {
+                bright += 1;
+            }
+        }
+        assert!(bright > 800);
+    }
+}
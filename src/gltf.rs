@@ -0,0 +1,1151 @@
+// Resources:
+// glTF 2.0 specification: https://registry.khronos.org/glTF/specs/2.0/glTF-2.0.html
+// Punctual lights: https://github.com/KhronosGroup/glTF/blob/main/extensions/2.0/Khronos/KHR_lights_punctual/README.md
+// Emissive strength: https://github.com/KhronosGroup/glTF/blob/main/extensions/2.0/Khronos/KHR_materials_emissive_strength/README.md
+
+use crate::bvh::Aabb;
+use crate::colour::Colour;
+use crate::image::FloatImage;
+use crate::instance::{Geometry, Instance};
+use crate::json;
+use crate::light::{DirectionalLight, Light, SphereLight};
+use crate::material::{Material, PrincipledMaterial};
+use crate::mesh::{Mesh, MeshData};
+use crate::png;
+use crate::texture::{Filter, ImageTexture, Texture, VertexColour, Wrap};
+use crate::vector::{Matrix, Transform, Vector};
+use crate::world::{Entity, Transformed, World};
+use core::f64;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Arc;
+
+const GLB_MAGIC: &[u8] = b"glTF";
+const JSON_CHUNK: u32 = 0x4e4f534a;
+const BIN_CHUNK: u32 = 0x004e4942;
+
+const TRIANGLES: u32 = 4;
+
+// An accessor without a buffer view is all zeros, which the file can ask for any number of
+// without holding them, so there's a limit on how many
+const MAX_ZEROED_VALUES: usize = 1 << 24;
+
+// glTF's point and spot lights are infinitely small, so they are given this radius to become
+// sphere lights
+const POINT_LIGHT_RADIUS: f64 = 0.01;
+
+// A `type = "gltf"` entity: the meshes of a .gltf or .glb file's scene, turned so that the file's
+// up is up in the render. Cameras and lights in the file are ignored. `material`, if given,
+// replaces the file's materials.
+#[derive(Debug, Clone, Deserialize)]
+pub struct GltfSpec {
+    pub file: String, // relative to the scene file
+    pub material: Option<Material>,
+}
+
+// glTF's +y is up and its cameras look down -z, while the render's y is down and its camera looks
+// down +z: a half turn about x
+pub fn upright() -> Matrix {
+    Matrix::rotation(Vector::new(1., 0., 0.), f64::consts::PI)
+}
+
+// The parts of the JSON document that are read. Indices refer to the other arrays.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Document {
+    asset: Asset,
+    scene: Option<usize>,
+    #[serde(default)]
+    scenes: Vec<Scene>,
+    #[serde(default)]
+    nodes: Vec<Node>,
+    #[serde(default)]
+    meshes: Vec<GltfMesh>,
+    #[serde(default)]
+    accessors: Vec<Accessor>,
+    #[serde(default)]
+    buffer_views: Vec<BufferView>,
+    #[serde(default)]
+    buffers: Vec<Buffer>,
+    #[serde(default)]
+    materials: Vec<GltfMaterial>,
+    #[serde(default)]
+    textures: Vec<GltfTexture>,
+    #[serde(default)]
+    images: Vec<GltfImage>,
+    #[serde(default)]
+    cameras: Vec<Camera>,
+    #[serde(default)]
+    extensions: DocumentExtensions,
+}
+
+#[derive(Debug, Deserialize)]
+struct Asset {
+    version: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Scene {
+    #[serde(default)]
+    nodes: Vec<usize>,
+}
+
+fn default_rotation() -> [f64; 4] {
+    [0., 0., 0., 1.]
+}
+
+fn default_scale() -> [f64; 3] {
+    [1., 1., 1.]
+}
+
+// Placed by either `matrix` or translation, rotation and scale, relative to its parent
+#[derive(Debug, Deserialize)]
+struct Node {
+    #[serde(default)]
+    children: Vec<usize>,
+    mesh: Option<usize>,
+    camera: Option<usize>,
+    matrix: Option<[f64; 16]>, // column major
+    #[serde(default)]
+    translation: [f64; 3],
+    #[serde(default = "default_rotation")]
+    rotation: [f64; 4], // quaternion, x y z w
+    #[serde(default = "default_scale")]
+    scale: [f64; 3],
+    #[serde(default)]
+    extensions: NodeExtensions,
+}
+
+impl Node {
+    fn local_matrix(&self) -> Matrix {
+        let mut matrix = Matrix::identity();
+        if let Some(columns) = self.matrix {
+            for (i, row) in matrix.m.iter_mut().enumerate() {
+                for (j, value) in row.iter_mut().enumerate() {
+                    *value = columns[j * 4 + i];
+                }
+            }
+            return matrix;
+        }
+        // https://en.wikipedia.org/wiki/Quaternions_and_spatial_rotation#Quaternion-derived_rotation_matrix
+        let [x, y, z, w] = self.rotation;
+        matrix.m[0][0] = 1. - 2. * (y * y + z * z);
+        matrix.m[0][1] = 2. * (x * y - z * w);
+        matrix.m[0][2] = 2. * (x * z + y * w);
+        matrix.m[1][0] = 2. * (x * y + z * w);
+        matrix.m[1][1] = 1. - 2. * (x * x + z * z);
+        matrix.m[1][2] = 2. * (y * z - x * w);
+        matrix.m[2][0] = 2. * (x * z - y * w);
+        matrix.m[2][1] = 2. * (y * z + x * w);
+        matrix.m[2][2] = 1. - 2. * (x * x + y * y);
+        let [tx, ty, tz] = self.translation;
+        let [sx, sy, sz] = self.scale;
+        Matrix::translation(Vector::new(tx, ty, tz))
+            * matrix
+            * Matrix::scaling(Vector::new(sx, sy, sz))
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct NodeExtensions {
+    #[serde(rename = "KHR_lights_punctual")]
+    light: Option<LightRef>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LightRef {
+    light: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct GltfMesh {
+    primitives: Vec<Primitive>,
+}
+
+fn default_mode() -> u32 {
+    TRIANGLES
+}
+
+#[derive(Debug, Deserialize)]
+struct Primitive {
+    attributes: HashMap<String, usize>,
+    indices: Option<usize>,
+    material: Option<usize>,
+    #[serde(default = "default_mode")]
+    mode: u32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Accessor {
+    buffer_view: Option<usize>, // all zeros if not given
+    #[serde(default)]
+    byte_offset: usize,
+    component_type: u32,
+    #[serde(default)]
+    normalized: bool,
+    count: usize,
+    #[serde(rename = "type")]
+    kind: String,
+    sparse: Option<toml::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BufferView {
+    buffer: usize,
+    #[serde(default)]
+    byte_offset: usize,
+    byte_length: usize,
+    byte_stride: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Buffer {
+    uri: Option<String>, // the .glb's binary chunk if not given
+    byte_length: usize,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct GltfMaterial {
+    pbr_metallic_roughness: MetallicRoughness,
+    emissive_factor: [f64; 3],
+    extensions: MaterialExtensions,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct MetallicRoughness {
+    base_color_factor: [f64; 4],
+    base_color_texture: Option<TextureRef>,
+    metallic_factor: f64,
+    roughness_factor: f64,
+}
+
+impl Default for MetallicRoughness {
+    fn default() -> Self {
+        MetallicRoughness {
+            base_color_factor: [1.; 4],
+            base_color_texture: None,
+            metallic_factor: 1.,
+            roughness_factor: 1.,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TextureRef {
+    index: usize,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct MaterialExtensions {
+    #[serde(rename = "KHR_materials_emissive_strength")]
+    emissive_strength: Option<EmissiveStrength>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EmissiveStrength {
+    emissive_strength: f64,
+}
+
+#[derive(Debug, Deserialize)]
+struct GltfTexture {
+    source: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GltfImage {
+    uri: Option<String>,
+    buffer_view: Option<usize>,
+}
+
+// Orthographic cameras have no `perspective` and are skipped
+#[derive(Debug, Deserialize)]
+struct Camera {
+    perspective: Option<Perspective>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Perspective {
+    yfov: f64, // radians
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct DocumentExtensions {
+    #[serde(rename = "KHR_lights_punctual")]
+    lights: Option<PunctualLights>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PunctualLights {
+    lights: Vec<PunctualLight>,
+}
+
+fn default_light_colour() -> [f64; 3] {
+    [1., 1., 1.]
+}
+
+fn default_light_intensity() -> f64 {
+    1.
+}
+
+// Point and spot intensities are in candela and directional ones in lux
+#[derive(Debug, Deserialize)]
+struct PunctualLight {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default = "default_light_colour")]
+    color: [f64; 3],
+    #[serde(default = "default_light_intensity")]
+    intensity: f64,
+}
+
+fn colour(rgb: &[f64]) -> Colour {
+    Colour::new(rgb[0] as f32, rgb[1] as f32, rgb[2] as f32)
+}
+
+// A parsed .gltf or .glb with its buffers loaded
+pub struct Gltf {
+    document: Document,
+    buffers: Vec<Vec<u8>>,
+    dir: PathBuf, // that URIs are relative to
+}
+
+impl Gltf {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let bytes = fs::read(path)?;
+        Self::parse(&bytes, path.parent().unwrap_or(Path::new("")))
+    }
+
+    // A .glb if `bytes` starts with its magic, otherwise a .gltf
+    pub fn parse(bytes: &[u8], dir: &Path) -> Result<Self, Box<dyn Error>> {
+        let (text, mut binary) = if bytes.starts_with(GLB_MAGIC) {
+            split_glb(bytes)?
+        } else {
+            (bytes, None)
+        };
+        let document: Document = json::parse(std::str::from_utf8(text)?)?.try_into()?;
+        if !document.asset.version.starts_with("2.") {
+            return Err(format!("glTF version {} isn't supported", document.asset.version).into());
+        }
+        let mut buffers = Vec::new();
+        for (i, buffer) in document.buffers.iter().enumerate() {
+            let data = match &buffer.uri {
+                Some(uri) => read_uri(uri, dir)?,
+                None => binary
+                    .take()
+                    .ok_or_else(|| format!("buffer {} has no data", i))?
+                    .to_vec(),
+            };
+            if data.len() < buffer.byte_length {
+                return Err(format!(
+                    "buffer {} is {} bytes but should be {}",
+                    i,
+                    data.len(),
+                    buffer.byte_length
+                )
+                .into());
+            }
+            buffers.push(data);
+        }
+        Ok(Gltf {
+            document,
+            buffers,
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    fn view(&self, index: usize) -> Result<&[u8], Box<dyn Error>> {
+        let view = self
+            .document
+            .buffer_views
+            .get(index)
+            .ok_or_else(|| format!("no buffer view {}", index))?;
+        let buffer = self
+            .buffers
+            .get(view.buffer)
+            .ok_or_else(|| format!("no buffer {}", view.buffer))?;
+        view.byte_offset
+            .checked_add(view.byte_length)
+            .and_then(|end| buffer.get(view.byte_offset..end))
+            .ok_or_else(|| format!("buffer view {} runs past the end of its buffer", index).into())
+    }
+
+    // An accessor's elements flattened, with how many numbers each has, which must be one of
+    // `components`. Normalised integers are mapped to [0, 1] or [-1, 1].
+    fn read(
+        &self,
+        index: usize,
+        components: &[usize],
+    ) -> Result<(usize, Vec<f64>), Box<dyn Error>> {
+        let accessor = self
+            .document
+            .accessors
+            .get(index)
+            .ok_or_else(|| format!("no accessor {}", index))?;
+        if accessor.sparse.is_some() {
+            return Err("sparse accessors aren't supported".into());
+        }
+        let count = match accessor.kind.as_str() {
+            "SCALAR" => 1,
+            "VEC2" => 2,
+            "VEC3" => 3,
+            "VEC4" => 4,
+            kind => return Err(format!("accessor type {} isn't supported", kind).into()),
+        };
+        if !components.contains(&count) {
+            return Err(format!("accessor {} has the wrong type {}", index, accessor.kind).into());
+        }
+        let (size, max) = match accessor.component_type {
+            5120 => (1, i8::MAX as f64),
+            5121 => (1, u8::MAX as f64),
+            5122 => (2, i16::MAX as f64),
+            5123 => (2, u16::MAX as f64),
+            5125 => (4, u32::MAX as f64),
+            5126 => (4, 1.),
+            other => return Err(format!("unknown component type {}", other).into()),
+        };
+        let too_long = || format!("accessor {} runs past its buffer view", index);
+        let length = accessor.count.checked_mul(count).ok_or_else(too_long)?;
+        let Some(view) = accessor.buffer_view else {
+            if length > MAX_ZEROED_VALUES {
+                return Err(format!("accessor {} has too many elements", index).into());
+            }
+            return Ok((count, vec![0.; length]));
+        };
+        let data = self.view(view)?;
+        let stride = self.document.buffer_views[view]
+            .byte_stride
+            .unwrap_or(size * count);
+        // Where the last element ends, checked before anything is allocated for them
+        let end = match accessor.count.checked_sub(1) {
+            None => 0,
+            Some(last) => last
+                .checked_mul(stride)
+                .and_then(|start| start.checked_add(accessor.byte_offset))
+                .and_then(|start| start.checked_add(size * count))
+                .ok_or_else(too_long)?,
+        };
+        if end > data.len() {
+            return Err(too_long().into());
+        }
+
+        let mut values = Vec::with_capacity(length);
+        for i in 0..accessor.count {
+            for c in 0..count {
+                let offset = accessor.byte_offset + i * stride + c * size;
+                let b = &data[offset..offset + size];
+                let value = match accessor.component_type {
+                    5120 => b[0] as i8 as f64,
+                    5121 => b[0] as f64,
+                    5122 => i16::from_le_bytes([b[0], b[1]]) as f64,
+                    5123 => u16::from_le_bytes([b[0], b[1]]) as f64,
+                    5125 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    _ => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                };
+                values.push(if accessor.normalized {
+                    (value / max).max(-1.)
+                } else {
+                    value
+                });
+            }
+        }
+        Ok((count, values))
+    }
+
+    fn mesh_data(&self, primitive: &Primitive) -> Result<MeshData, Box<dyn Error>> {
+        if primitive.mode != TRIANGLES {
+            return Err(format!("primitive mode {} isn't supported", primitive.mode).into());
+        }
+        let attribute = |name: &str, components: &[usize]| {
+            primitive
+                .attributes
+                .get(name)
+                .map(|&index| self.read(index, components))
+                .transpose()
+        };
+        let vectors = |values: Vec<f64>| -> Vec<Vector> {
+            values
+                .chunks(3)
+                .map(|v| Vector::new(v[0], v[1], v[2]))
+                .collect()
+        };
+
+        let (_, positions) =
+            attribute("POSITION", &[3])?.ok_or("the primitive has no positions")?;
+        let mut data = MeshData {
+            positions: vectors(positions),
+            ..Default::default()
+        };
+        if let Some((_, normals)) = attribute("NORMAL", &[3])? {
+            data.normals = vectors(normals);
+        }
+        if let Some((_, uvs)) = attribute("TEXCOORD_0", &[2])? {
+            data.uvs = uvs.chunks(2).map(|uv| (uv[0], uv[1])).collect();
+        }
+        if let Some((count, colours)) = attribute("COLOR_0", &[3, 4])? {
+            data.colours = colours.chunks(count).map(colour).collect();
+        }
+        let indices: Vec<usize> = match primitive.indices {
+            Some(index) => self
+                .read(index, &[1])?
+                .1
+                .iter()
+                .map(|&i| i as usize)
+                .collect(),
+            None => (0..data.positions.len()).collect(),
+        };
+        data.triangles = indices
+            .chunks_exact(3)
+            .map(|t| [t[0], t[1], t[2]])
+            .collect();
+        Ok(data)
+    }
+
+    fn image(&self, index: usize) -> Result<FloatImage, Box<dyn Error>> {
+        let image = self
+            .document
+            .images
+            .get(index)
+            .ok_or_else(|| format!("no image {}", index))?;
+        let bytes = match (&image.uri, image.buffer_view) {
+            (Some(uri), _) => read_uri(uri, &self.dir)?,
+            (None, Some(view)) => self.view(view)?.to_vec(),
+            (None, None) => return Err("the image has no data".into()),
+        };
+        png::decode(&bytes)
+    }
+
+    // The metallic-roughness model as a principled material. The base colour is the texture if
+    // it can be read, else the vertex colours if there are any, else the factor.
+    fn material(
+        &self,
+        index: Option<usize>,
+        vertex_colours: bool,
+        images: &mut HashMap<usize, Option<Arc<FloatImage>>>,
+    ) -> Material {
+        let default = GltfMaterial::default();
+        let gltf = index
+            .and_then(|i| self.document.materials.get(i))
+            .unwrap_or(&default);
+        let pbr = &gltf.pbr_metallic_roughness;
+
+        let image = pbr.base_color_texture.as_ref().and_then(|texture| {
+            let source = self.document.textures.get(texture.index)?.source?;
+            images
+                .entry(source)
+                .or_insert_with(|| match self.image(source) {
+                    Ok(image) => Some(Arc::new(image)),
+                    Err(e) => {
+                        eprintln!("Warning: failed to load glTF image {}: {}", source, e);
+                        None
+                    }
+                })
+                .clone()
+        });
+        let base_colour = match image {
+            Some(image) => Texture::Image(ImageTexture::new(image, Filter::Bilinear, Wrap::Repeat)),
+            None if vertex_colours => Texture::Vertex(VertexColour::Vertex),
+            None => colour(&pbr.base_color_factor).into(),
+        };
+
+        let mut material = Material::new(PrincipledMaterial {
+            base_colour,
+            metallic: pbr.metallic_factor.into(),
+            roughness: pbr.roughness_factor.into(),
+            ..Default::default()
+        });
+        let strength = gltf
+            .extensions
+            .emissive_strength
+            .as_ref()
+            .map_or(1., |e| e.emissive_strength);
+        material.emission = colour(&gltf.emissive_factor) * strength as f32;
+        material
+    }
+
+    // One glTF mesh, shared by every node that uses it
+    fn geometry(
+        &self,
+        index: usize,
+        images: &mut HashMap<usize, Option<Arc<FloatImage>>>,
+    ) -> Option<Rc<Geometry>> {
+        let Some(mesh) = self.document.meshes.get(index) else {
+            eprintln!("Warning: no glTF mesh {}", index);
+            return None;
+        };
+        let mut parts: Vec<Box<dyn Entity>> = Vec::new();
+        for primitive in &mesh.primitives {
+            let part = self.mesh_data(primitive).and_then(|data| {
+                let material = self.material(primitive.material, !data.colours.is_empty(), images);
+                Ok(Mesh::new(data, material)?)
+            });
+            match part {
+                Ok(part) => parts.push(Box::new(part)),
+                Err(e) => eprintln!(
+                    "Warning: skipping a primitive of glTF mesh {}: {}",
+                    index, e
+                ),
+            }
+        }
+        (!parts.is_empty()).then(|| Rc::new(Geometry::new(parts)))
+    }
+
+    // The default scene's nodes with their transforms to the scene, parents first
+    fn placed_nodes(&self) -> Vec<(&Node, Matrix)> {
+        let nodes = &self.document.nodes;
+        let roots: Vec<usize> = match self.document.scenes.get(self.document.scene.unwrap_or(0)) {
+            Some(scene) => scene.nodes.clone(),
+            // Without scenes, every node that isn't a child
+            None => (0..nodes.len())
+                .filter(|i| !nodes.iter().any(|node| node.children.contains(i)))
+                .collect(),
+        };
+
+        let mut placed = Vec::new();
+        let mut stack: Vec<(usize, Matrix, usize)> = roots
+            .into_iter()
+            .rev()
+            .map(|i| (i, Matrix::identity(), 0))
+            .collect();
+        while let Some((index, parent, depth)) = stack.pop() {
+            let Some(node) = nodes.get(index) else {
+                eprintln!("Warning: no glTF node {}", index);
+                continue;
+            };
+            // Deeper than there are nodes means the hierarchy loops
+            if depth > nodes.len() {
+                continue;
+            }
+            let matrix = parent * node.local_matrix();
+            stack.extend(
+                node.children
+                    .iter()
+                    .rev()
+                    .map(|&child| (child, matrix, depth + 1)),
+            );
+            placed.push((node, matrix));
+        }
+        placed
+    }
+
+    // The scene's meshes, moved by `view` into the render's space
+    pub fn entities(&self, view: Matrix) -> Vec<Box<dyn Entity>> {
+        let mut images = HashMap::new();
+        let mut geometries = HashMap::new();
+        let mut entities: Vec<Box<dyn Entity>> = Vec::new();
+        for (node, matrix) in self.placed_nodes() {
+            let Some(mesh) = node.mesh else {
+                continue;
+            };
+            let geometry = geometries
+                .entry(mesh)
+                .or_insert_with(|| self.geometry(mesh, &mut images));
+            let Some(geometry) = geometry else {
+                continue;
+            };
+            let Some(transform) = Transform::new(view * matrix) else {
+                eprintln!("Warning: skipping a glTF node whose transform can't be inverted");
+                continue;
+            };
+            let instance = Instance::new(geometry.clone(), None);
+            entities.push(Box::new(Transformed::new(Box::new(instance), transform)));
+        }
+        entities
+    }
+
+    // The scene's punctual lights, moved by `view`. Point and spot lights become small sphere
+    // lights with the same intensity; spot cones are ignored.
+    pub fn lights(&self, view: Matrix) -> Vec<Light> {
+        let definitions = self
+            .document
+            .extensions
+            .lights
+            .as_ref()
+            .map_or(&[][..], |lights| &lights.lights);
+        let mut lights = Vec::new();
+        for (node, matrix) in self.placed_nodes() {
+            let Some(index) = node.extensions.light.as_ref().map(|light| light.light) else {
+                continue;
+            };
+            let Some(light) = definitions.get(index) else {
+                eprintln!("Warning: no glTF light {}", index);
+                continue;
+            };
+            let matrix = view * matrix;
+            let colour = colour(&light.color);
+            match light.kind.as_str() {
+                // Lights shine down their node's -z
+                "directional" => lights.push(Light::Directional(DirectionalLight {
+                    direction: matrix
+                        .transform_vector(Vector::new(0., 0., 1.))
+                        .normalised(),
+                    intensity: light.intensity / f64::consts::PI,
+                    colour,
+                })),
+                kind @ ("point" | "spot") => {
+                    if kind == "spot" {
+                        eprintln!("Warning: glTF spot lights are treated as point lights");
+                    }
+                    let area = f64::consts::PI * POINT_LIGHT_RADIUS * POINT_LIGHT_RADIUS;
+                    lights.push(Light::Sphere(SphereLight {
+                        position: matrix.transform_point(Vector::zero()),
+                        radius: POINT_LIGHT_RADIUS,
+                        intensity: light.intensity / area,
+                        colour,
+                    }));
+                }
+                kind => eprintln!("Warning: unknown glTF light type {}", kind),
+            }
+        }
+        lights
+    }
+
+    // The first perspective camera's transform and vertical field of view in radians
+    pub fn camera(&self) -> Option<(Matrix, f64)> {
+        self.placed_nodes().into_iter().find_map(|(node, matrix)| {
+            let camera = self.document.cameras.get(node.camera?)?;
+            Some((matrix, camera.perspective.as_ref()?.yfov))
+        })
+    }
+
+    // Bounds of the scene's meshes, without building them
+    fn bounds(&self) -> Aabb {
+        let mut bounds = Aabb::empty();
+        for (node, matrix) in self.placed_nodes() {
+            let Some(mesh) = node.mesh.and_then(|i| self.document.meshes.get(i)) else {
+                continue;
+            };
+            for primitive in &mesh.primitives {
+                let Some(Ok((_, positions))) = primitive
+                    .attributes
+                    .get("POSITION")
+                    .map(|&index| self.read(index, &[3]))
+                else {
+                    continue;
+                };
+                let points: Vec<Vector> = positions
+                    .chunks(3)
+                    .map(|p| matrix.transform_point(Vector::new(p[0], p[1], p[2])))
+                    .collect();
+                bounds = bounds.union(&Aabb::from_points(&points));
+            }
+        }
+        bounds
+    }
+}
+
+// A whole scene from a .gltf or .glb: its meshes and lights, seen from its first camera. Without
+// a camera the scene is framed from in front (+z), and without lights it is lit from over the
+// camera's shoulder.
+pub fn load_world(path: &Path) -> Result<World, Box<dyn Error>> {
+    let gltf = Gltf::load(path)?;
+    let mut world = World::new();
+
+    let view = match gltf.camera() {
+        Some((camera, yfov)) => {
+            world.settings.fov = yfov.to_degrees();
+            upright()
+                * camera
+                    .inverse()
+                    .ok_or("the camera's transform can't be inverted")?
+        }
+        None => {
+            let bounds = gltf.bounds();
+            let radius = (bounds.max - bounds.min).length() / 2.;
+            let distance = radius / (world.settings.fov.to_radians() / 2.).sin();
+            upright() * Matrix::translation(-(bounds.centre() + Vector::new(0., 0., distance)))
+        }
+    };
+
+    let entities = gltf.entities(view);
+    if entities.is_empty() {
+        return Err("the scene has no meshes".into());
+    }
+    for entity in entities {
+        world.add_entity(entity);
+    }
+
+    world.lights = gltf.lights(view);
+    if world.lights.is_empty() && world.emitters().is_empty() {
+        eprintln!("Warning: no lights in the glTF scene, lighting from behind the camera");
+        world.lights.push(Light::Directional(DirectionalLight {
+            direction: Vector::new(-1., -1., -1.).normalised(),
+            intensity: 1.,
+            colour: Colour::white(),
+        }));
+    }
+    Ok(world)
+}
+
+// The JSON and, if there is one, binary chunk of a .glb
+type GlbChunks<'a> = (&'a [u8], Option<&'a [u8]>);
+
+fn split_glb(bytes: &[u8]) -> Result<GlbChunks<'_>, Box<dyn Error>> {
+    let word = |offset: usize| {
+        bytes
+            .get(offset..offset + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    };
+    if word(4) != Some(2) {
+        return Err("only version 2 .glb files are supported".into());
+    }
+    let end = (word(8).ok_or("truncated .glb header")? as usize).min(bytes.len());
+    let mut chunks = Vec::new();
+    let mut offset = 12;
+    while offset + 8 <= end {
+        let length = word(offset).unwrap_or(0) as usize;
+        let kind = word(offset + 4).unwrap_or(0);
+        let data = bytes
+            .get(offset + 8..offset + 8 + length)
+            .ok_or("truncated .glb chunk")?;
+        chunks.push((kind, data));
+        offset += 8 + length;
+    }
+    match chunks.as_slice() {
+        [(JSON_CHUNK, json), rest @ ..] => {
+            let binary = rest.first().filter(|(kind, _)| *kind == BIN_CHUNK);
+            Ok((json, binary.map(|(_, data)| *data)))
+        }
+        _ => Err("a .glb must start with a JSON chunk".into()),
+    }
+}
+
+// The contents of a base64 `data:` URI, or else of a file relative to `dir`
+fn read_uri(uri: &str, dir: &Path) -> Result<Vec<u8>, Box<dyn Error>> {
+    if let Some(data) = uri.strip_prefix("data:") {
+        let (_, encoded) = data
+            .split_once(";base64,")
+            .ok_or("only base64 data URIs are supported")?;
+        return base64(encoded);
+    }
+    Ok(fs::read(dir.join(percent_decode(uri)))?)
+}
+
+// https://www.rfc-editor.org/rfc/rfc4648#section-4, also accepting the URL-safe alphabet
+fn base64(text: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut bytes = Vec::with_capacity(text.len() * 3 / 4);
+    let (mut bits, mut count) = (0u32, 0);
+    for c in text
+        .bytes()
+        .filter(|&c| c != b'=' && !c.is_ascii_whitespace())
+    {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            _ => return Err(format!("bad base64 character '{}'", c as char).into()),
+        };
+        bits = (bits << 6) | value as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            bytes.push((bits >> count) as u8);
+        }
+    }
+    Ok(bytes)
+}
+
+// Relative URIs escape characters such as spaces as %XX
+fn percent_decode(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| uri.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray::Ray;
+    use crate::test_dir::TestDir;
+
+    fn encode_base64(bytes: &[u8]) -> String {
+        const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let mut text = String::new();
+        for chunk in bytes.chunks(3) {
+            let n = chunk
+                .iter()
+                .enumerate()
+                .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+            for i in 0..4 {
+                if i <= chunk.len() {
+                    text.push(ALPHABET[(n >> (18 - 6 * i)) as usize & 63] as char);
+                } else {
+                    text.push('=');
+                }
+            }
+        }
+        text
+    }
+
+    // A unit right triangle in the xy plane with its right angle at the origin, as float
+    // positions followed by u16 indices
+    fn triangle_buffer() -> Vec<u8> {
+        let mut buffer = Vec::new();
+        for p in [0f32, 0., 0., 1., 0., 0., 0., 1., 0.] {
+            buffer.extend(p.to_le_bytes());
+        }
+        for i in [0u16, 1, 2] {
+            buffer.extend(i.to_le_bytes());
+        }
+        buffer
+    }
+
+    // `extra` is spliced into the top level of the document
+    fn triangle_gltf(buffer_uri: Option<&str>, extra: &str) -> String {
+        let uri = buffer_uri.map_or(String::new(), |uri| format!(r#""uri": "{}","#, uri));
+        format!(
+            r#"{{
+                "asset": {{"version": "2.0"}},
+                "buffers": [{{{} "byteLength": 42}}],
+                "bufferViews": [
+                    {{"buffer": 0, "byteLength": 36}},
+                    {{"buffer": 0, "byteOffset": 36, "byteLength": 6}}
+                ],
+                "accessors": [
+                    {{"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3"}},
+                    {{"bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR"}}
+                ],
+                "meshes": [{{"primitives": [{{"attributes": {{"POSITION": 0}}, "indices": 1,
+                    "material": 0}}]}}],
+                "materials": [{{"pbrMetallicRoughness": {{"baseColorFactor": [1, 0, 0, 1],
+                    "metallicFactor": 0}}}}],
+                {}
+            }}"#,
+            uri, extra
+        )
+    }
+
+    #[test]
+    fn reads_glb_and_data_uris() {
+        assert_eq!(base64("aGVsbG8gd29ybGQ=").unwrap(), b"hello world");
+        assert_eq!(
+            base64(&encode_base64(&[0, 255, 7, 128])).unwrap(),
+            [0, 255, 7, 128]
+        );
+        assert!(base64("a*b").is_err());
+        assert_eq!(percent_decode("my%20mesh.bin"), "my mesh.bin");
+
+        // Two nodes share the mesh, the second moved along x and doubled in size
+        let json = triangle_gltf(
+            None,
+            r#""nodes": [{"mesh": 0}, {"mesh": 0, "translation": [5, 0, 0], "scale": [2, 2, 2]}],
+                "scenes": [{"nodes": [0, 1]}]"#,
+        );
+        let mut json = json.into_bytes();
+        json.resize(json.len().div_ceil(4) * 4, b' ');
+        let mut binary = triangle_buffer();
+        binary.resize(binary.len().div_ceil(4) * 4, 0);
+        let mut glb = GLB_MAGIC.to_vec();
+        glb.extend(2u32.to_le_bytes());
+        glb.extend(((12 + 8 + json.len() + 8 + binary.len()) as u32).to_le_bytes());
+        for (kind, data) in [(JSON_CHUNK, &json), (BIN_CHUNK, &binary)] {
+            glb.extend((data.len() as u32).to_le_bytes());
+            glb.extend(kind.to_le_bytes());
+            glb.extend(data);
+        }
+
+        let gltf = Gltf::parse(&glb, Path::new("")).unwrap();
+        let entities = gltf.entities(Matrix::identity());
+        assert_eq!(entities.len(), 2);
+        let bounds = entities[1].bounds();
+        assert!((bounds.min - Vector::new(5., 0., 0.)).length() < 1e-9);
+        assert!((bounds.max - Vector::new(7., 2., 0.)).length() < 1e-9);
+        assert!(Gltf::parse(&glb[..40], Path::new("")).is_err());
+    }
+
+    #[test]
+    fn loads_a_whole_scene() {
+        let dir = TestDir::new("gltf_whole_scene");
+        dir.write("triangle data.bin", triangle_buffer());
+        // The camera is 2 back along +z, looking down -z at the triangle, with a point light
+        // beside it and the sun overhead
+        let json = triangle_gltf(
+            Some("triangle%20data.bin"),
+            r#""nodes": [
+                    {"mesh": 0},
+                    {"camera": 0, "translation": [0, 0, 2]},
+                    {"children": [3], "translation": [1, 0, 2]},
+                    {"extensions": {"KHR_lights_punctual": {"light": 0}}},
+                    {"rotation": [-0.7071068, 0, 0, 0.7071068],
+                        "extensions": {"KHR_lights_punctual": {"light": 1}}}
+                ],
+                "scenes": [{"nodes": [0, 1, 2, 4]}],
+                "cameras": [{"type": "perspective", "perspective": {"yfov": 0.5, "znear": 0.1}}],
+                "extensions": {"KHR_lights_punctual": {"lights": [
+                    {"type": "point", "intensity": 2},
+                    {"type": "directional", "color": [1, 0.5, 0]}
+                ]}}"#,
+        );
+        dir.write("scene.gltf", json);
+
+        let world = load_world(&dir.path().join("scene.gltf")).unwrap();
+        assert!((world.settings.fov - 0.5f64.to_degrees()).abs() < 1e-9);
+        // glTF's +y is the render's -y
+        let result = world.find_nearest(&Ray::new(
+            Vector::zero(),
+            Vector::new(0.25, -0.25, 2.).normalised(),
+        ));
+        assert!(result.hit);
+        assert!((result.distance - Vector::new(0.25, -0.25, 2.).length()).abs() < 1e-9);
+        let red = result
+            .material
+            .bsdf(&result.texture_coords())
+            .evaluate(Vector::new(0., 0., 1.), Vector::new(0., 0., 1.));
+        assert!(red.r > 2. * red.g);
+
+        assert_eq!(world.lights.len(), 2);
+        let Light::Sphere(point) = world.lights[0] else {
+            panic!("{:?}", world.lights[0]);
+        };
+        assert!((point.position - Vector::new(1., 0., 0.)).length() < 1e-9);
+        // Pointing down from above, so towards the light is the render's -y
+        let Light::Directional(sun) = world.lights[1] else {
+            panic!("{:?}", world.lights[1]);
+        };
+        assert!((sun.direction - Vector::new(0., -1., 0.)).length() < 1e-6);
+        assert_eq!(sun.colour, Colour::new(1., 0.5, 0.));
+    }
+
+    #[test]
+    fn gltf_entities() {
+        let dir = TestDir::new("gltf_entities");
+        let uri = format!(
+            "data:application/octet-stream;base64,{}",
+            encode_base64(&triangle_buffer())
+        );
+        let json = triangle_gltf(Some(&uri), r#""nodes": [{"mesh": 0}]"#);
+        dir.write("triangle.gltf", json);
+        let scene: toml::Table = r#"
+        [[entities]]
+        type = "gltf"
+        file = "triangle.gltf"
+        material = {colour = [0, 0, 1]}
+        transform = {translate = [0, 0, 3]}
+
+        [[entities]]
+        type = "gltf"
+        file = "missing.glb"
+        "#
+        .parse()
+        .unwrap();
+        let world = World::from_toml_in_dir(&scene, dir.path());
+        assert_eq!(world.entities.len(), 1);
+
+        // Turned upright, so the triangle reaches up the render's -y
+        let hit = |y: f64| {
+            world.find_nearest(&Ray::new(Vector::new(0.25, y, 0.), Vector::new(0., 0., 1.)))
+        };
+        assert!(!hit(0.25).hit);
+        let result = hit(-0.25);
+        assert!(result.hit && (result.distance - 3.).abs() < 1e-9);
+        let wo = Vector::new(0., 0., 1.);
+        let blue = result
+            .material
+            .bsdf(&result.texture_coords())
+            .evaluate(wo, wo);
+        assert!(blue.b > 0. && blue.r == 0.);
+    }
+
+    #[test]
+    fn emissive_meshes_are_lights() {
+        let dir = TestDir::new("gltf_emissive");
+        dir.write("triangle.bin", triangle_buffer());
+        let json = triangle_gltf(Some("triangle.bin"), r#""nodes": [{"mesh": 0}]"#).replace(
+            r#""metallicFactor": 0}"#,
+            r#""metallicFactor": 0}, "emissiveFactor": [1, 1, 1]"#,
+        );
+        dir.write("lamp.gltf", json);
+
+        // The glowing triangle is the scene's only light, so none is made up for it
+        let world = load_world(&dir.path().join("lamp.gltf")).unwrap();
+        assert!(world.lights.is_empty());
+        assert_eq!(world.emitters(), &[0]);
+
+        let scene: toml::Table = r#"
+        [[entities]]
+        type = "gltf"
+        file = "lamp.gltf"
+        transform = {translate = [0, 0, 3]}
+        "#
+        .parse()
+        .unwrap();
+        let world = World::from_toml_in_dir(&scene, dir.path());
+        assert_eq!(world.emitters(), &[0]);
+        let sample = world.entities[0]
+            .sample(Vector::zero(), (0.5, 0.5))
+            .unwrap();
+        assert_eq!(sample.emission, Colour::white());
+        assert!((sample.position.z - 3.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn rejects_accessors_past_their_data() {
+        let uri = format!(
+            "data:application/octet-stream;base64,{}",
+            encode_base64(&triangle_buffer())
+        );
+        let parse = |positions: &str| {
+            let json = triangle_gltf(Some(&uri), r#""nodes": [{"mesh": 0}]"#).replace(
+                r#"{"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3"}"#,
+                positions,
+            );
+            Gltf::parse(json.as_bytes(), Path::new("")).unwrap()
+        };
+
+        let gltf = parse(r#"{"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3"}"#);
+        assert_eq!(gltf.read(0, &[3]).unwrap().1.len(), 9);
+        // One element too many, and so many that their size overflows
+        for count in ["4", "4611686018427387904"] {
+            let gltf = parse(&format!(
+                r#"{{"bufferView": 0, "componentType": 5126, "count": {}, "type": "VEC3"}}"#,
+                count
+            ));
+            assert!(gltf.read(0, &[3]).is_err());
+        }
+        let gltf = parse(
+            r#"{"bufferView": 0, "byteOffset": 4, "componentType": 5126, "count": 3,
+                "type": "VEC3"}"#,
+        );
+        assert!(gltf.read(0, &[3]).is_err());
+        // Zeros aren't stored, so a count is all there is to go on
+        let gltf = parse(r#"{"componentType": 5126, "count": 1000000000, "type": "VEC3"}"#);
+        assert!(gltf.read(0, &[3]).is_err());
+
+        // A view far past the end of its buffer
+        let json = triangle_gltf(Some(&uri), r#""nodes": [{"mesh": 0}]"#).replace(
+            r#"{"buffer": 0, "byteLength": 36}"#,
+            r#"{"buffer": 0, "byteOffset": 9223372036854775807, "byteLength": 36}"#,
+        );
+        let gltf = Gltf::parse(json.as_bytes(), Path::new("")).unwrap();
+        assert!(gltf.read(0, &[3]).is_err());
+    }
+}
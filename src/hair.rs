@@ -0,0 +1,115 @@
+// Resources:
+// Kajiya and Kay 1989, "Rendering fur with three dimensional textures": https://www.cs.drexel.edu/~deb39/Classes/Papers/p271-kajiya.pdf
+// Hair shading in a path tracer: https://pbr-book.org/3ed-2018/Light_Transport_II_Volume_Rendering/Hair
+
+use core::f64;
+
+use crate::bsdf::{self, Bsdf, BsdfSample};
+use crate::colour::Colour;
+use crate::sampling;
+use crate::vector::Vector;
+
+// Kajiya-Kay scattering from a thin fibre running along local +x. Light is scattered the same
+// way all round the fibre, so the normal only matters to the integrator: its cosine is divided
+// back out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hair {
+    pub colour: Colour,
+    pub specular: f64,  // fraction of light in the highlight
+    pub shininess: f64, // exponent narrowing the highlight
+}
+
+impl Hair {
+    // Scattering from wi to wo per unit solid angle, before dividing by the cosine to the normal
+    fn scattering(&self, wo: Vector, wi: Vector) -> Colour {
+        let (cos_o, cos_i) = (wo.x, wi.x);
+        let sin_o = (1. - cos_o * cos_o).max(0.).sqrt();
+        let sin_i = (1. - cos_i * cos_i).max(0.).sqrt();
+        // Diffuse light leaves in proportion to how side-on the fibre is to the light, which
+        // integrates to π^2 over the sphere
+        let diffuse = sin_i / (f64::consts::PI * f64::consts::PI);
+        // The highlight lies on the cone of mirror directions around the fibre. Scaled so that
+        // for a fibre side-on to wo it holds about `specular` of the light.
+        let cone = (sin_i * sin_o - cos_i * cos_o).max(0.);
+        let normalisation =
+            (self.shininess / (2. * f64::consts::PI)).sqrt() / (2. * f64::consts::PI);
+        let highlight = self.specular * normalisation * cone.powf(self.shininess);
+        self.colour * ((1. - self.specular) * diffuse) as f32 + Colour::white() * highlight as f32
+    }
+}
+
+impl Bsdf for Hair {
+    fn evaluate(&self, wo: Vector, wi: Vector) -> Colour {
+        if wi.z == 0. {
+            return Colour::black();
+        }
+        self.scattering(wo, wi) / wi.z.abs() as f32
+    }
+
+    // Fibres scatter light in every direction, forwards as well as back
+    fn sample(&self, wo: Vector, _u_lobe: f64, u: (f64, f64)) -> Option<BsdfSample> {
+        bsdf::sample_towards(self, wo, sampling::uniform_sphere(u))
+    }
+
+    fn pdf(&self, _wo: Vector, _wi: Vector) -> f64 {
+        1. / (4. * f64::consts::PI)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::Rng;
+
+    fn hair(specular: f64) -> Hair {
+        Hair {
+            colour: Colour::new(0.8, 0.5, 0.2),
+            specular,
+            shininess: 40.,
+        }
+    }
+
+    #[test]
+    fn diffuse_reflects_its_colour() {
+        // The cosines to the normal cancel, leaving the integral of the scattering
+        let hair = hair(0.);
+        let wo = Vector::new(0.3, 0.2, 0.9).normalised();
+        let mut rng = Rng::new(5);
+        let n = 100000;
+        let mut total = 0.;
+        for _ in 0..n {
+            let sample = hair.sample(wo, rng.next_f64(), rng.next_2d()).unwrap();
+            total += sample.f.r as f64 * sample.wi.z.abs() / sample.pdf;
+        }
+        assert!(
+            (total / n as f64 - 0.8).abs() < 0.01,
+            "{}",
+            total / n as f64
+        );
+    }
+
+    #[test]
+    fn highlight_lies_on_the_mirror_cone() {
+        let hair = hair(0.5);
+        // Looking 30° from the fibre, the highlight is 30° from it on the other side whichever
+        // way round the fibre the light is
+        let wo = Vector::new(0.5, 0., 0.75f64.sqrt());
+        let beside = hair.scattering(wo, Vector::new(-0.5, 0.75f64.sqrt(), 0.));
+        let behind = hair.scattering(wo, Vector::new(-0.5, 0., -(0.75f64.sqrt())));
+        let mirrored = hair.scattering(wo, Vector::new(0.5, 0.75f64.sqrt(), 0.));
+        assert!((beside.b - behind.b).abs() < 1e-6);
+        assert!(beside.b > 10. * mirrored.b);
+    }
+
+    #[test]
+    fn highlight_is_reciprocal() {
+        let shiny = Hair {
+            specular: 1.,
+            ..hair(0.)
+        };
+        let a = Vector::new(0.2, -0.6, 0.7).normalised();
+        let b = Vector::new(-0.4, 0.1, -0.8).normalised();
+        assert!(shiny.scattering(a, b).b > 0.);
+        assert_eq!(shiny.scattering(a, b), shiny.scattering(b, a));
+    }
+}
@@ -0,0 +1,431 @@
+// Resources:
+// Grid traversal: Amanatides and Woo 1987, "A Fast Voxel Traversal Algorithm for Ray Tracing"
+// Ray/triangle intersection: https://en.wikipedia.org/wiki/M%C3%B6ller%E2%80%93Trumbore_intersection_algorithm
+
+use crate::bvh::Aabb;
+use crate::colour::Colour;
+use crate::image::FloatImage;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::sampling::{self, Distribution1D};
+use crate::vector::{Matrix, Transform, Vector};
+use crate::world::{
+    Entity, Hit, IntersectionResult, SurfaceInteraction, SurfaceSample, Transformed,
+};
+use serde::Deserialize;
+
+// A `type = "heightfield"` entity: terrain whose elevation is read from a greyscale image laid
+// flat as if seen from above, with its top edge furthest away. `position` is the centre of the
+// terrain at zero elevation, and white pixels rise `height` above it.
+#[derive(Debug, Clone, Deserialize)]
+pub struct HeightfieldSpec {
+    pub image: String, // relative to the scene file
+    pub position: Vector,
+    pub width: f64, // along x
+    pub depth: f64, // along z
+    pub height: f64,
+    pub material: Material,
+}
+
+impl HeightfieldSpec {
+    pub fn build(self, image: &FloatImage) -> Result<Box<dyn Entity>, String> {
+        if image.width < 2 || image.height < 2 {
+            return Err("a heightfield's image must be at least 2 by 2 pixels".to_string());
+        }
+        if self.width <= 0. || self.depth <= 0. || self.height <= 0. {
+            return Err("width, depth and height must be positive".to_string());
+        }
+        let heightfield = Heightfield::new(image, self.material);
+
+        // From grid units, with rows running towards the viewer and elevation upwards
+        let (columns, rows) = ((image.width - 1) as f64, (image.height - 1) as f64);
+        let corner = Vector::new(
+            self.position.x - self.width / 2.,
+            self.position.y,
+            self.position.z + self.depth / 2.,
+        );
+        let matrix = Matrix {
+            m: [
+                [self.width / columns, 0., 0., corner.x],
+                [0., 0., -self.height, corner.y],
+                [0., -self.depth / rows, 0., corner.z],
+                [0., 0., 0., 1.],
+            ],
+        };
+        let transform = Transform::new(matrix).ok_or("a heightfield must not be flat")?;
+        Ok(Box::new(Transformed::new(Box::new(heightfield), transform)))
+    }
+}
+
+// Grid of samples at whole x and y from zero, with heights along z, joined by two triangles in
+// each cell. An emissive one is sampled as a light by picking a triangle in proportion to its
+// area.
+#[derive(Debug, Clone)]
+pub struct Heightfield {
+    columns: usize,
+    rows: usize,
+    heights: Vec<f64>, // row major
+    bounds: Aabb,
+    areas: Distribution1D, // over the triangles, two per cell with cells in row major order
+    area: f64,
+    material: Material,
+}
+
+// Plane z = a x + b y + c through one of a cell's triangles
+struct Plane {
+    a: f64,
+    b: f64,
+    c: f64,
+}
+
+impl Plane {
+    fn normal(&self) -> Vector {
+        Vector::new(-self.a, -self.b, 1.).normalised()
+    }
+}
+
+impl Heightfield {
+    pub fn new(image: &FloatImage, material: Material) -> Self {
+        let mut heights = Vec::with_capacity(image.width * image.height);
+        for y in 0..image.height {
+            for x in 0..image.width {
+                let pixel = image.pixel(x, y);
+                heights.push(((pixel.r + pixel.g + pixel.b) / 3.) as f64);
+            }
+        }
+        let (low, high) = heights
+            .iter()
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(low, high), &h| {
+                (low.min(h), high.max(h))
+            });
+        let bounds = Aabb::new(
+            Vector::new(0., 0., low),
+            Vector::new((image.width - 1) as f64, (image.height - 1) as f64, high),
+        );
+        let mut heightfield = Heightfield {
+            columns: image.width,
+            rows: image.height,
+            heights,
+            bounds,
+            areas: Distribution1D::new(vec![]),
+            area: 0.,
+            material,
+        };
+        // Each triangle covers half a cell, stretched by the slope of its plane
+        let areas: Vec<f64> = (0..image.height - 1)
+            .flat_map(|y| (0..image.width - 1).map(move |x| (x, y)))
+            .flat_map(|(x, y)| heightfield.planes(x, y))
+            .map(|plane| (1. + plane.a * plane.a + plane.b * plane.b).sqrt() / 2.)
+            .collect();
+        heightfield.area = areas.iter().sum();
+        heightfield.areas = Distribution1D::new(areas);
+        heightfield
+    }
+
+    fn height(&self, x: usize, y: usize) -> f64 {
+        self.heights[x + y * self.columns]
+    }
+
+    // The planes of the cell's two triangles: the one nearer (x, y) and the one nearer
+    // (x + 1, y + 1), split along the diagonal x + y = 1 within the cell
+    fn planes(&self, x: usize, y: usize) -> [Plane; 2] {
+        let (h00, h10) = (self.height(x, y), self.height(x + 1, y));
+        let (h01, h11) = (self.height(x, y + 1), self.height(x + 1, y + 1));
+        let (x, y) = (x as f64, y as f64);
+        let lower = (h10 - h00, h01 - h00);
+        let upper = (h11 - h01, h11 - h10);
+        [
+            Plane {
+                a: lower.0,
+                b: lower.1,
+                c: h00 - lower.0 * x - lower.1 * y,
+            },
+            Plane {
+                a: upper.0,
+                b: upper.1,
+                c: h11 - upper.0 * (x + 1.) - upper.1 * (y + 1.),
+            },
+        ]
+    }
+
+    // The cell a point lies over, and which of its triangles
+    fn locate(&self, p: Vector) -> (usize, usize, usize) {
+        let x = (p.x.floor().max(0.) as usize).min(self.columns - 2);
+        let y = (p.y.floor().max(0.) as usize).min(self.rows - 2);
+        let upper = (p.x - x as f64) + (p.y - y as f64) > 1.;
+        (x, y, upper as usize)
+    }
+
+    // Nearest hit within a cell, between the distances the ray spends over it
+    fn intersect_cell(
+        &self,
+        ray: &Ray,
+        x: usize,
+        y: usize,
+        t_enter: f64,
+        t_exit: f64,
+    ) -> Option<f64> {
+        // Skip cells the ray passes wholly above or below
+        let heights = [
+            self.height(x, y),
+            self.height(x + 1, y),
+            self.height(x, y + 1),
+            self.height(x + 1, y + 1),
+        ];
+        let low = heights.iter().copied().fold(f64::INFINITY, f64::min);
+        let high = heights.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        let (z0, z1) = (ray.at(t_enter).z, ray.at(t_exit).z);
+        if z0.min(z1) > high || z0.max(z1) < low {
+            return None;
+        }
+
+        let mut nearest: Option<f64> = None;
+        for (i, plane) in self.planes(x, y).iter().enumerate() {
+            // z - a x - b y = c along the ray
+            let (o, d) = (ray.origin, ray.direction);
+            let denominator = d.z - plane.a * d.x - plane.b * d.y;
+            if denominator == 0. {
+                continue;
+            }
+            let t = (plane.c - o.z + plane.a * o.x + plane.b * o.y) / denominator;
+            if t <= 0. || nearest.is_some_and(|nearest| t >= nearest) {
+                continue;
+            }
+            let p = ray.at(t);
+            let (fx, fy) = (p.x - x as f64, p.y - y as f64);
+            let inside = (-1e-9..=1. + 1e-9).contains(&fx) && (-1e-9..=1. + 1e-9).contains(&fy);
+            let on_side = if i == 0 { fx + fy <= 1. } else { fx + fy >= 1. };
+            if inside && on_side {
+                nearest = Some(t);
+            }
+        }
+        nearest
+    }
+}
+
+impl Entity for Heightfield {
+    // Step through the cells under the ray in order, so only those it passes over are tested
+    fn intersection(&self, ray: &Ray) -> IntersectionResult {
+        let Some((t_min, t_max)) = self.bounds.interval(ray) else {
+            return IntersectionResult::No;
+        };
+        if t_max <= 0. {
+            return IntersectionResult::No;
+        }
+        let mut t = t_min.max(0.);
+        let (mut x, mut y, _) = self.locate(ray.at(t));
+
+        // Distance along the ray between cell boundaries in x and y, and to the next ones
+        let step = |direction: f64, origin: f64, cell: usize| -> (i64, f64, f64) {
+            if direction > 0. {
+                (1, 1. / direction, (cell as f64 + 1. - origin) / direction)
+            } else if direction < 0. {
+                (-1, -1. / direction, (cell as f64 - origin) / direction)
+            } else {
+                (0, f64::INFINITY, f64::INFINITY)
+            }
+        };
+        let (step_x, delta_x, mut next_x) = step(ray.direction.x, ray.origin.x, x);
+        let (step_y, delta_y, mut next_y) = step(ray.direction.y, ray.origin.y, y);
+
+        loop {
+            let t_exit = next_x.min(next_y).min(t_max);
+            if let Some(hit) = self.intersect_cell(ray, x, y, t, t_exit) {
+                return IntersectionResult::One(hit);
+            }
+            if t_exit >= t_max {
+                return IntersectionResult::No;
+            }
+            t = t_exit;
+            if next_x < next_y {
+                let moved = x as i64 + step_x;
+                if moved < 0 || moved > self.columns as i64 - 2 {
+                    return IntersectionResult::No;
+                }
+                x = moved as usize;
+                next_x += delta_x;
+            } else {
+                let moved = y as i64 + step_y;
+                if moved < 0 || moved > self.rows as i64 - 2 {
+                    return IntersectionResult::No;
+                }
+                y = moved as usize;
+                next_y += delta_y;
+            }
+        }
+    }
+
+    fn surface(&self, ray: &Ray, hit: Hit) -> SurfaceInteraction<'_> {
+        let position = ray.at(hit.t);
+        let (x, y, triangle) = self.locate(position);
+        let plane = &self.planes(x, y)[triangle];
+        // Texture coordinates run across the image, so one the same size drapes over it
+        let (columns, rows) = ((self.columns - 1) as f64, (self.rows - 1) as f64);
+        SurfaceInteraction {
+            position,
+            normal: plane.normal(),
+            uv: (position.x / columns, position.y / rows),
+            dpdu: Vector::new(1., 0., plane.a) * columns,
+            dpdv: Vector::new(0., 1., plane.b) * rows,
+            object_position: position,
+            vertex_colour: None,
+            material: &self.material,
+        }
+    }
+
+    fn bounds(&self) -> Aabb {
+        self.bounds
+    }
+
+    fn position(&self) -> Vector {
+        self.bounds.centre()
+    }
+
+    fn emission(&self) -> Colour {
+        self.material.emission
+    }
+
+    fn can_sample(&self) -> bool {
+        true
+    }
+
+    fn area(&self) -> f64 {
+        self.area
+    }
+
+    fn sample(&self, reference: Vector, u: (f64, f64)) -> Option<SurfaceSample> {
+        let (offset, _, index) = self.areas.sample(u.0);
+        // Where in the chosen triangle's slice u.0 fell, reused for the point on it
+        let along = (offset * self.areas.len() as f64 - index as f64).clamp(0., 1.);
+        let (b0, b1) = sampling::uniform_triangle((along, u.1));
+        let cell = index / 2;
+        let (x, y) = (cell % (self.columns - 1), cell / (self.columns - 1));
+        let corners = if index % 2 == 0 {
+            [(x, y), (x + 1, y), (x, y + 1)]
+        } else {
+            [(x + 1, y + 1), (x, y + 1), (x + 1, y)]
+        };
+        let [p0, p1, p2] = corners.map(|(x, y)| Vector::new(x as f64, y as f64, self.height(x, y)));
+        let position = p0 * b0 + p1 * b1 + p2 * (1. - b0 - b1);
+        let normal = self.planes(x, y)[index % 2].normal();
+        let pdf = self.pdf(reference, position, normal);
+        if pdf == 0. {
+            return None;
+        }
+        Some(SurfaceSample {
+            position,
+            normal,
+            pdf,
+            emission: self.emission(),
+        })
+    }
+
+    fn pdf(&self, reference: Vector, point: Vector, normal: Vector) -> f64 {
+        sampling::area_to_solid_angle_pdf(1. / self.area, reference, point, normal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::Rng;
+
+    fn image(width: usize, height: usize, heights: &[f32]) -> FloatImage {
+        let pixels = heights.iter().map(|&h| Colour::new(h, h, h)).collect();
+        FloatImage::new(width, height, pixels)
+    }
+
+    #[test]
+    fn hits_slopes_with_upward_normals() {
+        // Rising along x
+        let field = Heightfield::new(
+            &image(3, 2, &[0., 0.5, 1., 0., 0.5, 1.]),
+            Material::default(),
+        );
+        let down = Vector::new(0., 0., -1.);
+        let ray = Ray::new(Vector::new(1.5, 0.5, 5.), down);
+        let t = field.intersection(&ray).nearest().unwrap();
+        assert!((t - 4.25).abs() < 1e-9);
+        let surface = field.surface(&ray, Hit::new(t));
+        assert!((surface.normal - Vector::new(-0.5, 0., 1.).normalised()).length() < 1e-9);
+        assert_eq!(surface.uv, (0.75, 0.5));
+        // Beside the terrain
+        let ray = Ray::new(Vector::new(2.5, 0.5, 5.), down);
+        assert_eq!(field.intersection(&ray), IntersectionResult::No);
+        // Flying under the slope towards it
+        let ray = Ray::new(Vector::new(-1., 0.5, 0.75), Vector::new(1., 0., 0.));
+        let t = field.intersection(&ray).nearest().unwrap();
+        assert!((t - 2.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn traversal_matches_testing_every_cell() {
+        let mut rng = Rng::new(4);
+        let (width, height) = (9, 7);
+        let heights: Vec<f32> = (0..width * height).map(|_| rng.next_f64() as f32).collect();
+        let field = Heightfield::new(&image(width, height, &heights), Material::default());
+        for _ in 0..500 {
+            let (a, b) = rng.next_2d();
+            let (c, d) = rng.next_2d();
+            let origin = Vector::new(a * 12. - 2., b * 10. - 2., 2.);
+            let target = Vector::new(c * 8., d * 6., rng.next_f64());
+            let ray = Ray::new(origin, target - origin);
+            let expected = (0..width - 1)
+                .flat_map(|x| (0..height - 1).map(move |y| (x, y)))
+                .filter_map(|(x, y)| field.intersect_cell(&ray, x, y, 0., f64::INFINITY))
+                .min_by(f64::total_cmp);
+            match (field.intersection(&ray).nearest(), expected) {
+                (Some(t), Some(e)) => assert!((t - e).abs() < 1e-9, "{} != {}", t, e),
+                (found, expected) => assert_eq!(found, expected),
+            }
+        }
+    }
+
+    #[test]
+    fn samples_lie_on_the_terrain() {
+        // Rising along y in the second row, more steeply towards larger x
+        let field = Heightfield::new(&image(3, 2, &[0., 0., 0., 0., 1., 2.]), Material::default());
+        // The triangles' slopes (a, b) are (0, 0), (1, 1), (0, 1) and (1, 2)
+        let area = (1. + 3f64.sqrt() + 2f64.sqrt() + 6f64.sqrt()) / 2.;
+        assert!((field.area - area).abs() < 1e-9, "{}", field.area);
+
+        let mut rng = Rng::new(5);
+        let reference = Vector::new(1., 0.5, 4.);
+        for _ in 0..200 {
+            let sample = field.sample(reference, rng.next_2d()).unwrap();
+            let p = sample.position;
+            let (x, y, triangle) = field.locate(p);
+            let plane = &field.planes(x, y)[triangle];
+            assert!((p.z - (plane.a * p.x + plane.b * p.y + plane.c)).abs() < 1e-9);
+            assert!((sample.normal - plane.normal()).length() < 1e-9);
+            let expected =
+                sampling::area_to_solid_angle_pdf(1. / area, reference, p, plane.normal());
+            assert!((sample.pdf - expected).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn placed_in_the_scene() {
+        let table: toml::Table = r#"
+        image = "terrain.png"
+        position = [0, 1, 10]
+        width = 4
+        depth = 2
+        height = 0.5
+        material = {colour = [1, 1, 1]}
+        "#
+        .parse()
+        .unwrap();
+        let spec = HeightfieldSpec::deserialize(toml::Value::Table(table)).unwrap();
+        // White at the far edge, black at the near one
+        let terrain = spec.build(&image(2, 2, &[1., 1., 0., 0.])).unwrap();
+        let up = Vector::new(0., -1., 0.);
+        let ray = Ray::new(Vector::new(0., -5., 10.), -up);
+        let t = terrain.intersection(&ray).nearest().unwrap();
+        // Halfway up the slope
+        assert!((t - 5.75).abs() < 1e-9);
+        let normal = terrain.surface(&ray, Hit::new(t)).normal;
+        assert!(normal.dot(&up) > 0. && normal.z < 0.);
+        assert!(terrain.bounds().min.x == -2. && terrain.bounds().max.z == 11.);
+    }
+}
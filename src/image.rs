@@ -1,3 +1,10 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use crate::colour::Colour;
+use crate::png;
+
 pub struct Image {
     pixel_data: Vec<u8>, // BGR888
     pub width: u16,
@@ -36,6 +43,262 @@ fn blank_pixel_data(width: u16, height: u16) -> Vec<u8> {
     return v;
 }
 
+// Largest image the decoders will allocate for, in pixels, so that a corrupt header can't ask for
+// an enormous buffer
+const MAX_PIXELS: usize = 1 << 26;
+
+// Dimensions read from a file's header, checked before anything is allocated for them
+pub fn check_dimensions(width: usize, height: usize) -> Result<(), Box<dyn Error>> {
+    if width == 0 || height == 0 {
+        return Err(format!("image is {}x{}", width, height).into());
+    }
+    if width
+        .checked_mul(height)
+        .is_none_or(|pixels| pixels > MAX_PIXELS)
+    {
+        return Err(format!("image is too large at {}x{}", width, height).into());
+    }
+    Ok(())
+}
+
+// Linear floating point image used for lookups (environment maps, textures)
+#[derive(Debug, Clone, PartialEq)]
+pub struct FloatImage {
+    pub width: usize,
+    pub height: usize,
+    pixels: Vec<Colour>, // row major, top row first
+}
+
+impl FloatImage {
+    pub fn new(width: usize, height: usize, pixels: Vec<Colour>) -> Self {
+        assert_eq!(pixels.len(), width * height);
+        FloatImage {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Colour {
+        self.pixels[x + y * self.width]
+    }
+
+    // Bilinearly filtered lookup with (0, 0) the top left corner and (1, 1) the bottom right,
+    // clamping at the edges
+    pub fn bilinear(&self, u: f64, v: f64) -> Colour {
+        let x = (u * self.width as f64 - 0.5).clamp(0., (self.width - 1) as f64);
+        let y = (v * self.height as f64 - 0.5).clamp(0., (self.height - 1) as f64);
+        let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        let (fx, fy) = ((x - x0 as f64) as f32, (y - y0 as f64) as f32);
+
+        let top = self.pixel(x0, y0) * (1. - fx) + self.pixel(x1, y0) * fx;
+        let bottom = self.pixel(x0, y1) * (1. - fx) + self.pixel(x1, y1) * fx;
+        top * (1. - fy) + bottom * fy
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let bytes = fs::read(path)?;
+        match path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_lowercase())
+        {
+            Some(e) if e == "hdr" => Self::from_hdr_bytes(&bytes),
+            Some(e) if e == "bmp" => Self::from_bmp_bytes(&bytes),
+            Some(e) if e == "png" => png::decode(&bytes),
+            _ => Err(format!("unsupported image format: {}", path.display()).into()),
+        }
+    }
+
+    // Radiance RGBE (.hdr) with flat or new-style run length encoded scanlines
+    // Format: https://paulbourke.net/dataformats/pic/
+    pub fn from_hdr_bytes(bytes: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let mut pos = 0;
+        let next_line = |pos: &mut usize| -> Result<String, Box<dyn Error>> {
+            let start = *pos;
+            while *pos < bytes.len() && bytes[*pos] != b'\n' {
+                *pos += 1;
+            }
+            if *pos >= bytes.len() {
+                return Err("unexpected end of HDR header".into());
+            }
+            *pos += 1;
+            Ok(String::from_utf8_lossy(&bytes[start..*pos - 1]).into_owned())
+        };
+
+        let magic = next_line(&mut pos)?;
+        if !magic.starts_with("#?") {
+            return Err("not a Radiance HDR file".into());
+        }
+        loop {
+            let line = next_line(&mut pos)?;
+            if line.is_empty() {
+                break;
+            }
+            if let Some(format) = line.strip_prefix("FORMAT=") {
+                if format != "32-bit_rle_rgbe" {
+                    return Err(format!("unsupported HDR format {}", format).into());
+                }
+            }
+        }
+
+        // Only the standard orientation (top to bottom, left to right) is supported
+        let resolution = next_line(&mut pos)?;
+        let parts: Vec<&str> = resolution.split_whitespace().collect();
+        if parts.len() != 4 || parts[0] != "-Y" || parts[2] != "+X" {
+            return Err(format!("unsupported HDR resolution line '{}'", resolution).into());
+        }
+        let height: usize = parts[1].parse()?;
+        let width: usize = parts[3].parse()?;
+        check_dimensions(width, height)?;
+
+        let mut pixels = Vec::with_capacity(width * height);
+        let mut scanline = vec![[0u8; 4]; width];
+        for _ in 0..height {
+            pos = read_hdr_scanline(bytes, pos, &mut scanline)?;
+            pixels.extend(scanline.iter().map(rgbe_to_colour));
+        }
+
+        Ok(FloatImage::new(width, height, pixels))
+    }
+
+    // Uncompressed 24 or 32 bit BMP, with either a core (OS/2) or BITMAPINFOHEADER style header
+    pub fn from_bmp_bytes(bytes: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let u16_at = |i: usize| -> Result<u16, Box<dyn Error>> {
+            let b = bytes.get(i..i + 2).ok_or("truncated BMP header")?;
+            Ok(u16::from_le_bytes([b[0], b[1]]))
+        };
+        let u32_at = |i: usize| -> Result<u32, Box<dyn Error>> {
+            let b = bytes.get(i..i + 4).ok_or("truncated BMP header")?;
+            Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        };
+
+        if bytes.get(0..2) != Some(b"BM") {
+            return Err("not a BMP file".into());
+        }
+        let offset_to_pixels = u32_at(10)? as usize;
+        let header_size = u32_at(14)?;
+
+        let (width, height, bpp, top_down) = if header_size == 12 {
+            (
+                u16_at(18)? as usize,
+                u16_at(20)? as usize,
+                u16_at(24)?,
+                false,
+            )
+        } else if header_size >= 40 {
+            let width = u32_at(18)? as i32;
+            let height = u32_at(22)? as i32;
+            let compression = u32_at(30)?;
+            // BI_RGB, or BI_BITFIELDS which we assume uses the usual BGRA masks
+            if compression != 0 && compression != 3 {
+                return Err("compressed BMPs are not supported".into());
+            }
+            (
+                width.unsigned_abs() as usize,
+                height.unsigned_abs() as usize,
+                u16_at(28)?,
+                height < 0,
+            )
+        } else {
+            return Err(format!("unsupported BMP header size {}", header_size).into());
+        };
+        if bpp != 24 && bpp != 32 {
+            return Err(format!("unsupported BMP bit depth {}", bpp).into());
+        }
+        check_dimensions(width, height)?;
+
+        let bytes_per_pixel = bpp as usize / 8;
+        let row_size = (bpp as usize * width).div_ceil(32) * 4;
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            // Rows are stored bottom to top unless the height was negative
+            let row = if top_down { y } else { height - 1 - y };
+            let start = offset_to_pixels + row * row_size;
+            let data = bytes
+                .get(start..start + width * bytes_per_pixel)
+                .ok_or("truncated BMP pixel data")?;
+            for bgr in data.chunks_exact(bytes_per_pixel) {
+                pixels.push(Colour::new(
+                    bgr[2] as f32 / 255.,
+                    bgr[1] as f32 / 255.,
+                    bgr[0] as f32 / 255.,
+                ));
+            }
+        }
+
+        Ok(FloatImage::new(width, height, pixels))
+    }
+}
+
+fn read_hdr_scanline(
+    bytes: &[u8],
+    mut pos: usize,
+    scanline: &mut [[u8; 4]],
+) -> Result<usize, Box<dyn Error>> {
+    let width = scanline.len();
+    let truncated = || -> Box<dyn Error> { "truncated HDR pixel data".into() };
+    let header = bytes.get(pos..pos + 4).ok_or_else(truncated)?;
+
+    let is_rle =
+        (8..0x8000).contains(&width) && header[0] == 2 && header[1] == 2 && header[2] & 0x80 == 0;
+    if !is_rle {
+        for pixel in scanline.iter_mut() {
+            pixel.copy_from_slice(bytes.get(pos..pos + 4).ok_or_else(truncated)?);
+            pos += 4;
+        }
+        return Ok(pos);
+    }
+
+    if ((header[2] as usize) << 8 | header[3] as usize) != width {
+        return Err("HDR scanline width mismatch".into());
+    }
+    pos += 4;
+
+    // Each channel is stored separately as runs or literal spans
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let count = *bytes.get(pos).ok_or_else(truncated)? as usize;
+            pos += 1;
+            if count > 128 {
+                let count = count - 128;
+                let value = *bytes.get(pos).ok_or_else(truncated)?;
+                pos += 1;
+                if x + count > width {
+                    return Err("bad HDR run length".into());
+                }
+                for pixel in &mut scanline[x..x + count] {
+                    pixel[channel] = value;
+                }
+                x += count;
+            } else {
+                if count == 0 || x + count > width {
+                    return Err("bad HDR run length".into());
+                }
+                let values = bytes.get(pos..pos + count).ok_or_else(truncated)?;
+                for (pixel, value) in scanline[x..x + count].iter_mut().zip(values) {
+                    pixel[channel] = *value;
+                }
+                pos += count;
+                x += count;
+            }
+        }
+    }
+
+    Ok(pos)
+}
+
+fn rgbe_to_colour(rgbe: &[u8; 4]) -> Colour {
+    if rgbe[3] == 0 {
+        return Colour::black();
+    }
+    // 2^(e - 128) / 256
+    let f = 2_f32.powi(rgbe[3] as i32 - 136);
+    Colour::new(rgbe[0] as f32 * f, rgbe[1] as f32 * f, rgbe[2] as f32 * f)
+}
+
 pub struct BMPFileHeader {
     signature: [u8; 2],
     size: u32,
@@ -155,7 +418,7 @@ impl BMPImage {
 
 // bpp is always 24
 fn bmp_rowsize(image_width: u16) -> u16 {
-    let bpp = 24 as f64;
+    let bpp = 24_f64;
     let image_width = image_width as f64;
     return ((bpp * image_width / 32.0).ceil() as u16) * 4;
 }
@@ -173,9 +436,9 @@ fn bmp_size_for_image(image: &Image) -> u32 {
 fn u16_to_bytes_little_endian(value: u16) -> [u8; 2] {
     let mut a: [u8; 2] = [0; 2];
     let mut value = value;
-    for i in 0..2 {
-        a[i] = (value & 0xFF) as u8;
-        value = value >> 8
+    for byte in a.iter_mut() {
+        *byte = (value & 0xFF) as u8;
+        value >>= 8
     }
 
     return a;
@@ -184,9 +447,9 @@ fn u16_to_bytes_little_endian(value: u16) -> [u8; 2] {
 fn u32_to_bytes_little_endian(value: u32) -> [u8; 4] {
     let mut a: [u8; 4] = [0; 4];
     let mut value = value;
-    for i in 0..4 {
-        a[i] = (value & 0xFF) as u8;
-        value = value >> 8
+    for byte in a.iter_mut() {
+        *byte = (value & 0xFF) as u8;
+        value >>= 8
     }
 
     return a;
@@ -203,6 +466,67 @@ mod tests {
         assert_eq!(array[1], 0x12);
     }
 
+    #[test]
+    fn hdr_flat_and_rle_scanlines() {
+        let mut bytes = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 2 +X 8\n".to_vec();
+        // Flat scanline: eight pixels of 0.5 red
+        for _ in 0..8 {
+            bytes.extend_from_slice(&[128, 0, 0, 128]);
+        }
+        // RLE scanline: each channel is one run of eight
+        bytes.extend_from_slice(&[2, 2, 0, 8]);
+        bytes.extend_from_slice(&[128 + 8, 0, 128 + 8, 128, 128 + 8, 0, 128 + 8, 129]);
+
+        let image = FloatImage::from_hdr_bytes(&bytes).unwrap();
+        assert_eq!(image.width, 8);
+        assert_eq!(image.height, 2);
+        assert_eq!(image.pixel(3, 0), Colour::new(0.5, 0., 0.));
+        assert_eq!(image.pixel(7, 1), Colour::new(0., 1., 0.));
+    }
+
+    #[test]
+    fn bmp_round_trip() {
+        let mut image = Image::new(3, 2);
+        image.put_pixel(0, 0, 0xFF0000);
+        image.put_pixel(2, 1, 0x0000FF);
+        let bytes = BMPImage::from(image).as_bytes();
+
+        let decoded = FloatImage::from_bmp_bytes(&bytes).unwrap();
+        assert_eq!((decoded.width, decoded.height), (3, 2));
+        assert_eq!(decoded.pixel(0, 0), Colour::new(1., 0., 0.));
+        assert_eq!(decoded.pixel(2, 1), Colour::new(0., 0., 1.));
+        assert_eq!(decoded.pixel(1, 1), Colour::white());
+    }
+
+    #[test]
+    fn rejects_empty_and_oversize_images() {
+        let hdr = |resolution: &str| {
+            format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n{}\n", resolution).into_bytes()
+        };
+        assert!(FloatImage::from_hdr_bytes(&hdr("-Y 0 +X 8")).is_err());
+        assert!(FloatImage::from_hdr_bytes(&hdr("-Y 100000 +X 100000")).is_err());
+
+        // Zero the width of a valid BMP's header
+        let mut bmp = BMPImage::from(Image::new(3, 2)).as_bytes();
+        bmp[18] = 0;
+        bmp[19] = 0;
+        assert!(FloatImage::from_bmp_bytes(&bmp).is_err());
+    }
+
+    #[test]
+    fn bilinear_interpolates_between_texel_centres() {
+        let image = FloatImage::new(2, 1, vec![Colour::black(), Colour::white()]);
+        assert_eq!(image.bilinear(0.5, 0.5), Colour::new(0.5, 0.5, 0.5));
+        assert_eq!(image.bilinear(0., 0.5), Colour::black());
+        assert_eq!(image.bilinear(1., 0.5), Colour::white());
+    }
+
+    #[test]
+    fn hdr_rejects_truncated_data() {
+        let bytes = b"#?RADIANCE\n\n-Y 2 +X 2\n\x01\x02".to_vec();
+        assert!(FloatImage::from_hdr_bytes(&bytes).is_err());
+    }
+
     #[test]
     fn u32_to_bytes_is_little_endian() {
         let array = u32_to_bytes_little_endian(0x12345678);
@@ -0,0 +1,295 @@
+use crate::bvh::{Aabb, Bvh};
+use crate::colour::Colour;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::sampling::Distribution1D;
+use crate::vector::Vector;
+use crate::world::{Entity, Hit, IntersectionResult, SurfaceInteraction, SurfaceSample};
+use serde::Deserialize;
+use std::rc::Rc;
+
+// Entities defined once under `[[geometry.<name>]]` and shared by every instance of them, with
+// their own hierarchy so an instance costs one box in the world's
+pub struct Geometry {
+    entities: Vec<Box<dyn Entity>>,
+    bvh: Bvh,
+}
+
+impl Geometry {
+    pub fn new(entities: Vec<Box<dyn Entity>>) -> Self {
+        let bounds: Vec<Aabb> = entities.iter().map(|entity| entity.bounds()).collect();
+        Geometry {
+            bvh: Bvh::new(&bounds),
+            entities,
+        }
+    }
+
+    // The entity hit first, with the hit on it folded in as a part of the geometry
+    fn hit(&self, ray: &Ray) -> Option<Hit> {
+        let (i, hit) = self.bvh.nearest(ray, |i| self.entities[i].hit(ray))?;
+        Some(hit.within(i, self.entities.len()))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct InstanceSpec {
+    pub geometry: String,
+    pub material: Option<Material>,
+}
+
+// One use of a shared geometry. It is placed like any other entity by its `transform`, and its
+// material, if given, replaces those of the geometry's entities. It is sampled as a light by
+// choosing one of its emissive entities in proportion to its area.
+pub struct Instance {
+    geometry: Rc<Geometry>,
+    material: Option<Material>,
+    areas: Distribution1D, // over the geometry's entities, zero for those that aren't lights
+    area: f64,
+}
+
+impl Instance {
+    pub fn new(geometry: Rc<Geometry>, material: Option<Material>) -> Self {
+        let mut instance = Instance {
+            geometry,
+            material,
+            areas: Distribution1D::new(vec![]),
+            area: 0.,
+        };
+        let areas: Vec<f64> = (0..instance.geometry.entities.len())
+            .map(|i| {
+                let entity = &instance.geometry.entities[i];
+                if entity.can_sample() && !instance.child_emission(i).is_black() {
+                    entity.area()
+                } else {
+                    0.
+                }
+            })
+            .collect();
+        instance.area = areas.iter().sum();
+        instance.areas = Distribution1D::new(areas);
+        instance
+    }
+
+    // Light given off by one of the geometry's entities, which the material replaces if given
+    fn child_emission(&self, index: usize) -> Colour {
+        match &self.material {
+            Some(material) => material.emission,
+            None => self.geometry.entities[index].emission(),
+        }
+    }
+}
+
+impl Entity for Instance {
+    fn intersection(&self, ray: &Ray) -> IntersectionResult {
+        match self.geometry.hit(ray) {
+            Some(hit) => IntersectionResult::One(hit.t),
+            None => IntersectionResult::No,
+        }
+    }
+
+    fn hit(&self, ray: &Ray) -> Option<Hit> {
+        self.geometry.hit(ray)
+    }
+
+    fn surface(&self, ray: &Ray, hit: Hit) -> SurfaceInteraction<'_> {
+        let entities = &self.geometry.entities;
+        let (i, hit) = hit.child(entities.len());
+        let surface = entities[i].surface(ray, hit);
+        SurfaceInteraction {
+            material: self.material.as_ref().unwrap_or(surface.material),
+            ..surface
+        }
+    }
+
+    fn bounds(&self) -> Aabb {
+        self.geometry.bvh.bounds()
+    }
+
+    fn position(&self) -> Vector {
+        self.bounds().centre()
+    }
+
+    // The emissive entities' light, averaged over their area
+    fn emission(&self) -> Colour {
+        if self.area == 0. {
+            return Colour::black();
+        }
+        let mut total = Colour::black();
+        for i in 0..self.areas.len() {
+            total += self.child_emission(i) * self.areas.chance(i) as f32;
+        }
+        total
+    }
+
+    fn can_sample(&self) -> bool {
+        self.area > 0.
+    }
+
+    fn area(&self) -> f64 {
+        self.area
+    }
+
+    fn sample(&self, reference: Vector, u: (f64, f64)) -> Option<SurfaceSample> {
+        if self.area == 0. {
+            return None;
+        }
+        let (x, _, i) = self.areas.sample(u.0);
+        // Reuse the part of u.0 within the chosen entity's share
+        let along = (x * self.areas.len() as f64 - i as f64).clamp(0., 1.);
+        let sample = self.geometry.entities[i].sample(reference, (along, u.1))?;
+        Some(SurfaceSample {
+            pdf: sample.pdf * self.areas.chance(i),
+            emission: self.child_emission(i),
+            ..sample
+        })
+    }
+
+    // The entity the point is on is the one seen looking from the reference towards it. Were
+    // another in the way, the light couldn't reach the reference anyway.
+    fn pdf(&self, reference: Vector, point: Vector, normal: Vector) -> f64 {
+        if self.area == 0. {
+            return 0.;
+        }
+        let ray = Ray::new(reference, (point - reference).normalised());
+        let Some(hit) = self.geometry.hit(&ray) else {
+            return 0.;
+        };
+        let (i, _) = hit.child(self.geometry.entities.len());
+        let chance = self.areas.chance(i);
+        if chance == 0. {
+            return 0.;
+        }
+        self.geometry.entities[i].pdf(reference, point, normal) * chance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::Rng;
+    use crate::world::World;
+
+    #[test]
+    fn instances_share_geometry() {
+        let toml_string = r#"
+        [materials.blue]
+        colour = [0, 0, 1]
+
+        [[geometry.pair]]
+        type = "sphere"
+        position = [-1, 0, 0]
+        radius = 0.5
+        material = {colour = [1, 0, 0]}
+
+        [[geometry.pair]]
+        type = "sphere"
+        position = [1, 0, 0]
+        radius = 0.5
+        material = {colour = [0, 1, 0]}
+
+        [[entities]]
+        type = "instance"
+        geometry = "pair"
+        transform = {translate = [0, 0, 5]}
+
+        [[entities]]
+        type = "instance"
+        geometry = "pair"
+        material = "blue"
+        transform = {rotate = {axis = [0, 1, 0], angle = 90}, translate = [0, 3, 5]}
+
+        [[entities]]
+        type = "instance"
+        geometry = "missing"
+        "#;
+        let world = World::from_toml(&toml_string.parse::<toml::Table>().unwrap());
+        assert_eq!(world.entities.len(), 2);
+
+        let up = Vector::new(0., 0., 1.);
+        let colour = |origin: Vector| {
+            let result = world.find_nearest(&Ray::new(origin, up));
+            assert!(result.hit);
+            result
+                .material
+                .bsdf(&result.texture_coords())
+                .evaluate(up, up)
+        };
+        // Each sphere of the first instance keeps its own material
+        let left = colour(Vector::new(-1., 0., 0.));
+        assert!(left.r > 0. && left.g == 0.);
+        let right = colour(Vector::new(1., 0., 0.));
+        assert!(right.g > 0. && right.r == 0.);
+        // The second is turned so its spheres line up along z, and painted blue
+        let result = world.find_nearest(&Ray::new(Vector::new(0., 3., 0.), up));
+        assert!((result.distance - 3.5).abs() < 1e-9);
+        assert!((result.normal - Vector::new(0., 0., -1.)).length() < 1e-9);
+        let blue = colour(Vector::new(0., 3., 0.));
+        assert!(blue.b > 0. && blue.r == 0.);
+    }
+
+    #[test]
+    fn emissive_instances_are_sampled() {
+        let toml_string = r#"
+        [[geometry.lamps]]
+        type = "sphere"
+        position = [-2, 0, 0]
+        radius = 1
+        material = {emission = [1, 0, 0]}
+
+        [[geometry.lamps]]
+        type = "sphere"
+        position = [2, 0, 0]
+        radius = 2
+        material = {emission = [0, 1, 0]}
+
+        [[geometry.lamps]]
+        type = "sphere"
+        position = [0, 4, 0]
+        radius = 1
+        material = {colour = [1, 1, 1]}
+
+        [[entities]]
+        type = "instance"
+        geometry = "lamps"
+        transform = {translate = [0, 0, 10]}
+
+        [[entities]]
+        type = "instance"
+        geometry = "lamps"
+        material = {emission = [0, 0, 1]}
+
+        [[entities]]
+        type = "instance"
+        geometry = "lamps"
+        material = {colour = [1, 1, 1]}
+        "#;
+        let world = World::from_toml(&toml_string.parse::<toml::Table>().unwrap());
+        // The plain material leaves the third with nothing to give off
+        assert_eq!(world.emitters(), &[0, 1]);
+
+        let reference = Vector::new(0., -5., 5.);
+        let mut rng = Rng::new(3);
+        let (mut reds, mut greens) = (0, 0);
+        for _ in 0..1000 {
+            let sample = world.entities[0].sample(reference, rng.next_2d()).unwrap();
+            // Only the emissive spheres are chosen, each keeping its own light
+            if sample.emission == Colour::new(1., 0., 0.) {
+                assert!((sample.position - Vector::new(-2., 0., 10.)).length() < 1. + 1e-9);
+                reds += 1;
+            } else {
+                assert_eq!(sample.emission, Colour::new(0., 1., 0.));
+                assert!((sample.position - Vector::new(2., 0., 10.)).length() < 2. + 1e-9);
+                greens += 1;
+            }
+            let pdf = world.entities[0].pdf(reference, sample.position, sample.normal);
+            assert!((pdf - sample.pdf).abs() < 1e-6 * sample.pdf);
+        }
+        // The larger sphere has four times the area
+        assert!((greens as f64 / reds as f64 - 4.).abs() < 0.5);
+
+        // The material given to the instance replaces the spheres' light, and lights all three
+        let sample = world.entities[1].sample(reference, (0.99, 0.5)).unwrap();
+        assert_eq!(sample.emission, Colour::new(0., 0., 1.));
+        assert!((sample.position - Vector::new(0., 4., 0.)).length() < 1. + 1e-9);
+    }
+}
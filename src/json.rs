@@ -0,0 +1,252 @@
+// Resources:
+// JSON grammar: https://www.rfc-editor.org/rfc/rfc8259
+
+use std::error::Error;
+
+// Parse JSON into a `toml::Value`, so that the same serde derives read both. TOML has no null,
+// so object members and array items that are null are left out.
+pub fn parse(text: &str) -> Result<toml::Value, Box<dyn Error>> {
+    let mut parser = Parser {
+        bytes: text.as_bytes(),
+        pos: 0,
+    };
+    let value = parser.value()?.ok_or("the document is null")?;
+    parser.skip_whitespace();
+    if parser.pos != parser.bytes.len() {
+        return Err(parser.error("unexpected text after the document"));
+    }
+    Ok(value)
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> Box<dyn Error> {
+        format!("{} at byte {}", message, self.pos).into()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.bytes.get(self.pos) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), Box<dyn Error>> {
+        self.skip_whitespace();
+        if self.bytes.get(self.pos) != Some(&byte) {
+            return Err(self.error(&format!("expected '{}'", byte as char)));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn literal(&mut self, word: &str) -> Result<(), Box<dyn Error>> {
+        if !self.bytes[self.pos..].starts_with(word.as_bytes()) {
+            return Err(self.error("unexpected character"));
+        }
+        self.pos += word.len();
+        Ok(())
+    }
+
+    // None for null
+    fn value(&mut self) -> Result<Option<toml::Value>, Box<dyn Error>> {
+        self.skip_whitespace();
+        let value = match self.bytes.get(self.pos) {
+            Some(b'{') => {
+                self.pos += 1;
+                let mut table = toml::Table::new();
+                self.skip_whitespace();
+                if self.bytes.get(self.pos) == Some(&b'}') {
+                    self.pos += 1;
+                } else {
+                    loop {
+                        self.expect(b'"')?;
+                        let key = self.string()?;
+                        self.expect(b':')?;
+                        if let Some(value) = self.value()? {
+                            table.insert(key, value);
+                        }
+                        if !self.more(b'}')? {
+                            break;
+                        }
+                    }
+                }
+                toml::Value::Table(table)
+            }
+            Some(b'[') => {
+                self.pos += 1;
+                let mut array = Vec::new();
+                self.skip_whitespace();
+                if self.bytes.get(self.pos) == Some(&b']') {
+                    self.pos += 1;
+                } else {
+                    loop {
+                        array.extend(self.value()?);
+                        if !self.more(b']')? {
+                            break;
+                        }
+                    }
+                }
+                toml::Value::Array(array)
+            }
+            Some(b'"') => {
+                self.pos += 1;
+                toml::Value::String(self.string()?)
+            }
+            Some(b't') => {
+                self.literal("true")?;
+                toml::Value::Boolean(true)
+            }
+            Some(b'f') => {
+                self.literal("false")?;
+                toml::Value::Boolean(false)
+            }
+            Some(b'n') => {
+                self.literal("null")?;
+                return Ok(None);
+            }
+            Some(b'-' | b'0'..=b'9') => self.number()?,
+            Some(_) => return Err(self.error("unexpected character")),
+            None => return Err(self.error("unexpected end of document")),
+        };
+        Ok(Some(value))
+    }
+
+    // After an item in an object or array: whether another follows, or it was the last before
+    // `close`
+    fn more(&mut self, close: u8) -> Result<bool, Box<dyn Error>> {
+        self.skip_whitespace();
+        match self.bytes.get(self.pos) {
+            Some(b',') => {
+                self.pos += 1;
+                Ok(true)
+            }
+            Some(&b) if b == close => {
+                self.pos += 1;
+                Ok(false)
+            }
+            _ => Err(self.error(&format!("expected ',' or '{}'", close as char))),
+        }
+    }
+
+    fn number(&mut self) -> Result<toml::Value, Box<dyn Error>> {
+        let start = self.pos;
+        while let Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9') = self.bytes.get(self.pos) {
+            self.pos += 1;
+        }
+        let text = std::str::from_utf8(&self.bytes[start..self.pos])?;
+        if let Ok(integer) = text.parse::<i64>() {
+            return Ok(toml::Value::Integer(integer));
+        }
+        text.parse::<f64>()
+            .map(toml::Value::Float)
+            .map_err(|_| self.error(&format!("bad number '{}'", text)))
+    }
+
+    // The rest of a string whose opening quote has been read
+    fn string(&mut self) -> Result<String, Box<dyn Error>> {
+        let mut string = String::new();
+        loop {
+            let start = self.pos;
+            while let Some(&b) = self.bytes.get(self.pos) {
+                if b == b'"' || b == b'\\' {
+                    break;
+                }
+                self.pos += 1;
+            }
+            string += std::str::from_utf8(&self.bytes[start..self.pos])?;
+            match self.bytes.get(self.pos) {
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(string);
+                }
+                Some(_) => {
+                    let escape = *self
+                        .bytes
+                        .get(self.pos + 1)
+                        .ok_or_else(|| self.error("unterminated string"))?;
+                    self.pos += 2;
+                    string.push(match escape {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => self.code_point()?,
+                        _ => return Err(self.error("unknown escape")),
+                    });
+                }
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+
+    // The character of a \u escape, which may be the first half of a surrogate pair
+    fn code_point(&mut self) -> Result<char, Box<dyn Error>> {
+        let high = self.hex()?;
+        let code = if (0xd800..0xdc00).contains(&high) {
+            self.literal("\\u")?;
+            let low = self.hex()?;
+            0x10000 + ((high - 0xd800) << 10) + (low.wrapping_sub(0xdc00) & 0x3ff)
+        } else {
+            high
+        };
+        char::from_u32(code).ok_or_else(|| self.error("bad \\u escape"))
+    }
+
+    fn hex(&mut self) -> Result<u32, Box<dyn Error>> {
+        let digits = self
+            .bytes
+            .get(self.pos..self.pos + 4)
+            .ok_or("unterminated escape")?;
+        self.pos += 4;
+        Ok(u32::from_str_radix(std::str::from_utf8(digits)?, 16)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_values() {
+        let value = parse(
+            r#" {"name": "Box\né😀", "count": 3, "scale": [1.5, -2e1, 0],
+                "nested": {"on": true, "off": false, "gone": null}, "empty": [], "none": {},
+                "escaped": "\u00e9\ud83d\ude00 \"\/"} "#,
+        )
+        .unwrap();
+        assert_eq!(value["name"].as_str(), Some("Box\né😀"));
+        assert_eq!(value["count"].as_integer(), Some(3));
+        let scale = value["scale"].as_array().unwrap();
+        assert_eq!(scale[0].as_float(), Some(1.5));
+        assert_eq!(scale[1].as_float(), Some(-20.));
+        assert_eq!(scale[2].as_integer(), Some(0));
+        assert_eq!(value["nested"]["on"].as_bool(), Some(true));
+        assert!(value["nested"].get("gone").is_none());
+        assert!(value["empty"].as_array().unwrap().is_empty());
+        assert_eq!(value["escaped"].as_str(), Some("é😀 \"/"));
+    }
+
+    #[test]
+    fn rejects_bad_documents() {
+        for text in [
+            "",
+            "{",
+            r#"{"a" 1}"#,
+            "[1, 2,]",
+            "[1] 2",
+            r#""unterminated"#,
+            "nul",
+            "null",
+        ] {
+            assert!(parse(text).is_err(), "{}", text);
+        }
+    }
+}
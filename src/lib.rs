@@ -0,0 +1,41 @@
+// The renderer as a library, so that other crates can add their own material models (see
+// `material::Material::register`) or drive rendering themselves. main.rs is the command line
+// front end.
+
+#![allow(clippy::needless_return)]
+
+pub mod background;
+pub mod bezier;
+pub mod bsdf;
+pub mod bvh;
+pub mod colour;
+pub mod csg;
+pub mod cuboid;
+pub mod curve;
+pub mod environment;
+pub mod gltf;
+pub mod hair;
+pub mod heightfield;
+pub mod image;
+pub mod instance;
+mod json;
+pub mod light;
+pub mod material;
+pub mod mesh;
+pub mod metaball;
+mod noise;
+mod png;
+mod polynomial;
+pub mod principled;
+pub mod quadric;
+pub mod ray;
+pub mod raytrace;
+pub mod sampling;
+pub mod sdf;
+pub mod sky;
+#[cfg(test)]
+mod test_dir;
+pub mod texture;
+pub mod torus;
+pub mod vector;
+pub mod world;
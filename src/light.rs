@@ -0,0 +1,387 @@
+use core::f64;
+
+use serde::Deserialize;
+
+use crate::colour::Colour;
+use crate::ray::Ray;
+use crate::sampling;
+use crate::vector::Vector;
+use crate::world::{intersect_sphere, sample_sphere, sphere_pdf};
+
+fn default_intensity() -> f64 {
+    1.
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct PointLight {
+    pub position: Vector,
+    pub intensity: f64,
+    #[serde(default = "Colour::white")]
+    pub colour: Colour,
+}
+
+// Rectangle centred on `position` facing `normal`. The width runs horizontally, i.e. perpendicular
+// to both the normal and the y axis (or the x axis when the light faces straight up or down).
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct RectLight {
+    pub position: Vector,
+    pub normal: Vector,
+    pub size: [f64; 2],
+    #[serde(default = "default_intensity")]
+    pub intensity: f64,
+    #[serde(default = "Colour::white")]
+    pub colour: Colour,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct DiskLight {
+    pub position: Vector,
+    pub normal: Vector,
+    pub radius: f64,
+    #[serde(default = "default_intensity")]
+    pub intensity: f64,
+    #[serde(default = "Colour::white")]
+    pub colour: Colour,
+}
+
+// Infinitely distant light such as the sun. `direction` points towards the light.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct DirectionalLight {
+    pub direction: Vector,
+    #[serde(default = "default_intensity")]
+    pub intensity: f64,
+    #[serde(default = "Colour::white")]
+    pub colour: Colour,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct SphereLight {
+    pub position: Vector,
+    pub radius: f64,
+    #[serde(default = "default_intensity")]
+    pub intensity: f64,
+    #[serde(default = "Colour::white")]
+    pub colour: Colour,
+}
+
+// A direction towards a point chosen on a light, how far away that point is, the radiance it sends
+// back towards the reference point and the density of choosing it with respect to solid angle
+// (1 for delta lights)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LightSample {
+    pub direction: Vector,
+    pub distance: f64,
+    pub pdf: f64,
+    pub radiance: Colour,
+}
+
+impl LightSample {
+    pub fn towards(reference: Vector, position: Vector, pdf: f64, radiance: Colour) -> Self {
+        let to_light = position - reference;
+        let distance = to_light.length();
+        LightSample {
+            direction: to_light / distance,
+            distance,
+            pdf,
+            radiance,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Light {
+    Point(PointLight),
+    Directional(DirectionalLight),
+    Rect(RectLight),
+    Disk(DiskLight),
+    Sphere(SphereLight),
+}
+
+impl Default for Light {
+    fn default() -> Self {
+        Light::Point(PointLight {
+            position: Vector::new(-1., 1., 1.),
+            intensity: 1.,
+            colour: Colour::white(),
+        })
+    }
+}
+
+impl Light {
+    // Lights without a `type` are point lights, as in the original single `[light]` table
+    pub fn from_toml(value: &toml::Value) -> Option<Self> {
+        let light_type = match value.get("type") {
+            Some(toml::Value::String(s)) => s.to_lowercase(),
+            Some(_) => return None,
+            None => "point".to_string(),
+        };
+
+        match light_type.as_str() {
+            "point" => toml::Value::try_into::<PointLight>(value.clone())
+                .ok()
+                .map(Light::Point),
+            "directional" => toml::Value::try_into::<DirectionalLight>(value.clone())
+                .ok()
+                .map(|mut directional| {
+                    directional.direction.normalise();
+                    Light::Directional(directional)
+                }),
+            "rect" => toml::Value::try_into::<RectLight>(value.clone())
+                .ok()
+                .map(|mut rect| {
+                    rect.normal.normalise();
+                    Light::Rect(rect)
+                }),
+            "disk" => toml::Value::try_into::<DiskLight>(value.clone())
+                .ok()
+                .map(|mut disk| {
+                    disk.normal.normalise();
+                    Light::Disk(disk)
+                }),
+            "sphere" => toml::Value::try_into::<SphereLight>(value.clone())
+                .ok()
+                .map(Light::Sphere),
+            _ => None,
+        }
+    }
+
+    pub fn intensity(&self) -> f64 {
+        match self {
+            Light::Point(l) => l.intensity,
+            Light::Directional(l) => l.intensity,
+            Light::Rect(l) => l.intensity,
+            Light::Disk(l) => l.intensity,
+            Light::Sphere(l) => l.intensity,
+        }
+    }
+
+    // Radiance leaving the light's surface
+    fn radiance(&self) -> Colour {
+        match self {
+            Light::Point(l) => l.colour * l.intensity as f32,
+            Light::Directional(l) => l.colour * l.intensity as f32,
+            Light::Rect(l) => l.colour * l.intensity as f32,
+            Light::Disk(l) => l.colour * l.intensity as f32,
+            Light::Sphere(l) => l.colour * l.intensity as f32,
+        }
+    }
+
+    // Radiance seen when looking at the light along `direction` at a point with `normal`.
+    // Rect and disk lights only emit from their front face.
+    pub fn emitted(&self, normal: Vector, direction: Vector) -> Colour {
+        match self {
+            Light::Rect(_) | Light::Disk(_) if normal.dot(&direction) >= 0. => Colour::black(),
+            _ => self.radiance(),
+        }
+    }
+
+    // Distance along the ray to the light's surface, and the surface normal there.
+    // Delta lights can't be hit.
+    pub fn intersection(&self, ray: &Ray) -> Option<(f64, Vector)> {
+        match self {
+            Light::Point(_) | Light::Directional(_) => None,
+            Light::Rect(rect) => {
+                let (t, local) = intersect_plane(rect.position, rect.normal, ray)?;
+                let (u, v) = rect_axes(rect.normal);
+                let inside = local.dot(&u).abs() <= rect.size[0] / 2.
+                    && local.dot(&v).abs() <= rect.size[1] / 2.;
+                inside.then_some((t, rect.normal))
+            }
+            Light::Disk(disk) => {
+                let (t, local) = intersect_plane(disk.position, disk.normal, ray)?;
+                (local.abs_squared() <= disk.radius * disk.radius).then_some((t, disk.normal))
+            }
+            Light::Sphere(sphere) => {
+                let t = intersect_sphere(sphere.position, sphere.radius, ray).nearest()?;
+                Some((t, (ray.at(t) - sphere.position).normalised()))
+            }
+        }
+    }
+
+    pub fn sample(&self, reference: Vector, u: (f64, f64)) -> Option<LightSample> {
+        let (position, normal, pdf) = match self {
+            Light::Point(point) => {
+                // Point lights deliver `intensity` at normal incidence with no distance falloff,
+                // which is what the original renderer did
+                let radiance = self.radiance() * f64::consts::PI as f32;
+                return Some(LightSample::towards(
+                    reference,
+                    point.position,
+                    1.,
+                    radiance,
+                ));
+            }
+            Light::Directional(directional) => {
+                // Directional lights follow the same convention as point lights
+                return Some(LightSample {
+                    direction: directional.direction,
+                    distance: f64::INFINITY,
+                    pdf: 1.,
+                    radiance: self.radiance() * f64::consts::PI as f32,
+                });
+            }
+            Light::Rect(rect) => {
+                let (axis_u, axis_v) = rect_axes(rect.normal);
+                let position = rect.position
+                    + axis_u * ((u.0 - 0.5) * rect.size[0])
+                    + axis_v * ((u.1 - 0.5) * rect.size[1]);
+                (
+                    position,
+                    rect.normal,
+                    self.pdf(reference, position, rect.normal),
+                )
+            }
+            Light::Disk(disk) => {
+                let (axis_u, axis_v) = sampling::orthonormal_basis(disk.normal);
+                let r = disk.radius * u.0.sqrt();
+                let phi = 2. * f64::consts::PI * u.1;
+                let position = disk.position + axis_u * (r * phi.cos()) + axis_v * (r * phi.sin());
+                (
+                    position,
+                    disk.normal,
+                    self.pdf(reference, position, disk.normal),
+                )
+            }
+            Light::Sphere(sphere) => {
+                let sample = sample_sphere(sphere.position, sphere.radius, reference, u);
+                (sample.position, sample.normal, sample.pdf)
+            }
+        };
+
+        if pdf <= 0. {
+            return None;
+        }
+
+        let radiance = self.emitted(normal, position - reference);
+        Some(LightSample::towards(reference, position, pdf, radiance))
+    }
+
+    // Solid angle density with which `sample` would have picked `point` from `reference`
+    pub fn pdf(&self, reference: Vector, point: Vector, normal: Vector) -> f64 {
+        match self {
+            Light::Point(_) | Light::Directional(_) => 0.,
+            Light::Rect(rect) => {
+                if (reference - rect.position).dot(&rect.normal) <= 0. {
+                    return 0.;
+                }
+                let area = rect.size[0] * rect.size[1];
+                sampling::area_to_solid_angle_pdf(1. / area, reference, point, normal)
+            }
+            Light::Disk(disk) => {
+                if (reference - disk.position).dot(&disk.normal) <= 0. {
+                    return 0.;
+                }
+                let area = f64::consts::PI * disk.radius * disk.radius;
+                sampling::area_to_solid_angle_pdf(1. / area, reference, point, normal)
+            }
+            Light::Sphere(sphere) => {
+                sphere_pdf(sphere.position, sphere.radius, reference, point, normal)
+            }
+        }
+    }
+
+    // Delta lights can only be reached by explicit sampling, never by a BSDF-sampled ray
+    pub fn is_delta(&self) -> bool {
+        matches!(self, Light::Point(_) | Light::Directional(_))
+    }
+}
+
+fn rect_axes(normal: Vector) -> (Vector, Vector) {
+    let up = if normal.y.abs() < 0.999 {
+        Vector::new(0., 1., 0.)
+    } else {
+        Vector::new(1., 0., 0.)
+    };
+    let u = up.cross(&normal).normalised();
+    let v = normal.cross(&u);
+    (u, v)
+}
+
+// Distance to the plane through `position`, and the hit point relative to `position`
+fn intersect_plane(position: Vector, normal: Vector, ray: &Ray) -> Option<(f64, Vector)> {
+    let denominator = ray.direction.dot(&normal);
+    if denominator.abs() < 1e-12 {
+        return None;
+    }
+    let t = (position - ray.origin).dot(&normal) / denominator;
+    if t <= 0. {
+        return None;
+    }
+    Some((t, ray.at(t) - position))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Option<Light> {
+        Light::from_toml(&toml::Value::Table(s.parse::<toml::Table>().unwrap()))
+    }
+
+    #[test]
+    fn untyped_light_is_point() {
+        let light = parse("position = [0, 1, 0]\nintensity = 2").unwrap();
+        assert!(light.is_delta());
+        assert_eq!(light.intensity(), 2.);
+    }
+
+    #[test]
+    fn rect_light_sampling_and_intersection_agree() {
+        let light = parse(
+            r#"
+            type = "rect"
+            position = [0, 2, 0]
+            normal = [0, -2, 0]
+            size = [2, 1]
+            colour = [1, 0.5, 0]
+            "#,
+        )
+        .unwrap();
+
+        let reference = Vector::zero();
+        let sample = light.sample(reference, (0.25, 0.75)).unwrap();
+        assert_eq!(sample.radiance, Colour::new(1., 0.5, 0.));
+
+        let ray = Ray::new(reference, sample.direction);
+        let (t, normal) = light.intersection(&ray).unwrap();
+        assert!((t - sample.distance).abs() < 1e-9);
+        assert_eq!(normal, Vector::new(0., -1., 0.));
+        assert!((light.pdf(reference, ray.at(t), normal) - sample.pdf).abs() < 1e-9);
+
+        // Nothing is emitted from behind
+        assert!(light.sample(Vector::new(0., 3., 0.), (0.5, 0.5)).is_none());
+    }
+
+    #[test]
+    fn disk_light_samples_stay_on_disk() {
+        let light = parse(
+            r#"
+            type = "disk"
+            position = [0, 0, 5]
+            normal = [0, 0, -1]
+            radius = 0.5
+            "#,
+        )
+        .unwrap();
+        let mut rng = sampling::Rng::new(3);
+        for _ in 0..100 {
+            let sample = light.sample(Vector::zero(), rng.next_2d()).unwrap();
+            let position = sample.direction * sample.distance;
+            assert!((position - Vector::new(0., 0., 5.)).length() <= 0.5 + 1e-9);
+        }
+    }
+
+    #[test]
+    fn directional_light_is_infinitely_far() {
+        let light = parse("type = \"directional\"\ndirection = [0, -2, 0]").unwrap();
+        let sample = light.sample(Vector::new(1., 2., 3.), (0.5, 0.5)).unwrap();
+        assert_eq!(sample.direction, Vector::new(0., -1., 0.));
+        assert_eq!(sample.distance, f64::INFINITY);
+        assert!(light.is_delta());
+    }
+
+    #[test]
+    fn unknown_light_type_fails() {
+        assert!(parse("type = \"laser\"\nposition = [0, 0, 0]").is_none());
+    }
+}
@@ -1,16 +1,15 @@
-mod colour;
-mod image;
-mod raytrace;
-mod vector;
-mod world;
+#![allow(clippy::needless_return)]
 
 use std::env;
 use std::error::Error;
 use std::io::Read;
+use std::path::Path;
 use std::{fs::File, io::Write};
 
-use image::BMPImage;
-use world::World;
+use raytrace::gltf;
+use raytrace::image::BMPImage;
+use raytrace::raytrace::render;
+use raytrace::world::World;
 
 const USAGE: &str = "USAGE: ./raytrace [world_spec] [output_file]";
 
@@ -40,15 +39,15 @@ fn main() {
         return;
     }
 
-    match open_and_parse_toml(world_spec_filename.unwrap().as_str()) {
+    match open_scene(world_spec_filename.unwrap().as_str()) {
         Ok(world) => {
-            let image = raytrace::render(&world, 400, 400);
+            let image = render(&world, 400, 400);
 
             let bmpimage = BMPImage::from(image);
 
             File::create(output_filename.unwrap())
                 .unwrap()
-                .write(bmpimage.as_bytes().as_slice())
+                .write_all(bmpimage.as_bytes().as_slice())
                 .unwrap();
         }
         Err(e) => {
@@ -57,10 +56,22 @@ fn main() {
     }
 }
 
+// A TOML scene, or a whole glTF scene by its extension
+fn open_scene(filename: &str) -> Result<World, Box<dyn Error>> {
+    let extension = Path::new(filename)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    match extension.as_deref() {
+        Some("gltf" | "glb") => gltf::load_world(Path::new(filename)),
+        _ => open_and_parse_toml(filename),
+    }
+}
+
 fn open_and_parse_toml(filename: &str) -> Result<World, Box<dyn Error>> {
     let mut file = File::open(filename)?;
     let mut buf = String::new();
     file.read_to_string(&mut buf)?;
     let table = buf.parse::<toml::Table>()?;
-    return Ok(World::from_toml(&table));
+    let scene_dir = Path::new(filename).parent().unwrap_or(Path::new(""));
+    return Ok(World::from_toml_in_dir(&table, scene_dir));
 }
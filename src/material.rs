@@ -0,0 +1,726 @@
+use crate::bsdf::{self, Bsdf, Conductor, Dielectric, Diffuse, TrowbridgeReitz};
+use crate::colour::Colour;
+use crate::hair::Hair;
+use crate::principled::Principled;
+use crate::sampling;
+use crate::texture::{FloatTexture, Texture, TextureCoords, TextureLoader, VertexColour};
+use crate::vector::Vector;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::Debug;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, LazyLock, RwLock};
+
+// Turns the textures of a material into a BSDF at each point shaded. The built-in models are
+// chosen by `type` in the scene file; other models can be added by implementing this and
+// passing them to `Material::new`, or registering a `type` for them with `Material::register`.
+pub trait MaterialModel: Debug {
+    fn bsdf(&self, coords: &TextureCoords) -> Box<dyn Bsdf>;
+
+    // Load any image textures, relative to the scene file
+    fn load_textures(&mut self, _loader: &mut TextureLoader) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+fn default_bump_scale() -> f64 {
+    1.
+}
+
+// Step in UV used to find the slope of a bump map
+const BUMP_DELTA: f64 = 1e-3;
+
+// White, except on meshes with vertex colours, which then show through
+fn default_colour() -> Texture {
+    Texture::Vertex(VertexColour::Vertex)
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct DiffuseMaterial {
+    #[serde(default = "default_colour")]
+    pub colour: Texture,
+}
+
+impl MaterialModel for DiffuseMaterial {
+    fn bsdf(&self, coords: &TextureCoords) -> Box<dyn Bsdf> {
+        Box::new(Diffuse {
+            albedo: self.colour.evaluate(coords),
+        })
+    }
+
+    fn load_textures(&mut self, loader: &mut TextureLoader) -> Result<(), Box<dyn Error>> {
+        self.colour.load(loader)
+    }
+}
+
+// Metal, with a complex IOR
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConductorMaterial {
+    pub eta: Colour,
+    pub k: Colour,
+    pub roughness: f64,
+}
+
+impl MaterialModel for ConductorMaterial {
+    fn bsdf(&self, _coords: &TextureCoords) -> Box<dyn Bsdf> {
+        Box::new(Conductor {
+            eta: self.eta,
+            k: self.k,
+            distribution: TrowbridgeReitz::from_roughness(self.roughness),
+        })
+    }
+}
+
+// Either a named metal ("gold", "copper", "aluminium" or "silver") or its eta and k
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct ConductorSpec {
+    metal: Option<String>,
+    eta: Option<Colour>,
+    k: Option<Colour>,
+    #[serde(default)]
+    roughness: f64,
+}
+
+fn default_ior() -> f64 {
+    1.5
+}
+
+// Glass and other transparent materials; the surface normal points outside
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct DielectricMaterial {
+    #[serde(default = "default_ior")]
+    pub ior: f64,
+    #[serde(default)]
+    pub roughness: f64,
+}
+
+impl MaterialModel for DielectricMaterial {
+    fn bsdf(&self, _coords: &TextureCoords) -> Box<dyn Bsdf> {
+        Box::new(Dielectric {
+            eta: self.ior,
+            distribution: TrowbridgeReitz::from_roughness(self.roughness),
+        })
+    }
+}
+
+fn default_base_colour() -> Texture {
+    Colour::new(0.8, 0.8, 0.8).into()
+}
+
+fn default_half() -> FloatTexture {
+    0.5.into()
+}
+
+fn default_clearcoat_roughness() -> FloatTexture {
+    0.03.into()
+}
+
+// Disney-style parameters, each either a number (a colour for `base_colour`) or a texture. All
+// are in [0, 1] apart from `ior`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct PrincipledMaterial {
+    #[serde(default = "default_base_colour", alias = "colour")]
+    pub base_colour: Texture,
+    #[serde(default)]
+    pub metallic: FloatTexture,
+    #[serde(default = "default_half")]
+    pub roughness: FloatTexture,
+    #[serde(default = "default_half")]
+    pub specular: FloatTexture,
+    #[serde(default)]
+    pub sheen: FloatTexture,
+    #[serde(default = "default_half")]
+    pub sheen_tint: FloatTexture,
+    #[serde(default)]
+    pub clearcoat: FloatTexture,
+    #[serde(default = "default_clearcoat_roughness")]
+    pub clearcoat_roughness: FloatTexture,
+    #[serde(default)]
+    pub transmission: FloatTexture,
+    #[serde(default = "default_ior")]
+    pub ior: f64,
+}
+
+impl Default for PrincipledMaterial {
+    fn default() -> Self {
+        PrincipledMaterial {
+            base_colour: default_base_colour(),
+            metallic: FloatTexture::default(),
+            roughness: default_half(),
+            specular: default_half(),
+            sheen: FloatTexture::default(),
+            sheen_tint: default_half(),
+            clearcoat: FloatTexture::default(),
+            clearcoat_roughness: default_clearcoat_roughness(),
+            transmission: FloatTexture::default(),
+            ior: default_ior(),
+        }
+    }
+}
+
+impl PrincipledMaterial {
+    fn textures_mut(&mut self) -> [&mut FloatTexture; 8] {
+        [
+            &mut self.metallic,
+            &mut self.roughness,
+            &mut self.specular,
+            &mut self.sheen,
+            &mut self.sheen_tint,
+            &mut self.clearcoat,
+            &mut self.clearcoat_roughness,
+            &mut self.transmission,
+        ]
+    }
+}
+
+impl MaterialModel for PrincipledMaterial {
+    fn bsdf(&self, coords: &TextureCoords) -> Box<dyn Bsdf> {
+        let value = |texture: &FloatTexture| texture.evaluate(coords).clamp(0., 1.);
+        Box::new(Principled {
+            base_colour: self.base_colour.evaluate(coords),
+            metallic: value(&self.metallic),
+            roughness: value(&self.roughness),
+            specular: value(&self.specular),
+            sheen: value(&self.sheen),
+            sheen_tint: value(&self.sheen_tint),
+            clearcoat: value(&self.clearcoat),
+            clearcoat_roughness: value(&self.clearcoat_roughness),
+            transmission: value(&self.transmission),
+            ior: self.ior,
+        })
+    }
+
+    fn load_textures(&mut self, loader: &mut TextureLoader) -> Result<(), Box<dyn Error>> {
+        self.base_colour.load(loader)?;
+        for texture in self.textures_mut() {
+            texture.load(loader)?;
+        }
+        Ok(())
+    }
+}
+
+fn default_hair_specular() -> f64 {
+    0.2
+}
+
+fn default_shininess() -> f64 {
+    40.
+}
+
+// Hair and fur for curves, which run along dpdu. `specular` is the part of the light in the
+// highlight and `shininess` how tight it is.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct HairMaterial {
+    #[serde(default = "default_colour")]
+    pub colour: Texture,
+    #[serde(default = "default_hair_specular")]
+    pub specular: f64,
+    #[serde(default = "default_shininess")]
+    pub shininess: f64,
+}
+
+impl MaterialModel for HairMaterial {
+    fn bsdf(&self, coords: &TextureCoords) -> Box<dyn Bsdf> {
+        Box::new(Hair {
+            colour: self.colour.evaluate(coords),
+            specular: self.specular.clamp(0., 1.),
+            shininess: self.shininess,
+        })
+    }
+
+    fn load_textures(&mut self, loader: &mut TextureLoader) -> Result<(), Box<dyn Error>> {
+        self.colour.load(loader)
+    }
+}
+
+// Fields shared by every type of material
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct SurfaceSpec {
+    #[serde(default = "Colour::black")]
+    emission: Colour,
+    #[serde(default)]
+    normal_map: Option<Texture>,
+    #[serde(default)]
+    bump_map: Option<Texture>,
+    #[serde(default = "default_bump_scale")]
+    bump_scale: f64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "toml::Value")]
+pub struct Material {
+    // How the surface scatters light, chosen by its `type` (diffuse if not given)
+    pub model: Arc<dyn MaterialModel>,
+    pub emission: Colour,
+    // Tangent space normals: red along +u, green towards the top of the image (-v), blue out of
+    // the surface
+    pub normal_map: Option<Texture>,
+    // Height above the surface, in units of `bump_scale`
+    pub bump_map: Option<Texture>,
+    pub bump_scale: f64,
+    // Set when written as `material = "name"`, until `MaterialLibrary::resolve` swaps in the
+    // named material
+    pub name: Option<String>,
+}
+
+// Builds a registered model from the material's table in the scene file
+pub type MaterialConstructor = fn(&toml::Value) -> Result<Arc<dyn MaterialModel>, String>;
+
+// Types added with `Material::register`, by lowercase name
+static REGISTERED: LazyLock<RwLock<HashMap<String, MaterialConstructor>>> =
+    LazyLock::new(Default::default);
+
+impl TryFrom<toml::Value> for Material {
+    type Error = String;
+
+    fn try_from(value: toml::Value) -> Result<Self, Self::Error> {
+        if let toml::Value::String(name) = value {
+            return Ok(Material {
+                name: Some(name),
+                ..Material::default()
+            });
+        }
+        let model_type = match value.get("type") {
+            Some(toml::Value::String(s)) => s.to_lowercase(),
+            Some(_) => return Err("material type must be a string".to_string()),
+            None => "diffuse".to_string(),
+        };
+        let parse_error = |e: toml::de::Error| e.to_string();
+        let model: Arc<dyn MaterialModel> = match model_type.as_str() {
+            "diffuse" => {
+                Arc::new(DiffuseMaterial::deserialize(value.clone()).map_err(parse_error)?)
+            }
+            "conductor" | "metal" => {
+                let spec = ConductorSpec::deserialize(value.clone()).map_err(parse_error)?;
+                let (eta, k) = match (&spec.metal, spec.eta, spec.k) {
+                    (Some(metal), _, _) => bsdf::metal_ior(metal)
+                        .ok_or_else(|| format!("unknown metal '{}'", metal))?,
+                    (None, Some(eta), Some(k)) => (eta, k),
+                    _ => return Err("a conductor needs a metal, or eta and k".to_string()),
+                };
+                Arc::new(ConductorMaterial {
+                    eta,
+                    k,
+                    roughness: spec.roughness,
+                })
+            }
+            "dielectric" | "glass" => {
+                Arc::new(DielectricMaterial::deserialize(value.clone()).map_err(parse_error)?)
+            }
+            "principled" => {
+                Arc::new(PrincipledMaterial::deserialize(value.clone()).map_err(parse_error)?)
+            }
+            "hair" => Arc::new(HairMaterial::deserialize(value.clone()).map_err(parse_error)?),
+            other => {
+                let registered = REGISTERED.read().unwrap().get(other).copied();
+                match registered {
+                    Some(constructor) => constructor(&value)?,
+                    None => return Err(format!("unknown material type '{}'", other)),
+                }
+            }
+        };
+
+        let surface = SurfaceSpec::deserialize(value).map_err(parse_error)?;
+        Ok(Material {
+            model,
+            emission: surface.emission,
+            normal_map: surface.normal_map,
+            bump_map: surface.bump_map,
+            bump_scale: surface.bump_scale,
+            name: None,
+        })
+    }
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Material::new(DiffuseMaterial {
+            colour: default_colour(),
+        })
+    }
+}
+
+impl Material {
+    // Let scene files use `type = name` for a model defined outside this crate. Built-in types
+    // take precedence over registered ones.
+    pub fn register(name: &str, constructor: MaterialConstructor) {
+        REGISTERED
+            .write()
+            .unwrap()
+            .insert(name.to_lowercase(), constructor);
+    }
+
+    // A non-emissive material with no normal or bump map
+    pub fn new(model: impl MaterialModel + 'static) -> Self {
+        Material {
+            model: Arc::new(model),
+            emission: Colour::black(),
+            normal_map: None,
+            bump_map: None,
+            bump_scale: default_bump_scale(),
+            name: None,
+        }
+    }
+
+    pub fn load_textures(&mut self, loader: &mut TextureLoader) -> Result<(), Box<dyn Error>> {
+        Arc::get_mut(&mut self.model)
+            .ok_or("textures must be loaded before a material is shared")?
+            .load_textures(loader)?;
+        if let Some(texture) = &mut self.normal_map {
+            texture.load(loader)?;
+        }
+        if let Some(texture) = &mut self.bump_map {
+            texture.load(loader)?;
+        }
+        Ok(())
+    }
+
+    // The scattering function at a point, with any textures evaluated
+    pub fn bsdf(&self, coords: &TextureCoords) -> Box<dyn Bsdf> {
+        self.model.bsdf(coords)
+    }
+
+    // The normal to shade with after applying any normal or bump map, on the same side as `normal`
+    pub fn shading_normal(
+        &self,
+        coords: &TextureCoords,
+        normal: Vector,
+        dpdu: Vector,
+        dpdv: Vector,
+    ) -> Vector {
+        let mut shading = normal;
+
+        if let Some(bump_map) = &self.bump_map {
+            // Displace the surface along the normal and take the normal of the displaced surface,
+            // using finite differences for the slope of the height
+            let height = |du: f64, dv: f64| {
+                let offset = dpdu * du + dpdv * dv;
+                let shifted = TextureCoords {
+                    uv: (coords.uv.0 + du, coords.uv.1 + dv),
+                    position: coords.position + offset,
+                    object_position: coords.object_position + offset,
+                    ..*coords
+                };
+                bump_map.value(&shifted) * self.bump_scale
+            };
+            let h = height(0., 0.);
+            let dhdu = (height(BUMP_DELTA, 0.) - h) / BUMP_DELTA;
+            let dhdv = (height(0., BUMP_DELTA) - h) / BUMP_DELTA;
+            let bumped = (dpdu + normal * dhdu).cross(&(dpdv + normal * dhdv));
+            if bumped.abs_squared() > 0. {
+                let bumped = bumped.normalised();
+                shading = if bumped.dot(&normal) < 0. {
+                    -bumped
+                } else {
+                    bumped
+                };
+            }
+        }
+
+        if let Some(normal_map) = &self.normal_map {
+            // Orthonormal frame around the normal, aligned with the texture's u direction
+            let tangent = dpdu - shading * shading.dot(&dpdu);
+            let tangent = if tangent.abs_squared() > 0. {
+                tangent.normalised()
+            } else {
+                sampling::orthonormal_basis(shading).0
+            };
+            let mut bitangent = shading.cross(&tangent);
+            // Image up is -v, whichever side of the surface is being shaded
+            if bitangent.dot(&dpdv) > 0. {
+                bitangent = -bitangent;
+            }
+            let c = normal_map.evaluate(coords);
+            let local = Vector::new(
+                2. * c.r as f64 - 1.,
+                2. * c.g as f64 - 1.,
+                2. * c.b as f64 - 1.,
+            );
+            let mapped = tangent * local.x + bitangent * local.y + shading * local.z;
+            if mapped.abs_squared() > 0. && mapped.dot(&normal) > 0. {
+                shading = mapped.normalised();
+            }
+        }
+
+        shading
+    }
+}
+
+// Named materials from a scene's `[materials.<name>]` tables, and any library files listed in
+// `material_library`, which entities refer to with `material = "name"`. The scene's own
+// materials take precedence over a library's. Texture paths are relative to the file the
+// material is defined in. Each material is parsed and its textures loaded once, then shared by
+// every entity using it.
+#[derive(Debug, Clone, Default)]
+pub struct MaterialLibrary {
+    materials: HashMap<String, Material>,
+}
+
+impl MaterialLibrary {
+    pub fn from_toml(table: &toml::Table, scene_dir: &Path, textures: &mut TextureLoader) -> Self {
+        let mut library = MaterialLibrary::default();
+
+        let files = match table.get("material_library") {
+            Some(toml::Value::String(path)) => vec![path.as_str()],
+            Some(toml::Value::Array(paths)) => paths.iter().filter_map(|p| p.as_str()).collect(),
+            Some(_) => {
+                eprintln!("Warning: material_library must be a path or a list of paths");
+                vec![]
+            }
+            None => vec![],
+        };
+        for file in files {
+            match Self::read_file(&scene_dir.join(file)) {
+                Ok(table) => {
+                    let library_dir = Path::new(file).parent().unwrap_or(Path::new(""));
+                    let scene_textures = textures.set_dir(scene_dir.join(library_dir));
+                    library.add(&table, textures);
+                    textures.set_dir(scene_textures);
+                }
+                Err(e) => eprintln!("Warning: failed to load material library {}: {}", file, e),
+            }
+        }
+
+        library.add(table, textures);
+        library
+    }
+
+    fn read_file(path: &Path) -> Result<toml::Table, Box<dyn Error>> {
+        Ok(fs::read_to_string(path)?.parse::<toml::Table>()?)
+    }
+
+    // Add the materials in a table's `materials` section, skipping any that don't parse
+    fn add(&mut self, table: &toml::Table, textures: &mut TextureLoader) {
+        let Some(materials) = table.get("materials") else {
+            return;
+        };
+        let Some(materials) = materials.as_table() else {
+            eprintln!("Warning: materials must be a table of named materials");
+            return;
+        };
+        for (name, value) in materials {
+            let mut material = match Material::try_from(value.clone()) {
+                Ok(material) if material.name.is_none() => material,
+                Ok(_) => {
+                    eprintln!("Warning: material {} must be a table", name);
+                    continue;
+                }
+                Err(e) => {
+                    eprintln!("Warning: failed to parse material {}: {}", name, e);
+                    continue;
+                }
+            };
+            if let Err(e) = material.load_textures(textures) {
+                eprintln!(
+                    "Warning: failed to load texture for material {}: {}",
+                    name, e
+                );
+            }
+            self.materials.insert(name.clone(), material);
+        }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.materials.contains_key(name)
+    }
+
+    // Replace a `material = "name"` placeholder with a copy of the named material
+    pub fn resolve(&self, material: &mut Material) -> Result<(), String> {
+        if let Some(name) = &material.name {
+            *material = self
+                .materials
+                .get(name)
+                .ok_or_else(|| format!("unknown material '{}'", name))?
+                .clone();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::{BMPImage, FloatImage, Image};
+    use crate::test_dir::TestDir;
+    use crate::texture::{Filter, ImageTexture, Wrap};
+
+    fn coords() -> TextureCoords {
+        TextureCoords {
+            uv: (0.5, 0.5),
+            position: Vector::zero(),
+            object_position: Vector::zero(),
+            vertex_colour: None,
+        }
+    }
+
+    fn shading_normal_with(material: &str) -> Vector {
+        let table: toml::Table = format!("material = {}", material).parse().unwrap();
+        let material: Material = table["material"].clone().try_into().unwrap();
+        let normal = Vector::new(0., 0., -1.);
+        let (dpdu, dpdv) = (Vector::new(1., 0., 0.), Vector::new(0., 1., 0.));
+        material.shading_normal(&coords(), normal, dpdu, dpdv)
+    }
+
+    #[test]
+    fn flat_maps_leave_the_normal_alone() {
+        let flat = shading_normal_with("{colour = [1, 1, 1], normal_map = [0.5, 0.5, 1]}");
+        assert!((flat - Vector::new(0., 0., -1.)).length() < 1e-9);
+        let flat = shading_normal_with("{colour = [1, 1, 1], bump_map = [0.3, 0.3, 0.3]}");
+        assert!((flat - Vector::new(0., 0., -1.)).length() < 1e-9);
+    }
+
+    #[test]
+    fn normal_map_tilts_towards_u_and_image_up() {
+        let n = shading_normal_with("{colour = [1, 1, 1], normal_map = [0.9, 0.5, 0.6]}");
+        assert!(n.x > 0. && n.y.abs() < 1e-9 && n.z < 0.);
+        let n = shading_normal_with("{colour = [1, 1, 1], normal_map = [0.5, 0.9, 0.6]}");
+        assert!(n.y < 0. && n.z < 0.);
+    }
+
+    #[test]
+    fn bump_map_tilts_away_from_rising_height() {
+        // Height rises along u
+        let ramp = FloatImage::new(2, 1, vec![Colour::black(), Colour::white()]);
+        let material = Material {
+            bump_map: Some(Texture::Image(ImageTexture::new(
+                Arc::new(ramp),
+                Filter::Bilinear,
+                Wrap::Clamp,
+            ))),
+            bump_scale: 0.1,
+            ..Material::default()
+        };
+        let normal = Vector::new(0., 0., -1.);
+        let (dpdu, dpdv) = (Vector::new(1., 0., 0.), Vector::new(0., 1., 0.));
+        let n = material.shading_normal(&coords(), normal, dpdu, dpdv);
+        // The slope is 0.2 per unit u, so the normal leans back by that much along -u
+        let expected = Vector::new(-0.2, 0., -1.).normalised();
+        assert!((n - expected).length() < 1e-4, "{:?}", n);
+    }
+
+    #[test]
+    fn material_types() {
+        let table: toml::Table = r#"
+        default = {colour = [0.5, 0.5, 0.5], emission = [1, 1, 1]}
+        gold = {type = "conductor", metal = "gold", roughness = 0.2}
+        custom = {type = "conductor", eta = [1.5, 1.5, 1.5], k = [0, 0, 0]}
+        glass = {type = "dielectric", roughness = 0.1}
+        unknown_metal = {type = "conductor", metal = "unobtainium"}
+        no_ior = {type = "conductor"}
+        principled = {type = "principled", colour = [1, 0, 0], metallic = 1, roughness = {type = "noise"}}
+        hair = {type = "hair", colour = [0.4, 0.2, 0.1]}
+        unknown = {type = "velvet"}
+        "#
+        .parse()
+        .unwrap();
+        let material = |name: &str| Material::try_from(table[name].clone());
+        let up = Vector::new(0., 0., 1.);
+        let down = Vector::new(0., 0., -1.);
+        let reflected = |name: &str| material(name).unwrap().bsdf(&coords()).evaluate(up, up);
+
+        let default = material("default").unwrap();
+        assert_eq!(default.emission, Colour::white());
+        let f = default.bsdf(&coords()).evaluate(up, up);
+        assert!((f.r as f64 - 0.5 / std::f64::consts::PI).abs() < 1e-6);
+
+        let gold = reflected("gold");
+        assert!(gold.r > gold.b);
+        // Without absorption a conductor is colourless
+        let custom = reflected("custom");
+        assert!(custom.r == custom.b && custom.r > 0.);
+        let glass = material("glass").unwrap().bsdf(&coords());
+        assert!(!glass.evaluate(up, down).is_black());
+        let principled = reflected("principled");
+        assert!(principled.r > principled.g && principled.g == principled.b);
+        // Hair scatters through to the far side too
+        let hair = material("hair").unwrap().bsdf(&coords());
+        assert!(!hair.evaluate(up, down).is_black());
+
+        assert!(material("unknown_metal").is_err());
+        assert!(material("no_ior").is_err());
+        assert!(material("unknown").is_err());
+    }
+
+    #[test]
+    fn library_resolves_named_materials() {
+        let dir = TestDir::new("material_library");
+        dir.write(
+            "library.toml",
+            r#"
+            [materials.red]
+            colour = [1, 0, 0]
+
+            [materials.brass]
+            type = "metal"
+            metal = "gold"
+            "#,
+        );
+        let scene: toml::Table = r#"
+        material_library = "library.toml"
+
+        [materials.red]
+        colour = [0.5, 0, 0]
+
+        [materials.broken]
+        type = "velvet"
+        "#
+        .parse()
+        .unwrap();
+        let library =
+            MaterialLibrary::from_toml(&scene, dir.path(), &mut TextureLoader::new(dir.path()));
+
+        let resolve = |material: &str| {
+            let table: toml::Table = format!("material = {}", material).parse().unwrap();
+            let mut material: Material = table["material"].clone().try_into().unwrap();
+            library.resolve(&mut material).map(|_| material)
+        };
+        let up = Vector::new(0., 0., 1.);
+        let reflected = |material: &Material| material.bsdf(&coords()).evaluate(up, up);
+        // The scene's own definition wins over the library's
+        let red = resolve(r#""red""#).unwrap();
+        assert!((reflected(&red).r as f64 - 0.5 / std::f64::consts::PI).abs() < 1e-6);
+        // Every use shares the one parsed model
+        let brass = resolve(r#""brass""#).unwrap();
+        assert!(Arc::ptr_eq(
+            &brass.model,
+            &resolve(r#""brass""#).unwrap().model
+        ));
+        assert!(brass.name.is_none());
+        // Inline materials are left alone
+        let inline = resolve("{colour = [0, 1, 0]}").unwrap();
+        assert!(reflected(&inline).g > 0. && reflected(&inline).r == 0.);
+        assert!(resolve(r#""broken""#).is_err());
+        assert!(resolve(r#""missing""#).is_err());
+    }
+
+    #[test]
+    fn library_textures_are_relative_to_the_library() {
+        let dir = TestDir::new("material_library_dir");
+        dir.write(
+            "libs/library.toml",
+            r#"
+            [materials.dark]
+            colour = {image = "dark.bmp"}
+            "#,
+        );
+        let mut image = Image::new(1, 1);
+        image.put_pixel(0, 0, 0);
+        dir.write("libs/dark.bmp", BMPImage::from(image).as_bytes());
+        let scene: toml::Table = r#"material_library = "libs/library.toml""#.parse().unwrap();
+        let mut textures = TextureLoader::new(dir.path());
+        let library = MaterialLibrary::from_toml(&scene, dir.path(), &mut textures);
+
+        let mut dark = Material {
+            name: Some("dark".to_string()),
+            ..Material::default()
+        };
+        library.resolve(&mut dark).unwrap();
+        // An image that failed to load would look up as white
+        let up = Vector::new(0., 0., 1.);
+        assert!(dark.bsdf(&coords()).evaluate(up, up).is_black());
+        // The scene's own textures are still looked up next to the scene
+        assert!(textures.load("libs/dark.bmp").is_ok());
+    }
+}
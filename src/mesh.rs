@@ -0,0 +1,724 @@
+// Resources:
+// PLY format: https://paulbourke.net/dataformats/ply/
+// STL format: https://en.wikipedia.org/wiki/STL_(file_format)
+// Möller–Trumbore: https://en.wikipedia.org/wiki/M%C3%B6ller%E2%80%93Trumbore_intersection_algorithm
+
+use crate::bvh::{Aabb, Bvh};
+use crate::colour::Colour;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::sampling;
+use crate::sampling::Distribution1D;
+use crate::vector::Vector;
+use crate::world::{Entity, Hit, IntersectionResult, SurfaceInteraction, SurfaceSample};
+use serde::Deserialize;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+// A `type = "mesh"` entity, read from a .ply or .stl file. A diffuse material without a `colour`
+// takes the file's vertex colours, if it has any; other material types need `colour = "vertex"`
+// for them.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MeshSpec {
+    pub file: String, // relative to the scene file
+    pub material: Material,
+}
+
+// Triangles sharing a list of vertices. Normals, UVs and colours are per vertex, and each is
+// either empty or as long as `positions`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MeshData {
+    pub positions: Vec<Vector>,
+    pub normals: Vec<Vector>,
+    pub uvs: Vec<(f64, f64)>,
+    pub colours: Vec<Colour>,
+    pub triangles: Vec<[usize; 3]>,
+}
+
+impl MeshData {
+    pub fn validate(&self) -> Result<(), String> {
+        let count = self.positions.len();
+        for (name, len) in [
+            ("normals", self.normals.len()),
+            ("uvs", self.uvs.len()),
+            ("colours", self.colours.len()),
+        ] {
+            if len != 0 && len != count {
+                return Err(format!("{} {} for {} vertices", len, name, count));
+            }
+        }
+        for (i, triangle) in self.triangles.iter().enumerate() {
+            if let Some(index) = triangle.iter().find(|&&index| index >= count) {
+                return Err(format!(
+                    "triangle {} uses vertex {} but there are only {}",
+                    i, index, count
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn load_mesh(path: &Path) -> Result<MeshData, Box<dyn Error>> {
+    let bytes = fs::read(path)?;
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    match extension.to_lowercase().as_str() {
+        "ply" => parse_ply(&bytes),
+        "stl" => parse_stl(&bytes),
+        _ => Err(format!("unsupported mesh format '{}'", extension).into()),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Result<Self, String> {
+        Ok(match name {
+            "char" | "int8" => Scalar::I8,
+            "uchar" | "uint8" => Scalar::U8,
+            "short" | "int16" => Scalar::I16,
+            "ushort" | "uint16" => Scalar::U16,
+            "int" | "int32" => Scalar::I32,
+            "uint" | "uint32" => Scalar::U32,
+            "float" | "float32" => Scalar::F32,
+            "double" | "float64" => Scalar::F64,
+            _ => return Err(format!("unknown property type '{}'", name)),
+        })
+    }
+
+    // Colours stored as integers run up to the type's maximum
+    fn colour_scale(self) -> f64 {
+        match self {
+            Scalar::U8 => 255.,
+            Scalar::U16 => 65535.,
+            _ => 1.,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Property {
+    Scalar(String, Scalar),
+    List(String, Scalar, Scalar), // types of the count and of the items
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct PlyElement {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+// The values after a PLY header, read one at a time as whichever type the header says comes next
+enum PlyBody<'a> {
+    Ascii(std::str::SplitAsciiWhitespace<'a>),
+    Binary { data: &'a [u8], big_endian: bool },
+}
+
+impl PlyBody<'_> {
+    fn read(&mut self, kind: Scalar) -> Result<f64, String> {
+        let (data, big_endian) = match self {
+            PlyBody::Ascii(tokens) => {
+                let token = tokens.next().ok_or("unexpected end of file")?;
+                return token
+                    .parse()
+                    .map_err(|_| format!("expected a number, found '{}'", token));
+            }
+            PlyBody::Binary { data, big_endian } => (data, *big_endian),
+        };
+        // Little endian bytes of the next N, whatever order they are stored in
+        fn take<const N: usize>(data: &mut &[u8], big_endian: bool) -> Result<[u8; N], String> {
+            let (bytes, rest) = data.split_at_checked(N).ok_or("unexpected end of file")?;
+            *data = rest;
+            let mut bytes: [u8; N] = bytes.try_into().unwrap();
+            if big_endian {
+                bytes.reverse();
+            }
+            Ok(bytes)
+        }
+        Ok(match kind {
+            Scalar::I8 => i8::from_le_bytes(take(data, big_endian)?) as f64,
+            Scalar::U8 => u8::from_le_bytes(take(data, big_endian)?) as f64,
+            Scalar::I16 => i16::from_le_bytes(take(data, big_endian)?) as f64,
+            Scalar::U16 => u16::from_le_bytes(take(data, big_endian)?) as f64,
+            Scalar::I32 => i32::from_le_bytes(take(data, big_endian)?) as f64,
+            Scalar::U32 => u32::from_le_bytes(take(data, big_endian)?) as f64,
+            Scalar::F32 => f32::from_le_bytes(take(data, big_endian)?) as f64,
+            Scalar::F64 => f64::from_le_bytes(take(data, big_endian)?),
+        })
+    }
+}
+
+// Reads vertex positions, and normals, UVs and colours if there are any, from the `vertex`
+// element, and polygons from the `face` element. Other elements and properties are skipped.
+pub fn parse_ply(bytes: &[u8]) -> Result<MeshData, Box<dyn Error>> {
+    let end = bytes
+        .windows(b"end_header".len())
+        .position(|w| w == b"end_header")
+        .ok_or("missing end_header")?;
+    let body_start = bytes[end..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .map_or(bytes.len(), |i| end + i + 1);
+    let header = std::str::from_utf8(&bytes[..end])?;
+
+    let mut lines = header.lines().map(str::trim);
+    if lines.next() != Some("ply") {
+        return Err("not a PLY file".into());
+    }
+    let mut format = None;
+    let mut elements: Vec<PlyElement> = Vec::new();
+    for line in lines {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words[..] {
+            ["format", name, _version] => format = Some(name.to_string()),
+            ["element", name, count] => elements.push(PlyElement {
+                name: name.to_string(),
+                count: count.parse()?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, item, name] => elements
+                .last_mut()
+                .ok_or("property before any element")?
+                .properties
+                .push(Property::List(
+                    name.to_string(),
+                    Scalar::parse(count)?,
+                    Scalar::parse(item)?,
+                )),
+            ["property", kind, name] => elements
+                .last_mut()
+                .ok_or("property before any element")?
+                .properties
+                .push(Property::Scalar(name.to_string(), Scalar::parse(kind)?)),
+            ["comment", ..] | ["obj_info", ..] | [] => {}
+            _ => return Err(format!("unexpected header line '{}'", line).into()),
+        }
+    }
+
+    let data = &bytes[body_start..];
+    let mut body = match format.as_deref() {
+        Some("ascii") => PlyBody::Ascii(std::str::from_utf8(data)?.split_ascii_whitespace()),
+        Some("binary_little_endian") => PlyBody::Binary {
+            data,
+            big_endian: false,
+        },
+        Some("binary_big_endian") => PlyBody::Binary {
+            data,
+            big_endian: true,
+        },
+        _ => return Err("missing or unknown format".into()),
+    };
+
+    let mut mesh = MeshData::default();
+    for element in &elements {
+        for _ in 0..element.count {
+            let mut values = Vec::new();
+            let mut polygon = Vec::new();
+            for property in &element.properties {
+                match property {
+                    Property::Scalar(name, kind) => {
+                        values.push((name.as_str(), *kind, body.read(*kind)?))
+                    }
+                    Property::List(name, count, item) => {
+                        let count = body.read(*count)?;
+                        let items = (0..count as usize)
+                            .map(|_| body.read(*item))
+                            .collect::<Result<Vec<f64>, String>>()?;
+                        if name == "vertex_indices" || name == "vertex_index" {
+                            polygon = items;
+                        }
+                    }
+                }
+            }
+            match element.name.as_str() {
+                "vertex" => add_ply_vertex(&mut mesh, &values)?,
+                "face" => {
+                    let face = mesh.triangles.len();
+                    if polygon.len() < 3 || polygon.iter().any(|&i| i < 0. || i.fract() != 0.) {
+                        return Err(format!("face {} has bad vertex indices", face).into());
+                    }
+                    // Fan out from the first corner
+                    for i in 1..polygon.len() - 1 {
+                        mesh.triangles.push([
+                            polygon[0] as usize,
+                            polygon[i] as usize,
+                            polygon[i + 1] as usize,
+                        ]);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    mesh.validate()?;
+    Ok(mesh)
+}
+
+fn add_ply_vertex(mesh: &mut MeshData, values: &[(&str, Scalar, f64)]) -> Result<(), String> {
+    let find = |names: &[&str]| {
+        values
+            .iter()
+            .find(|(name, _, _)| names.contains(name))
+            .map(|&(_, kind, value)| (kind, value))
+    };
+    let value = |name: &str| find(&[name]).map(|(_, value)| value);
+    let (Some(x), Some(y), Some(z)) = (value("x"), value("y"), value("z")) else {
+        return Err("vertices need x, y and z".to_string());
+    };
+    mesh.positions.push(Vector::new(x, y, z));
+    if let (Some(x), Some(y), Some(z)) = (value("nx"), value("ny"), value("nz")) {
+        mesh.normals.push(Vector::new(x, y, z));
+    }
+    let u = find(&["u", "s", "texture_u", "texture_s"]);
+    let v = find(&["v", "t", "texture_v", "texture_t"]);
+    if let (Some((_, u)), Some((_, v))) = (u, v) {
+        // PLY puts v = 0 at the bottom of the image
+        mesh.uvs.push((u, 1. - v));
+    }
+    let channel = |names: &[&str]| find(names).map(|(kind, value)| value / kind.colour_scale());
+    if let (Some(r), Some(g), Some(b)) = (
+        channel(&["red", "r"]),
+        channel(&["green", "g"]),
+        channel(&["blue", "b"]),
+    ) {
+        mesh.colours.push(Colour::new(r as f32, g as f32, b as f32));
+    }
+    Ok(())
+}
+
+// Binary files are 80 bytes of header, a triangle count and 50 bytes per triangle; anything else
+// starting with "solid" is read as text
+pub fn parse_stl(bytes: &[u8]) -> Result<MeshData, Box<dyn Error>> {
+    let binary_count = bytes
+        .get(80..84)
+        .map(|count| u32::from_le_bytes(count.try_into().unwrap()) as usize);
+    let positions: Vec<Vector> = match binary_count {
+        Some(count) if bytes.len() == 84 + 50 * count => bytes[84..]
+            .chunks_exact(50)
+            .flat_map(|facet| {
+                // Skip the facet normal, which is recomputed from the winding
+                (1..4).map(move |corner| {
+                    let float = |i: usize| {
+                        let start = corner * 12 + i * 4;
+                        f32::from_le_bytes(facet[start..start + 4].try_into().unwrap()) as f64
+                    };
+                    Vector::new(float(0), float(1), float(2))
+                })
+            })
+            .collect(),
+        _ => {
+            let text = std::str::from_utf8(bytes).map_err(|_| "not an STL file")?;
+            let mut tokens = text.split_ascii_whitespace();
+            if tokens.next() != Some("solid") {
+                return Err("not an STL file".into());
+            }
+            let mut positions = Vec::new();
+            while let Some(token) = tokens.next() {
+                if token == "vertex" {
+                    let mut coordinate = || -> Result<f64, Box<dyn Error>> {
+                        Ok(tokens.next().ok_or("unexpected end of file")?.parse()?)
+                    };
+                    positions.push(Vector::new(coordinate()?, coordinate()?, coordinate()?));
+                }
+            }
+            if positions.len() % 3 != 0 {
+                return Err("facets must have three vertices".into());
+            }
+            positions
+        }
+    };
+    let triangles = (0..positions.len() / 3)
+        .map(|i| [3 * i, 3 * i + 1, 3 * i + 2])
+        .collect();
+    Ok(MeshData {
+        positions,
+        triangles,
+        ..MeshData::default()
+    })
+}
+
+// Triangles loaded from a file, with their own hierarchy. Vertex normals, where given, are
+// blended across each face for smooth shading, and vertex colours are passed on to any
+// `"vertex"` texture of the material. An emissive mesh is sampled as a light by picking a
+// triangle in proportion to its area.
+pub struct Mesh {
+    data: MeshData,
+    bvh: Bvh,
+    areas: Distribution1D, // over the triangles
+    area: f64,
+    material: Material,
+}
+
+impl Mesh {
+    pub fn new(data: MeshData, material: Material) -> Result<Self, String> {
+        data.validate()?;
+        if data.triangles.is_empty() {
+            return Err("the mesh has no triangles".to_string());
+        }
+        let bounds: Vec<Aabb> = data
+            .triangles
+            .iter()
+            .map(|triangle| Aabb::from_points(&triangle.map(|i| data.positions[i])))
+            .collect();
+        let areas: Vec<f64> = data
+            .triangles
+            .iter()
+            .map(|triangle| {
+                let [p0, p1, p2] = triangle.map(|i| data.positions[i]);
+                (p1 - p0).cross(&(p2 - p0)).length() / 2.
+            })
+            .collect();
+        Ok(Mesh {
+            bvh: Bvh::new(&bounds),
+            area: areas.iter().sum(),
+            areas: Distribution1D::new(areas),
+            data,
+            material,
+        })
+    }
+
+    fn corners(&self, triangle: usize) -> [Vector; 3] {
+        self.data.triangles[triangle].map(|i| self.data.positions[i])
+    }
+
+    // Distance to the triangle and the weights of its second and third corners there
+    fn intersect_triangle(&self, ray: &Ray, triangle: usize) -> Option<(f64, f64, f64)> {
+        let [p0, p1, p2] = self.corners(triangle);
+        let (e1, e2) = (p1 - p0, p2 - p0);
+        let p = ray.direction.cross(&e2);
+        let det = e1.dot(&p);
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let inv_det = 1. / det;
+        let s = ray.origin - p0;
+        let b1 = s.dot(&p) * inv_det;
+        if !(0. ..=1.).contains(&b1) {
+            return None;
+        }
+        let q = s.cross(&e1);
+        let b2 = ray.direction.dot(&q) * inv_det;
+        if b2 < 0. || b1 + b2 > 1. {
+            return None;
+        }
+        let t = e2.dot(&q) * inv_det;
+        (t > 0.).then_some((t, b1, b2))
+    }
+}
+
+impl Entity for Mesh {
+    fn intersection(&self, ray: &Ray) -> IntersectionResult {
+        match self.hit(ray) {
+            Some(hit) => IntersectionResult::One(hit.t),
+            None => IntersectionResult::No,
+        }
+    }
+
+    // The part hit is the triangle
+    fn hit(&self, ray: &Ray) -> Option<Hit> {
+        let (triangle, hit) = self.bvh.nearest(ray, |i| {
+            self.intersect_triangle(ray, i).map(|hit| Hit::new(hit.0))
+        })?;
+        Some(Hit {
+            part: triangle,
+            ..hit
+        })
+    }
+
+    fn surface(&self, ray: &Ray, hit: Hit) -> SurfaceInteraction<'_> {
+        // Test the triangle again for where on it the hit is. It is the test that found the hit,
+        // so it can't miss.
+        let triangle = hit.part;
+        let (_, b1, b2) =
+            self.intersect_triangle(ray, triangle)
+                .unwrap_or((hit.t, 1. / 3., 1. / 3.));
+        let weights = [1. - b1 - b2, b1, b2];
+        let indices = self.data.triangles[triangle];
+        let [p0, p1, p2] = self.corners(triangle);
+        let (e1, e2) = (p1 - p0, p2 - p0);
+
+        let face_normal = e1.cross(&e2).normalised();
+        let mut normal = face_normal;
+        if !self.data.normals.is_empty() {
+            let blended = (0..3).fold(Vector::zero(), |sum, k| {
+                sum + self.data.normals[indices[k]] * weights[k]
+            });
+            if blended.abs_squared() > 1e-20 {
+                normal = blended.normalised();
+            }
+        }
+
+        let (uv, (dpdu, dpdv)) = if self.data.uvs.is_empty() {
+            ((b1, b2), (e1, e2))
+        } else {
+            let [uv0, uv1, uv2] = indices.map(|i| self.data.uvs[i]);
+            let uv = (
+                weights[0] * uv0.0 + weights[1] * uv1.0 + weights[2] * uv2.0,
+                weights[0] * uv0.1 + weights[1] * uv1.1 + weights[2] * uv2.1,
+            );
+            let (du1, dv1) = (uv1.0 - uv0.0, uv1.1 - uv0.1);
+            let (du2, dv2) = (uv2.0 - uv0.0, uv2.1 - uv0.1);
+            let det = du1 * dv2 - dv1 * du2;
+            if det.abs() < 1e-12 {
+                (uv, sampling::orthonormal_basis(normal))
+            } else {
+                (
+                    uv,
+                    ((e1 * dv2 - e2 * dv1) / det, (e2 * du1 - e1 * du2) / det),
+                )
+            }
+        };
+
+        let vertex_colour = (!self.data.colours.is_empty()).then(|| {
+            (0..3).fold(Colour::black(), |sum, k| {
+                sum + self.data.colours[indices[k]] * weights[k] as f32
+            })
+        });
+
+        let position = ray.at(hit.t);
+        SurfaceInteraction {
+            position,
+            normal,
+            uv,
+            dpdu,
+            dpdv,
+            object_position: position,
+            vertex_colour,
+            material: &self.material,
+        }
+    }
+
+    fn bounds(&self) -> Aabb {
+        self.bvh.bounds()
+    }
+
+    fn position(&self) -> Vector {
+        self.bounds().centre()
+    }
+
+    fn emission(&self) -> Colour {
+        self.material.emission
+    }
+
+    fn can_sample(&self) -> bool {
+        true
+    }
+
+    fn area(&self) -> f64 {
+        self.area
+    }
+
+    fn sample(&self, reference: Vector, u: (f64, f64)) -> Option<SurfaceSample> {
+        let (x, _, triangle) = self.areas.sample(u.0);
+        // Where in the chosen triangle's slice u.0 fell, reused for the point on it
+        let along = (x * self.areas.len() as f64 - triangle as f64).clamp(0., 1.);
+        let (b0, b1) = sampling::uniform_triangle((along, u.1));
+        let [p0, p1, p2] = self.corners(triangle);
+        let position = p0 * b0 + p1 * b1 + p2 * (1. - b0 - b1);
+        let normal = (p1 - p0).cross(&(p2 - p0)).normalised();
+        let pdf = self.pdf(reference, position, normal);
+        if pdf == 0. {
+            return None;
+        }
+        Some(SurfaceSample {
+            position,
+            normal,
+            pdf,
+            emission: self.emission(),
+        })
+    }
+
+    fn pdf(&self, reference: Vector, point: Vector, normal: Vector) -> f64 {
+        if self.area == 0. {
+            return 0.;
+        }
+        sampling::area_to_solid_angle_pdf(1. / self.area, reference, point, normal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::Rng;
+    use crate::test_dir::TestDir;
+    use crate::world::World;
+
+    const SQUARE_PLY: &str = "ply
+format ascii 1.0
+comment a unit square in the z = 5 plane, red at one corner
+element vertex 4
+property float x
+property float y
+property float z
+property uchar red
+property uchar green
+property uchar blue
+element face 1
+property list uchar int vertex_indices
+end_header
+0 0 5 255 0 0
+1 0 5 0 0 0
+1 1 5 0 0 0
+0 1 5 0 0 0
+4 0 1 2 3
+";
+
+    #[test]
+    fn reads_ascii_ply() {
+        let mesh = parse_ply(SQUARE_PLY.as_bytes()).unwrap();
+        assert_eq!(mesh.positions.len(), 4);
+        assert_eq!(mesh.colours[0], Colour::new(1., 0., 0.));
+        // The quad is split in two
+        assert_eq!(mesh.triangles, vec![[0, 1, 2], [0, 2, 3]]);
+        assert!(mesh.normals.is_empty() && mesh.uvs.is_empty());
+
+        let out_of_range = SQUARE_PLY.replace("4 0 1 2 3", "3 0 1 4");
+        let error = parse_ply(out_of_range.as_bytes()).unwrap_err();
+        assert!(error.to_string().contains("vertex 4"), "{}", error);
+        let too_few = SQUARE_PLY.replace("4 0 1 2 3", "2 0 1");
+        assert!(parse_ply(too_few.as_bytes()).is_err());
+        let truncated = &SQUARE_PLY[..SQUARE_PLY.len() - 4];
+        assert!(parse_ply(truncated.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn reads_binary_ply_and_stl() {
+        let triangle = [[0f32, 0., 1.], [1., 0., 1.], [0., 1., 1.]];
+        for big_endian in [false, true] {
+            let format = if big_endian { "big" } else { "little" };
+            let mut bytes = format!(
+                "ply\nformat binary_{}_endian 1.0\nelement vertex 3\nproperty float x\n\
+                 property float y\nproperty float z\nproperty float nx\nproperty float ny\n\
+                 property float nz\nelement face 1\nproperty list uchar uint vertex_index\n\
+                 end_header\n",
+                format
+            )
+            .into_bytes();
+            for vertex in triangle {
+                for value in vertex.iter().chain(&[0., 0., -1.]) {
+                    let value = if big_endian {
+                        value.to_be_bytes()
+                    } else {
+                        value.to_le_bytes()
+                    };
+                    bytes.extend(value);
+                }
+            }
+            bytes.push(3);
+            for index in [0u32, 1, 2] {
+                bytes.extend(if big_endian {
+                    index.to_be_bytes()
+                } else {
+                    index.to_le_bytes()
+                });
+            }
+            let mesh = parse_ply(&bytes).unwrap();
+            assert_eq!(mesh.positions[1], Vector::new(1., 0., 1.));
+            assert_eq!(mesh.normals[2], Vector::new(0., 0., -1.));
+            assert_eq!(mesh.triangles, vec![[0, 1, 2]]);
+        }
+
+        let mut stl = vec![0u8; 80];
+        stl.extend(1u32.to_le_bytes());
+        stl.extend([0u8; 12]);
+        for value in triangle.iter().flatten() {
+            stl.extend(value.to_le_bytes());
+        }
+        stl.extend([0u8; 2]);
+        let binary = parse_stl(&stl).unwrap();
+        let ascii = parse_stl(
+            b"solid t\nfacet normal 0 0 -1\nouter loop\nvertex 0 0 1\nvertex 1 0 1\n\
+              vertex 0 1 1\nendloop\nendfacet\nendsolid t\n",
+        )
+        .unwrap();
+        assert_eq!(binary, ascii);
+        assert_eq!(binary.positions[2], Vector::new(0., 1., 1.));
+        assert!(parse_stl(b"solid t\nfacet\nvertex 0 0 1\nendsolid t\n").is_err());
+    }
+
+    #[test]
+    fn samples_pick_triangles_by_area() {
+        // Two triangles in the z = 5 plane, the one at x > 0 three times the other's area
+        let data = MeshData {
+            positions: vec![
+                Vector::new(0., 0., 5.),
+                Vector::new(3., 0., 5.),
+                Vector::new(0., 1., 5.),
+                Vector::new(-1., 0., 5.),
+            ],
+            triangles: vec![[0, 1, 2], [0, 2, 3]],
+            ..MeshData::default()
+        };
+        let material = Material {
+            emission: Colour::white(),
+            ..Material::default()
+        };
+        let mesh = Mesh::new(data, material).unwrap();
+        assert_eq!(mesh.emission(), Colour::white());
+
+        let mut rng = Rng::new(3);
+        let reference = Vector::zero();
+        let mut right = 0;
+        for _ in 0..4000 {
+            let sample = mesh.sample(reference, rng.next_2d()).unwrap();
+            let p = sample.position;
+            assert!((p.z - 5.).abs() < 1e-9 && p.x >= -1. - 1e-9 && p.x <= 3. + 1e-9);
+            assert!(p.y >= -1e-9 && p.y <= 1. + 1e-9);
+            assert_eq!(sample.normal.z.abs(), 1.);
+            let expected = sampling::area_to_solid_angle_pdf(0.5, reference, p, sample.normal);
+            assert!((sample.pdf - expected).abs() < 1e-9);
+            if p.x > 0. {
+                right += 1;
+            }
+        }
+        assert!((right as f64 / 4000. - 0.75).abs() < 0.03, "{}", right);
+    }
+
+    #[test]
+    fn mesh_entities() {
+        let dir = TestDir::new("mesh_entities");
+        dir.write("square.ply", SQUARE_PLY);
+        let scene: toml::Table = r#"
+        [[entities]]
+        type = "mesh"
+        file = "square.ply"
+        material = {}
+
+        [[entities]]
+        type = "mesh"
+        file = "missing.stl"
+        material = {colour = [1, 1, 1]}
+        "#
+        .parse()
+        .unwrap();
+        let world = World::from_toml_in_dir(&scene, dir.path());
+        assert_eq!(world.entities.len(), 1);
+
+        let forward = Vector::new(0., 0., 1.);
+        let colour_at = |x: f64, y: f64| {
+            let result = world.find_nearest(&Ray::new(Vector::new(x, y, 0.), forward));
+            assert!(result.hit && (result.distance - 5.).abs() < 1e-9);
+            let wo = Vector::new(0., 0., 1.);
+            result
+                .material
+                .bsdf(&result.texture_coords())
+                .evaluate(wo, wo)
+        };
+        // The default colour is the vertex colours: red fading out away from the first corner
+        let corner = colour_at(0.01, 0.01);
+        let middle = colour_at(0.5, 0.5);
+        assert!(corner.r > middle.r && middle.r > 0. && corner.g == 0.);
+    }
+}
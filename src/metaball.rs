@@ -0,0 +1,306 @@
+// Resources:
+// Field function: Wyvill et al. 1986, "Data structure for soft objects"
+// Blobby surfaces: https://en.wikipedia.org/wiki/Metaballs
+
+use crate::bvh::Aabb;
+use crate::colour::Colour;
+use crate::material::Material;
+use crate::polynomial;
+use crate::ray::Ray;
+use crate::sampling;
+use crate::vector::Vector;
+use crate::world::{hit_spans, Entity, Hit, IntersectionResult, SurfaceInteraction};
+use serde::Deserialize;
+
+// Samples taken per radius of the smallest ball when searching for the surface
+const STEPS_PER_RADIUS: f64 = 16.;
+// Halvings of a bracketed crossing to pin it down
+const BISECTIONS: usize = 48;
+
+fn default_strength() -> f64 {
+    1.
+}
+
+fn default_threshold() -> f64 {
+    0.5
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct Ball {
+    pub centre: Vector,
+    pub radius: f64, // beyond which it has no influence
+    #[serde(default = "default_strength")]
+    pub strength: f64, // negative to carve into the others
+}
+
+impl Ball {
+    // Falls smoothly from `strength` at the centre to zero at the radius
+    fn field(&self, p: Vector) -> f64 {
+        let r2 = (p - self.centre).abs_squared() / (self.radius * self.radius);
+        if r2 >= 1. {
+            return 0.;
+        }
+        self.strength * (1. - r2).powi(3)
+    }
+
+    fn gradient(&self, p: Vector) -> Vector {
+        let offset = p - self.centre;
+        let r2 = offset.abs_squared() / (self.radius * self.radius);
+        if r2 >= 1. {
+            return Vector::zero();
+        }
+        offset * (-6. * self.strength * (1. - r2).powi(2) / (self.radius * self.radius))
+    }
+}
+
+// A `type = "metaballs"` entity
+#[derive(Debug, Clone, Deserialize)]
+pub struct MetaballsSpec {
+    pub balls: Vec<Ball>,
+    #[serde(default = "default_threshold")]
+    pub threshold: f64,
+    pub material: Material,
+}
+
+impl MetaballsSpec {
+    pub fn build(self) -> Result<Box<dyn Entity>, String> {
+        if self.threshold <= 0. {
+            return Err("threshold must be positive".to_string());
+        }
+        if self.balls.iter().any(|ball| ball.radius <= 0.) {
+            return Err("every ball's radius must be positive".to_string());
+        }
+        if !self.balls.iter().any(|ball| ball.strength > 0.) {
+            return Err("at least one ball must have a positive strength".to_string());
+        }
+        Ok(Box::new(Metaballs {
+            balls: self.balls,
+            threshold: self.threshold,
+            material: self.material,
+        }))
+    }
+}
+
+// Surface where the summed fields of a set of balls reach a threshold, so that balls close
+// together flow into one another
+#[derive(Debug, Clone)]
+pub struct Metaballs {
+    balls: Vec<Ball>,
+    threshold: f64,
+    material: Material,
+}
+
+impl Metaballs {
+    // Positive inside the surface
+    fn field(&self, p: Vector) -> f64 {
+        self.balls.iter().map(|ball| ball.field(p)).sum::<f64>() - self.threshold
+    }
+
+    // Whether the ray starts inside, and every distance along it where it crosses the surface.
+    // The field is only above the threshold within a ball that adds to it, so the search is
+    // limited to the spans of the ray in those.
+    fn crossings(&self, ray: &Ray) -> (bool, Vec<f64>) {
+        let length = ray.direction.length();
+        let mut spans: Vec<(f64, f64, f64)> = self
+            .balls
+            .iter()
+            .filter(|ball| ball.strength > 0.)
+            .filter_map(|ball| {
+                let offset = ray.origin - ball.centre;
+                let hits = polynomial::solve_quadratic(
+                    ray.direction.abs_squared(),
+                    2. * offset.dot(&ray.direction),
+                    offset.abs_squared() - ball.radius * ball.radius,
+                );
+                match hits[..] {
+                    [t1, t2] if t2 > 0. => Some((t1.max(0.), t2, ball.radius / length)),
+                    _ => None,
+                }
+            })
+            .collect();
+        spans.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let inside = self.field(ray.origin) > 0.;
+        let mut crossings = Vec::new();
+        let mut searched = 0.;
+        for (start, end, radius) in spans {
+            // Overlapping spans are searched from where the last one left off
+            let start = start.max(searched);
+            if end <= start {
+                continue;
+            }
+            searched = end;
+            let steps = ((end - start) / radius * STEPS_PER_RADIUS).ceil().max(1.) as usize;
+            let mut t0 = start;
+            let mut f0 = self.field(ray.at(t0));
+            for i in 1..=steps {
+                let t1 = start + (end - start) * i as f64 / steps as f64;
+                let f1 = self.field(ray.at(t1));
+                if (f0 > 0.) != (f1 > 0.) {
+                    crossings.push(self.bisect(ray, t0, t1, f0 > 0.));
+                }
+                (t0, f0) = (t1, f1);
+            }
+        }
+        crossings.retain(|&t| t > 0.);
+        (inside, crossings)
+    }
+
+    fn bisect(&self, ray: &Ray, mut low: f64, mut high: f64, low_inside: bool) -> f64 {
+        for _ in 0..BISECTIONS {
+            let middle = (low + high) / 2.;
+            if (self.field(ray.at(middle)) > 0.) == low_inside {
+                low = middle;
+            } else {
+                high = middle;
+            }
+        }
+        (low + high) / 2.
+    }
+}
+
+impl Entity for Metaballs {
+    fn intersection(&self, ray: &Ray) -> IntersectionResult {
+        IntersectionResult::from_distances(self.crossings(ray).1)
+    }
+
+    fn surface(&self, ray: &Ray, hit: Hit) -> SurfaceInteraction<'_> {
+        let position = ray.at(hit.t);
+        let gradient = self
+            .balls
+            .iter()
+            .fold(Vector::zero(), |sum, ball| sum + ball.gradient(position));
+        // The field falls away outwards
+        let normal = (-gradient).normalised();
+        // As for SDFs, any tangents will do
+        let (dpdu, dpdv) = sampling::orthonormal_basis(normal);
+        SurfaceInteraction {
+            position,
+            normal,
+            uv: (0., 0.),
+            dpdu,
+            dpdv,
+            object_position: position,
+            vertex_colour: None,
+            material: &self.material,
+        }
+    }
+
+    fn bounds(&self) -> Aabb {
+        self.balls
+            .iter()
+            .filter(|ball| ball.strength > 0.)
+            .fold(Aabb::empty(), |bounds, ball| {
+                let r = Vector::new(ball.radius, ball.radius, ball.radius);
+                bounds.union(&Aabb::new(ball.centre - r, ball.centre + r))
+            })
+    }
+
+    fn position(&self) -> Vector {
+        self.bounds().centre()
+    }
+
+    fn emission(&self) -> Colour {
+        self.material.emission
+    }
+
+    fn is_closed(&self) -> bool {
+        true
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<(Hit, Hit)> {
+        let (inside, mut crossings) = self.crossings(ray);
+        if inside {
+            crossings.insert(0, 0.);
+        }
+        hit_spans(
+            crossings
+                .chunks_exact(2)
+                .map(|pair| (pair[0], pair[1]))
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blobs(balls: &[(Vector, f64, f64)]) -> Metaballs {
+        Metaballs {
+            balls: balls
+                .iter()
+                .map(|&(centre, radius, strength)| Ball {
+                    centre,
+                    radius,
+                    strength,
+                })
+                .collect(),
+            threshold: 0.5,
+            material: Material::default(),
+        }
+    }
+
+    #[test]
+    fn single_ball_is_a_sphere() {
+        let blob = blobs(&[(Vector::new(0., 0., 5.), 2., 1.)]);
+        // (1 - r^2)^3 = 0.5 where r^2 = 1 - 0.5^(1/3)
+        let radius = 2. * (1. - 0.5f64.cbrt()).sqrt();
+        let ray = Ray::new(Vector::zero(), Vector::new(0., 0., 1.));
+        let IntersectionResult::Two(t1, t2) = blob.intersection(&ray) else {
+            panic!("expected to pass through the ball");
+        };
+        assert!((t1 - (5. - radius)).abs() < 1e-9);
+        assert!((t2 - (5. + radius)).abs() < 1e-9);
+        let normal = blob.surface(&ray, Hit::new(t1)).normal;
+        assert!((normal - Vector::new(0., 0., -1.)).length() < 1e-9);
+        // From the centre, out the far side
+        let inside = Ray::new(Vector::new(0., 0., 5.), Vector::new(0., 3., 0.));
+        let t = blob.intersection(&inside).nearest().unwrap();
+        assert!((t * 3. - radius).abs() < 1e-9);
+        assert_eq!(blob.intervals(&inside), hit_spans(vec![(0., t)]));
+    }
+
+    #[test]
+    fn nearby_balls_merge() {
+        // Alone, neither ball reaches the threshold halfway between them
+        let pair = blobs(&[
+            (Vector::new(-0.6, 0., 5.), 1., 1.),
+            (Vector::new(0.6, 0., 5.), 1., 1.),
+        ]);
+        let ray = Ray::new(Vector::zero(), Vector::new(0., 0., 1.));
+        assert!(pair.intersection(&ray).nearest().is_some());
+        // A negative ball bites into them
+        let bitten = blobs(&[
+            (Vector::new(-0.6, 0., 5.), 1., 1.),
+            (Vector::new(0.6, 0., 5.), 1., 1.),
+            (Vector::new(0., 0., 5.), 0.5, -2.),
+        ]);
+        assert_eq!(bitten.intersection(&ray), IntersectionResult::No);
+        // Normals point away from the pair's centre
+        let across = Ray::new(Vector::new(-5., 0., 5.), Vector::new(1., 0., 0.));
+        let t = pair.intersection(&across).nearest().unwrap();
+        assert!(pair.surface(&across, Hit::new(t)).normal.x < -0.99);
+    }
+
+    #[test]
+    fn spec_checks() {
+        let table: toml::Table = r#"
+        ok = {balls = [{centre = [0, 0, 5], radius = 1}], material = {colour = [1, 1, 1]}}
+        carved = {balls = [{centre = [0, 0, 5], radius = 1, strength = -1}], material = {colour = [1, 1, 1]}}
+        glowing = {balls = [{centre = [0, 0, 5], radius = 1}], material = {emission = [1, 1, 1]}}
+        "#
+        .parse()
+        .unwrap();
+        let build = |name: &str| {
+            MetaballsSpec::deserialize(table[name].clone())
+                .unwrap()
+                .build()
+        };
+        assert!(build("ok").is_ok());
+        assert!(build("carved").is_err());
+        // Seen by paths that hit them, but not sampled as a light
+        let glowing = build("glowing").unwrap();
+        assert!(glowing.emission() == Colour::white() && !glowing.can_sample());
+    }
+}
@@ -0,0 +1,153 @@
+// Resources:
+// Improved Perlin noise: https://mrl.cs.nyu.edu/~perlin/noise/
+// Turbulence and marble: Perlin 1985, "An Image Synthesizer"
+
+use crate::vector::Vector;
+
+// Ken Perlin's reference permutation
+#[rustfmt::skip]
+const PERMUTATION: [u8; 256] = [
+    151, 160, 137, 91, 90, 15, 131, 13, 201, 95, 96, 53, 194, 233, 7, 225, 140, 36, 103, 30, 69,
+    142, 8, 99, 37, 240, 21, 10, 23, 190, 6, 148, 247, 120, 234, 75, 0, 26, 197, 62, 94, 252, 219,
+    203, 117, 35, 11, 32, 57, 177, 33, 88, 237, 149, 56, 87, 174, 20, 125, 136, 171, 168, 68, 175,
+    74, 165, 71, 134, 139, 48, 27, 166, 77, 146, 158, 231, 83, 111, 229, 122, 60, 211, 133, 230,
+    220, 105, 92, 41, 55, 46, 245, 40, 244, 102, 143, 54, 65, 25, 63, 161, 1, 216, 80, 73, 209, 76,
+    132, 187, 208, 89, 18, 169, 200, 196, 135, 130, 116, 188, 159, 86, 164, 100, 109, 198, 173,
+    186, 3, 64, 52, 217, 226, 250, 124, 123, 5, 202, 38, 147, 118, 126, 255, 82, 85, 212, 207, 206,
+    59, 227, 47, 16, 58, 17, 182, 189, 28, 42, 223, 183, 170, 213, 119, 248, 152, 2, 44, 154, 163,
+    70, 221, 153, 101, 155, 167, 43, 172, 9, 129, 22, 39, 253, 19, 98, 108, 110, 79, 113, 224, 232,
+    178, 185, 112, 104, 218, 246, 97, 228, 251, 34, 242, 193, 238, 210, 144, 12, 191, 179, 162,
+    241, 81, 51, 145, 235, 249, 14, 239, 107, 49, 192, 214, 31, 181, 199, 106, 157, 184, 84, 204,
+    176, 115, 121, 50, 45, 127, 4, 150, 254, 138, 236, 205, 93, 222, 114, 67, 29, 24, 72, 243, 141,
+    128, 195, 78, 66, 215, 61, 156, 180,
+];
+
+// The reference implementation doubles the table to avoid wrapping; masking does the same
+fn permute(i: usize) -> usize {
+    PERMUTATION[i & 255] as usize
+}
+
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6. - 15.) + 10.)
+}
+
+fn lerp(t: f64, a: f64, b: f64) -> f64 {
+    a + t * (b - a)
+}
+
+// Dot product of the offset with one of 12 edge directions of a cube picked by the hash
+fn gradient(hash: usize, x: f64, y: f64, z: f64) -> f64 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 {
+        y
+    } else if h == 12 || h == 14 {
+        x
+    } else {
+        z
+    };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+// Gradient noise in roughly [-1, 1], zero at integer lattice points
+pub fn perlin(p: Vector) -> f64 {
+    let (fx, fy, fz) = (p.x.floor(), p.y.floor(), p.z.floor());
+    // Lattice cell, wrapped to the table size (the mask handles negative cells too)
+    let (xi, yi, zi) = (
+        (fx as i64 & 255) as usize,
+        (fy as i64 & 255) as usize,
+        (fz as i64 & 255) as usize,
+    );
+    let (x, y, z) = (p.x - fx, p.y - fy, p.z - fz);
+    let (u, v, w) = (fade(x), fade(y), fade(z));
+
+    // Hash each of the cell's eight corners
+    let a = permute(xi) + yi;
+    let (aa, ab) = (permute(a) + zi, permute(a + 1) + zi);
+    let b = permute(xi + 1) + yi;
+    let (ba, bb) = (permute(b) + zi, permute(b + 1) + zi);
+
+    let near = lerp(
+        v,
+        lerp(
+            u,
+            gradient(permute(aa), x, y, z),
+            gradient(permute(ba), x - 1., y, z),
+        ),
+        lerp(
+            u,
+            gradient(permute(ab), x, y - 1., z),
+            gradient(permute(bb), x - 1., y - 1., z),
+        ),
+    );
+    let far = lerp(
+        v,
+        lerp(
+            u,
+            gradient(permute(aa + 1), x, y, z - 1.),
+            gradient(permute(ba + 1), x - 1., y, z - 1.),
+        ),
+        lerp(
+            u,
+            gradient(permute(ab + 1), x, y - 1., z - 1.),
+            gradient(permute(bb + 1), x - 1., y - 1., z - 1.),
+        ),
+    );
+    lerp(w, near, far)
+}
+
+// Fractal Brownian motion: octaves of noise, each at double the frequency and half the amplitude
+pub fn fbm(p: Vector, octaves: u32) -> f64 {
+    let mut sum = 0.;
+    let mut amplitude = 1.;
+    let mut frequency = 1.;
+    for _ in 0..octaves {
+        sum += amplitude * perlin(p * frequency);
+        amplitude *= 0.5;
+        frequency *= 2.;
+    }
+    sum
+}
+
+// Like fbm but summing the absolute value of each octave, giving sharp creases
+pub fn turbulence(p: Vector, octaves: u32) -> f64 {
+    let mut sum = 0.;
+    let mut amplitude = 1.;
+    let mut frequency = 1.;
+    for _ in 0..octaves {
+        sum += amplitude * perlin(p * frequency).abs();
+        amplitude *= 0.5;
+        frequency *= 2.;
+    }
+    sum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn perlin_is_zero_on_lattice_and_bounded() {
+        assert_eq!(perlin(Vector::new(3., -2., 7.)), 0.);
+        for i in 0..1000 {
+            let t = i as f64 * 0.137;
+            let n = perlin(Vector::new(t, t * 0.7 - 5., t * 1.3 + 2.));
+            assert!((-1.1..=1.1).contains(&n));
+        }
+    }
+
+    #[test]
+    fn perlin_is_continuous() {
+        let p = Vector::new(1.999999, 0.5, 0.25);
+        let q = Vector::new(2.000001, 0.5, 0.25);
+        assert!((perlin(p) - perlin(q)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn turbulence_is_non_negative() {
+        for i in 0..100 {
+            let p = Vector::new(i as f64 * 0.31, 1.7, -0.4 * i as f64);
+            assert!(turbulence(p, 4) >= 0.);
+        }
+    }
+}
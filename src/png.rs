@@ -0,0 +1,637 @@
+// Resources:
+// PNG specification: https://www.w3.org/TR/png/
+// DEFLATE: https://www.rfc-editor.org/rfc/rfc1951
+// zlib: https://www.rfc-editor.org/rfc/rfc1950
+// Huffman decoding approach from zlib's puff.c
+
+use std::error::Error;
+
+use crate::colour::Colour;
+use crate::image::{self, FloatImage};
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+// Decode a non-interlaced PNG. Alpha is ignored and values are kept as stored, without gamma
+// decoding, the same way rendered colours are written out.
+pub fn decode(bytes: &[u8]) -> Result<FloatImage, Box<dyn Error>> {
+    if bytes.len() < 8 || bytes[0..8] != PNG_SIGNATURE {
+        return Err("not a PNG file".into());
+    }
+
+    let mut pos = 8;
+    let mut header = None;
+    let mut palette: Vec<[u8; 3]> = Vec::new();
+    let mut compressed = Vec::new();
+    while pos + 8 <= bytes.len() {
+        let length = u32::from_be_bytes(bytes[pos..pos + 4].try_into()?) as usize;
+        let chunk_type = &bytes[pos + 4..pos + 8];
+        let data = bytes
+            .get(pos + 8..pos + 8 + length)
+            .ok_or("truncated PNG chunk")?;
+        pos += 12 + length; // length, type, data, crc
+
+        match chunk_type {
+            b"IHDR" => header = Some(Header::parse(data)?),
+            b"PLTE" => {
+                palette = data.chunks_exact(3).map(|c| [c[0], c[1], c[2]]).collect();
+            }
+            b"IDAT" => compressed.extend_from_slice(data),
+            b"IEND" => break,
+            _ => {}
+        }
+    }
+
+    let header = header.ok_or("PNG is missing its IHDR chunk")?;
+    // Each scanline is preceded by its filter type
+    let raw = zlib_decompress(&compressed, (header.row_bytes() + 1) * header.height)?;
+    let scanlines = unfilter(&raw, &header)?;
+
+    let mut pixels = Vec::with_capacity(header.width * header.height);
+    for row in scanlines.chunks_exact(header.row_bytes()) {
+        for x in 0..header.width {
+            pixels.push(header.pixel(row, x, &palette)?);
+        }
+    }
+
+    Ok(FloatImage::new(header.width, header.height, pixels))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Header {
+    width: usize,
+    height: usize,
+    bit_depth: u8,
+    colour_type: u8,
+}
+
+impl Header {
+    fn parse(data: &[u8]) -> Result<Self, Box<dyn Error>> {
+        if data.len() < 13 {
+            return Err("bad PNG header".into());
+        }
+        let header = Header {
+            width: u32::from_be_bytes(data[0..4].try_into()?) as usize,
+            height: u32::from_be_bytes(data[4..8].try_into()?) as usize,
+            bit_depth: data[8],
+            colour_type: data[9],
+        };
+        image::check_dimensions(header.width, header.height)?;
+        if data[12] != 0 {
+            return Err("interlaced PNGs are not supported".into());
+        }
+        let valid_depth = match header.colour_type {
+            0 => matches!(header.bit_depth, 1 | 2 | 4 | 8 | 16),
+            3 => matches!(header.bit_depth, 1 | 2 | 4 | 8),
+            2 | 4 | 6 => matches!(header.bit_depth, 8 | 16),
+            _ => false,
+        };
+        if !valid_depth {
+            return Err(format!(
+                "unsupported PNG colour type {} with bit depth {}",
+                header.colour_type, header.bit_depth
+            )
+            .into());
+        }
+        Ok(header)
+    }
+
+    fn channels(&self) -> usize {
+        match self.colour_type {
+            2 => 3,
+            4 => 2,
+            6 => 4,
+            _ => 1, // greyscale or palette
+        }
+    }
+
+    fn bits_per_pixel(&self) -> usize {
+        self.channels() * self.bit_depth as usize
+    }
+
+    fn row_bytes(&self) -> usize {
+        (self.width * self.bits_per_pixel()).div_ceil(8)
+    }
+
+    // Sample `channel` of pixel `x`, unscaled
+    fn sample(&self, row: &[u8], x: usize, channel: usize) -> u32 {
+        let index = x * self.channels() + channel;
+        match self.bit_depth {
+            16 => u16::from_be_bytes([row[2 * index], row[2 * index + 1]]) as u32,
+            8 => row[index] as u32,
+            depth => {
+                let depth = depth as usize;
+                let bit = index * depth;
+                let shift = 8 - depth - bit % 8;
+                ((row[bit / 8] >> shift) & ((1 << depth) - 1) as u8) as u32
+            }
+        }
+    }
+
+    fn pixel(&self, row: &[u8], x: usize, palette: &[[u8; 3]]) -> Result<Colour, Box<dyn Error>> {
+        let max = ((1u32 << self.bit_depth) - 1) as f32;
+        let value = |channel| self.sample(row, x, channel) as f32 / max;
+        Ok(match self.colour_type {
+            0 | 4 => Colour::new(value(0), value(0), value(0)),
+            2 | 6 => Colour::new(value(0), value(1), value(2)),
+            _ => {
+                let entry = palette
+                    .get(self.sample(row, x, 0) as usize)
+                    .ok_or("PNG palette index out of range")?;
+                Colour::new(
+                    entry[0] as f32 / 255.,
+                    entry[1] as f32 / 255.,
+                    entry[2] as f32 / 255.,
+                )
+            }
+        })
+    }
+}
+
+// Undo the per-scanline filters, returning the rows without their filter type bytes
+fn unfilter(raw: &[u8], header: &Header) -> Result<Vec<u8>, Box<dyn Error>> {
+    let row_bytes = header.row_bytes();
+    // Filters work on whole bytes, with sub-byte pixels treated as one byte
+    let bpp = header.bits_per_pixel().div_ceil(8);
+    if raw.len() < (row_bytes + 1) * header.height {
+        return Err("truncated PNG image data".into());
+    }
+
+    let mut out = vec![0u8; row_bytes * header.height];
+    for y in 0..header.height {
+        let filter = raw[y * (row_bytes + 1)];
+        let line = &raw[y * (row_bytes + 1) + 1..(y + 1) * (row_bytes + 1)];
+        let (previous_rows, current) = out.split_at_mut(y * row_bytes);
+        let previous = if y > 0 {
+            &previous_rows[(y - 1) * row_bytes..]
+        } else {
+            &[][..]
+        };
+        let current = &mut current[..row_bytes];
+
+        for i in 0..row_bytes {
+            let a = if i >= bpp { current[i - bpp] } else { 0 };
+            let b = previous.get(i).copied().unwrap_or(0);
+            let c = if i >= bpp {
+                previous.get(i - bpp).copied().unwrap_or(0)
+            } else {
+                0
+            };
+            let predictor = match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => return Err(format!("bad PNG filter type {}", filter).into()),
+            };
+            current[i] = line[i].wrapping_add(predictor);
+        }
+    }
+    Ok(out)
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+// Decompresses no more than `limit` bytes, so a small stream can't claim a huge output
+pub fn zlib_decompress(data: &[u8], limit: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+    if data.len() < 6 {
+        return Err("truncated zlib stream".into());
+    }
+    let (cmf, flg) = (data[0], data[1]);
+    if cmf & 0x0F != 8 || !(cmf as u16 * 256 + flg as u16).is_multiple_of(31) {
+        return Err("bad zlib header".into());
+    }
+    if flg & 0x20 != 0 {
+        return Err("zlib preset dictionaries are not supported".into());
+    }
+
+    let mut reader = BitReader::new(&data[2..]);
+    let out = inflate(&mut reader, limit)?;
+
+    let trailer = data
+        .get(2 + reader.pos..2 + reader.pos + 4)
+        .ok_or("missing zlib checksum")?;
+    if u32::from_be_bytes(trailer.try_into()?) != adler32(&out) {
+        return Err("zlib checksum mismatch".into());
+    }
+    Ok(out)
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for byte in chunk {
+            a += *byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize, // next byte to load
+    buffer: u32,
+    count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        BitReader {
+            data,
+            pos: 0,
+            buffer: 0,
+            count: 0,
+        }
+    }
+
+    // DEFLATE packs values starting from the least significant bit
+    fn bits(&mut self, n: u32) -> Result<u32, Box<dyn Error>> {
+        while self.count < n {
+            let byte = *self.data.get(self.pos).ok_or("truncated deflate stream")?;
+            self.pos += 1;
+            self.buffer |= (byte as u32) << self.count;
+            self.count += 8;
+        }
+        let value = self.buffer & ((1u64 << n) - 1) as u32;
+        self.buffer >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+
+    fn align_to_byte(&mut self) {
+        self.buffer = 0;
+        self.count = 0;
+    }
+}
+
+const MAX_BITS: usize = 15;
+
+// Canonical Huffman code: how many codes there are of each length, and the symbols ordered by code
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0u16; MAX_BITS + 1];
+        for length in 1..MAX_BITS {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, Box<dyn Error>> {
+        let mut code: i32 = 0; // bits read so far
+        let mut first: i32 = 0; // first code of the current length
+        let mut index: i32 = 0; // index of that first code in symbols
+        for length in 1..=MAX_BITS {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+        Err("invalid Huffman code".into())
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+// Order in which code length code lengths are stored in a dynamic block header
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+const TOO_LONG: &str = "decompressed data is longer than expected";
+
+fn inflate(reader: &mut BitReader, limit: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut out = Vec::new();
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => stored_block(reader, &mut out, limit)?,
+            1 => {
+                let (literals, distances) = fixed_codes();
+                compressed_block(reader, &mut out, limit, &literals, &distances)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_codes(reader)?;
+                compressed_block(reader, &mut out, limit, &literals, &distances)?;
+            }
+            _ => return Err("invalid deflate block type".into()),
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+fn stored_block(
+    reader: &mut BitReader,
+    out: &mut Vec<u8>,
+    limit: usize,
+) -> Result<(), Box<dyn Error>> {
+    reader.align_to_byte();
+    let header = reader
+        .data
+        .get(reader.pos..reader.pos + 4)
+        .ok_or("truncated stored block")?;
+    let length = u16::from_le_bytes([header[0], header[1]]);
+    let complement = u16::from_le_bytes([header[2], header[3]]);
+    if length != !complement {
+        return Err("stored block length mismatch".into());
+    }
+    let start = reader.pos + 4;
+    let data = reader
+        .data
+        .get(start..start + length as usize)
+        .ok_or("truncated stored block")?;
+    if out.len() + data.len() > limit {
+        return Err(TOO_LONG.into());
+    }
+    out.extend_from_slice(data);
+    reader.pos = start + length as usize;
+    Ok(())
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    lengths[0..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..288].fill(8);
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+fn dynamic_codes(reader: &mut BitReader) -> Result<(Huffman, Huffman), Box<dyn Error>> {
+    let num_literals = reader.bits(5)? as usize + 257;
+    let num_distances = reader.bits(5)? as usize + 1;
+    let num_code_lengths = reader.bits(4)? as usize + 4;
+
+    let mut code_length_lengths = [0u8; 19];
+    for &i in CODE_LENGTH_ORDER.iter().take(num_code_lengths) {
+        code_length_lengths[i] = reader.bits(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&code_length_lengths);
+
+    let mut lengths = Vec::with_capacity(num_literals + num_distances);
+    while lengths.len() < num_literals + num_distances {
+        let symbol = code_lengths.decode(reader)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths.last().ok_or("repeat with no previous length")?;
+                (previous, 3 + reader.bits(2)?)
+            }
+            17 => (0, 3 + reader.bits(3)?),
+            _ => (0, 11 + reader.bits(7)?),
+        };
+        for _ in 0..repeat {
+            lengths.push(value);
+        }
+    }
+    if lengths.len() != num_literals + num_distances {
+        return Err("too many code lengths".into());
+    }
+
+    Ok((
+        Huffman::new(&lengths[..num_literals]),
+        Huffman::new(&lengths[num_literals..]),
+    ))
+}
+
+fn compressed_block(
+    reader: &mut BitReader,
+    out: &mut Vec<u8>,
+    limit: usize,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> Result<(), Box<dyn Error>> {
+    loop {
+        let symbol = literals.decode(reader)? as usize;
+        match symbol {
+            0..=255 => {
+                if out.len() == limit {
+                    return Err(TOO_LONG.into());
+                }
+                out.push(symbol as u8);
+            }
+            256 => return Ok(()),
+            _ => {
+                let index = symbol - 257;
+                if index >= LENGTH_BASE.len() {
+                    return Err("invalid length symbol".into());
+                }
+                let length =
+                    LENGTH_BASE[index] as usize + reader.bits(LENGTH_EXTRA[index] as u32)? as usize;
+
+                let index = distances.decode(reader)? as usize;
+                if index >= DISTANCE_BASE.len() {
+                    return Err("invalid distance symbol".into());
+                }
+                let distance = DISTANCE_BASE[index] as usize
+                    + reader.bits(DISTANCE_EXTRA[index] as u32)? as usize;
+                if distance > out.len() {
+                    return Err("distance too far back".into());
+                }
+                if out.len() + length > limit {
+                    return Err(TOO_LONG.into());
+                }
+
+                // Copies may overlap the bytes being written
+                let start = out.len() - distance;
+                for i in 0..length {
+                    out.push(out[start + i]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Streams below were produced with Python's zlib.compress
+
+    #[test]
+    fn inflate_fixed_block() {
+        let data = [
+            0x78, 0x9c, 0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x27, 0x01, 0x68, 0x03,
+            0x08, 0xb1,
+        ];
+        assert_eq!(
+            zlib_decompress(&data, 100).unwrap(),
+            b"hello hello hello hello"
+        );
+        // Most of it is copies of the first "hello ", which mustn't run past the limit
+        assert!(zlib_decompress(&data, 22).is_err());
+        assert!(zlib_decompress(&data, 3).is_err());
+    }
+
+    #[test]
+    fn inflate_dynamic_block() {
+        let data = [
+            0x78, 0xda, 0xb5, 0xcb, 0xd1, 0x01, 0x80, 0x10, 0x14, 0x46, 0xe1, 0x55, 0xfe, 0x16,
+            0x68, 0x96, 0x1e, 0x2c, 0x40, 0x11, 0x15, 0x37, 0x84, 0x98, 0xbe, 0xbb, 0x44, 0xcf,
+            0xe7, 0x3b, 0xc2, 0x6a, 0xc4, 0xe2, 0xd6, 0x13, 0x2a, 0x51, 0x0b, 0x30, 0xf4, 0xe2,
+            0x28, 0xfe, 0xce, 0xa0, 0xaa, 0x13, 0x1e, 0xce, 0x97, 0x1c, 0x1d, 0x1b, 0xed, 0x33,
+            0xc4, 0x6f, 0x78, 0x91, 0xec, 0x7c, 0x87, 0x62, 0xd4, 0xdc, 0x63, 0x61, 0x5c, 0xd5,
+            0x9c, 0x86, 0x0e, 0xb8, 0x5c, 0x2c, 0x94, 0xf8, 0xdd, 0xf3, 0xf4, 0x01, 0xb2, 0xee,
+            0x3f, 0x00,
+        ];
+        let mut expected = b"The quick brown fox jumps over the lazy dog. ".repeat(3);
+        expected.extend_from_slice(b"Pack my box with five dozen liquor jugs!");
+        assert_eq!(zlib_decompress(&data, expected.len()).unwrap(), expected);
+    }
+
+    #[test]
+    fn inflate_stored_block() {
+        let payload = b"raw bytes";
+        let mut data = vec![0x78, 0x01, 0x01];
+        data.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+        data.extend_from_slice(&(!(payload.len() as u16)).to_le_bytes());
+        data.extend_from_slice(payload);
+        data.extend_from_slice(&adler32(payload).to_be_bytes());
+        assert_eq!(zlib_decompress(&data, payload.len()).unwrap(), payload);
+        assert!(zlib_decompress(&data, payload.len() - 1).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_checksum() {
+        let mut data = vec![0x78, 0x01, 0x01, 1, 0, 0xfe, 0xff, 42];
+        data.extend_from_slice(&[0, 0, 0, 0]);
+        assert!(zlib_decompress(&data, 100).is_err());
+    }
+
+    #[test]
+    fn paeth_predictor() {
+        assert_eq!(paeth(10, 20, 10), 20);
+        assert_eq!(paeth(20, 10, 10), 20);
+        assert_eq!(paeth(5, 5, 5), 5);
+    }
+
+    fn chunk(chunk_type: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut out = (data.len() as u32).to_be_bytes().to_vec();
+        out.extend_from_slice(chunk_type);
+        out.extend_from_slice(data);
+        out.extend_from_slice(&[0; 4]); // crc is not checked
+        out
+    }
+
+    fn stored_zlib(payload: &[u8]) -> Vec<u8> {
+        let mut data = vec![0x78, 0x01, 0x01];
+        data.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+        data.extend_from_slice(&(!(payload.len() as u16)).to_le_bytes());
+        data.extend_from_slice(payload);
+        data.extend_from_slice(&adler32(payload).to_be_bytes());
+        data
+    }
+
+    #[test]
+    fn decode_filtered_rgb_png() {
+        // 2x2 RGB: first row unfiltered, second row using the Up filter
+        let raw = [
+            0, 255, 0, 0, 0, 255, 0, //
+            2, 0, 0, 255, 0, 0, 0,
+        ];
+        let mut ihdr = Vec::new();
+        ihdr.extend_from_slice(&2u32.to_be_bytes());
+        ihdr.extend_from_slice(&2u32.to_be_bytes());
+        ihdr.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+        let mut png = PNG_SIGNATURE.to_vec();
+        png.extend(chunk(b"IHDR", &ihdr));
+        png.extend(chunk(b"IDAT", &stored_zlib(&raw)));
+        png.extend(chunk(b"IEND", &[]));
+
+        let image = decode(&png).unwrap();
+        assert_eq!(image.pixel(0, 0), Colour::new(1., 0., 0.));
+        assert_eq!(image.pixel(1, 0), Colour::new(0., 1., 0.));
+        assert_eq!(image.pixel(0, 1), Colour::new(1., 0., 1.));
+        assert_eq!(image.pixel(1, 1), Colour::new(0., 1., 0.));
+    }
+
+    #[test]
+    fn decode_palette_png_with_sub_byte_indices() {
+        // 4x1, 2 bits per index
+        let raw = [0, 0b00_01_10_11];
+        let mut ihdr = Vec::new();
+        ihdr.extend_from_slice(&4u32.to_be_bytes());
+        ihdr.extend_from_slice(&1u32.to_be_bytes());
+        ihdr.extend_from_slice(&[2, 3, 0, 0, 0]);
+        let palette = [0, 0, 0, 255, 0, 0, 0, 255, 0, 0, 0, 255];
+
+        let mut png = PNG_SIGNATURE.to_vec();
+        png.extend(chunk(b"IHDR", &ihdr));
+        png.extend(chunk(b"PLTE", &palette));
+        png.extend(chunk(b"IDAT", &stored_zlib(&raw)));
+        png.extend(chunk(b"IEND", &[]));
+
+        let image = decode(&png).unwrap();
+        assert_eq!(image.pixel(0, 0), Colour::black());
+        assert_eq!(image.pixel(1, 0), Colour::new(1., 0., 0.));
+        assert_eq!(image.pixel(3, 0), Colour::new(0., 0., 1.));
+    }
+
+    #[test]
+    fn rejects_empty_and_oversize_headers() {
+        for (width, height) in [(0u32, 4u32), (4, 0), (u32::MAX, u32::MAX)] {
+            let mut ihdr = Vec::new();
+            ihdr.extend_from_slice(&width.to_be_bytes());
+            ihdr.extend_from_slice(&height.to_be_bytes());
+            ihdr.extend_from_slice(&[8, 2, 0, 0, 0]);
+            let mut png = PNG_SIGNATURE.to_vec();
+            png.extend(chunk(b"IHDR", &ihdr));
+            png.extend(chunk(b"IDAT", &stored_zlib(&[])));
+            png.extend(chunk(b"IEND", &[]));
+            assert!(decode(&png).is_err(), "{}x{}", width, height);
+        }
+    }
+}
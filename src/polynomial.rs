@@ -0,0 +1,186 @@
+// Resources:
+// Real roots of cubics: https://en.wikipedia.org/wiki/Cubic_equation#Trigonometric_and_hyperbolic_solutions
+// Ferrari's method for quartics: https://en.wikipedia.org/wiki/Quartic_function#Ferrari's_solution
+// Numerically stable quadratics: https://pbr-book.org/4ed/Utilities/Mathematical_Infrastructure#Quadratic
+
+use core::f64;
+
+// Newton steps used to polish roots found in closed form
+const POLISH_ITERATIONS: usize = 4;
+
+// Real roots of a t^2 + b t + c = 0, in ascending order
+pub fn solve_quadratic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    if a == 0. {
+        if b == 0. {
+            return vec![];
+        }
+        return vec![-c / b];
+    }
+    let discriminant = b * b - 4. * a * c;
+    if discriminant < 0. {
+        return vec![];
+    }
+    // Avoid cancellation between -b and the root of the discriminant
+    let q = -0.5 * (b + discriminant.sqrt().copysign(b));
+    if q == 0. {
+        // b and c are both zero
+        return vec![0.];
+    }
+    let (t0, t1) = (q / a, c / q);
+    if t0 < t1 {
+        vec![t0, t1]
+    } else {
+        vec![t1, t0]
+    }
+}
+
+// Real roots of t^3 + a t^2 + b t + c = 0, in ascending order
+pub fn solve_cubic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    // Substituting t = x - a/3 gives the depressed cubic x^3 + p x + q = 0
+    let shift = a / 3.;
+    let p = b - a * shift;
+    let q = c + shift * (2. * shift * shift - b);
+
+    let mut roots = if p == 0. {
+        vec![(-q).cbrt()]
+    } else {
+        let discriminant = (q / 2.).powi(2) + (p / 3.).powi(3);
+        if discriminant > 0. {
+            // One real root
+            let root = discriminant.sqrt();
+            vec![(-q / 2. + root).cbrt() + (-q / 2. - root).cbrt()]
+        } else {
+            // Three real roots, found with trigonometry to stay in the reals
+            let m = 2. * (-p / 3.).sqrt();
+            let theta = (3. * q / (p * m)).clamp(-1., 1.).acos() / 3.;
+            (0..3)
+                .map(|k| m * (theta - 2. * f64::consts::PI * k as f64 / 3.).cos())
+                .collect()
+        }
+    };
+    for x in &mut roots {
+        *x -= shift;
+        *x = polish(&[1., a, b, c], *x);
+    }
+    roots.sort_by(f64::total_cmp);
+    roots
+}
+
+// Real roots of t^4 + a t^3 + b t^2 + c t + d = 0, in ascending order
+pub fn solve_quartic(a: f64, b: f64, c: f64, d: f64) -> Vec<f64> {
+    // Substituting t = x - a/4 gives the depressed quartic x^4 + p x^2 + q x + r = 0
+    let shift = a / 4.;
+    let a2 = a * a;
+    let p = b - 3. / 8. * a2;
+    let q = c - a * b / 2. + a2 * a / 8.;
+    let r = d - a * c / 4. + a2 * b / 16. - 3. / 256. * a2 * a2;
+
+    let mut roots = Vec::with_capacity(4);
+    if q.abs() < 1e-12 * (1. + p.abs() + r.abs()) {
+        // Biquadratic: a quadratic in x^2
+        for y in solve_quadratic(1., p, r) {
+            if y >= 0. {
+                roots.extend([-y.sqrt(), y.sqrt()]);
+            }
+        }
+    } else {
+        // Adding 2 m x^2 + m^2 + m p to both sides of x^4 = -p x^2 - q x - r makes the left
+        // (x^2 + p/2 + m)^2, and the right a perfect square when m is a positive root of
+        // 8 m^3 + 8 p m^2 + (2 p^2 - 8 r) m - q^2 = 0. There always is one since q isn't zero.
+        let resolvent = solve_cubic(p, p * p / 4. - r, -q * q / 8.);
+        let m = resolvent.last().copied().unwrap_or(0.);
+        if m <= 0. {
+            return vec![];
+        }
+        let s = (2. * m).sqrt();
+        // x^2 + p/2 + m = ±(s x - q / 2s)
+        roots.extend(solve_quadratic(1., -s, p / 2. + m + q / (2. * s)));
+        roots.extend(solve_quadratic(1., s, p / 2. + m - q / (2. * s)));
+    }
+    for x in &mut roots {
+        *x = polish(&[1., a, b, c, d], *x - shift);
+    }
+    roots.sort_by(f64::total_cmp);
+    roots
+}
+
+// Value and derivative of the polynomial with the given coefficients, highest power first
+fn evaluate(coefficients: &[f64], x: f64) -> (f64, f64) {
+    coefficients.iter().fold((0., 0.), |(value, slope), &k| {
+        (value * x + k, slope * x + value)
+    })
+}
+
+// Refine a root with Newton's method, keeping the original if a step makes it worse
+fn polish(coefficients: &[f64], mut x: f64) -> f64 {
+    let (mut value, mut slope) = evaluate(coefficients, x);
+    for _ in 0..POLISH_ITERATIONS {
+        if slope == 0. || value == 0. {
+            break;
+        }
+        let next = x - value / slope;
+        let (next_value, next_slope) = evaluate(coefficients, next);
+        if next_value.abs() >= value.abs() {
+            break;
+        }
+        (x, value, slope) = (next, next_value, next_slope);
+    }
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_roots(found: Vec<f64>, expected: &[f64]) {
+        assert_eq!(found.len(), expected.len(), "{:?}", found);
+        for (x, y) in found.iter().zip(expected) {
+            assert!((x - y).abs() < 1e-9, "{:?} != {:?}", found, expected);
+        }
+    }
+
+    #[test]
+    fn quadratics_and_cubics() {
+        assert_roots(solve_quadratic(1., -3., 2.), &[1., 2.]);
+        assert_roots(solve_quadratic(0., 2., -4.), &[2.]);
+        assert_roots(solve_quadratic(1., 0., 1.), &[]);
+        // Roots far apart in size, where the textbook formula loses the small one
+        assert_roots(solve_quadratic(1., -1e8, 1.), &[1e-8, 1e8]);
+        // (t - 1)(t - 2)(t - 3)
+        assert_roots(solve_cubic(-6., 11., -6.), &[1., 2., 3.]);
+        // (t + 2)(t^2 + 1)
+        assert_roots(solve_cubic(2., 1., 2.), &[-2.]);
+    }
+
+    #[test]
+    fn quartics() {
+        // (t - 1)(t - 2)(t - 3)(t - 4)
+        assert_roots(solve_quartic(-10., 35., -50., 24.), &[1., 2., 3., 4.]);
+        // (t^2 - 1)(t^2 - 4), which is biquadratic
+        assert_roots(solve_quartic(0., -5., 0., 4.), &[-2., -1., 1., 2.]);
+        // (t - 0.5)(t + 3)(t^2 + 1)
+        assert_roots(solve_quartic(2.5, -0.5, 2.5, -1.5), &[-3., 0.5]);
+        assert_roots(solve_quartic(0., 1., 0., 1.), &[]);
+        // Roots spread like those of a ray grazing a distant torus
+        let roots = [10., 10.001, 12., 12.5];
+        let (e1, e2, e3, e4) = (
+            roots.iter().sum::<f64>(),
+            roots[0] * roots[1]
+                + roots[0] * roots[2]
+                + roots[0] * roots[3]
+                + roots[1] * roots[2]
+                + roots[1] * roots[3]
+                + roots[2] * roots[3],
+            roots[0] * roots[1] * roots[2]
+                + roots[0] * roots[1] * roots[3]
+                + roots[0] * roots[2] * roots[3]
+                + roots[1] * roots[2] * roots[3],
+            roots.iter().product::<f64>(),
+        );
+        let found = solve_quartic(-e1, e2, -e3, e4);
+        assert_eq!(found.len(), 4);
+        for (x, y) in found.iter().zip(&roots) {
+            assert!((x - y).abs() < 1e-6, "{:?}", found);
+        }
+    }
+}
@@ -0,0 +1,284 @@
+// Resources:
+// Burley 2012, "Physically Based Shading at Disney": https://media.disneyanimation.com/uploads/production/publication_asset/48/asset/s2012_pbs_disney_brdf_notes_v3.pdf
+// Burley 2015, "Extending the Disney BRDF to a BSDF with Integrated Subsurface Scattering": https://blog.selfshadow.com/publications/s2015-shading-course/burley/s2015_pbs_disney_bsdf_notes.pdf
+
+use core::f64;
+
+use crate::bsdf::{self, Bsdf, BsdfSample, Dielectric, TrowbridgeReitz};
+use crate::colour::Colour;
+use crate::sampling;
+use crate::vector::Vector;
+
+// Parameters of the principled BSDF at one point, all in [0, 1] except `ior`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Principled {
+    pub base_colour: Colour,
+    pub metallic: f64,
+    pub roughness: f64,
+    pub specular: f64, // scales normal-incidence reflectance of the dielectric base, 0.5 is 4%
+    pub sheen: f64,
+    pub sheen_tint: f64,
+    pub clearcoat: f64,
+    pub clearcoat_roughness: f64,
+    pub transmission: f64,
+    pub ior: f64,
+}
+
+fn schlick_weight(cos_theta: f64) -> f64 {
+    (1. - cos_theta.clamp(0., 1.)).powi(5)
+}
+
+fn luminance(c: Colour) -> f64 {
+    (0.2126 * c.r + 0.7152 * c.g + 0.0722 * c.b) as f64
+}
+
+fn mix(a: Colour, b: Colour, t: f64) -> Colour {
+    a * (1. - t) as f32 + b * t as f32
+}
+
+// Each lobe is sampled in proportion to a rough estimate of how much it reflects
+struct LobeWeights {
+    diffuse: f64,
+    specular: f64,
+    clearcoat: f64,
+    transmission: f64,
+}
+
+impl LobeWeights {
+    fn total(&self) -> f64 {
+        self.diffuse + self.specular + self.clearcoat + self.transmission
+    }
+}
+
+impl Principled {
+    fn diffuse_weight(&self) -> f64 {
+        (1. - self.metallic) * (1. - self.transmission)
+    }
+
+    fn transmission_weight(&self) -> f64 {
+        (1. - self.metallic) * self.transmission
+    }
+
+    // Normal incidence reflectance of the specular lobe, blending from the dielectric value to
+    // the base colour as the surface becomes metallic
+    fn specular_f0(&self) -> Colour {
+        let dielectric = Colour::white() * (0.08 * self.specular) as f32;
+        mix(dielectric, self.base_colour, self.metallic)
+    }
+
+    fn lobe_weights(&self) -> LobeWeights {
+        LobeWeights {
+            diffuse: self.diffuse_weight() * luminance(self.base_colour),
+            specular: (1. - self.transmission_weight()) * luminance(self.specular_f0()).max(0.1),
+            clearcoat: 0.25 * self.clearcoat,
+            transmission: self.transmission_weight(),
+        }
+    }
+
+    fn distribution(&self) -> TrowbridgeReitz {
+        TrowbridgeReitz::from_roughness(self.roughness)
+    }
+
+    fn clearcoat_distribution(&self) -> TrowbridgeReitz {
+        TrowbridgeReitz::from_roughness(self.clearcoat_roughness)
+    }
+
+    fn transmission_bsdf(&self) -> Dielectric {
+        Dielectric {
+            eta: self.ior,
+            distribution: self.distribution(),
+        }
+    }
+}
+
+impl Bsdf for Principled {
+    fn evaluate(&self, wo: Vector, wi: Vector) -> Colour {
+        let mut f = Colour::black();
+
+        let transmission = self.transmission_weight();
+        if transmission > 0. {
+            let mut t = self.transmission_bsdf().evaluate(wo, wi) * transmission as f32;
+            // Light passing through the surface is tinted by the base colour
+            if wo.z * wi.z < 0. {
+                t *= self.base_colour;
+            }
+            f += t;
+        }
+
+        if wo.z * wi.z <= 0. {
+            return f;
+        }
+        // The reflective lobes are two-sided
+        let (wo, wi) = if wo.z < 0. { (-wo, -wi) } else { (wo, wi) };
+        let wm = (wo + wi).normalised();
+        let (cos_o, cos_i, cos_d) = (wo.z, wi.z, wi.dot(&wm));
+
+        let diffuse = self.diffuse_weight();
+        if diffuse > 0. {
+            // Burley diffuse, with retro-reflection at grazing angles on rough surfaces
+            let fd90 = 0.5 + 2. * self.roughness * cos_d * cos_d;
+            let fresnel_o = 1. + (fd90 - 1.) * schlick_weight(cos_o);
+            let fresnel_i = 1. + (fd90 - 1.) * schlick_weight(cos_i);
+            f += self.base_colour * (diffuse * fresnel_o * fresnel_i / f64::consts::PI) as f32;
+
+            if self.sheen > 0. {
+                let luminance = luminance(self.base_colour);
+                let tint = if luminance > 0. {
+                    self.base_colour / luminance as f32
+                } else {
+                    Colour::white()
+                };
+                let sheen = mix(Colour::white(), tint, self.sheen_tint);
+                f += sheen * (diffuse * self.sheen * schlick_weight(cos_d)) as f32;
+            }
+        }
+
+        let specular = 1. - transmission;
+        if specular > 0. {
+            let f0 = self.specular_f0();
+            let fresnel = mix(f0, Colour::white(), schlick_weight(cos_d));
+            let distribution = self.distribution();
+            let value = distribution.d(wm) * distribution.g(wo, wi) / (4. * cos_o * cos_i);
+            f += fresnel * (specular * value) as f32;
+        }
+
+        if self.clearcoat > 0. {
+            let fresnel = 0.04 + 0.96 * schlick_weight(cos_d);
+            let distribution = self.clearcoat_distribution();
+            let value = distribution.d(wm) * distribution.g(wo, wi) / (4. * cos_o * cos_i);
+            f += Colour::white() * (0.25 * self.clearcoat * fresnel * value) as f32;
+        }
+
+        f
+    }
+
+    fn sample(&self, wo: Vector, u_lobe: f64, u: (f64, f64)) -> Option<BsdfSample> {
+        let weights = self.lobe_weights();
+        let total = weights.total();
+        if total <= 0. || wo.z == 0. {
+            return None;
+        }
+        let mut u_lobe = u_lobe * total;
+
+        if u_lobe < weights.transmission {
+            // Reuse the remaining fraction of the lobe choice for reflection or refraction
+            let u_lobe = u_lobe / weights.transmission;
+            let wi = self.transmission_bsdf().sample(wo, u_lobe, u)?.wi;
+            return bsdf::sample_towards(self, wo, wi);
+        }
+        u_lobe -= weights.transmission;
+
+        let flip = if wo.z < 0. { -1. } else { 1. };
+        let wo_up = wo * flip;
+        let wi = if u_lobe < weights.diffuse {
+            sampling::cosine_hemisphere(Vector::new(0., 0., 1.), u)
+        } else if u_lobe < weights.diffuse + weights.specular {
+            bsdf::reflect(wo_up, self.distribution().sample_wm(wo_up, u))
+        } else {
+            bsdf::reflect(wo_up, self.clearcoat_distribution().sample_wm(wo_up, u))
+        };
+        bsdf::sample_towards(self, wo, wi * flip)
+    }
+
+    fn pdf(&self, wo: Vector, wi: Vector) -> f64 {
+        let weights = self.lobe_weights();
+        let total = weights.total();
+        if total <= 0. {
+            return 0.;
+        }
+
+        let mut pdf = weights.transmission * self.transmission_bsdf().pdf(wo, wi);
+        if wo.z * wi.z > 0. {
+            let (wo, wi) = if wo.z < 0. { (-wo, -wi) } else { (wo, wi) };
+            let wm = (wo + wi).normalised();
+            let reflect_pdf = |d: TrowbridgeReitz| d.pdf(wo, wm) / (4. * wo.dot(&wm).abs());
+            pdf += weights.diffuse * sampling::cosine_hemisphere_pdf(wi.z);
+            pdf += weights.specular * reflect_pdf(self.distribution());
+            pdf += weights.clearcoat * reflect_pdf(self.clearcoat_distribution());
+        }
+        pdf / total
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::Rng;
+
+    fn principled() -> Principled {
+        Principled {
+            base_colour: Colour::new(0.8, 0.5, 0.2),
+            metallic: 0.3,
+            roughness: 0.4,
+            specular: 0.5,
+            sheen: 0.5,
+            sheen_tint: 0.5,
+            clearcoat: 1.,
+            clearcoat_roughness: 0.1,
+            transmission: 0.4,
+            ior: 1.45,
+        }
+    }
+
+    #[test]
+    fn samples_agree_with_pdf() {
+        let bsdf = principled();
+        let mut rng = Rng::new(4);
+        for wo in [
+            Vector::new(0.3, 0.2, 0.9).normalised(),
+            Vector::new(-0.5, 0.1, -0.6).normalised(),
+        ] {
+            let mut transmitted = 0;
+            for _ in 0..1000 {
+                if let Some(sample) = bsdf.sample(wo, rng.next_f64(), rng.next_2d()) {
+                    let pdf = bsdf.pdf(wo, sample.wi);
+                    assert!((pdf - sample.pdf).abs() <= 1e-6 * pdf);
+                    if sample.wi.z * wo.z < 0. {
+                        transmitted += 1;
+                    }
+                }
+            }
+            assert!(transmitted > 0);
+        }
+    }
+
+    #[test]
+    fn metallic_reflects_base_colour_and_does_not_transmit() {
+        let metal = Principled {
+            metallic: 1.,
+            sheen: 0.,
+            clearcoat: 0.,
+            ..principled()
+        };
+        let wo = Vector::new(0., 0., 1.);
+        assert!(metal.evaluate(wo, Vector::new(0., 0., -1.)).is_black());
+        let f = metal.evaluate(wo, wo);
+        assert!(f.r > f.g && f.g > f.b);
+    }
+
+    #[test]
+    fn white_diffuse_does_not_gain_energy() {
+        let diffuse = Principled {
+            base_colour: Colour::white(),
+            metallic: 0.,
+            roughness: 1.,
+            specular: 0.,
+            sheen: 0.,
+            clearcoat: 0.,
+            transmission: 0.,
+            ..principled()
+        };
+        let bsdf = diffuse;
+        let wo = Vector::new(0.2, 0., 1.).normalised();
+        let mut rng = Rng::new(8);
+        let n = 50000;
+        let mut total = 0.;
+        for _ in 0..n {
+            if let Some(sample) = bsdf.sample(wo, rng.next_f64(), rng.next_2d()) {
+                total += sample.f.r as f64 * sample.wi.z.abs() / sample.pdf;
+            }
+        }
+        let albedo = total / n as f64;
+        assert!(albedo > 0.8 && albedo < 1.1, "{}", albedo);
+    }
+}
@@ -0,0 +1,555 @@
+// Resources:
+// Cylinders, disks and other quadrics: https://pbr-book.org/4ed/Shapes/Cylinders
+// Ray/cone intersection: https://lousodrome.net/blog/light/2017/01/03/intersection-of-a-ray-and-a-cone/
+
+use core::f64;
+
+use crate::bvh::Aabb;
+use crate::colour::Colour;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::sampling;
+use crate::vector::{Matrix, Transform, Vector};
+use crate::world::{
+    Entity, Hit, IntersectionResult, SurfaceInteraction, SurfaceSample, Transformed,
+};
+use serde::Deserialize;
+
+// Up on screen
+pub(crate) fn default_axis() -> Vector {
+    Vector::new(0., -1., 0.)
+}
+
+fn default_capped() -> bool {
+    true
+}
+
+// Move a shape built around the z axis at the origin to `position`, with z along `axis`
+pub fn place(shape: impl Entity + 'static, position: Vector, axis: Vector) -> Box<dyn Entity> {
+    let axis = axis.normalised();
+    let (t, s) = sampling::orthonormal_basis(axis);
+    let mut matrix = Matrix::translation(position);
+    for (column, v) in [t, s, axis].iter().enumerate() {
+        matrix.m[0][column] = v.x;
+        matrix.m[1][column] = v.y;
+        matrix.m[2][column] = v.z;
+    }
+    // An orthonormal frame is always invertible
+    let transform = Transform::new(matrix).unwrap();
+    Box::new(Transformed::new(Box::new(shape), transform))
+}
+
+// A `type = "cylinder"` entity. `position` is the centre of its base and it extends `height`
+// along `axis`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CylinderSpec {
+    pub position: Vector,
+    #[serde(default = "default_axis")]
+    pub axis: Vector,
+    pub radius: f64,
+    pub height: f64,
+    #[serde(default = "default_capped")]
+    pub capped: bool,
+    pub material: Material,
+}
+
+impl CylinderSpec {
+    pub fn build(self) -> Result<Box<dyn Entity>, String> {
+        ConeSpec {
+            position: self.position,
+            axis: self.axis,
+            radius: self.radius,
+            top_radius: Some(self.radius),
+            height: self.height,
+            capped: self.capped,
+            material: self.material,
+        }
+        .build()
+    }
+}
+
+// A `type = "cone"` entity, like a cylinder but narrowing to `top_radius` (a point if not given)
+#[derive(Debug, Clone, Deserialize)]
+pub struct ConeSpec {
+    pub position: Vector,
+    #[serde(default = "default_axis")]
+    pub axis: Vector,
+    pub radius: f64,
+    pub top_radius: Option<f64>,
+    pub height: f64,
+    #[serde(default = "default_capped")]
+    pub capped: bool,
+    pub material: Material,
+}
+
+impl ConeSpec {
+    pub fn build(self) -> Result<Box<dyn Entity>, String> {
+        let top_radius = self.top_radius.unwrap_or(0.);
+        if self.radius < 0. || top_radius < 0. || self.radius + top_radius <= 0. {
+            return Err("radii must not be negative, and one must be positive".to_string());
+        }
+        if self.height <= 0. {
+            return Err("height must be positive".to_string());
+        }
+        if self.axis.abs_squared() == 0. {
+            return Err("axis must not be zero".to_string());
+        }
+        let cone = Cone {
+            radius: self.radius,
+            top_radius,
+            height: self.height,
+            capped: self.capped,
+            material: self.material,
+        };
+        Ok(place(cone, self.position, self.axis))
+    }
+}
+
+// A `type = "disk"` entity facing along `normal`, with a hole of `inner_radius` if given
+#[derive(Debug, Clone, Deserialize)]
+pub struct DiskSpec {
+    pub position: Vector,
+    #[serde(default = "default_axis")]
+    pub normal: Vector,
+    pub radius: f64,
+    #[serde(default)]
+    pub inner_radius: f64,
+    pub material: Material,
+}
+
+impl DiskSpec {
+    pub fn build(self) -> Result<Box<dyn Entity>, String> {
+        if self.radius <= 0. || self.inner_radius < 0. || self.inner_radius >= self.radius {
+            return Err("a disk needs 0 <= inner_radius < radius".to_string());
+        }
+        if self.normal.abs_squared() == 0. {
+            return Err("normal must not be zero".to_string());
+        }
+        let disk = Disk {
+            radius: self.radius,
+            inner_radius: self.inner_radius,
+            material: self.material,
+        };
+        Ok(place(disk, self.position, self.normal))
+    }
+}
+
+// Where a ray crosses the plane z = `z` within an annulus around the z axis
+fn intersect_annulus(ray: &Ray, z: f64, radius: f64, inner_radius: f64) -> Option<f64> {
+    if ray.direction.z == 0. {
+        return None;
+    }
+    let t = (z - ray.origin.z) / ray.direction.z;
+    let p = ray.at(t);
+    let r2 = p.x * p.x + p.y * p.y;
+    if t <= 0. || r2 > radius * radius || r2 < inner_radius * inner_radius {
+        return None;
+    }
+    Some(t)
+}
+
+// Texture coordinates across a disk of the given radius, as if an image were laid over it
+fn annulus_uv(p: Vector, radius: f64) -> ((f64, f64), Vector, Vector) {
+    let uv = (0.5 + p.x / (2. * radius), 0.5 - p.y / (2. * radius));
+    let dpdu = Vector::new(2. * radius, 0., 0.);
+    let dpdv = Vector::new(0., -2. * radius, 0.);
+    (uv, dpdu, dpdv)
+}
+
+// Uniformly distributed point on an annulus in the plane z = `z`
+fn sample_annulus(z: f64, radius: f64, inner_radius: f64, u: (f64, f64)) -> Vector {
+    let inner2 = inner_radius * inner_radius;
+    let r = (inner2 + u.0 * (radius * radius - inner2)).sqrt();
+    let phi = 2. * f64::consts::PI * u.1;
+    Vector::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+// Cone, truncated cone or (with equal radii) cylinder around the z axis from z = 0 to `height`
+#[derive(Debug, Clone)]
+pub struct Cone {
+    radius: f64, // at z = 0
+    top_radius: f64,
+    height: f64,
+    capped: bool,
+    material: Material,
+}
+
+// Which part of a cone a point is on
+enum ConePart {
+    Side,
+    Base,
+    Top,
+}
+
+impl Cone {
+    // Change in radius per unit of height
+    fn slope(&self) -> f64 {
+        (self.top_radius - self.radius) / self.height
+    }
+
+    fn radius_at(&self, z: f64) -> f64 {
+        self.radius + self.slope() * z
+    }
+
+    fn part(&self, p: Vector) -> ConePart {
+        let epsilon = 1e-9 * (self.height + self.radius + self.top_radius);
+        if self.capped && p.z.abs() < epsilon && self.radius > 0. {
+            ConePart::Base
+        } else if self.capped && (p.z - self.height).abs() < epsilon && self.top_radius > 0. {
+            ConePart::Top
+        } else {
+            ConePart::Side
+        }
+    }
+
+    fn side_area(&self) -> f64 {
+        let slant = ((self.top_radius - self.radius).powi(2) + self.height * self.height).sqrt();
+        f64::consts::PI * (self.radius + self.top_radius) * slant
+    }
+
+    fn cap_areas(&self) -> (f64, f64) {
+        if !self.capped {
+            return (0., 0.);
+        }
+        let disk = |r: f64| f64::consts::PI * r * r;
+        (disk(self.radius), disk(self.top_radius))
+    }
+
+    fn side_normal(&self, p: Vector) -> Vector {
+        // Gradient of x^2 + y^2 - r(z)^2
+        let n = Vector::new(p.x, p.y, -self.slope() * self.radius_at(p.z));
+        if n.abs_squared() == 0. {
+            // The apex
+            return Vector::new(0., 0., 1.);
+        }
+        n.normalised()
+    }
+}
+
+impl Entity for Cone {
+    fn intersection(&self, ray: &Ray) -> IntersectionResult {
+        let (o, d) = (ray.origin, ray.direction);
+        let k = self.slope();
+        let r = self.radius_at(o.z);
+
+        // x^2 + y^2 = r(z)^2 along the ray
+        let a = d.x * d.x + d.y * d.y - k * k * d.z * d.z;
+        let b = 2. * (o.x * d.x + o.y * d.y - k * r * d.z);
+        let c = o.x * o.x + o.y * o.y - r * r;
+        let mut hits = Vec::with_capacity(4);
+        if a.abs() > 1e-12 {
+            let delta = b * b - 4. * a * c;
+            if delta >= 0. {
+                let root = delta.sqrt();
+                hits.push((-b - root) / (2. * a));
+                hits.push((-b + root) / (2. * a));
+            }
+        } else if b != 0. {
+            // Parallel to the side of the cone, crossing it once
+            hits.push(-c / b);
+        }
+        // Only the part of the infinite (double) cone between the caps
+        hits.retain(|&t| {
+            let z = o.z + t * d.z;
+            (0. ..=self.height).contains(&z)
+        });
+
+        if self.capped {
+            hits.extend(intersect_annulus(ray, 0., self.radius, 0.));
+            hits.extend(intersect_annulus(ray, self.height, self.top_radius, 0.));
+        }
+        IntersectionResult::from_distances(hits)
+    }
+
+    fn surface(&self, ray: &Ray, hit: Hit) -> SurfaceInteraction<'_> {
+        let position = ray.at(hit.t);
+        let (normal, uv, dpdu, dpdv) = match self.part(position) {
+            ConePart::Base => {
+                let (uv, dpdu, dpdv) = annulus_uv(position, self.radius);
+                (Vector::new(0., 0., -1.), uv, dpdu, dpdv)
+            }
+            ConePart::Top => {
+                let (uv, dpdu, dpdv) = annulus_uv(position, self.top_radius);
+                (Vector::new(0., 0., 1.), uv, dpdu, dpdv)
+            }
+            ConePart::Side => {
+                // u around the axis and v down from the top
+                let phi = position.y.atan2(position.x);
+                let u = phi.rem_euclid(2. * f64::consts::PI) / (2. * f64::consts::PI);
+                let v = 1. - position.z / self.height;
+                let (sin, cos) = phi.sin_cos();
+                let r = self.radius_at(position.z);
+                let dpdu = Vector::new(-r * sin, r * cos, 0.) * (2. * f64::consts::PI);
+                let dpdv = Vector::new(self.slope() * cos, self.slope() * sin, 1.) * -self.height;
+                (self.side_normal(position), (u, v), dpdu, dpdv)
+            }
+        };
+        SurfaceInteraction {
+            position,
+            normal,
+            uv,
+            dpdu,
+            dpdv,
+            object_position: position,
+            vertex_colour: None,
+            material: &self.material,
+        }
+    }
+
+    fn bounds(&self) -> Aabb {
+        let r = self.radius.max(self.top_radius);
+        Aabb::new(Vector::new(-r, -r, 0.), Vector::new(r, r, self.height))
+    }
+
+    fn is_closed(&self) -> bool {
+        self.capped
+    }
+
+    fn position(&self) -> Vector {
+        Vector::zero()
+    }
+
+    fn emission(&self) -> Colour {
+        self.material.emission
+    }
+
+    fn can_sample(&self) -> bool {
+        true
+    }
+
+    fn area(&self) -> f64 {
+        let (base, top) = self.cap_areas();
+        self.side_area() + base + top
+    }
+
+    // Uniform over the surface area, choosing the side or a cap in proportion to its area
+    fn sample(&self, reference: Vector, u: (f64, f64)) -> Option<SurfaceSample> {
+        let (base, top) = self.cap_areas();
+        let side = self.side_area();
+        let pick = u.0 * self.area();
+        let (position, normal) = if pick < side {
+            // The side's area grows with its radius, so heights are chosen in proportion to it
+            let along = pick / side;
+            let total = self.radius * self.height + self.slope() * self.height * self.height / 2.;
+            let target = along * total;
+            let z = 2. * target
+                / (self.radius + (self.radius * self.radius + 2. * self.slope() * target).sqrt());
+            let phi = 2. * f64::consts::PI * u.1;
+            let r = self.radius_at(z);
+            let position = Vector::new(r * phi.cos(), r * phi.sin(), z);
+            (position, self.side_normal(position))
+        } else if pick < side + base {
+            let along = (pick - side) / base;
+            let position = sample_annulus(0., self.radius, 0., (along, u.1));
+            (position, Vector::new(0., 0., -1.))
+        } else {
+            let along = ((pick - side - base) / top).min(1.);
+            let position = sample_annulus(self.height, self.top_radius, 0., (along, u.1));
+            (position, Vector::new(0., 0., 1.))
+        };
+        let pdf = self.pdf(reference, position, normal);
+        if pdf == 0. {
+            return None;
+        }
+        Some(SurfaceSample {
+            position,
+            normal,
+            pdf,
+            emission: self.emission(),
+        })
+    }
+
+    fn pdf(&self, reference: Vector, point: Vector, normal: Vector) -> f64 {
+        sampling::area_to_solid_angle_pdf(1. / self.area(), reference, point, normal)
+    }
+}
+
+// Flat disk, or annulus, at the origin facing +z
+#[derive(Debug, Clone)]
+pub struct Disk {
+    radius: f64,
+    inner_radius: f64,
+    material: Material,
+}
+
+impl Entity for Disk {
+    fn intersection(&self, ray: &Ray) -> IntersectionResult {
+        match intersect_annulus(ray, 0., self.radius, self.inner_radius) {
+            Some(t) => IntersectionResult::One(t),
+            None => IntersectionResult::No,
+        }
+    }
+
+    fn surface(&self, ray: &Ray, hit: Hit) -> SurfaceInteraction<'_> {
+        let position = ray.at(hit.t);
+        let (uv, dpdu, dpdv) = annulus_uv(position, self.radius);
+        SurfaceInteraction {
+            position,
+            normal: Vector::new(0., 0., 1.),
+            uv,
+            dpdu,
+            dpdv,
+            object_position: position,
+            vertex_colour: None,
+            material: &self.material,
+        }
+    }
+
+    fn bounds(&self) -> Aabb {
+        let r = self.radius;
+        Aabb::new(Vector::new(-r, -r, 0.), Vector::new(r, r, 0.))
+    }
+
+    fn position(&self) -> Vector {
+        Vector::zero()
+    }
+
+    fn emission(&self) -> Colour {
+        self.material.emission
+    }
+
+    fn can_sample(&self) -> bool {
+        true
+    }
+
+    fn area(&self) -> f64 {
+        f64::consts::PI * (self.radius * self.radius - self.inner_radius * self.inner_radius)
+    }
+
+    fn sample(&self, reference: Vector, u: (f64, f64)) -> Option<SurfaceSample> {
+        let position = sample_annulus(0., self.radius, self.inner_radius, u);
+        let normal = Vector::new(0., 0., 1.);
+        let pdf = self.pdf(reference, position, normal);
+        if pdf == 0. {
+            return None;
+        }
+        Some(SurfaceSample {
+            position,
+            normal,
+            pdf,
+            emission: self.emission(),
+        })
+    }
+
+    fn pdf(&self, reference: Vector, point: Vector, normal: Vector) -> f64 {
+        sampling::area_to_solid_angle_pdf(1. / self.area(), reference, point, normal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::Rng;
+
+    fn cone(radius: f64, top_radius: f64, capped: bool) -> Cone {
+        Cone {
+            radius,
+            top_radius,
+            height: 2.,
+            capped,
+            material: Material::default(),
+        }
+    }
+
+    #[test]
+    fn cylinder_hits_side_and_caps() {
+        let cylinder = cone(1., 1., true);
+        // Across the side
+        let ray = Ray::new(Vector::new(-5., 0., 1.), Vector::new(1., 0., 0.));
+        assert_eq!(cylinder.intersection(&ray), IntersectionResult::Two(4., 6.));
+        let normal = cylinder.surface(&ray, Hit::new(4.)).normal;
+        assert!((normal - Vector::new(-1., 0., 0.)).length() < 1e-9);
+        // Down the axis, through both caps
+        let ray = Ray::new(Vector::new(0.5, 0., -1.), Vector::new(0., 0., 1.));
+        assert_eq!(cylinder.intersection(&ray), IntersectionResult::Two(1., 3.));
+        assert_eq!(
+            cylinder.surface(&ray, Hit::new(1.)).normal,
+            Vector::new(0., 0., -1.)
+        );
+        assert_eq!(
+            cylinder.surface(&ray, Hit::new(3.)).normal,
+            Vector::new(0., 0., 1.)
+        );
+        // Uncapped, the same ray passes straight through
+        let open = cone(1., 1., false);
+        assert_eq!(open.intersection(&ray), IntersectionResult::No);
+        // From inside the open tube, the far wall
+        let ray = Ray::new(Vector::new(0., 0., 1.), Vector::new(0., 1., 0.));
+        assert_eq!(open.intersection(&ray), IntersectionResult::One(1.));
+    }
+
+    #[test]
+    fn cone_side_normals_lean_up() {
+        let cone = cone(1., 0., true);
+        // Halfway up the radius is 0.5
+        let ray = Ray::new(Vector::new(-5., 0., 1.), Vector::new(1., 0., 0.));
+        let t = cone.intersection(&ray).nearest().unwrap();
+        assert!((t - 4.5).abs() < 1e-9);
+        // The side rises 2 over a run of 1, so the normal is (-2, 0, 1) / sqrt(5)
+        let normal = cone.surface(&ray, Hit::new(t)).normal;
+        assert!((normal - Vector::new(-2., 0., 1.).normalised()).length() < 1e-9);
+        // Rays above the apex miss the mirrored half of the double cone
+        let above = Ray::new(Vector::new(-5., 0., 3.), Vector::new(1., 0., 0.));
+        assert_eq!(cone.intersection(&above), IntersectionResult::No);
+    }
+
+    #[test]
+    fn side_uvs_follow_dpduv() {
+        let frustum = cone(1., 0.4, false);
+        let ray = Ray::new(Vector::new(-3., 0.4, 0.7), Vector::new(1., 0., 0.));
+        let t = frustum.intersection(&ray).nearest().unwrap();
+        let surface = frustum.surface(&ray, Hit::new(t));
+        let h = 1e-6;
+        let moved = |offset: Vector| {
+            // Project the moved point back onto the surface
+            let p = surface.position + offset;
+            let probe = Ray::new(p - surface.normal, surface.normal);
+            let t = frustum.intersection(&probe).nearest().unwrap();
+            frustum.surface(&probe, Hit::new(t)).uv
+        };
+        let (u, v) = surface.uv;
+        let (u2, v2) = moved(surface.dpdu * h);
+        assert!(((u2 - u) / h - 1.).abs() < 1e-3 && ((v2 - v) / h).abs() < 1e-3);
+        let (u3, v3) = moved(surface.dpdv * h);
+        assert!(((u3 - u) / h).abs() < 1e-3 && ((v3 - v) / h - 1.).abs() < 1e-3);
+    }
+
+    #[test]
+    fn samples_lie_on_the_surface() {
+        let frustum = cone(1., 0.5, true);
+        let mut rng = Rng::new(6);
+        let reference = Vector::new(3., 0., 1.);
+        for _ in 0..200 {
+            let sample = frustum.sample(reference, rng.next_2d()).unwrap();
+            let p = sample.position;
+            let on_side = ((p.x * p.x + p.y * p.y).sqrt() - frustum.radius_at(p.z)).abs() < 1e-9;
+            let on_cap = p.z.abs() < 1e-9 || (p.z - 2.).abs() < 1e-9;
+            assert!(on_side || on_cap, "{:?}", p);
+        }
+    }
+
+    #[test]
+    fn placed_disk_faces_along_its_normal() {
+        let table: toml::Table = r#"
+        disk = {position = [0, 0, 5], normal = [0, 0, -1], radius = 1, inner_radius = 0.5, material = {colour = [1, 1, 1]}}
+        bad = {position = [0, 0, 5], radius = 1, inner_radius = 2, material = {colour = [1, 1, 1]}}
+        "#
+        .parse()
+        .unwrap();
+        let disk = DiskSpec::deserialize(table["disk"].clone())
+            .unwrap()
+            .build()
+            .unwrap();
+        let ray = Ray::new(Vector::new(0.7, 0., 0.), Vector::new(0., 0., 1.));
+        let t = disk.intersection(&ray).nearest().unwrap();
+        assert!((t - 5.).abs() < 1e-9);
+        assert!(
+            (disk.surface(&ray, Hit::new(t)).normal - Vector::new(0., 0., -1.)).length() < 1e-9
+        );
+        // Through the hole
+        let ray = Ray::new(Vector::new(0.2, 0., 0.), Vector::new(0., 0., 1.));
+        assert_eq!(disk.intersection(&ray), IntersectionResult::No);
+        let bad = DiskSpec::deserialize(table["bad"].clone()).unwrap();
+        assert!(bad.build().is_err());
+    }
+}
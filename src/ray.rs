@@ -0,0 +1,50 @@
+use crate::vector::Vector;
+
+// Offset applied to secondary ray origins so they don't re-hit the surface they leave
+pub const RAY_EPSILON: f64 = 1e-5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ray {
+    pub origin: Vector,
+    pub direction: Vector,
+}
+
+impl Ray {
+    pub fn new(origin: Vector, direction: Vector) -> Self {
+        Self { origin, direction }
+    }
+
+    // Ray leaving a surface, nudged along the normal to the side the direction points to
+    pub fn spawn(position: Vector, normal: Vector, direction: Vector) -> Self {
+        let offset = if direction.dot(&normal) >= 0. {
+            normal * RAY_EPSILON
+        } else {
+            normal * -RAY_EPSILON
+        };
+        Self::new(position + offset, direction)
+    }
+
+    pub fn at(&self, t: f64) -> Vector {
+        self.origin + self.direction * t
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ray_at() {
+        let ray = Ray::new(Vector::new(1., 0., 0.), Vector::new(0., 2., 0.));
+        assert_eq!(ray.at(0.5), Vector::new(1., 1., 0.));
+    }
+
+    #[test]
+    fn spawned_ray_is_offset_to_the_outgoing_side() {
+        let normal = Vector::new(0., 1., 0.);
+        let out = Ray::spawn(Vector::zero(), normal, Vector::new(1., 1., 0.));
+        assert!(out.origin.y > 0.);
+        let into = Ray::spawn(Vector::zero(), normal, Vector::new(1., -1., 0.));
+        assert!(into.origin.y < 0.);
+    }
+}
@@ -2,58 +2,342 @@
 // Intersection maths: https://upload.wikimedia.org/wikipedia/commons/9/95/Ray_Tracing_Illustration_First_Bounce.png
 // Ray tracing algo: https://en.wikipedia.org/wiki/Path_tracing#Algorithm
 // Basic aligned camera: https://computergraphics.stackexchange.com/questions/8479/how-to-calculate-ray
+// Next-event estimation with MIS: https://pbr-book.org/3ed-2018/Light_Transport_I_Surface_Reflection/Path_Tracing
 
 use core::f64;
 
+use serde::Deserialize;
+
+use crate::bsdf::Bsdf;
 use crate::colour::Colour;
 use crate::image::Image;
+use crate::light::LightSample;
+use crate::ray::{Ray, RAY_EPSILON};
+use crate::sampling::{self, Frame, Rng};
 use crate::vector::Vector;
 use crate::world::World;
 
-pub fn trace_path_no_recurse(world: &World, ray: Vector) -> Option<Colour> {
-    let result = world.find_nearest(ray);
+// Shadow rays stop just short of the sampled light point so they don't hit the light itself
+const SHADOW_EPSILON: f64 = 1e-4;
+
+// Paths shorter than this are never terminated by russian roulette
+const RUSSIAN_ROULETTE_DEPTH: u32 = 3;
 
-    if !result.hit {
-        return None;
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct RenderSettings {
+    pub samples: u32,
+    pub max_depth: u32,
+    pub fov: f64, // vertical field of view, in degrees
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            samples: 16,
+            max_depth: 5,
+            fov: 90.,
+        }
     }
+}
 
-    let material = result.material;
-    // TODO: material emittance
+// A point being shaded, with the direction the path arrived from and how the surface scatters
+struct ShadingPoint {
+    position: Vector,
+    geometric_normal: Vector,
+    // The BSDF's local frame: z is the normal after normal or bump mapping and x runs along dpdu
+    frame: Frame,
+    wo: Vector, // towards where the path came from, in the local frame
+    bsdf: Box<dyn Bsdf>,
+}
 
-    let light = world.light;
-    let hit_to_light = (light.position - result.position).normalised();
-    let cos_angle = hit_to_light.dot(&result.normal) as f32;
+impl ShadingPoint {
+    fn to_local(&self, v: Vector) -> Vector {
+        self.frame.to_local(v)
+    }
 
-    Some(material.colour * cos_angle * light.intensity as f32)
+    // Ray leaving the surface, or None if `direction` is on different sides of the geometric
+    // and shading normals. Perturbed normals would otherwise let light leak through surfaces.
+    fn spawn(&self, direction: Vector) -> Option<Ray> {
+        if direction.dot(&self.geometric_normal) * direction.dot(&self.frame.z) <= 0. {
+            return None;
+        }
+        Some(Ray::spawn(self.position, self.geometric_normal, direction))
+    }
+
+    // BSDF times cosine for light arriving from world space direction `wi`
+    fn scattered(&self, wi: Vector) -> Colour {
+        let wi = self.to_local(wi);
+        self.bsdf.evaluate(self.wo, wi) * wi.z.abs() as f32
+    }
+
+    fn pdf(&self, wi: Vector) -> f64 {
+        self.bsdf.pdf(self.wo, self.to_local(wi))
+    }
+}
+
+// Sample one light (a light, an emissive entity or the environment) and return its MIS-weighted
+// contribution
+fn sample_direct(world: &World, point: &ShadingPoint, rng: &mut Rng) -> Colour {
+    let num_lights = world.num_lights();
+    if num_lights == 0 {
+        return Colour::black();
+    }
+    let select_pdf = 1. / num_lights as f64;
+    let choice = ((rng.next_f64() * num_lights as f64) as usize).min(num_lights - 1);
+    let num_finite = world.lights.len() + world.emitters().len();
+
+    let (sample, is_delta) = if choice < world.lights.len() {
+        let light = &world.lights[choice];
+        let Some(sample) = light.sample(point.position, rng.next_2d()) else {
+            return Colour::black();
+        };
+        (sample, light.is_delta())
+    } else if choice < num_finite {
+        let entity = &world.entities[world.emitters()[choice - world.lights.len()]];
+        let Some(sample) = entity.sample(point.position, rng.next_2d()) else {
+            return Colour::black();
+        };
+        let sample =
+            LightSample::towards(point.position, sample.position, sample.pdf, sample.emission);
+        (sample, false)
+    } else {
+        let Some(environment) = &world.environment else {
+            return Colour::black();
+        };
+        let Some((direction, radiance, pdf)) = environment.sample(rng.next_2d()) else {
+            return Colour::black();
+        };
+        let sample = LightSample {
+            direction,
+            distance: f64::INFINITY,
+            pdf,
+            radiance,
+        };
+        (sample, false)
+    };
+    // The shading point may lie on the emitter being sampled
+    if sample.pdf <= 0. || sample.radiance.is_black() || sample.distance < RAY_EPSILON {
+        return Colour::black();
+    }
+
+    let wi = sample.direction;
+    let contribution = shade_direct(world, point, wi, sample.distance * (1. - SHADOW_EPSILON));
+
+    let light_pdf = sample.pdf * select_pdf;
+    // A delta light can't be hit by BSDF sampling, so there's nothing to weight against
+    let weight = if is_delta {
+        1.
+    } else {
+        sampling::power_heuristic(light_pdf, point.pdf(wi))
+    };
+
+    contribution * sample.radiance * (weight / light_pdf) as f32
+}
+
+// BSDF times cosine for light arriving from `wi`, or black if it is blocked before `max_distance`
+fn shade_direct(world: &World, point: &ShadingPoint, wi: Vector, max_distance: f64) -> Colour {
+    let scattered = point.scattered(wi);
+    if scattered.is_black() {
+        return Colour::black();
+    }
+    let Some(shadow_ray) = point.spawn(wi) else {
+        return Colour::black();
+    };
+    if world.occluded(&shadow_ray, max_distance) {
+        return Colour::black();
+    }
+    scattered
+}
+
+// MIS weight for an emitter found by BSDF sampling from `previous`, given the solid angle
+// density with which the emitter itself would have sampled the point
+fn bsdf_hit_weight(
+    world: &World,
+    previous: Option<(Vector, f64)>,
+    emitter_pdf: impl FnOnce(Vector) -> f64,
+) -> f64 {
+    match previous {
+        None => 1.,
+        Some((origin, bsdf_pdf)) => {
+            let light_pdf = emitter_pdf(origin) / world.num_lights() as f64;
+            sampling::power_heuristic(bsdf_pdf, light_pdf)
+        }
+    }
+}
+
+// `screen_uv` is where the camera ray passes through the image, for looking up the background
+pub fn trace_path(world: &World, mut ray: Ray, screen_uv: (f64, f64), rng: &mut Rng) -> Colour {
+    let mut radiance = Colour::black();
+    let mut throughput = Colour::white();
+    // Where the previous bounce was sampled from, for weighting emitters hit by BSDF sampling
+    let mut previous: Option<(Vector, f64)> = None;
+
+    for depth in 0..=world.settings.max_depth {
+        let result = world.find_nearest(&ray);
+
+        if let Some((i, t, normal)) = world.find_nearest_light(&ray) {
+            if !result.hit || t < result.distance {
+                // Lights only emit, so the path ends here
+                let light = &world.lights[i];
+                let position = ray.at(t);
+                let weight = bsdf_hit_weight(world, previous, |origin| {
+                    light.pdf(origin, position, normal)
+                });
+                radiance += throughput * light.emitted(normal, ray.direction) * weight as f32;
+                break;
+            }
+        }
+
+        if !result.hit {
+            if let Some(environment) = &world.environment {
+                let weight = bsdf_hit_weight(world, previous, |_| environment.pdf(ray.direction));
+                radiance += throughput * environment.radiance(ray.direction) * weight as f32;
+            } else if depth == 0 {
+                // The background is only seen directly, it doesn't light the scene
+                radiance += world.background.colour_at(screen_uv);
+            }
+            break;
+        }
+
+        let material = &result.material;
+        if !material.emission.is_black() {
+            let entity = &world.entities[result.entity];
+            let weight = bsdf_hit_weight(world, previous, |origin| {
+                entity.pdf(origin, result.position, result.normal)
+            });
+            radiance += throughput * material.emission * weight as f32;
+        }
+
+        if depth == world.settings.max_depth {
+            break;
+        }
+
+        let coords = result.texture_coords();
+        let normal = material.shading_normal(&coords, result.normal, result.dpdu, result.dpdv);
+        let frame = Frame::new(normal, result.dpdu);
+        let point = ShadingPoint {
+            position: result.position,
+            geometric_normal: result.normal,
+            frame,
+            wo: frame.to_local(-ray.direction),
+            bsdf: material.bsdf(&coords),
+        };
+
+        radiance += throughput * sample_direct(world, &point, rng);
+
+        let Some(sample) = point.bsdf.sample(point.wo, rng.next_f64(), rng.next_2d()) else {
+            break;
+        };
+        let wi = point.frame.to_world(sample.wi);
+        let Some(next) = point.spawn(wi) else {
+            break;
+        };
+        throughput *= sample.f * (sample.wi.z.abs() / sample.pdf) as f32;
+        previous = Some((result.position, sample.pdf));
+        ray = next;
+
+        if depth + 1 >= RUSSIAN_ROULETTE_DEPTH {
+            let survival = throughput.max_component().min(0.95);
+            if rng.next_f64() as f32 >= survival {
+                break;
+            }
+            throughput /= survival;
+        }
+    }
+
+    radiance
 }
 
 pub fn render(world: &World, width: u16, height: u16) -> Image {
     let mut image = Image::new(width, height);
 
-    let theta_fov = 90. * f64::consts::PI / 180.;
+    let theta_fov = world.settings.fov.to_radians();
     let d = 1. / (theta_fov / 2.).tan();
 
     let aspect_ratio = width as f64 / height as f64;
+    let samples = world.settings.samples.max(1);
 
     for y in 0..height {
-        let p_y = y as f64 + 0.5;
         for x in 0..width {
-            let p_x = x as f64 + 0.5;
-
-            // Ray from camera
-            let ray = Vector::new(
-                aspect_ratio * 2. * p_x / width as f64 - 1.,
-                2. * p_y / height as f64 - 1.,
-                d,
-            );
-
-            let mut c = world.background;
-            if let Some(colour) = trace_path_no_recurse(world, ray) {
-                c = colour;
+            // Seed per pixel so renders are reproducible
+            let mut rng = Rng::new(y as u64 * width as u64 + x as u64);
+            let mut c = Colour::black();
+
+            for _ in 0..samples {
+                let p_x = x as f64 + rng.next_f64();
+                let p_y = y as f64 + rng.next_f64();
+
+                // Ray from camera
+                let direction = Vector::new(
+                    aspect_ratio * 2. * p_x / width as f64 - 1.,
+                    2. * p_y / height as f64 - 1.,
+                    d,
+                );
+                let ray = Ray::new(Vector::zero(), direction.normalised());
+                let screen_uv = (p_x / width as f64, p_y / height as f64);
+                c += trace_path(world, ray, screen_uv, &mut rng);
             }
-            image.put_pixel(x, y, c.as_rgb24());
+
+            image.put_pixel(x, y, (c / samples as f32).as_rgb24());
         }
     }
 
     image
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn furnace_world(emission: f32) -> World {
+        let toml_string = format!(
+            r#"
+            background = [0, 0, 0]
+            light = {{position = [0, 0, 0], intensity = 0}}
+            render = {{max_depth = 2}}
+
+            [[entities]]
+            type = "sphere"
+            position = [0, 0, 0]
+            radius = 10
+            material = {{colour = [0, 0, 0], emission = [{0}, {0}, {0}]}}
+
+            [[entities]]
+            type = "sphere"
+            position = [0, 0, 5]
+            radius = 1
+            material = {{colour = [0.5, 0.5, 0.5]}}
+            "#,
+            emission
+        );
+        World::from_toml(&toml_string.parse::<toml::Table>().unwrap())
+    }
+
+    #[test]
+    fn camera_sees_emission_directly() {
+        let world = furnace_world(2.);
+        let mut rng = Rng::new(0);
+        let ray = Ray::new(Vector::zero(), Vector::new(0., 1., 0.));
+        assert_eq!(
+            trace_path(&world, ray, (0.5, 0.5), &mut rng),
+            Colour::new(2., 2., 2.)
+        );
+    }
+
+    #[test]
+    fn diffuse_surface_in_uniform_enclosure_reflects_albedo() {
+        // Inside a uniformly emitting enclosure, a diffuse surface reflects albedo * emission
+        // whichever strategy found the light, so MIS weights must sum to one
+        let world = furnace_world(1.);
+        let mut rng = Rng::new(7);
+        let n = 20000;
+        let mut total = Colour::black();
+        for _ in 0..n {
+            let ray = Ray::new(Vector::zero(), Vector::new(0., 0., 1.));
+            total += trace_path(&world, ray, (0.5, 0.5), &mut rng);
+        }
+        let mean = total / n as f32;
+        assert!((mean.r - 0.5).abs() < 0.02, "mean was {:?}", mean);
+    }
+}
@@ -0,0 +1,357 @@
+// Resources:
+// PCG random number generation: https://www.pcg-random.org/download.html
+// Sampling shapes and MIS: https://pbr-book.org/3ed-2018/Monte_Carlo_Integration
+
+use core::f64;
+
+use crate::vector::Vector;
+
+// PCG32 (XSH RR variant). Small, fast and good enough for Monte Carlo, and seedable
+// per pixel so renders are reproducible.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+    increment: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = Rng {
+            state: 0,
+            increment: (seed << 1) | 1,
+        };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(0x853c49e6748fea9b);
+        rng.next_u32();
+        return rng;
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(self.increment);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        let rot = (old >> 59) as u32;
+        xorshifted.rotate_right(rot)
+    }
+
+    // Uniform in [0, 1)
+    pub fn next_f64(&mut self) -> f64 {
+        self.next_u32() as f64 / (u32::MAX as f64 + 1.)
+    }
+
+    pub fn next_2d(&mut self) -> (f64, f64) {
+        (self.next_f64(), self.next_f64())
+    }
+}
+
+// Two unit vectors that together with `n` form a right-handed orthonormal basis
+pub fn orthonormal_basis(n: Vector) -> (Vector, Vector) {
+    // Duff et al. 2017, "Building an Orthonormal Basis, Revisited"
+    let sign = 1_f64.copysign(n.z);
+    let a = -1. / (sign + n.z);
+    let b = n.x * n.y * a;
+    let t = Vector::new(1. + sign * n.x * n.x * a, sign * b, -sign * n.x);
+    let s = Vector::new(b, sign + n.y * n.y * a, -n.y);
+    (t, s)
+}
+
+// Express a direction given in the local frame (z along `n`) in world space
+pub fn local_to_world(local: Vector, n: Vector) -> Vector {
+    let (t, s) = orthonormal_basis(n);
+    t * local.x + s * local.y + n * local.z
+}
+
+// Local frame with z along a normal and x along the surface tangent, so that BSDFs which depend
+// on direction within the surface (like hair) know which way it runs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frame {
+    pub x: Vector,
+    pub y: Vector,
+    pub z: Vector,
+}
+
+impl Frame {
+    // `tangent` need not be perpendicular to `n`; if it is zero or along `n` any basis is used
+    pub fn new(n: Vector, tangent: Vector) -> Self {
+        let x = tangent - n * tangent.dot(&n);
+        if x.abs_squared() > 1e-12 * tangent.abs_squared() {
+            let x = x.normalised();
+            return Frame {
+                x,
+                y: n.cross(&x),
+                z: n,
+            };
+        }
+        let (x, y) = orthonormal_basis(n);
+        Frame { x, y, z: n }
+    }
+
+    pub fn to_local(self, v: Vector) -> Vector {
+        Vector::new(v.dot(&self.x), v.dot(&self.y), v.dot(&self.z))
+    }
+
+    pub fn to_world(self, local: Vector) -> Vector {
+        self.x * local.x + self.y * local.y + self.z * local.z
+    }
+}
+
+pub fn cosine_hemisphere(normal: Vector, u: (f64, f64)) -> Vector {
+    let r = u.0.sqrt();
+    let phi = 2. * f64::consts::PI * u.1;
+    let local = Vector::new(r * phi.cos(), r * phi.sin(), (1. - u.0).max(0.).sqrt());
+    local_to_world(local, normal)
+}
+
+pub fn cosine_hemisphere_pdf(cos_theta: f64) -> f64 {
+    cos_theta.max(0.) / f64::consts::PI
+}
+
+// Uniformly distributed direction inside the cone around `axis` with half-angle acos(cos_theta_max)
+pub fn uniform_cone(axis: Vector, cos_theta_max: f64, u: (f64, f64)) -> Vector {
+    let cos_theta = 1. - u.0 * (1. - cos_theta_max);
+    let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+    let phi = 2. * f64::consts::PI * u.1;
+    let local = Vector::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+    local_to_world(local, axis)
+}
+
+pub fn uniform_cone_pdf(cos_theta_max: f64) -> f64 {
+    1. / (2. * f64::consts::PI * (1. - cos_theta_max))
+}
+
+pub fn uniform_sphere(u: (f64, f64)) -> Vector {
+    let z = 1. - 2. * u.0;
+    let r = (1. - z * z).max(0.).sqrt();
+    let phi = 2. * f64::consts::PI * u.1;
+    Vector::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+// Barycentric coordinates (b0, b1) uniformly distributed over a triangle
+pub fn uniform_triangle(u: (f64, f64)) -> (f64, f64) {
+    let su0 = u.0.sqrt();
+    (1. - su0, u.1 * su0)
+}
+
+// Convert a pdf with respect to area at `point` into one with respect to solid angle at `reference`
+pub fn area_to_solid_angle_pdf(
+    pdf_area: f64,
+    reference: Vector,
+    point: Vector,
+    normal: Vector,
+) -> f64 {
+    let to_point = point - reference;
+    let distance_squared = to_point.abs_squared();
+    let cos_theta = normal.dot(&to_point.normalised()).abs();
+    if cos_theta == 0. {
+        return 0.;
+    }
+    pdf_area * distance_squared / cos_theta
+}
+
+// Piecewise constant distribution over [0, 1) proportional to `func`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Distribution1D {
+    func: Vec<f64>,
+    cdf: Vec<f64>,
+    integral: f64,
+}
+
+impl Distribution1D {
+    pub fn new(func: Vec<f64>) -> Self {
+        let n = func.len();
+        let mut cdf = Vec::with_capacity(n + 1);
+        cdf.push(0.);
+        for i in 0..n {
+            cdf.push(cdf[i] + func[i].abs() / n as f64);
+        }
+        let integral = cdf[n];
+        for (i, c) in cdf.iter_mut().enumerate() {
+            // An all-zero function falls back to a uniform distribution
+            *c = if integral == 0. {
+                i as f64 / n as f64
+            } else {
+                *c / integral
+            };
+        }
+        Distribution1D {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.func.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.func.is_empty()
+    }
+
+    // Returns the sampled position in [0, 1), its density and the index of the segment it is in
+    pub fn sample(&self, u: f64) -> (f64, f64, usize) {
+        // Last cdf entry that is <= u
+        let offset = self.cdf.partition_point(|&c| c <= u).clamp(1, self.len()) - 1;
+        let mut du = u - self.cdf[offset];
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        if width > 0. {
+            du /= width;
+        }
+        let x = ((offset as f64 + du) / self.len() as f64).min(1. - f64::EPSILON);
+        (x, self.pdf_at(offset), offset)
+    }
+
+    fn pdf_at(&self, offset: usize) -> f64 {
+        if self.integral == 0. {
+            1.
+        } else {
+            self.func[offset].abs() / self.integral
+        }
+    }
+
+    pub fn pdf(&self, x: f64) -> f64 {
+        let offset = ((x * self.len() as f64) as usize).min(self.len() - 1);
+        self.pdf_at(offset)
+    }
+
+    // Chance of sampling a position in the segment at `offset`
+    pub fn chance(&self, offset: usize) -> f64 {
+        self.pdf_at(offset) / self.len() as f64
+    }
+}
+
+// Piecewise constant distribution over [0, 1)^2 given row-major values, sampling the row
+// (v) from the marginal and then the column (u) from that row's conditional
+#[derive(Debug, Clone, PartialEq)]
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    pub fn new(values: &[f64], width: usize, height: usize) -> Self {
+        let conditional: Vec<Distribution1D> = values
+            .chunks(width)
+            .take(height)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(conditional.iter().map(|d| d.integral).collect());
+        Distribution2D {
+            conditional,
+            marginal,
+        }
+    }
+
+    // Returns (u, v) and the density with respect to area in [0, 1)^2
+    pub fn sample(&self, u: (f64, f64)) -> ((f64, f64), f64) {
+        let (v, pdf_v, row) = self.marginal.sample(u.1);
+        let (u, pdf_u, _) = self.conditional[row].sample(u.0);
+        ((u, v), pdf_u * pdf_v)
+    }
+
+    pub fn pdf(&self, uv: (f64, f64)) -> f64 {
+        let row = ((uv.1 * self.marginal.len() as f64) as usize).min(self.marginal.len() - 1);
+        self.marginal.pdf(uv.1) * self.conditional[row].pdf(uv.0)
+    }
+}
+
+// MIS weight for a sample drawn from f, when g could also have produced it (beta = 2)
+pub fn power_heuristic(f_pdf: f64, g_pdf: f64) -> f64 {
+    let f = f_pdf * f_pdf;
+    let g = g_pdf * g_pdf;
+    if f + g == 0. {
+        return 0.;
+    }
+    f / (f + g)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rng_is_deterministic_and_in_range() {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+        for _ in 0..1000 {
+            let x = a.next_f64();
+            assert_eq!(x, b.next_f64());
+            assert!((0. ..1.).contains(&x));
+        }
+    }
+
+    #[test]
+    fn orthonormal_basis_is_orthonormal() {
+        let n = Vector::new(0.3, -0.5, 0.8).normalised();
+        let (t, s) = orthonormal_basis(n);
+        assert!(t.dot(&n).abs() < 1e-12);
+        assert!(s.dot(&n).abs() < 1e-12);
+        assert!(t.dot(&s).abs() < 1e-12);
+        assert!((t.length() - 1.).abs() < 1e-12);
+        assert!((s.length() - 1.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn local_frame_round_trip() {
+        let n = Vector::new(-0.2, 0.9, 0.1).normalised();
+        let v = Vector::new(0.4, -1.3, 2.);
+        // Without a tangent a frame is the same one `local_to_world` uses
+        let local = Frame::new(n, Vector::zero()).to_local(v);
+        assert!((local.z - v.dot(&n)).abs() < 1e-12);
+        assert!((local_to_world(local, n) - v).length() < 1e-12);
+    }
+
+    #[test]
+    fn frame_follows_tangent() {
+        let n = Vector::new(0., 0., -1.);
+        let frame = Frame::new(n, Vector::new(2., 0., 1.));
+        assert!((frame.x - Vector::new(1., 0., 0.)).length() < 1e-12);
+        assert!((frame.x.cross(&frame.y) - n).length() < 1e-12);
+        let v = Vector::new(0.4, -1.3, 2.);
+        assert!((frame.to_world(frame.to_local(v)) - v).length() < 1e-12);
+        // A tangent along the normal gives some other basis
+        let frame = Frame::new(n, n);
+        assert!(frame.x.dot(&n).abs() < 1e-12 && (frame.x.length() - 1.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn cone_samples_stay_inside_cone() {
+        let mut rng = Rng::new(1);
+        let axis = Vector::new(1., 1., 0.).normalised();
+        for _ in 0..100 {
+            let d = uniform_cone(axis, 0.9, rng.next_2d());
+            assert!(d.dot(&axis) >= 0.9 - 1e-9);
+        }
+    }
+
+    #[test]
+    fn distribution_1d_follows_function() {
+        let d = Distribution1D::new(vec![1., 3., 0., 0.]);
+        let (x, pdf, offset) = d.sample(0.5);
+        assert_eq!(offset, 1);
+        assert!((0.25..0.5).contains(&x));
+        assert_eq!(pdf, 3.);
+        assert_eq!(d.pdf(0.8), 0.);
+        assert_eq!(d.sample(0.1).2, 0);
+    }
+
+    #[test]
+    fn distribution_2d_pdf_matches_sample() {
+        let d = Distribution2D::new(&[1., 2., 3., 4., 0., 6.], 3, 2);
+        let mut rng = Rng::new(5);
+        for _ in 0..100 {
+            let (uv, pdf) = d.sample(rng.next_2d());
+            assert!((d.pdf(uv) - pdf).abs() < 1e-9);
+            assert!(pdf > 0.);
+        }
+    }
+
+    #[test]
+    fn power_heuristic_weights_sum_to_one() {
+        let w = power_heuristic(0.5, 2.) + power_heuristic(2., 0.5);
+        assert!((w - 1.).abs() < 1e-12);
+        assert_eq!(power_heuristic(0., 0.), 0.);
+    }
+}
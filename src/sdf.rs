@@ -0,0 +1,488 @@
+// Resources:
+// Distance functions: https://iquilezles.org/articles/distfunctions/
+// Smooth minimum: https://iquilezles.org/articles/smin/
+// Sphere tracing: Hart 1996, "Sphere Tracing: A Geometric Method for the Antialiased Ray Tracing of Implicit Surfaces"
+// Normals by the tetrahedron technique: https://iquilezles.org/articles/normalsSDF/
+
+use crate::bvh::Aabb;
+use crate::colour::Colour;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::sampling;
+use crate::vector::Vector;
+use crate::world::{Entity, Hit, IntersectionResult, SurfaceInteraction};
+use serde::Deserialize;
+
+// Most steps taken along a ray before giving up on it
+const MAX_STEPS: usize = 512;
+// How close to the surface counts as on it
+const SURFACE_EPSILON: f64 = 1e-6;
+// Offset for finite differences of the distance, for normals
+const GRADIENT_DELTA: f64 = 1e-5;
+
+fn default_count() -> [u32; 3] {
+    [1, 1, 1]
+}
+
+// A node of the expression tree giving the signed distance to a shape, negative inside it
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Shape {
+    Sphere {
+        #[serde(default = "Vector::zero")]
+        centre: Vector,
+        radius: f64,
+    },
+    // With its edges rounded off by `rounding`, within the same size
+    #[serde(rename = "box")]
+    Cuboid {
+        #[serde(default = "Vector::zero")]
+        centre: Vector,
+        size: Vector,
+        #[serde(default)]
+        rounding: f64,
+    },
+    // Lying flat, with its hole along y
+    Torus {
+        #[serde(default = "Vector::zero")]
+        centre: Vector,
+        major_radius: f64,
+        minor_radius: f64,
+    },
+    // Rounded cylinder between two points
+    Capsule {
+        start: Vector,
+        end: Vector,
+        radius: f64,
+    },
+    // Blended together over a distance `smoothing`, if given
+    Union {
+        shapes: Vec<Shape>,
+        #[serde(default)]
+        smoothing: f64,
+    },
+    Intersection {
+        shapes: Vec<Shape>,
+    },
+    // `shape` with `cut` carved out of it
+    Subtraction {
+        shape: Box<Shape>,
+        cut: Box<Shape>,
+        #[serde(default)]
+        smoothing: f64,
+    },
+    // `count` copies along each axis, `spacing` apart and centred on the origin
+    Repeat {
+        shape: Box<Shape>,
+        spacing: Vector,
+        #[serde(default = "default_count")]
+        count: [u32; 3],
+    },
+    // Turned about the y axis by `angle` degrees per unit along it
+    Twist {
+        shape: Box<Shape>,
+        angle: f64,
+    },
+}
+
+// Polynomial smooth minimum, which is at most k/4 below the true one
+fn smooth_min(a: f64, b: f64, k: f64) -> f64 {
+    if k <= 0. {
+        return a.min(b);
+    }
+    let h = (k - (a - b).abs()).max(0.) / k;
+    a.min(b) - h * h * k / 4.
+}
+
+// Where a coordinate falls in the nearest of `count` copies `spacing` apart
+fn repeat(x: f64, spacing: f64, count: u32) -> f64 {
+    if spacing == 0. || count <= 1 {
+        return x;
+    }
+    let half = (count - 1) as f64 / 2.;
+    let cell = ((x / spacing + half).round()).clamp(0., 2. * half);
+    x - spacing * (cell - half)
+}
+
+impl Shape {
+    pub fn distance(&self, p: Vector) -> f64 {
+        match self {
+            Shape::Sphere { centre, radius } => (p - *centre).length() - radius,
+            Shape::Cuboid {
+                centre,
+                size,
+                rounding,
+            } => {
+                let d = p - *centre;
+                let q = Vector::new(
+                    d.x.abs() - size.x / 2. + rounding,
+                    d.y.abs() - size.y / 2. + rounding,
+                    d.z.abs() - size.z / 2. + rounding,
+                );
+                let outside = Vector::new(q.x.max(0.), q.y.max(0.), q.z.max(0.)).length();
+                let inside = q.x.max(q.y).max(q.z).min(0.);
+                outside + inside - rounding
+            }
+            Shape::Torus {
+                centre,
+                major_radius,
+                minor_radius,
+            } => {
+                let d = p - *centre;
+                let ring = (d.x * d.x + d.z * d.z).sqrt() - major_radius;
+                ring.hypot(d.y) - minor_radius
+            }
+            Shape::Capsule { start, end, radius } => {
+                let (pa, ba) = (p - *start, *end - *start);
+                let along = if ba.abs_squared() > 0. {
+                    (pa.dot(&ba) / ba.abs_squared()).clamp(0., 1.)
+                } else {
+                    0.
+                };
+                (pa - ba * along).length() - radius
+            }
+            Shape::Union { shapes, smoothing } => shapes
+                .iter()
+                .map(|shape| shape.distance(p))
+                .reduce(|a, b| smooth_min(a, b, *smoothing))
+                .unwrap_or(f64::INFINITY),
+            Shape::Intersection { shapes } => shapes
+                .iter()
+                .map(|shape| shape.distance(p))
+                .fold(f64::NEG_INFINITY, f64::max),
+            Shape::Subtraction {
+                shape,
+                cut,
+                smoothing,
+            } => -smooth_min(-shape.distance(p), cut.distance(p), *smoothing),
+            Shape::Repeat {
+                shape,
+                spacing,
+                count,
+            } => shape.distance(Vector::new(
+                repeat(p.x, spacing.x, count[0]),
+                repeat(p.y, spacing.y, count[1]),
+                repeat(p.z, spacing.z, count[2]),
+            )),
+            Shape::Twist { shape, angle } => {
+                let (sin, cos) = (-angle.to_radians() * p.y).sin_cos();
+                shape.distance(Vector::new(
+                    cos * p.x - sin * p.z,
+                    p.y,
+                    sin * p.x + cos * p.z,
+                ))
+            }
+        }
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        let around = |centre: Vector, half: Vector| Aabb::new(centre - half, centre + half);
+        match self {
+            Shape::Sphere { centre, radius } => {
+                around(*centre, Vector::new(*radius, *radius, *radius))
+            }
+            Shape::Cuboid { centre, size, .. } => around(*centre, *size / 2.),
+            Shape::Torus {
+                centre,
+                major_radius,
+                minor_radius,
+            } => {
+                let outer = major_radius + minor_radius;
+                around(*centre, Vector::new(outer, *minor_radius, outer))
+            }
+            Shape::Capsule { start, end, radius } => {
+                let r = Vector::new(*radius, *radius, *radius);
+                Aabb::from_points(&[*start - r, *start + r, *end - r, *end + r])
+            }
+            Shape::Union { shapes, smoothing } => {
+                let bounds = shapes
+                    .iter()
+                    .fold(Aabb::empty(), |b, shape| b.union(&shape.bounds()));
+                // Blending can swell the surface by up to a quarter of the smoothing
+                let pad = Vector::new(*smoothing, *smoothing, *smoothing) / 4.;
+                Aabb::new(bounds.min - pad, bounds.max + pad)
+            }
+            Shape::Intersection { shapes } => shapes
+                .iter()
+                .map(|shape| shape.bounds())
+                .reduce(|a, b| {
+                    Aabb::new(
+                        Vector::new(
+                            a.min.x.max(b.min.x),
+                            a.min.y.max(b.min.y),
+                            a.min.z.max(b.min.z),
+                        ),
+                        Vector::new(
+                            a.max.x.min(b.max.x),
+                            a.max.y.min(b.max.y),
+                            a.max.z.min(b.max.z),
+                        ),
+                    )
+                })
+                .unwrap_or(Aabb::empty()),
+            Shape::Subtraction { shape, .. } => shape.bounds(),
+            Shape::Repeat {
+                shape,
+                spacing,
+                count,
+            } => {
+                let bounds = shape.bounds();
+                let spread =
+                    |spacing: f64, count: u32| spacing.abs() * count.saturating_sub(1) as f64 / 2.;
+                let half = Vector::new(
+                    spread(spacing.x, count[0]),
+                    spread(spacing.y, count[1]),
+                    spread(spacing.z, count[2]),
+                );
+                Aabb::new(bounds.min - half, bounds.max + half)
+            }
+            Shape::Twist { shape, .. } => {
+                let bounds = shape.bounds();
+                let r = self.twist_radius(&bounds);
+                Aabb::new(
+                    Vector::new(-r, bounds.min.y, -r),
+                    Vector::new(r, bounds.max.y, r),
+                )
+            }
+        }
+    }
+
+    // Furthest a box reaches from the y axis
+    fn twist_radius(&self, bounds: &Aabb) -> f64 {
+        let x = bounds.min.x.abs().max(bounds.max.x.abs());
+        let z = bounds.min.z.abs().max(bounds.max.z.abs());
+        x.hypot(z)
+    }
+
+    // Bound on how fast the distance can change, which is more than one where the space is
+    // distorted. Steps are shortened by it so they don't overshoot the surface.
+    pub fn lipschitz(&self) -> f64 {
+        match self {
+            Shape::Sphere { .. }
+            | Shape::Cuboid { .. }
+            | Shape::Torus { .. }
+            | Shape::Capsule { .. } => 1.,
+            Shape::Union { shapes, .. } | Shape::Intersection { shapes } => shapes
+                .iter()
+                .map(|shape| shape.lipschitz())
+                .fold(1., f64::max),
+            Shape::Subtraction { shape, cut, .. } => shape.lipschitz().max(cut.lipschitz()),
+            Shape::Repeat { shape, .. } => shape.lipschitz(),
+            Shape::Twist { shape, angle } => {
+                let r = self.twist_radius(&shape.bounds());
+                shape.lipschitz() * (1. + (angle.to_radians() * r).powi(2)).sqrt()
+            }
+        }
+    }
+}
+
+// A `type = "sdf"` entity
+#[derive(Debug, Clone, Deserialize)]
+pub struct SdfSpec {
+    pub shape: Shape,
+    pub material: Material,
+}
+
+impl SdfSpec {
+    pub fn build(self) -> Result<Box<dyn Entity>, String> {
+        let bounds = self.shape.bounds();
+        let finite = |v: Vector| v.x.is_finite() && v.y.is_finite() && v.z.is_finite();
+        if !finite(bounds.min) || !finite(bounds.max) {
+            return Err("an sdf's shape must be bounded".to_string());
+        }
+        Ok(Box::new(Sdf {
+            lipschitz: self.shape.lipschitz(),
+            shape: self.shape,
+            bounds,
+            material: self.material,
+        }))
+    }
+}
+
+// Surface where a signed distance field is zero, found by sphere tracing: stepping along the ray
+// by the distance to the nearest surface, which can't pass through it
+#[derive(Debug, Clone)]
+pub struct Sdf {
+    shape: Shape,
+    bounds: Aabb,
+    lipschitz: f64,
+    material: Material,
+}
+
+impl Sdf {
+    fn gradient(&self, p: Vector) -> Vector {
+        let h = GRADIENT_DELTA;
+        [
+            Vector::new(1., -1., -1.),
+            Vector::new(-1., -1., 1.),
+            Vector::new(-1., 1., -1.),
+            Vector::new(1., 1., 1.),
+        ]
+        .iter()
+        .fold(Vector::zero(), |sum, &k| {
+            sum + k * self.shape.distance(p + k * h)
+        })
+    }
+}
+
+impl Entity for Sdf {
+    fn intersection(&self, ray: &Ray) -> IntersectionResult {
+        let Some((t_min, t_max)) = self.bounds.interval(ray) else {
+            return IntersectionResult::No;
+        };
+        if t_max <= 0. {
+            return IntersectionResult::No;
+        }
+        // Step in units of length, whatever the ray's direction is scaled by
+        let length = ray.direction.length();
+        let mut t = t_min.max(0.) * length;
+        let end = t_max * length;
+        let direction = ray.direction / length;
+
+        // Rays starting inside trace the negated field out to the surface
+        let sign = self.shape.distance(ray.origin + direction * t).signum();
+        for _ in 0..MAX_STEPS {
+            let distance = sign * self.shape.distance(ray.origin + direction * t) / self.lipschitz;
+            if distance < SURFACE_EPSILON {
+                if t == 0. {
+                    // Already on the surface
+                    return IntersectionResult::No;
+                }
+                return IntersectionResult::One(t / length);
+            }
+            t += distance;
+            if t > end {
+                break;
+            }
+        }
+        IntersectionResult::No
+    }
+
+    fn surface(&self, ray: &Ray, hit: Hit) -> SurfaceInteraction<'_> {
+        let position = ray.at(hit.t);
+        let normal = self.gradient(position).normalised();
+        // There is no natural parameterisation, so textures should be solid ones
+        let (dpdu, dpdv) = sampling::orthonormal_basis(normal);
+        SurfaceInteraction {
+            position,
+            normal,
+            uv: (0., 0.),
+            dpdu,
+            dpdv,
+            object_position: position,
+            vertex_colour: None,
+            material: &self.material,
+        }
+    }
+
+    fn bounds(&self) -> Aabb {
+        self.bounds
+    }
+
+    fn position(&self) -> Vector {
+        self.bounds.centre()
+    }
+
+    fn emission(&self) -> Colour {
+        self.material.emission
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::World;
+
+    fn shape(toml_string: &str) -> Shape {
+        let table: toml::Table = toml_string.parse().unwrap();
+        Shape::deserialize(toml::Value::Table(table)).unwrap()
+    }
+
+    #[test]
+    fn primitive_distances() {
+        let sphere = shape("type = 'sphere'\nradius = 1");
+        assert_eq!(sphere.distance(Vector::new(0., 3., 0.)), 2.);
+        assert_eq!(sphere.distance(Vector::zero()), -1.);
+        let cuboid = shape("type = 'box'\nsize = [2, 2, 2]\nrounding = 0.5");
+        assert!((cuboid.distance(Vector::new(3., 0., 0.)) - 2.).abs() < 1e-12);
+        // The corner is rounded off
+        let corner = Vector::new(1., 1., 1.);
+        assert!((cuboid.distance(corner) - (0.5 * 3f64.sqrt() - 0.5)).abs() < 1e-12);
+        let torus = shape("type = 'torus'\nmajor_radius = 2\nminor_radius = 0.5");
+        assert_eq!(torus.distance(Vector::new(2., 0., 0.)), -0.5);
+        assert_eq!(torus.distance(Vector::new(0., 1., 0.)), 5f64.sqrt() - 0.5);
+        let capsule = shape("type = 'capsule'\nstart = [0, 0, 0]\nend = [0, 2, 0]\nradius = 0.5");
+        assert_eq!(capsule.distance(Vector::new(1., 1., 0.)), 0.5);
+        assert_eq!(capsule.distance(Vector::new(0., 3., 0.)), 0.5);
+    }
+
+    #[test]
+    fn combining_shapes() {
+        let union = shape(
+            "type = 'union'\nsmoothing = 0.5
+            shapes = [{type = 'sphere', centre = [-1, 0, 0], radius = 1}, {type = 'sphere', centre = [1, 0, 0], radius = 1}]",
+        );
+        // Smoothing fills in where the spheres meet
+        assert!(union.distance(Vector::new(0., 0.1, 0.)) < 0.);
+        let subtraction = shape(
+            "type = 'subtraction'
+            shape = {type = 'sphere', radius = 1}
+            cut = {type = 'sphere', centre = [1, 0, 0], radius = 0.5}",
+        );
+        assert_eq!(subtraction.distance(Vector::new(0.75, 0., 0.)), 0.25);
+        let repeated = shape(
+            "type = 'repeat'\nspacing = [3, 0, 0]\ncount = [3, 1, 1]
+            shape = {type = 'sphere', radius = 1}",
+        );
+        assert_eq!(repeated.distance(Vector::new(3., 0., 0.)), -1.);
+        // Beyond the last copy
+        assert_eq!(repeated.distance(Vector::new(9., 0., 0.)), 5.);
+        assert_eq!(repeated.bounds().max.x, 4.);
+        let twisted = shape(
+            "type = 'twist'\nangle = 90
+            shape = {type = 'box', size = [2, 4, 0.2]}",
+        );
+        // A unit up, the box has turned to lie along z
+        assert!(twisted.distance(Vector::new(0., 1., 0.9)) < 0.);
+        assert!(twisted.distance(Vector::new(0.9, 1., 0.)) > 0.);
+        assert!(twisted.lipschitz() > 1.);
+    }
+
+    #[test]
+    fn sphere_traced_alongside_analytic_entities() {
+        let toml_string = r#"
+        [[entities]]
+        type = "sdf"
+        material = {colour = [1, 1, 1], emission = [1, 1, 1]}
+        shape = {type = "sphere", centre = [0, 0, 5], radius = 1}
+
+        [[entities]]
+        type = "sphere"
+        position = [0, 0, 8]
+        radius = 1
+        material = {colour = [1, 1, 1], emission = [1, 1, 1]}
+
+        [[entities]]
+        type = "sdf"
+        material = {colour = [1, 1, 1]}
+        shape = {type = "torus", centre = [3, 0, 5], major_radius = 1, minor_radius = 0.25}
+        "#;
+        let world = World::from_toml(&toml_string.parse::<toml::Table>().unwrap());
+        assert_eq!(world.entities.len(), 3);
+        // Both glow, but only the sphere can be sampled as a light
+        assert_eq!(world.emitters(), &[1]);
+        let forward = Vector::new(0., 0., 1.);
+        let result = world.find_nearest(&Ray::new(Vector::zero(), forward));
+        assert!((result.distance - 4.).abs() < 1e-5);
+        assert!((result.normal - Vector::new(0., 0., -1.)).length() < 1e-4);
+        assert_eq!(result.material.emission, Colour::white());
+        // Through the torus's hole to nothing
+        let down = Vector::new(0., 1., 0.);
+        let result = world.find_nearest(&Ray::new(Vector::new(3., -5., 5.), down));
+        assert!(!result.hit);
+        // From inside the tube, out through its wall
+        let inside = Ray::new(Vector::new(4., 0., 5.), Vector::new(0., 1., 0.));
+        let t = world.entities[2].intersection(&inside).nearest().unwrap();
+        assert!((t - 0.25).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,260 @@
+// Resources:
+// Preetham, Shirley, Smits 1999, "A Practical Analytic Model for Daylight": https://courses.cs.duke.edu/cps124/fall01/resources/p91-preetham.pdf
+// XYZ to linear sRGB: http://www.brucelindbloom.com/index.html?Eqn_RGB_XYZ_Matrix.html
+
+use core::f64;
+
+use serde::Deserialize;
+
+use crate::colour::Colour;
+use crate::vector::Vector;
+
+// The model gives luminance in kcd/m^2; this brings a clear midday sky to roughly the same
+// brightness as a white surface lit by a unit intensity light
+const SKY_SCALE: f64 = 0.03;
+
+fn default_turbidity() -> f64 {
+    3.
+}
+
+fn default_sun_elevation() -> f64 {
+    45.
+}
+
+fn default_intensity() -> f64 {
+    1.
+}
+
+fn default_ground() -> Colour {
+    Colour::new(0.1, 0.1, 0.1)
+}
+
+// Angles are in degrees. Azimuth is measured from straight ahead (+z) towards +x.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct SkySpec {
+    #[serde(default = "default_sun_elevation")]
+    pub sun_elevation: f64,
+    #[serde(default)]
+    pub sun_azimuth: f64,
+    #[serde(default = "default_turbidity")]
+    pub turbidity: f64,
+    #[serde(default = "default_intensity")]
+    pub intensity: f64,
+    #[serde(default = "default_intensity")]
+    pub sun_intensity: f64,
+    #[serde(default = "default_ground")]
+    pub ground: Colour, // radiance below the horizon
+}
+
+// Perez et al. luminance distribution coefficients
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Perez {
+    a: f64,
+    b: f64,
+    c: f64,
+    d: f64,
+    e: f64,
+}
+
+impl Perez {
+    // theta is the view zenith angle, gamma the angle between the view and sun directions
+    fn f(&self, cos_theta: f64, gamma: f64) -> f64 {
+        (1. + self.a * (self.b / cos_theta).exp())
+            * (1. + self.c * (self.d * gamma).exp() + self.e * gamma.cos() * gamma.cos())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sky {
+    sun_direction: Vector,
+    intensity: f64,
+    ground: Colour,
+    sun_colour: Colour,
+    sun_intensity: f64,
+    // Zenith values of Y, x, y and the Perez distribution of each
+    zenith: [f64; 3],
+    perez: [Perez; 3],
+    // F(0, theta_s) for each distribution, normalising the zenith values
+    perez_zenith: [f64; 3],
+}
+
+impl Sky {
+    pub fn new(spec: &SkySpec) -> Self {
+        let t = spec.turbidity.max(1.);
+        let elevation = spec.sun_elevation.to_radians();
+        let azimuth = spec.sun_azimuth.to_radians();
+        // Up is -y
+        let sun_direction = Vector::new(
+            elevation.cos() * azimuth.sin(),
+            -elevation.sin(),
+            elevation.cos() * azimuth.cos(),
+        );
+
+        // Keep the sun just above the horizon so the zenith formulae stay finite
+        let theta_s = (f64::consts::FRAC_PI_2 - elevation).clamp(0., f64::consts::FRAC_PI_2 - 1e-3);
+
+        let perez = [
+            Perez {
+                a: 0.1787 * t - 1.4630,
+                b: -0.3554 * t + 0.4275,
+                c: -0.0227 * t + 5.3251,
+                d: 0.1206 * t - 2.5771,
+                e: -0.0670 * t + 0.3703,
+            },
+            Perez {
+                a: -0.0193 * t - 0.2592,
+                b: -0.0665 * t + 0.0008,
+                c: -0.0004 * t + 0.2125,
+                d: -0.0641 * t - 0.8989,
+                e: -0.0033 * t + 0.0452,
+            },
+            Perez {
+                a: -0.0167 * t - 0.2608,
+                b: -0.0950 * t + 0.0092,
+                c: -0.0079 * t + 0.2102,
+                d: -0.0441 * t - 1.6537,
+                e: -0.0109 * t + 0.0529,
+            },
+        ];
+
+        let chi = (4. / 9. - t / 120.) * (f64::consts::PI - 2. * theta_s);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let theta = [theta_s.powi(3), theta_s.powi(2), theta_s, 1.];
+        let chromaticity = |m: [[f64; 4]; 3]| {
+            let row = |r: [f64; 4]| r.iter().zip(theta).map(|(a, b)| a * b).sum::<f64>();
+            t * t * row(m[0]) + t * row(m[1]) + row(m[2])
+        };
+        let zenith_x = chromaticity([
+            [0.00166, -0.00375, 0.00209, 0.],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ]);
+        let zenith_y = chromaticity([
+            [0.00275, -0.00610, 0.00317, 0.],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ]);
+
+        let perez_zenith = perez.map(|p| p.f(1., theta_s));
+
+        let (sun_colour, sun_intensity) = if spec.sun_elevation > 0. {
+            (sun_transmittance(theta_s, t), spec.sun_intensity)
+        } else {
+            (Colour::black(), 0.)
+        };
+
+        Sky {
+            sun_direction,
+            intensity: spec.intensity,
+            ground: spec.ground,
+            sun_colour,
+            sun_intensity,
+            zenith: [zenith_luminance.max(0.), zenith_x, zenith_y],
+            perez,
+            perez_zenith,
+        }
+    }
+
+    // Unit vector pointing towards the sun
+    pub fn sun_direction(&self) -> Vector {
+        self.sun_direction
+    }
+
+    // Colour of direct sunlight after passing through the atmosphere, and its intensity
+    pub fn sun(&self) -> (Colour, f64) {
+        (self.sun_colour, self.sun_intensity)
+    }
+
+    pub fn radiance(&self, direction: Vector) -> Colour {
+        let d = direction.normalised();
+        let cos_theta = -d.y;
+        if cos_theta <= 0. {
+            return self.ground;
+        }
+        // Avoid the Perez exp(b / cos) term blowing up right at the horizon
+        let cos_theta = cos_theta.max(0.01);
+        let gamma = d.dot(&self.sun_direction).clamp(-1., 1.).acos();
+
+        let [luminance, x, y] = [0, 1, 2]
+            .map(|i| self.zenith[i] * self.perez[i].f(cos_theta, gamma) / self.perez_zenith[i]);
+
+        xyy_to_rgb(x, y, luminance * SKY_SCALE * self.intensity)
+    }
+}
+
+fn xyy_to_rgb(x: f64, y: f64, luminance: f64) -> Colour {
+    if y <= 0. {
+        return Colour::black();
+    }
+    let cx = x * luminance / y;
+    let cy = luminance;
+    let cz = (1. - x - y) * luminance / y;
+    let r = 3.2406 * cx - 1.5372 * cy - 0.4986 * cz;
+    let g = -0.9689 * cx + 1.8758 * cy + 0.0415 * cz;
+    let b = 0.0557 * cx - 0.2040 * cy + 1.0570 * cz;
+    Colour::new(r.max(0.) as f32, g.max(0.) as f32, b.max(0.) as f32)
+}
+
+// Rayleigh and aerosol extinction of sunlight (appendix A.2 of Preetham et al.), evaluated at a
+// representative wavelength for each of red, green and blue. Ozone and water vapour are ignored.
+fn sun_transmittance(theta_s: f64, turbidity: f64) -> Colour {
+    let theta_degrees = theta_s.to_degrees();
+    let relative_optical_mass = 1. / (theta_s.cos() + 0.15 * (93.885 - theta_degrees).powf(-1.253));
+    let beta = 0.04608365822050 * turbidity - 0.04586025928522;
+    let alpha = 1.3;
+
+    let transmittance = |lambda_um: f64| {
+        let rayleigh = (-0.008735 * lambda_um.powf(-4.08) * relative_optical_mass).exp();
+        let aerosol = (-beta * lambda_um.powf(-alpha) * relative_optical_mass).exp();
+        (rayleigh * aerosol) as f32
+    };
+
+    Colour::new(
+        transmittance(0.65),
+        transmittance(0.57),
+        transmittance(0.475),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sky(sun_elevation: f64, turbidity: f64) -> Sky {
+        Sky::new(&SkySpec {
+            sun_elevation,
+            sun_azimuth: 90.,
+            turbidity,
+            intensity: 1.,
+            sun_intensity: 1.,
+            ground: default_ground(),
+        })
+    }
+
+    #[test]
+    fn sun_direction_from_angles() {
+        let s = sky(90., 3.);
+        assert!((s.sun_direction() - Vector::new(0., -1., 0.)).length() < 1e-9);
+        let s = sky(0., 3.);
+        assert!((s.sun_direction() - Vector::new(1., 0., 0.)).length() < 1e-9);
+    }
+
+    #[test]
+    fn clear_sky_is_blue_and_brightest_near_sun() {
+        let s = sky(30., 2.5);
+        let zenith = s.radiance(Vector::new(0., -1., 0.));
+        assert!(zenith.b > zenith.r);
+        let near_sun = s.radiance(s.sun_direction());
+        let away = s.radiance(Vector::new(-0.9, -0.4, 0.).normalised());
+        assert!(near_sun.max_component() > away.max_component());
+        assert_eq!(s.radiance(Vector::new(0., 1., 0.)), default_ground());
+    }
+
+    #[test]
+    fn low_sun_is_redder() {
+        let (high, _) = sky(80., 3.).sun();
+        let (low, _) = sky(5., 3.).sun();
+        assert!(low.b / low.r < high.b / high.r);
+        assert_eq!(sky(-5., 3.).sun().1, 0.);
+    }
+}
@@ -0,0 +1,34 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Fixture files for one test, in a directory named after the test and the process so that
+// concurrent runs don't share it. Removed when dropped.
+pub struct TestDir(PathBuf);
+
+impl TestDir {
+    pub fn new(test: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("raytrace_{}_{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TestDir(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    // Write a file, given relative to the directory, creating any directories it is in
+    pub fn write(&self, name: &str, contents: impl AsRef<[u8]>) {
+        let path = self.0.join(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).unwrap();
+        }
+        fs::write(path, contents).unwrap();
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,530 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::Deserialize;
+
+use crate::colour::Colour;
+use crate::image::FloatImage;
+use crate::noise;
+use crate::vector::{Transform, Vector};
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Filter {
+    Nearest,
+    Bilinear,
+}
+
+// What happens to UVs outside [0, 1)
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Wrap {
+    Repeat,
+    Clamp,
+}
+
+fn default_filter() -> Filter {
+    Filter::Bilinear
+}
+
+fn default_wrap() -> Wrap {
+    Wrap::Repeat
+}
+
+fn default_scale() -> (f64, f64) {
+    (1., 1.)
+}
+
+// An image looked up by UV, with (0, 0) the top left of the image. `scale` tiles the image
+// that many times across the surface.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ImageTexture {
+    pub image: String,
+    #[serde(default = "default_filter")]
+    pub filter: Filter,
+    #[serde(default = "default_wrap")]
+    pub wrap: Wrap,
+    #[serde(default = "default_scale")]
+    pub scale: (f64, f64),
+    #[serde(skip)]
+    data: Option<Arc<FloatImage>>, // filled in by `TextureLoader`
+}
+
+impl ImageTexture {
+    pub fn new(data: Arc<FloatImage>, filter: Filter, wrap: Wrap) -> Self {
+        ImageTexture {
+            image: String::new(),
+            filter,
+            wrap,
+            scale: default_scale(),
+            data: Some(data),
+        }
+    }
+
+    pub fn lookup(&self, uv: (f64, f64)) -> Colour {
+        let Some(image) = &self.data else {
+            return Colour::white();
+        };
+        let (width, height) = (image.width as f64, image.height as f64);
+        let (x, y) = (uv.0 * self.scale.0 * width, uv.1 * self.scale.1 * height);
+
+        match self.filter {
+            Filter::Nearest => image.pixel(
+                self.wrap.apply(x.floor() as i64, image.width),
+                self.wrap.apply(y.floor() as i64, image.height),
+            ),
+            Filter::Bilinear => {
+                // Blend the four texels whose centres surround the lookup point
+                let (x, y) = (x - 0.5, y - 0.5);
+                let (x0, y0) = (x.floor(), y.floor());
+                let (fx, fy) = ((x - x0) as f32, (y - y0) as f32);
+                let (x0, y0) = (x0 as i64, y0 as i64);
+                let texel = |dx: i64, dy: i64| {
+                    image.pixel(
+                        self.wrap.apply(x0 + dx, image.width),
+                        self.wrap.apply(y0 + dy, image.height),
+                    )
+                };
+                let top = texel(0, 0) * (1. - fx) + texel(1, 0) * fx;
+                let bottom = texel(0, 1) * (1. - fx) + texel(1, 1) * fx;
+                top * (1. - fy) + bottom * fy
+            }
+        }
+    }
+}
+
+impl Wrap {
+    fn apply(&self, i: i64, size: usize) -> usize {
+        match self {
+            Wrap::Repeat => i.rem_euclid(size as i64) as usize,
+            Wrap::Clamp => i.clamp(0, size as i64 - 1) as usize,
+        }
+    }
+}
+
+// Which position procedural textures are evaluated at
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Space {
+    Object, // moves with the entity
+    World,
+}
+
+fn default_space() -> Space {
+    Space::Object
+}
+
+fn default_colours() -> [Colour; 2] {
+    [Colour::black(), Colour::white()]
+}
+
+fn default_procedural_scale() -> f64 {
+    1.
+}
+
+fn default_octaves() -> u32 {
+    6
+}
+
+fn default_turbulence() -> f64 {
+    5.
+}
+
+fn default_wood_turbulence() -> f64 {
+    0.5
+}
+
+fn default_rings() -> f64 {
+    8.
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Pattern {
+    // Alternating unit cubes
+    Checker,
+    Noise,
+    Fbm {
+        #[serde(default = "default_octaves")]
+        octaves: u32,
+    },
+    Turbulence {
+        #[serde(default = "default_octaves")]
+        octaves: u32,
+    },
+    // Bands along x, distorted by turbulence
+    Marble {
+        #[serde(default = "default_octaves")]
+        octaves: u32,
+        #[serde(default = "default_turbulence")]
+        turbulence: f64,
+    },
+    // Rings around the vertical axis, per unit of distance, with a little turbulence
+    Wood {
+        #[serde(default = "default_rings")]
+        rings: f64,
+        #[serde(default = "default_octaves")]
+        octaves: u32,
+        #[serde(default = "default_wood_turbulence")]
+        turbulence: f64,
+    },
+}
+
+impl Pattern {
+    // Value in [0, 1] used to blend between the two colours
+    fn value(&self, p: Vector) -> f64 {
+        let t = match *self {
+            Pattern::Checker => {
+                let sum = p.x.floor() + p.y.floor() + p.z.floor();
+                sum.rem_euclid(2.)
+            }
+            Pattern::Noise => 0.5 * (noise::perlin(p) + 1.),
+            Pattern::Fbm { octaves } => 0.5 * (noise::fbm(p, octaves) + 1.),
+            Pattern::Turbulence { octaves } => noise::turbulence(p, octaves),
+            Pattern::Marble {
+                octaves,
+                turbulence,
+            } => 0.5 * (1. + (p.x + turbulence * noise::turbulence(p, octaves)).sin()),
+            Pattern::Wood {
+                rings,
+                octaves,
+                turbulence,
+            } => {
+                let r = (p.x * p.x + p.z * p.z).sqrt() * rings;
+                let g = r + turbulence * noise::fbm(p, octaves);
+                g - g.floor()
+            }
+        };
+        t.clamp(0., 1.)
+    }
+}
+
+// A pattern evaluated at the hit position, scaled (larger is finer) and offset, blending from
+// the first colour to the second. `transform` places the pattern the way an entity's transform
+// block places the entity, so e.g. `{scale = [4, 1, 1]}` stretches it along x.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct ProceduralTexture {
+    #[serde(flatten)]
+    pub pattern: Pattern,
+    #[serde(default = "default_colours")]
+    pub colours: [Colour; 2],
+    #[serde(default = "default_procedural_scale")]
+    pub scale: f64,
+    #[serde(default = "Vector::zero")]
+    pub offset: Vector,
+    #[serde(default = "default_space")]
+    pub space: Space,
+    pub transform: Option<Transform>,
+}
+
+impl ProceduralTexture {
+    pub fn evaluate(&self, coords: &TextureCoords) -> Colour {
+        let mut position = match self.space {
+            Space::Object => coords.object_position,
+            Space::World => coords.position,
+        };
+        if let Some(transform) = &self.transform {
+            position = transform.inverse.transform_point(position);
+        }
+        let t = self.pattern.value(position * self.scale + self.offset) as f32;
+        self.colours[0] * (1. - t) + self.colours[1] * t
+    }
+}
+
+// Everything a texture might be looked up by at a point on a surface
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextureCoords {
+    pub uv: (f64, f64),
+    pub position: Vector,
+    pub object_position: Vector, // relative to the entity
+    pub vertex_colour: Option<Colour>,
+}
+
+// Written as `"vertex"`: the colours a mesh gives its vertices, blended across each face, or
+// white on surfaces without them
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VertexColour {
+    Vertex,
+}
+
+// A colour that may vary over a surface
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum Texture {
+    Constant(Colour),
+    Image(ImageTexture),
+    Procedural(Box<ProceduralTexture>), // boxed for its transform's size
+    Vertex(VertexColour),
+}
+
+impl Texture {
+    pub fn evaluate(&self, coords: &TextureCoords) -> Colour {
+        match self {
+            Texture::Constant(colour) => *colour,
+            Texture::Image(texture) => texture.lookup(coords.uv),
+            Texture::Procedural(texture) => texture.evaluate(coords),
+            Texture::Vertex(_) => coords.vertex_colour.unwrap_or(Colour::white()),
+        }
+    }
+
+    // Mean of the channels, for textures used as a single value such as a height
+    pub fn value(&self, coords: &TextureCoords) -> f64 {
+        let c = self.evaluate(coords);
+        (c.r + c.g + c.b) as f64 / 3.
+    }
+
+    // Load any image this texture refers to
+    pub fn load(&mut self, loader: &mut TextureLoader) -> Result<(), Box<dyn Error>> {
+        if let Texture::Image(texture) = self {
+            texture.data = Some(loader.load(&texture.image)?);
+        }
+        Ok(())
+    }
+}
+
+impl From<Colour> for Texture {
+    fn from(colour: Colour) -> Self {
+        Texture::Constant(colour)
+    }
+}
+
+// A single value that may vary over a surface, such as roughness. Given as a number or as any
+// texture, which is reduced to the mean of its channels.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum FloatTexture {
+    Constant(f64),
+    Texture(Texture),
+}
+
+impl FloatTexture {
+    pub fn evaluate(&self, coords: &TextureCoords) -> f64 {
+        match self {
+            FloatTexture::Constant(value) => *value,
+            FloatTexture::Texture(texture) => texture.value(coords),
+        }
+    }
+
+    pub fn load(&mut self, loader: &mut TextureLoader) -> Result<(), Box<dyn Error>> {
+        match self {
+            FloatTexture::Constant(_) => Ok(()),
+            FloatTexture::Texture(texture) => texture.load(loader),
+        }
+    }
+}
+
+impl Default for FloatTexture {
+    fn default() -> Self {
+        FloatTexture::Constant(0.)
+    }
+}
+
+impl From<f64> for FloatTexture {
+    fn from(value: f64) -> Self {
+        FloatTexture::Constant(value)
+    }
+}
+
+// Loads images relative to the scene file, sharing them between every texture that uses them
+pub struct TextureLoader {
+    dir: PathBuf, // that relative paths are looked up in
+    images: HashMap<PathBuf, Arc<FloatImage>>,
+}
+
+impl TextureLoader {
+    pub fn new(scene_dir: &Path) -> Self {
+        TextureLoader {
+            dir: scene_dir.to_path_buf(),
+            images: HashMap::new(),
+        }
+    }
+
+    // Look up relative paths in `dir` from now on, returning the directory used before
+    pub fn set_dir(&mut self, dir: PathBuf) -> PathBuf {
+        std::mem::replace(&mut self.dir, dir)
+    }
+
+    pub fn load(&mut self, path: &str) -> Result<Arc<FloatImage>, Box<dyn Error>> {
+        let path = self.dir.join(path);
+        if let Some(image) = self.images.get(&path) {
+            return Ok(image.clone());
+        }
+        let image = Arc::new(FloatImage::load(&path)?);
+        self.images.insert(path, image.clone());
+        Ok(image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checker(filter: Filter, wrap: Wrap) -> ImageTexture {
+        let pixels = vec![
+            Colour::black(),
+            Colour::white(),
+            Colour::white(),
+            Colour::black(),
+        ];
+        ImageTexture::new(Arc::new(FloatImage::new(2, 2, pixels)), filter, wrap)
+    }
+
+    #[test]
+    fn nearest_repeats_and_clamps() {
+        let repeat = checker(Filter::Nearest, Wrap::Repeat);
+        assert_eq!(repeat.lookup((0.25, 0.25)), Colour::black());
+        assert_eq!(repeat.lookup((0.75, 0.25)), Colour::white());
+        assert_eq!(repeat.lookup((1.25, -0.75)), Colour::black());
+
+        let clamp = checker(Filter::Nearest, Wrap::Clamp);
+        assert_eq!(clamp.lookup((1.75, 0.25)), Colour::white());
+        assert_eq!(clamp.lookup((-3., 0.25)), Colour::black());
+    }
+
+    #[test]
+    fn bilinear_blends_neighbours() {
+        let texture = checker(Filter::Bilinear, Wrap::Clamp);
+        assert_eq!(texture.lookup((0.5, 0.25)), Colour::new(0.5, 0.5, 0.5));
+        assert_eq!(texture.lookup((0.25, 0.25)), Colour::black());
+        // Repeating blends across the edge with the opposite side
+        let texture = checker(Filter::Bilinear, Wrap::Repeat);
+        assert_eq!(texture.lookup((0., 0.25)), Colour::new(0.5, 0.5, 0.5));
+    }
+
+    #[test]
+    fn parses_colour_or_image() {
+        let table: toml::Table = r#"
+        a = [1, 0, 0]
+        b = {image = "wood.png", filter = "nearest", wrap = "clamp"}
+        "#
+        .parse()
+        .unwrap();
+        let a: Texture = table["a"].clone().try_into().unwrap();
+        assert_eq!(a, Texture::Constant(Colour::new(1., 0., 0.)));
+        let Texture::Image(b) = table["b"].clone().try_into().unwrap() else {
+            panic!("expected an image texture");
+        };
+        assert_eq!((b.filter, b.wrap), (Filter::Nearest, Wrap::Clamp));
+        assert_eq!(b.image, "wood.png");
+    }
+
+    fn at(position: Vector) -> TextureCoords {
+        TextureCoords {
+            uv: (0., 0.),
+            position,
+            object_position: position - Vector::new(10., 0., 0.),
+            vertex_colour: None,
+        }
+    }
+
+    #[test]
+    fn parses_procedural_textures() {
+        let table: toml::Table = r#"
+        checker = {type = "checker", colours = [[1, 0, 0], [0, 0, 1]], scale = 2}
+        marble = {type = "marble", octaves = 3, space = "world"}
+        "#
+        .parse()
+        .unwrap();
+        let Texture::Procedural(checker) = table["checker"].clone().try_into().unwrap() else {
+            panic!("expected a procedural texture");
+        };
+        assert_eq!(checker.pattern, Pattern::Checker);
+        assert_eq!(checker.scale, 2.);
+        assert_eq!(checker.space, Space::Object);
+        let Texture::Procedural(marble) = table["marble"].clone().try_into().unwrap() else {
+            panic!("expected a procedural texture");
+        };
+        assert_eq!(
+            marble.pattern,
+            Pattern::Marble {
+                octaves: 3,
+                turbulence: default_turbulence()
+            }
+        );
+        assert_eq!(marble.space, Space::World);
+    }
+
+    #[test]
+    fn transform_stretches_and_turns_patterns() {
+        let checker = |transform: &str| {
+            let table: toml::Table = format!(
+                "texture = {{type = \"checker\", colours = [[0, 0, 0], [1, 1, 1]]{}}}",
+                transform
+            )
+            .parse()
+            .unwrap();
+            let Texture::Procedural(checker) = table["texture"].clone().try_into().unwrap() else {
+                panic!("expected a procedural texture");
+            };
+            move |x: f64, y: f64| checker.evaluate(&at(Vector::new(x + 10., y, 0.5))).r
+        };
+        let plain = checker("");
+        assert_eq!((plain(0.5, 0.5), plain(1.5, 0.5)), (0., 1.));
+        // Twice as wide along x, so the next square along starts at 2
+        let stretched = checker(", transform = {scale = [2, 1, 1]}");
+        assert_eq!((stretched(1.5, 0.5), stretched(2.5, 0.5)), (0., 1.));
+        assert_eq!(stretched(1.5, 1.5), 1.);
+        // A quarter turn about z takes the white square at (1.5, 0.5) to (-0.5, 1.5)
+        let turned = checker(", transform = {rotate = {axis = [0, 0, 1], angle = 90}}");
+        assert_eq!((turned(-0.5, 1.5), turned(-0.5, 0.5)), (1., 0.));
+    }
+
+    #[test]
+    fn float_textures_take_numbers_or_textures() {
+        let table: toml::Table = r#"
+        a = 0.25
+        b = 1
+        c = {type = "checker", colours = [[0, 0, 0], [0.3, 0.6, 0.9]]}
+        "#
+        .parse()
+        .unwrap();
+        let coords = at(Vector::new(11.5, 0.5, 0.5));
+        let value = |name: &str| {
+            let texture: FloatTexture = table[name].clone().try_into().unwrap();
+            texture.evaluate(&coords)
+        };
+        assert_eq!(value("a"), 0.25);
+        assert_eq!(value("b"), 1.);
+        assert!((value("c") - 0.6).abs() < 1e-6);
+    }
+
+    #[test]
+    fn checker_alternates_in_3d() {
+        let texture: Texture =
+            toml::Value::try_into(r#"type = "checker""#.parse::<toml::Table>().unwrap().into())
+                .unwrap();
+        let a = texture.evaluate(&at(Vector::new(10.5, 0.5, 0.5)));
+        let b = texture.evaluate(&at(Vector::new(11.5, 0.5, 0.5)));
+        let c = texture.evaluate(&at(Vector::new(11.5, 1.5, 0.5)));
+        assert_eq!(a, Colour::black());
+        assert_eq!(b, Colour::white());
+        assert_eq!(c, Colour::black());
+    }
+
+    #[test]
+    fn patterns_stay_in_range() {
+        let patterns = [
+            Pattern::Noise,
+            Pattern::Fbm { octaves: 6 },
+            Pattern::Turbulence { octaves: 6 },
+            Pattern::Marble {
+                octaves: 6,
+                turbulence: 5.,
+            },
+            Pattern::Wood {
+                rings: 8.,
+                octaves: 2,
+                turbulence: 0.5,
+            },
+        ];
+        for pattern in patterns {
+            for i in 0..200 {
+                let p = Vector::new(i as f64 * 0.173, -0.3 * i as f64, 1.1);
+                assert!((0. ..=1.).contains(&pattern.value(p)));
+            }
+        }
+    }
+}
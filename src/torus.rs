@@ -0,0 +1,272 @@
+// Resources:
+// Ray/torus intersection: https://marcin-chwedczuk.github.io/ray-tracing-torus
+// Sampling by inverting a distribution's CDF: https://pbr-book.org/4ed/Monte_Carlo_Integration/Sampling_Using_the_Inversion_Method
+
+use core::f64;
+
+use crate::bvh::Aabb;
+use crate::colour::Colour;
+use crate::material::Material;
+use crate::polynomial;
+use crate::quadric;
+use crate::ray::Ray;
+use crate::sampling;
+use crate::vector::Vector;
+use crate::world::{Entity, Hit, IntersectionResult, SurfaceInteraction, SurfaceSample};
+use serde::Deserialize;
+
+// A `type = "torus"` entity centred on `position`, with its hole along `axis`
+#[derive(Debug, Clone, Deserialize)]
+pub struct TorusSpec {
+    pub position: Vector,
+    #[serde(default = "quadric::default_axis")]
+    pub axis: Vector,
+    pub major_radius: f64,
+    pub minor_radius: f64,
+    pub material: Material,
+}
+
+impl TorusSpec {
+    pub fn build(self) -> Result<Box<dyn Entity>, String> {
+        if self.minor_radius <= 0. || self.major_radius <= self.minor_radius {
+            return Err("a torus needs 0 < minor_radius < major_radius".to_string());
+        }
+        if self.axis.abs_squared() == 0. {
+            return Err("axis must not be zero".to_string());
+        }
+        let torus = Torus {
+            major_radius: self.major_radius,
+            minor_radius: self.minor_radius,
+            material: self.material,
+        };
+        Ok(quadric::place(torus, self.position, self.axis))
+    }
+}
+
+// Ring around the z axis at the origin, swept out by a circle of `minor_radius` whose centre is
+// `major_radius` from the axis
+#[derive(Debug, Clone)]
+pub struct Torus {
+    major_radius: f64,
+    minor_radius: f64,
+    material: Material,
+}
+
+impl Torus {
+    // Angles around the axis (phi) and around the tube (theta) of a point on the surface
+    fn angles(&self, p: Vector) -> (f64, f64) {
+        let phi = p.y.atan2(p.x);
+        let theta =
+            p.z.atan2((p.x * p.x + p.y * p.y).sqrt() - self.major_radius);
+        (phi, theta)
+    }
+
+    fn point(&self, phi: f64, theta: f64) -> Vector {
+        let ring = self.major_radius + self.minor_radius * theta.cos();
+        Vector::new(
+            ring * phi.cos(),
+            ring * phi.sin(),
+            self.minor_radius * theta.sin(),
+        )
+    }
+
+    fn normal(phi: f64, theta: f64) -> Vector {
+        Vector::new(
+            theta.cos() * phi.cos(),
+            theta.cos() * phi.sin(),
+            theta.sin(),
+        )
+    }
+}
+
+impl Entity for Torus {
+    fn intersection(&self, ray: &Ray) -> IntersectionResult {
+        let length = ray.direction.length();
+        let d = ray.direction / length;
+        let (big, small) = (self.major_radius, self.minor_radius);
+
+        // Start from where the ray enters the bounding sphere, so the roots are found close to
+        // the origin of the quartic, where it is best conditioned
+        let bound = big + small;
+        let entry = polynomial::solve_quadratic(
+            1.,
+            2. * ray.origin.dot(&d),
+            ray.origin.abs_squared() - bound * bound,
+        );
+        let [_, exit] = entry[..] else {
+            return IntersectionResult::No;
+        };
+        if exit <= 0. {
+            return IntersectionResult::No;
+        }
+        let start = entry[0].max(0.);
+        let o = ray.origin + d * start;
+
+        // (|p|^2 + R^2 - r^2)^2 = 4 R^2 (x^2 + y^2) along the ray p = o + t d
+        let e = o.abs_squared() + big * big - small * small;
+        let f = o.dot(&d);
+        let g = 4. * big * big;
+        let roots = polynomial::solve_quartic(
+            4. * f,
+            2. * e + 4. * f * f - g * (d.x * d.x + d.y * d.y),
+            4. * f * e - 2. * g * (o.x * d.x + o.y * d.y),
+            e * e - g * (o.x * o.x + o.y * o.y),
+        );
+        IntersectionResult::from_distances(roots.iter().map(|t| (t + start) / length).collect())
+    }
+
+    fn surface(&self, ray: &Ray, hit: Hit) -> SurfaceInteraction<'_> {
+        let position = ray.at(hit.t);
+        let (phi, theta) = self.angles(position);
+        // u goes around the axis and v down around the tube, from the outer equator
+        let tau = 2. * f64::consts::PI;
+        let uv = (phi.rem_euclid(tau) / tau, 1. - theta.rem_euclid(tau) / tau);
+        let ring = self.major_radius + self.minor_radius * theta.cos();
+        let dpdu = Vector::new(-ring * phi.sin(), ring * phi.cos(), 0.) * tau;
+        let dpdv = Vector::new(
+            -theta.sin() * phi.cos(),
+            -theta.sin() * phi.sin(),
+            theta.cos(),
+        ) * (-tau * self.minor_radius);
+        SurfaceInteraction {
+            position,
+            normal: Torus::normal(phi, theta),
+            uv,
+            dpdu,
+            dpdv,
+            object_position: position,
+            vertex_colour: None,
+            material: &self.material,
+        }
+    }
+
+    fn bounds(&self) -> Aabb {
+        let outer = self.major_radius + self.minor_radius;
+        let r = self.minor_radius;
+        Aabb::new(
+            Vector::new(-outer, -outer, -r),
+            Vector::new(outer, outer, r),
+        )
+    }
+
+    fn is_closed(&self) -> bool {
+        true
+    }
+
+    fn position(&self) -> Vector {
+        Vector::zero()
+    }
+
+    fn emission(&self) -> Colour {
+        self.material.emission
+    }
+
+    fn can_sample(&self) -> bool {
+        true
+    }
+
+    fn area(&self) -> f64 {
+        4. * f64::consts::PI * f64::consts::PI * self.major_radius * self.minor_radius
+    }
+
+    // Uniform over the surface area, where the outside of the ring has more area than the inside
+    fn sample(&self, reference: Vector, u: (f64, f64)) -> Option<SurfaceSample> {
+        let (big, small) = (self.major_radius, self.minor_radius);
+        let tau = 2. * f64::consts::PI;
+        // Invert the CDF of theta, (R theta + r sin theta) / 2 pi R, with Newton's method
+        let target = u.0 * tau * big;
+        let mut theta = u.0 * tau;
+        for _ in 0..8 {
+            let error = big * theta + small * theta.sin() - target;
+            theta = (theta - error / (big + small * theta.cos())).clamp(0., tau);
+        }
+        let phi = u.1 * tau;
+
+        let position = self.point(phi, theta);
+        let normal = Torus::normal(phi, theta);
+        let pdf = self.pdf(reference, position, normal);
+        if pdf == 0. {
+            return None;
+        }
+        Some(SurfaceSample {
+            position,
+            normal,
+            pdf,
+            emission: self.emission(),
+        })
+    }
+
+    fn pdf(&self, reference: Vector, point: Vector, normal: Vector) -> f64 {
+        sampling::area_to_solid_angle_pdf(1. / self.area(), reference, point, normal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::Rng;
+
+    fn torus() -> Torus {
+        Torus {
+            major_radius: 2.,
+            minor_radius: 0.5,
+            material: Material::default(),
+        }
+    }
+
+    #[test]
+    fn ray_through_both_sides_hits_four_times() {
+        let torus = torus();
+        let ray = Ray::new(Vector::new(-5., 0., 0.), Vector::new(2., 0., 0.));
+        let IntersectionResult::Four(t1, t2, t3, t4) = torus.intersection(&ray) else {
+            panic!("expected four hits");
+        };
+        // The direction isn't normalised, so distances are in units of it
+        let expected = [1.25, 1.75, 3.25, 3.75];
+        for (t, e) in [t1, t2, t3, t4].iter().zip(expected) {
+            assert!((t - e).abs() < 1e-9, "{} != {}", t, e);
+        }
+        let surface = torus.surface(&ray, Hit::new(t1));
+        assert!((surface.normal - Vector::new(-1., 0., 0.)).length() < 1e-9);
+        // The inside of the hole faces the axis
+        let surface = torus.surface(&ray, Hit::new(t2));
+        assert!((surface.normal - Vector::new(1., 0., 0.)).length() < 1e-9);
+
+        // Down the axis, through the hole
+        let ray = Ray::new(Vector::new(0., 0., -5.), Vector::new(0., 0., 1.));
+        assert_eq!(torus.intersection(&ray), IntersectionResult::No);
+        // From inside the tube
+        let ray = Ray::new(Vector::new(2., 0., 0.), Vector::new(0., 0., 1.));
+        let t = torus.intersection(&ray).nearest().unwrap();
+        assert!((t - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn grazing_rays_from_far_away() {
+        let torus = torus();
+        // Just inside the top of the tube, from a long way off
+        let ray = Ray::new(Vector::new(-1e4, 0., 0.499), Vector::new(1., 0., 0.));
+        let hits = torus.intersection(&ray).distances();
+        assert_eq!(hits.len(), 4);
+        for t in hits {
+            let p = ray.at(t);
+            let tube = ((p.x * p.x + p.y * p.y).sqrt() - 2.).hypot(p.z);
+            assert!((tube - 0.5).abs() < 1e-6, "{:?}", p);
+        }
+    }
+
+    #[test]
+    fn samples_lie_on_the_surface() {
+        let torus = torus();
+        let mut rng = Rng::new(8);
+        let reference = Vector::new(0., 0., 4.);
+        for _ in 0..200 {
+            let sample = torus.sample(reference, rng.next_2d()).unwrap();
+            let p = sample.position;
+            let tube = ((p.x * p.x + p.y * p.y).sqrt() - 2.).hypot(p.z);
+            assert!((tube - 0.5).abs() < 1e-9);
+            let (phi, theta) = torus.angles(p);
+            assert!((sample.normal - Torus::normal(phi, theta)).length() < 1e-9);
+        }
+    }
+}
@@ -2,13 +2,13 @@ use serde::{
     de::{self, Visitor},
     Deserialize,
 };
-use std::ops::{Add, AddAssign, Mul, MulAssign, Sub, SubAssign};
+use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign};
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Vector {
-    x: f64,
-    y: f64,
-    z: f64,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
 }
 
 // From here: https://serde.rs/deserialize-struct.html
@@ -205,6 +205,290 @@ impl MulAssign<i32> for Vector {
     }
 }
 
+impl Div<f64> for Vector {
+    type Output = Self;
+    fn div(mut self, rhs: f64) -> Self {
+        self /= rhs;
+        return self;
+    }
+}
+
+impl DivAssign<f64> for Vector {
+    fn div_assign(&mut self, rhs: f64) {
+        self.x /= rhs;
+        self.y /= rhs;
+        self.z /= rhs;
+    }
+}
+
+impl Neg for Vector {
+    type Output = Self;
+    fn neg(self) -> Self {
+        Self {
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
+}
+
+// 4x4 matrix acting on column vectors, with points taking w = 1 and directions w = 0
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Matrix {
+    pub m: [[f64; 4]; 4], // rows
+}
+
+impl Matrix {
+    pub fn identity() -> Self {
+        let mut m = [[0.; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            row[i] = 1.;
+        }
+        Self { m }
+    }
+
+    pub fn translation(offset: Vector) -> Self {
+        let mut matrix = Self::identity();
+        matrix.m[0][3] = offset.x;
+        matrix.m[1][3] = offset.y;
+        matrix.m[2][3] = offset.z;
+        matrix
+    }
+
+    pub fn scaling(scale: Vector) -> Self {
+        let mut matrix = Self::identity();
+        matrix.m[0][0] = scale.x;
+        matrix.m[1][1] = scale.y;
+        matrix.m[2][2] = scale.z;
+        matrix
+    }
+
+    // Right-handed rotation by `angle` radians about `axis`
+    // https://en.wikipedia.org/wiki/Rotation_matrix#Rotation_matrix_from_axis_and_angle
+    pub fn rotation(axis: Vector, angle: f64) -> Self {
+        let a = axis.normalised();
+        let (sin, cos) = angle.sin_cos();
+        let t = 1. - cos;
+        let mut matrix = Self::identity();
+        matrix.m[0][0] = cos + a.x * a.x * t;
+        matrix.m[0][1] = a.x * a.y * t - a.z * sin;
+        matrix.m[0][2] = a.x * a.z * t + a.y * sin;
+        matrix.m[1][0] = a.y * a.x * t + a.z * sin;
+        matrix.m[1][1] = cos + a.y * a.y * t;
+        matrix.m[1][2] = a.y * a.z * t - a.x * sin;
+        matrix.m[2][0] = a.z * a.x * t - a.y * sin;
+        matrix.m[2][1] = a.z * a.y * t + a.x * sin;
+        matrix.m[2][2] = cos + a.z * a.z * t;
+        matrix
+    }
+
+    pub fn transpose(&self) -> Self {
+        let mut m = [[0.; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = self.m[j][i];
+            }
+        }
+        Self { m }
+    }
+
+    // Gauss-Jordan elimination with partial pivoting, or None if the matrix is singular
+    pub fn inverse(&self) -> Option<Self> {
+        let mut a = self.m;
+        let mut inverse = Self::identity().m;
+        for column in 0..4 {
+            let pivot = (column..4)
+                .max_by(|&i, &j| a[i][column].abs().total_cmp(&a[j][column].abs()))
+                .unwrap();
+            if a[pivot][column].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(column, pivot);
+            inverse.swap(column, pivot);
+
+            let scale = 1. / a[column][column];
+            for j in 0..4 {
+                a[column][j] *= scale;
+                inverse[column][j] *= scale;
+            }
+            for i in 0..4 {
+                if i == column {
+                    continue;
+                }
+                let factor = a[i][column];
+                for j in 0..4 {
+                    a[i][j] -= factor * a[column][j];
+                    inverse[i][j] -= factor * inverse[column][j];
+                }
+            }
+        }
+        Some(Self { m: inverse })
+    }
+
+    // Determinant of the upper 3x3 part, how much the matrix scales volumes
+    pub fn determinant3(&self) -> f64 {
+        let m = &self.m;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    pub fn transform_point(&self, p: Vector) -> Vector {
+        let m = &self.m;
+        let v = self.transform_vector(p) + Vector::new(m[0][3], m[1][3], m[2][3]);
+        let w = m[3][0] * p.x + m[3][1] * p.y + m[3][2] * p.z + m[3][3];
+        if w == 1. {
+            v
+        } else {
+            v / w
+        }
+    }
+
+    pub fn transform_vector(&self, v: Vector) -> Vector {
+        let m = &self.m;
+        Vector::new(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        )
+    }
+}
+
+impl Mul for Matrix {
+    type Output = Self;
+    fn mul(self, other: Self) -> Self {
+        let mut m = [[0.; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[i][k] * other.m[k][j]).sum();
+            }
+        }
+        Self { m }
+    }
+}
+
+// An invertible affine transform from an object's own space into the world, kept alongside its
+// inverse. Deserialises from a `transform` block: `scale` (a number or per axis), `rotate`
+// (`{axis, angle}` or Euler angles about x, y then z) and `translate`, applied in that order, or
+// a list of such blocks applied one after another. Angles are in degrees.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "TransformSpec")]
+pub struct Transform {
+    pub matrix: Matrix,
+    pub inverse: Matrix,
+}
+
+impl Transform {
+    pub fn new(matrix: Matrix) -> Option<Self> {
+        Some(Self {
+            matrix,
+            inverse: matrix.inverse()?,
+        })
+    }
+
+    pub fn inverse(&self) -> Self {
+        Self {
+            matrix: self.inverse,
+            inverse: self.matrix,
+        }
+    }
+
+    pub fn point(&self, p: Vector) -> Vector {
+        self.matrix.transform_point(p)
+    }
+
+    pub fn vector(&self, v: Vector) -> Vector {
+        self.matrix.transform_vector(v)
+    }
+
+    // Normals transform by the inverse transpose to stay perpendicular to the surface
+    pub fn normal(&self, n: Vector) -> Vector {
+        self.inverse.transpose().transform_vector(n)
+    }
+}
+
+// `a * b` applies b first, then a
+impl Mul for Transform {
+    type Output = Self;
+    fn mul(self, other: Self) -> Self {
+        Self {
+            matrix: self.matrix * other.matrix,
+            inverse: other.inverse * self.inverse,
+        }
+    }
+}
+
+// A rotation as `{axis, angle}` or Euler angles about x, y then z, in degrees
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum Rotation {
+    AxisAngle { axis: Vector, angle: f64 },
+    Euler(Vector),
+}
+
+impl Rotation {
+    pub fn matrix(&self) -> Matrix {
+        match *self {
+            Rotation::AxisAngle { axis, angle } => Matrix::rotation(axis, angle.to_radians()),
+            Rotation::Euler(angles) => {
+                Matrix::rotation(Vector::new(0., 0., 1.), angles.z.to_radians())
+                    * Matrix::rotation(Vector::new(0., 1., 0.), angles.y.to_radians())
+                    * Matrix::rotation(Vector::new(1., 0., 0.), angles.x.to_radians())
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ScaleSpec {
+    Uniform(f64),
+    Axes(Vector),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TransformStep {
+    scale: Option<ScaleSpec>,
+    rotate: Option<Rotation>,
+    translate: Option<Vector>,
+}
+
+impl TransformStep {
+    fn matrix(&self) -> Matrix {
+        let scale = match self.scale {
+            Some(ScaleSpec::Uniform(s)) => Matrix::scaling(Vector::new(s, s, s)),
+            Some(ScaleSpec::Axes(s)) => Matrix::scaling(s),
+            None => Matrix::identity(),
+        };
+        let rotate = self.rotate.map_or(Matrix::identity(), |r| r.matrix());
+        let translate = Matrix::translation(self.translate.unwrap_or(Vector::zero()));
+        translate * rotate * scale
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TransformSpec {
+    Step(TransformStep),
+    Steps(Vec<TransformStep>),
+}
+
+impl TryFrom<TransformSpec> for Transform {
+    type Error = String;
+
+    fn try_from(spec: TransformSpec) -> Result<Self, Self::Error> {
+        let steps = match spec {
+            TransformSpec::Step(step) => vec![step],
+            TransformSpec::Steps(steps) => steps,
+        };
+        let matrix = steps
+            .iter()
+            .fold(Matrix::identity(), |matrix, step| step.matrix() * matrix);
+        Transform::new(matrix).ok_or_else(|| "transform is not invertible".to_string())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -231,7 +515,7 @@ mod tests {
     fn vector_abs_squared_and_length() {
         let a = Vector::new(1., 2., 3.);
         assert_eq!(a.abs_squared(), 14.);
-        assert_eq!(a.length(), (14.0 as f64).sqrt());
+        assert_eq!(a.length(), 14.0_f64.sqrt());
         assert_eq!(a.abs_squared(), a.dot(&a));
     }
 
@@ -258,6 +542,14 @@ mod tests {
         assert_eq!(a.y, 8.);
     }
 
+    #[test]
+    fn vector_scalar_div_and_neg() {
+        let mut a = Vector::new(2., 4., 6.);
+        assert_eq!(a / 2., Vector::new(1., 2., 3.));
+        a /= 2.;
+        assert_eq!(-a, Vector::new(-1., -2., -3.));
+    }
+
     #[test]
     fn vector_dot() {
         let a = Vector::new(1., 2., 3.);
@@ -273,4 +565,74 @@ mod tests {
         assert_eq!(c.dot(&a), 0.0);
         assert_eq!(c.dot(&b), 0.0);
     }
+
+    fn assert_close(a: Vector, b: Vector) {
+        assert!((a - b).length() < 1e-9, "{:?} {:?}", a, b);
+    }
+
+    #[test]
+    fn matrix_inverse_and_transpose() {
+        let m = Matrix::translation(Vector::new(1., 2., 3.))
+            * Matrix::rotation(Vector::new(1., 1., 0.), 0.7)
+            * Matrix::scaling(Vector::new(2., 0.5, 3.));
+        let product = m * m.inverse().unwrap();
+        for (i, row) in product.m.iter().enumerate() {
+            for (j, value) in row.iter().enumerate() {
+                let expected = if i == j { 1. } else { 0. };
+                assert!((value - expected).abs() < 1e-9);
+            }
+        }
+        assert_eq!(m.transpose().transpose(), m);
+        assert!((m.determinant3() - 3.).abs() < 1e-9);
+        assert!(Matrix::scaling(Vector::new(1., 0., 1.)).inverse().is_none());
+    }
+
+    #[test]
+    fn rotation_is_right_handed() {
+        let m = Matrix::rotation(Vector::new(0., 0., 1.), std::f64::consts::FRAC_PI_2);
+        assert_close(
+            m.transform_vector(Vector::new(1., 0., 0.)),
+            Vector::new(0., 1., 0.),
+        );
+        // Directions ignore translation, points don't
+        let t = Matrix::translation(Vector::new(1., 2., 3.));
+        assert_close(
+            t.transform_vector(Vector::new(1., 0., 0.)),
+            Vector::new(1., 0., 0.),
+        );
+        assert_close(t.transform_point(Vector::zero()), Vector::new(1., 2., 3.));
+    }
+
+    #[test]
+    fn transforms_from_toml() {
+        let table: toml::Table = r#"
+        trs = {scale = [2, 1, 1], rotate = {axis = [0, 1, 0], angle = 90}, translate = [0, 0, 5]}
+        euler = {rotate = [0, 0, 90]}
+        steps = [{translate = [1, 0, 0]}, {scale = 2}]
+        singular = {scale = 0}
+        "#
+        .parse()
+        .unwrap();
+        let transform = |name: &str| Transform::deserialize(table[name].clone());
+
+        let trs = transform("trs").unwrap();
+        // Scaled to (2, 0, 0), rotated to (0, 0, -2) then moved
+        assert_close(trs.point(Vector::new(1., 0., 0.)), Vector::new(0., 0., 3.));
+        assert_close(
+            trs.inverse().point(Vector::new(0., 0., 3.)),
+            Vector::new(1., 0., 0.),
+        );
+        // Normals stay perpendicular to transformed tangents
+        let (tangent, normal) = (Vector::new(1., 1., 0.), Vector::new(1., -1., 0.));
+        assert!(trs.vector(tangent).dot(&trs.normal(normal)).abs() < 1e-9);
+
+        let euler = transform("euler").unwrap();
+        assert_close(
+            euler.vector(Vector::new(1., 0., 0.)),
+            Vector::new(0., 1., 0.),
+        );
+        let steps = transform("steps").unwrap();
+        assert_close(steps.point(Vector::zero()), Vector::new(2., 0., 0.));
+        assert!(transform("singular").is_err());
+    }
 }
@@ -1,174 +1,1130 @@
+use crate::background::Background;
+use crate::bezier::{self, BezierSpec, BezierSurface};
+use crate::bvh::{Aabb, Bvh};
 use crate::colour::Colour;
-use crate::vector::Vector;
+use crate::csg::{Csg, CsgSpec};
+use crate::cuboid::CuboidSpec;
+use crate::curve::CurvesSpec;
+use crate::environment::{Environment, EnvironmentSpec};
+use crate::gltf::{self, Gltf, GltfSpec};
+use crate::heightfield::HeightfieldSpec;
+use crate::instance::{Geometry, Instance, InstanceSpec};
+use crate::light::{DirectionalLight, Light};
+use crate::material::{Material, MaterialLibrary};
+use crate::mesh::{self, Mesh, MeshSpec};
+use crate::metaball::MetaballsSpec;
+use crate::quadric::{ConeSpec, CylinderSpec, DiskSpec};
+use crate::ray::Ray;
+use crate::raytrace::RenderSettings;
+use crate::sampling;
+use crate::sdf::SdfSpec;
+use crate::sky::{Sky, SkySpec};
+use crate::texture::{TextureCoords, TextureLoader};
+use crate::torus::TorusSpec;
+use crate::vector::{Transform, Vector};
+use core::f64;
+use serde::de::DeserializeOwned;
 use serde::Deserialize;
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
-#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
-pub struct Material {
-    pub colour: Colour,
+const INTERSECTION_EPSILON: f64 = 1e-4;
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IntersectionResult {
+    No,
+    One(f64),
+    Two(f64, f64),
+    Three(f64, f64, f64),
+    Four(f64, f64, f64, f64),
 }
 
-impl Material {
-    pub fn default() -> Self {
-        Material {
-            colour: Colour::white(),
+impl IntersectionResult {
+    // The nearest (up to four) of some distances along a ray that are in front of its origin
+    pub fn from_distances(mut distances: Vec<f64>) -> Self {
+        distances.retain(|&t| t > 0.);
+        distances.sort_by(f64::total_cmp);
+        match distances[..] {
+            [] => IntersectionResult::No,
+            [t] => IntersectionResult::One(t),
+            [t1, t2] => IntersectionResult::Two(t1, t2),
+            [t1, t2, t3] => IntersectionResult::Three(t1, t2, t3),
+            [t1, t2, t3, t4, ..] => IntersectionResult::Four(t1, t2, t3, t4),
+        }
+    }
+
+    // Distances to every hit, nearest first
+    pub fn distances(&self) -> Vec<f64> {
+        match *self {
+            IntersectionResult::No => vec![],
+            IntersectionResult::One(t) => vec![t],
+            IntersectionResult::Two(t1, t2) => vec![t1, t2],
+            IntersectionResult::Three(t1, t2, t3) => vec![t1, t2, t3],
+            IntersectionResult::Four(t1, t2, t3, t4) => vec![t1, t2, t3, t4],
+        }
+    }
+
+    // Spans of the ray inside a closed surface these are all the hits of. An odd number of hits
+    // means the ray starts inside, so the first span starts at zero.
+    pub fn intervals(&self) -> Vec<(f64, f64)> {
+        let mut distances = self.distances();
+        if distances.len() % 2 == 1 {
+            distances.insert(0, 0.);
+        }
+        distances.chunks(2).map(|pair| (pair[0], pair[1])).collect()
+    }
+
+    // Distance to the closest hit in front of the ray origin
+    pub fn nearest(&self) -> Option<f64> {
+        match *self {
+            IntersectionResult::No => None,
+            IntersectionResult::One(t)
+            | IntersectionResult::Two(t, ..)
+            | IntersectionResult::Three(t, ..)
+            | IntersectionResult::Four(t, ..) => Some(t),
         }
     }
 }
 
-const INTERSECTION_EPSILON: f64 = 1e-4;
+// Where a ray hits an entity. Entities made of parts (a mesh's triangles, an instance's
+// entities) record which part in `part`, so that `surface` needn't search for it again, and
+// one holding others folds a child's part into its own with `within`.
 #[derive(Debug, Clone, Copy, PartialEq)]
-pub enum IntersectionResult {
-    No,
-    One(f64),
-    Two(f64, f64),
+pub struct Hit {
+    pub t: f64,
+    pub part: usize,
+}
+
+impl Hit {
+    pub fn new(t: f64) -> Self {
+        Hit { t, part: 0 }
+    }
+
+    // This hit, on the `index`th of `count` children
+    pub fn within(self, index: usize, count: usize) -> Self {
+        Hit {
+            t: self.t,
+            part: self.part * count + index,
+        }
+    }
+
+    // Undo `within`, giving the child's index and its own hit
+    pub fn child(self, count: usize) -> (usize, Self) {
+        let hit = Hit {
+            t: self.t,
+            part: self.part / count,
+        };
+        (self.part % count, hit)
+    }
+}
+
+// Spans of distances as hits on an entity without parts
+pub fn hit_spans(spans: Vec<(f64, f64)>) -> Vec<(Hit, Hit)> {
+    spans
+        .into_iter()
+        .map(|(t1, t2)| (Hit::new(t1), Hit::new(t2)))
+        .collect()
+}
+
+// A point chosen on an entity's surface for light sampling, with the density of choosing it
+// expressed with respect to solid angle as seen from the reference point, and the light given
+// off there
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SurfaceSample {
+    pub position: Vector,
+    pub normal: Vector,
+    pub pdf: f64,
+    pub emission: Colour,
+}
+
+// What shading needs to know about the point where a ray hits an entity
+#[derive(Debug, Clone, Copy)]
+pub struct SurfaceInteraction<'a> {
+    pub position: Vector,
+    pub normal: Vector, // outward
+    pub uv: (f64, f64),
+    // Partial derivatives of the position with respect to u and v, for orienting normal maps
+    pub dpdu: Vector,
+    pub dpdv: Vector,
+    pub object_position: Vector, // in the entity's own space, for solid textures
+    pub vertex_colour: Option<Colour>, // from meshes that give a colour per vertex
+    pub material: &'a Material,
 }
 
 pub trait Entity {
-    fn intersection(&self, ray: Vector) -> IntersectionResult;
-    fn material(&self) -> Material;
-    #[allow(dead_code)] // used by test
+    fn intersection(&self, ray: &Ray) -> IntersectionResult;
+
+    // The nearest hit in front of the ray's origin
+    fn hit(&self, ray: &Ray) -> Option<Hit> {
+        self.intersection(ray).nearest().map(Hit::new)
+    }
+
+    // The surface at a hit found by `hit`
+    fn surface(&self, ray: &Ray, hit: Hit) -> SurfaceInteraction<'_>;
+
+    fn bounds(&self) -> Aabb;
     fn position(&self) -> Vector;
-    fn normal(&self, position: Vector) -> Vector;
+
+    // Whether the entity encloses a solid, so it has an inside for constructive solid geometry
+    fn is_closed(&self) -> bool {
+        false
+    }
+
+    // Spans of the ray inside a closed entity, nearest first, with the first starting at zero if
+    // the ray starts inside. Their ends are hits that `surface` accepts.
+    fn intervals(&self, ray: &Ray) -> Vec<(Hit, Hit)> {
+        hit_spans(self.intersection(ray).intervals())
+    }
+
+    // Light given off by the entity as a whole. Paths that hit an entity see its material's
+    // emission whatever this says; it only decides whether the entity is also sampled as a light.
+    fn emission(&self) -> Colour;
+
+    // Whether `sample` and `pdf` are implemented. Only emissive entities that can be sampled
+    // are added to the world's emitters, so SDFs, metaballs, Bézier patches, curves and CSG
+    // solids light the scene only through paths that happen to hit them.
+    fn can_sample(&self) -> bool {
+        false
+    }
+
+    // Surface area, by which an instance shares its light samples out between its emissive
+    // entities. Only entities that can be sampled need it.
+    fn area(&self) -> f64 {
+        0.
+    }
+
+    fn sample(&self, _reference: Vector, _u: (f64, f64)) -> Option<SurfaceSample> {
+        None
+    }
+
+    // Solid angle density with which `sample` would have picked `point` from `reference`
+    fn pdf(&self, _reference: Vector, _point: Vector, _normal: Vector) -> f64 {
+        0.
+    }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Sphere {
     position: Vector,
     radius: f64,
     material: Material,
 }
 
-impl Entity for Sphere {
-    fn intersection(&self, normalised_ray: Vector) -> IntersectionResult {
-        // Solving this https://upload.wikimedia.org/wikipedia/commons/9/95/Ray_Tracing_Illustration_First_Bounce.png
+// Sphere maths shared by the sphere entity and spherical lights
 
-        // d^2 t^2 + 2(o - c).d t + (o - c)^2 - r^2 = 0
-        // a = d^2, b = 2(o - c).d, c = (o - c)^2 - r^2
-        // t = (-b ± sqrt(b^2 - 4ac)) / 2a
+pub fn intersect_sphere(centre: Vector, radius: f64, ray: &Ray) -> IntersectionResult {
+    // Solving this https://upload.wikimedia.org/wikipedia/commons/9/95/Ray_Tracing_Illustration_First_Bounce.png
 
-        // Camera is at origin in our system
-        let o = Vector::zero();
-        let d = normalised_ray;
-        let c = self.position;
-        let r = self.radius;
+    // d^2 t^2 + 2(o - c).d t + (o - c)^2 - r^2 = 0
+    // a = d^2, b = 2(o - c).d, c = (o - c)^2 - r^2
+    // t = (-b ± sqrt(b^2 - 4ac)) / 2a
 
-        let a = d.dot(&d);
-        let b = 2. * (o - c).dot(&d);
-        let c = (o - c).abs_squared() - r * r;
+    let o = ray.origin;
+    let d = ray.direction;
+    let c = centre;
+    let r = radius;
 
-        let delta = b * b - 4. * a * c;
-        if delta < 0. {
-            return IntersectionResult::No;
-        } else if delta.abs() < INTERSECTION_EPSILON {
-            let t = -b / (2. * a);
-            // Sphere is behind camera
-            if t > 0. {
-                return IntersectionResult::One(t);
-            } else {
-                return IntersectionResult::No;
-            }
+    let a = d.dot(&d);
+    let b = 2. * (o - c).dot(&d);
+    let c = (o - c).abs_squared() - r * r;
+
+    let delta = b * b - 4. * a * c;
+    if delta < 0. {
+        return IntersectionResult::No;
+    } else if delta.abs() < INTERSECTION_EPSILON {
+        let t = -b / (2. * a);
+        // Sphere is behind ray origin
+        if t > 0. {
+            return IntersectionResult::One(t);
         } else {
-            let t1 = (-b - delta.sqrt()) / (2. * a);
-            let t2 = (-b + delta.sqrt()) / (2. * a);
+            return IntersectionResult::No;
+        }
+    } else {
+        let t1 = (-b - delta.sqrt()) / (2. * a);
+        let t2 = (-b + delta.sqrt()) / (2. * a);
+        if t1 > 0. {
+            return IntersectionResult::Two(t1, t2);
+        } else if t2 > 0. {
             // We're inside the sphere
-            if t1 > 0. {
-                return IntersectionResult::Two(t1, t2);
-            } else {
-                return IntersectionResult::No;
-            }
+            return IntersectionResult::One(t2);
+        } else {
+            return IntersectionResult::No;
         }
     }
+}
 
-    fn material(&self) -> Material {
-        self.material
+// The emission is left black for the caller to fill in
+pub fn sample_sphere(
+    centre: Vector,
+    radius: f64,
+    reference: Vector,
+    u: (f64, f64),
+) -> SurfaceSample {
+    let to_centre = centre - reference;
+    let distance_squared = to_centre.abs_squared();
+    let radius_squared = radius * radius;
+
+    if distance_squared <= radius_squared {
+        // Inside the sphere every point is visible, so fall back to sampling by area
+        let position = centre + sampling::uniform_sphere(u) * radius;
+        let normal = (position - centre).normalised();
+        return SurfaceSample {
+            position,
+            normal,
+            pdf: sphere_pdf(centre, radius, reference, position, normal),
+            emission: Colour::black(),
+        };
     }
 
-    fn position(&self) -> Vector {
-        self.position
+    // Sample the cone of directions subtended by the sphere
+    let cos_theta_max = (1. - radius_squared / distance_squared).max(0.).sqrt();
+    let direction = sampling::uniform_cone(to_centre.normalised(), cos_theta_max, u);
+    let ray = Ray::new(reference, direction);
+    // Grazing directions can numerically miss; the closest point on the ray is then on the silhouette
+    let t = intersect_sphere(centre, radius, &ray)
+        .nearest()
+        .unwrap_or_else(|| to_centre.dot(&direction));
+    let position = ray.at(t);
+
+    SurfaceSample {
+        position,
+        normal: (position - centre).normalised(),
+        pdf: sampling::uniform_cone_pdf(cos_theta_max),
+        emission: Colour::black(),
     }
+}
 
+pub fn sphere_pdf(
+    centre: Vector,
+    radius: f64,
+    reference: Vector,
+    point: Vector,
+    normal: Vector,
+) -> f64 {
+    let distance_squared = (centre - reference).abs_squared();
+    let radius_squared = radius * radius;
+
+    if distance_squared <= radius_squared {
+        let area = 4. * f64::consts::PI * radius_squared;
+        return sampling::area_to_solid_angle_pdf(1. / area, reference, point, normal);
+    }
+
+    let cos_theta_max = (1. - radius_squared / distance_squared).max(0.).sqrt();
+    sampling::uniform_cone_pdf(cos_theta_max)
+}
+
+impl Sphere {
     fn normal(&self, at: Vector) -> Vector {
         (at - self.position).normalised()
     }
+
+    // Lat-long, with u = 0.5 on the side facing the camera and v = 0 at the top
+    fn uv(&self, at: Vector) -> (f64, f64) {
+        let d = (at - self.position).normalised();
+        let u = 0.5 + d.x.atan2(-d.z) / (2. * f64::consts::PI);
+        let v = (-d.y).clamp(-1., 1.).acos() / f64::consts::PI;
+        (u, v)
+    }
+
+    fn dpduv(&self, at: Vector) -> (Vector, Vector) {
+        let p = at - self.position;
+        let rho = (p.x * p.x + p.z * p.z).sqrt();
+        if rho < 1e-9 * self.radius {
+            // The poles, where u is undefined
+            return sampling::orthonormal_basis(self.normal(at));
+        }
+        let dpdu = Vector::new(-p.z, 0., p.x) * (2. * f64::consts::PI);
+        let dpdv = Vector::new(-p.x * p.y / rho, rho, -p.z * p.y / rho) * f64::consts::PI;
+        (dpdu, dpdv)
+    }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+impl Entity for Sphere {
+    fn intersection(&self, ray: &Ray) -> IntersectionResult {
+        intersect_sphere(self.position, self.radius, ray)
+    }
+
+    fn surface(&self, ray: &Ray, hit: Hit) -> SurfaceInteraction<'_> {
+        let position = ray.at(hit.t);
+        let (dpdu, dpdv) = self.dpduv(position);
+        SurfaceInteraction {
+            position,
+            normal: self.normal(position),
+            uv: self.uv(position),
+            dpdu,
+            dpdv,
+            object_position: position - self.position,
+            vertex_colour: None,
+            material: &self.material,
+        }
+    }
+
+    fn bounds(&self) -> Aabb {
+        let r = Vector::new(self.radius, self.radius, self.radius);
+        Aabb::new(self.position - r, self.position + r)
+    }
+
+    fn is_closed(&self) -> bool {
+        true
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<(Hit, Hit)> {
+        match self.intersection(ray) {
+            // Grazing the sphere from outside
+            IntersectionResult::One(_) if (ray.origin - self.position).length() > self.radius => {
+                vec![]
+            }
+            result => hit_spans(result.intervals()),
+        }
+    }
+
+    fn position(&self) -> Vector {
+        self.position
+    }
+
+    fn emission(&self) -> Colour {
+        self.material.emission
+    }
+
+    fn can_sample(&self) -> bool {
+        true
+    }
+
+    fn area(&self) -> f64 {
+        4. * f64::consts::PI * self.radius * self.radius
+    }
+
+    fn sample(&self, reference: Vector, u: (f64, f64)) -> Option<SurfaceSample> {
+        Some(SurfaceSample {
+            emission: self.material.emission,
+            ..sample_sphere(self.position, self.radius, reference, u)
+        })
+    }
+
+    fn pdf(&self, reference: Vector, point: Vector, normal: Vector) -> f64 {
+        sphere_pdf(self.position, self.radius, reference, point, normal)
+    }
+}
+
+fn default_triangle_uvs() -> [(f64, f64); 3] {
+    [(0., 0.), (1., 0.), (0., 1.)]
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Triangle {
+    vertices: [Vector; 3],
+    #[serde(default = "default_triangle_uvs")]
+    uvs: [(f64, f64); 3], // per vertex
+    material: Material,
+}
+
+impl Triangle {
+    fn edges(&self) -> (Vector, Vector) {
+        (
+            self.vertices[1] - self.vertices[0],
+            self.vertices[2] - self.vertices[0],
+        )
+    }
+
+    // Weights of each vertex for a point in the triangle's plane
+    fn barycentric(&self, position: Vector) -> (f64, f64, f64) {
+        let (e1, e2) = self.edges();
+        let n = e1.cross(&e2);
+        let denominator = n.abs_squared();
+        if denominator == 0. {
+            return (1., 0., 0.);
+        }
+        let p = position - self.vertices[0];
+        let b1 = p.cross(&e2).dot(&n) / denominator;
+        let b2 = e1.cross(&p).dot(&n) / denominator;
+        (1. - b1 - b2, b1, b2)
+    }
+
+    fn normal(&self, _at: Vector) -> Vector {
+        let (e1, e2) = self.edges();
+        e1.cross(&e2).normalised()
+    }
+
+    fn uv(&self, at: Vector) -> (f64, f64) {
+        let (b0, b1, b2) = self.barycentric(at);
+        let [uv0, uv1, uv2] = self.uvs;
+        (
+            b0 * uv0.0 + b1 * uv1.0 + b2 * uv2.0,
+            b0 * uv0.1 + b1 * uv1.1 + b2 * uv2.1,
+        )
+    }
+
+    fn dpduv(&self, at: Vector) -> (Vector, Vector) {
+        let (e1, e2) = self.edges();
+        let [uv0, uv1, uv2] = self.uvs;
+        let (du1, dv1) = (uv1.0 - uv0.0, uv1.1 - uv0.1);
+        let (du2, dv2) = (uv2.0 - uv0.0, uv2.1 - uv0.1);
+        let det = du1 * dv2 - dv1 * du2;
+        if det.abs() < 1e-12 {
+            // Degenerate UVs
+            return sampling::orthonormal_basis(self.normal(at));
+        }
+        let dpdu = (e1 * dv2 - e2 * dv1) / det;
+        let dpdv = (e2 * du1 - e1 * du2) / det;
+        (dpdu, dpdv)
+    }
+}
+
+impl Entity for Triangle {
+    fn intersection(&self, ray: &Ray) -> IntersectionResult {
+        // Möller–Trumbore: https://en.wikipedia.org/wiki/M%C3%B6ller%E2%80%93Trumbore_intersection_algorithm
+        let (e1, e2) = self.edges();
+        let p = ray.direction.cross(&e2);
+        let det = e1.dot(&p);
+        // Ray is parallel to the triangle
+        if det.abs() < 1e-12 {
+            return IntersectionResult::No;
+        }
+
+        let inv_det = 1. / det;
+        let s = ray.origin - self.vertices[0];
+        let u = s.dot(&p) * inv_det;
+        if !(0. ..=1.).contains(&u) {
+            return IntersectionResult::No;
+        }
+
+        let q = s.cross(&e1);
+        let v = ray.direction.dot(&q) * inv_det;
+        if v < 0. || u + v > 1. {
+            return IntersectionResult::No;
+        }
+
+        let t = e2.dot(&q) * inv_det;
+        if t > 0. {
+            IntersectionResult::One(t)
+        } else {
+            IntersectionResult::No
+        }
+    }
+
+    fn surface(&self, ray: &Ray, hit: Hit) -> SurfaceInteraction<'_> {
+        let position = ray.at(hit.t);
+        let (dpdu, dpdv) = self.dpduv(position);
+        SurfaceInteraction {
+            position,
+            normal: self.normal(position),
+            uv: self.uv(position),
+            dpdu,
+            dpdv,
+            object_position: position - self.position(),
+            vertex_colour: None,
+            material: &self.material,
+        }
+    }
+
+    fn bounds(&self) -> Aabb {
+        Aabb::from_points(&self.vertices)
+    }
+
+    fn position(&self) -> Vector {
+        (self.vertices[0] + self.vertices[1] + self.vertices[2]) / 3.
+    }
+
+    fn emission(&self) -> Colour {
+        self.material.emission
+    }
+
+    fn can_sample(&self) -> bool {
+        true
+    }
+
+    fn area(&self) -> f64 {
+        let (e1, e2) = self.edges();
+        0.5 * e1.cross(&e2).length()
+    }
+
+    fn sample(&self, reference: Vector, u: (f64, f64)) -> Option<SurfaceSample> {
+        let (b0, b1) = sampling::uniform_triangle(u);
+        let position =
+            self.vertices[0] * b0 + self.vertices[1] * b1 + self.vertices[2] * (1. - b0 - b1);
+        let normal = self.normal(position);
+        let pdf = self.pdf(reference, position, normal);
+        if pdf == 0. {
+            return None;
+        }
+        Some(SurfaceSample {
+            position,
+            normal,
+            pdf,
+            emission: self.emission(),
+        })
+    }
+
+    fn pdf(&self, reference: Vector, point: Vector, normal: Vector) -> f64 {
+        sampling::area_to_solid_angle_pdf(1. / self.area(), reference, point, normal)
+    }
+}
+
+// An entity placed in the world by a transform from its own (object) space, intersected by
+// moving rays into object space
+pub struct Transformed {
+    entity: Box<dyn Entity>,
+    transform: Transform,
+}
+
+impl Transformed {
+    pub fn new(entity: Box<dyn Entity>, transform: Transform) -> Self {
+        Transformed { entity, transform }
+    }
+
+    fn to_object(&self, p: Vector) -> Vector {
+        self.transform.inverse().point(p)
+    }
+
+    // The ray in object space, with a unit direction since entities may assume one, and how
+    // many object space units one world space unit along it covers
+    fn to_object_ray(&self, ray: &Ray) -> (Ray, f64) {
+        let direction = self.transform.inverse().vector(ray.direction);
+        let scale = direction.length();
+        (
+            Ray::new(self.to_object(ray.origin), direction / scale),
+            scale,
+        )
+    }
+
+    // Ratio of world to object surface area around a point with object space normal `normal`
+    fn area_scale(&self, normal: Vector) -> f64 {
+        self.transform.matrix.determinant3().abs() * self.transform.normal(normal).length()
+    }
+}
+
+impl Entity for Transformed {
+    fn intersection(&self, ray: &Ray) -> IntersectionResult {
+        let (local, scale) = self.to_object_ray(ray);
+        let distances = self.entity.intersection(&local).distances();
+        IntersectionResult::from_distances(distances.iter().map(|t| t / scale).collect())
+    }
+
+    fn hit(&self, ray: &Ray) -> Option<Hit> {
+        let (local, scale) = self.to_object_ray(ray);
+        let hit = self.entity.hit(&local)?;
+        Some(Hit {
+            t: hit.t / scale,
+            ..hit
+        })
+    }
+
+    fn surface(&self, ray: &Ray, hit: Hit) -> SurfaceInteraction<'_> {
+        let (local, scale) = self.to_object_ray(ray);
+        let local_hit = Hit {
+            t: hit.t * scale,
+            ..hit
+        };
+        let surface = self.entity.surface(&local, local_hit);
+        SurfaceInteraction {
+            position: ray.at(hit.t),
+            normal: self.transform.normal(surface.normal).normalised(),
+            dpdu: self.transform.vector(surface.dpdu),
+            dpdv: self.transform.vector(surface.dpdv),
+            ..surface
+        }
+    }
+
+    fn bounds(&self) -> Aabb {
+        self.entity.bounds().transformed(&self.transform)
+    }
+
+    fn is_closed(&self) -> bool {
+        self.entity.is_closed()
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<(Hit, Hit)> {
+        let (local, scale) = self.to_object_ray(ray);
+        let intervals = self.entity.intervals(&local);
+        let world = |hit: Hit| Hit {
+            t: hit.t / scale,
+            ..hit
+        };
+        intervals
+            .iter()
+            .map(|&(start, end)| (world(start), world(end)))
+            .collect()
+    }
+
+    fn position(&self) -> Vector {
+        self.transform.point(self.entity.position())
+    }
+
+    fn emission(&self) -> Colour {
+        self.entity.emission()
+    }
+
+    fn can_sample(&self) -> bool {
+        self.entity.can_sample()
+    }
+
+    // Exact for uniform scales. Otherwise it's the area scaled by the volume's change, which is
+    // near enough for weighing the entity against others.
+    fn area(&self) -> f64 {
+        self.entity.area() * self.transform.matrix.determinant3().abs().powf(2. / 3.)
+    }
+
+    fn sample(&self, reference: Vector, u: (f64, f64)) -> Option<SurfaceSample> {
+        let sample = self.entity.sample(self.to_object(reference), u)?;
+        let position = self.transform.point(sample.position);
+        let normal = self.transform.normal(sample.normal).normalised();
+        let pdf = self.pdf(reference, position, normal);
+        if pdf == 0. {
+            return None;
+        }
+        Some(SurfaceSample {
+            position,
+            normal,
+            pdf,
+            emission: sample.emission,
+        })
+    }
+
+    fn pdf(&self, reference: Vector, point: Vector, normal: Vector) -> f64 {
+        // Convert the object's solid angle density to one over its surface area, then to world
+        // surface area and back to solid angle as seen in the world
+        let (local_reference, local_point) = (self.to_object(reference), self.to_object(point));
+        let local_normal = self.transform.inverse().normal(normal).normalised();
+        let pdf = self.entity.pdf(local_reference, local_point, local_normal);
+        let jacobian =
+            sampling::area_to_solid_angle_pdf(1., local_reference, local_point, local_normal);
+        if pdf == 0. || jacobian == 0. {
+            return 0.;
+        }
+        let pdf_area = pdf / jacobian / self.area_scale(local_normal);
+        sampling::area_to_solid_angle_pdf(pdf_area, reference, point, normal)
+    }
+}
+
+// An entity's TOML description, which `EntityLoader::build` parses, gives its material, then
+// turns into the entity
+trait Build: DeserializeOwned {
+    fn material(&mut self) -> Option<&mut Material>;
+    fn build_entity(self, loader: &mut EntityLoader) -> Result<Box<dyn Entity>, String>;
+}
+
+// Builds entities from their TOML, resolving named materials and geometry
+struct EntityLoader {
+    scene_dir: PathBuf, // model files are loaded relative to it
+    materials: MaterialLibrary,
+    textures: TextureLoader,
+    geometry: HashMap<String, Rc<Geometry>>,
+}
+
+impl EntityLoader {
+    fn load_geometry(&mut self, table: &toml::Table) {
+        for (name, entities) in table {
+            let Some(entities) = entities.as_array() else {
+                eprintln!("Warning: geometry {} must be a list of entities", name);
+                continue;
+            };
+            let mut loaded = Vec::new();
+            for entity in entities {
+                if matches!(entity.get("type"), Some(toml::Value::String(s)) if s.to_lowercase() == "instance")
+                {
+                    eprintln!("Warning: geometry {} can't contain instances", name);
+                } else if let Some(entity) = self.load(entity) {
+                    loaded.push(entity);
+                }
+            }
+            self.geometry
+                .insert(name.clone(), Rc::new(Geometry::new(loaded)));
+        }
+    }
+
+    // Swap in a named material from the library, or load an inline one's textures
+    fn load_material(&mut self, material: &mut Material) {
+        if material.name.is_some() {
+            if let Err(e) = self.materials.resolve(material) {
+                eprintln!("Warning: {}", e);
+            }
+        } else if let Err(e) = material.load_textures(&mut self.textures) {
+            eprintln!("Warning: failed to load texture: {}", e);
+        }
+    }
+
+    // Parse an entity's spec and build it, warning about whatever goes wrong
+    fn build<S: Build>(&mut self, kind: &str, entity: &toml::Value) -> Option<Box<dyn Entity>> {
+        let mut spec = match S::deserialize(entity.clone()) {
+            Ok(spec) => spec,
+            Err(e) => {
+                eprintln!("Warning: failed to parse {}: {}", kind, e);
+                return None;
+            }
+        };
+        if let Some(material) = spec.material() {
+            self.load_material(material);
+        }
+        match spec.build_entity(self) {
+            Ok(entity) => Some(entity),
+            Err(e) => {
+                eprintln!("Warning: failed to parse {}: {}", kind, e);
+                None
+            }
+        }
+    }
+
+    fn load(&mut self, entity: &toml::Value) -> Option<Box<dyn Entity>> {
+        if let Some(toml::Value::String(name)) = entity.get("material") {
+            if !self.materials.contains(name) {
+                eprintln!(
+                    "Warning: failed to parse an entity: unknown material '{}'",
+                    name
+                );
+                return None;
+            }
+        }
+        let transform = match entity.get("transform") {
+            Some(value) => match toml::Value::try_into::<Transform>(value.clone()) {
+                Ok(transform) => Some(transform),
+                Err(e) => {
+                    eprintln!("Warning: failed to parse a transform: {}", e);
+                    return None;
+                }
+            },
+            None => None,
+        };
+
+        let Some(toml::Value::String(s)) = entity.get("type") else {
+            eprintln!("Warning: failed to parse an entity");
+            return None;
+        };
+        let loaded = match s.to_lowercase().as_str() {
+            "sphere" => self.build::<Sphere>("a sphere", entity)?,
+            "triangle" => self.build::<Triangle>("a triangle", entity)?,
+            "box" => self.build::<CuboidSpec>("a box", entity)?,
+            "cylinder" => self.build::<CylinderSpec>("a cylinder", entity)?,
+            "cone" => self.build::<ConeSpec>("a cone", entity)?,
+            "disk" => self.build::<DiskSpec>("a disk", entity)?,
+            "torus" => self.build::<TorusSpec>("a torus", entity)?,
+            "instance" => self.build::<InstanceSpec>("an instance", entity)?,
+            "sdf" => self.build::<SdfSpec>("an sdf", entity)?,
+            "heightfield" => self.build::<HeightfieldSpec>("a heightfield", entity)?,
+            "metaballs" => self.build::<MetaballsSpec>("metaballs", entity)?,
+            "bezier" => self.build::<BezierSpec>("a bezier entity", entity)?,
+            "curves" => self.build::<CurvesSpec>("curves", entity)?,
+            "mesh" => self.build::<MeshSpec>("a mesh", entity)?,
+            "gltf" => self.build::<GltfSpec>("a glTF entity", entity)?,
+            "csg" => self.build::<CsgSpec>("a csg entity", entity)?,
+            _ => {
+                eprintln!("Warning: missing entity type");
+                return None;
+            }
+        };
+
+        match transform {
+            Some(transform) => Some(Box::new(Transformed::new(loaded, transform))),
+            None => Some(loaded),
+        }
+    }
+}
+
+// `Build` for entities and specs that carry their own material and need nothing from the loader
+macro_rules! impl_build {
+    ($($kind:ty),+ => |$value:ident| $build:expr) => {
+        $(impl Build for $kind {
+            fn material(&mut self) -> Option<&mut Material> {
+                Some(&mut self.material)
+            }
+
+            fn build_entity(self, _: &mut EntityLoader) -> Result<Box<dyn Entity>, String> {
+                let $value = self;
+                $build
+            }
+        })+
+    };
+}
+
+impl_build!(Sphere, Triangle => |entity| Ok(Box::new(entity)));
+impl_build!(
+    CuboidSpec,
+    CylinderSpec,
+    ConeSpec,
+    DiskSpec,
+    TorusSpec,
+    SdfSpec,
+    MetaballsSpec,
+    CurvesSpec => |spec| spec.build()
+);
+
+impl Build for InstanceSpec {
+    fn material(&mut self) -> Option<&mut Material> {
+        self.material.as_mut()
+    }
+
+    fn build_entity(self, loader: &mut EntityLoader) -> Result<Box<dyn Entity>, String> {
+        let geometry = loader
+            .geometry
+            .get(&self.geometry)
+            .cloned()
+            .ok_or_else(|| format!("unknown geometry '{}'", self.geometry))?;
+        Ok(Box::new(Instance::new(geometry, self.material)))
+    }
+}
+
+impl Build for HeightfieldSpec {
+    fn material(&mut self) -> Option<&mut Material> {
+        Some(&mut self.material)
+    }
+
+    fn build_entity(self, loader: &mut EntityLoader) -> Result<Box<dyn Entity>, String> {
+        let image = loader
+            .textures
+            .load(&self.image)
+            .map_err(|e| format!("failed to load heightfield image: {}", e))?;
+        self.build(&image)
+    }
+}
+
+impl Build for BezierSpec {
+    fn material(&mut self) -> Option<&mut Material> {
+        Some(&mut self.material)
+    }
+
+    fn build_entity(self, loader: &mut EntityLoader) -> Result<Box<dyn Entity>, String> {
+        let patches = bezier::load_bpt(&loader.scene_dir.join(&self.file))
+            .map_err(|e| format!("failed to load patches from {}: {}", self.file, e))?;
+        Ok(Box::new(BezierSurface::new(patches, self.material)?))
+    }
+}
+
+impl Build for MeshSpec {
+    fn material(&mut self) -> Option<&mut Material> {
+        Some(&mut self.material)
+    }
+
+    fn build_entity(self, loader: &mut EntityLoader) -> Result<Box<dyn Entity>, String> {
+        let data = mesh::load_mesh(&loader.scene_dir.join(&self.file))
+            .map_err(|e| format!("failed to load mesh from {}: {}", self.file, e))?;
+        Ok(Box::new(Mesh::new(data, self.material)?))
+    }
+}
+
+impl Build for GltfSpec {
+    fn material(&mut self) -> Option<&mut Material> {
+        self.material.as_mut()
+    }
+
+    fn build_entity(self, loader: &mut EntityLoader) -> Result<Box<dyn Entity>, String> {
+        let gltf = Gltf::load(&loader.scene_dir.join(&self.file))
+            .map_err(|e| format!("failed to load glTF from {}: {}", self.file, e))?;
+        let entities = gltf.entities(gltf::upright());
+        if entities.is_empty() {
+            return Err(format!("no meshes in {}", self.file));
+        }
+        Ok(Box::new(Instance::new(
+            Rc::new(Geometry::new(entities)),
+            self.material,
+        )))
+    }
+}
+
+impl Build for CsgSpec {
+    fn material(&mut self) -> Option<&mut Material> {
+        self.material.as_mut()
+    }
+
+    // The operands warn about their own problems
+    fn build_entity(self, loader: &mut EntityLoader) -> Result<Box<dyn Entity>, String> {
+        let left = loader
+            .load(&self.left)
+            .ok_or("its left operand is invalid")?;
+        let right = loader
+            .load(&self.right)
+            .ok_or("its right operand is invalid")?;
+        Ok(Box::new(Csg::new(
+            self.operation,
+            left,
+            right,
+            self.material,
+        )?))
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct RaycastResult {
     pub hit: bool,
+    pub entity: usize,
+    pub distance: f64,
     pub position: Vector,
     pub normal: Vector,
+    pub uv: (f64, f64),
+    pub dpdu: Vector,
+    pub dpdv: Vector,
+    pub object_position: Vector,
+    pub vertex_colour: Option<Colour>,
     pub material: Material,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
-pub struct Light {
-    pub position: Vector,
-    pub intensity: f64,
-}
-
-impl Light {
-    pub fn new(position: Vector, intensity: f64) -> Self {
-        Self {
-            position,
-            intensity,
+impl RaycastResult {
+    pub fn texture_coords(&self) -> TextureCoords {
+        TextureCoords {
+            uv: self.uv,
+            position: self.position,
+            object_position: self.object_position,
+            vertex_colour: self.vertex_colour,
         }
     }
-    pub fn default() -> Self {
-        Self::new(Vector::new(-1., 1., 1.), 1.)
-    }
 }
 
 pub struct World {
     pub entities: Vec<Box<dyn Entity>>,
-    pub light: Light,
-    pub background: Colour,
+    pub lights: Vec<Light>,
+    pub background: Background,
+    pub environment: Option<Environment>,
+    pub settings: RenderSettings,
+    emitters: Vec<usize>, // indices into entities that can be sampled, with non-black emission
+    bvh: OnceCell<Bvh>,   // over the entities, built when first needed
+}
+
+impl Default for World {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl World {
     pub fn new() -> Self {
         Self {
             entities: Vec::new(),
-            light: Light::default(),
-            background: Colour::white(),
+            lights: Vec::new(),
+            background: Background::Colour(Colour::white()),
+            environment: None,
+            settings: RenderSettings::default(),
+            emitters: Vec::new(),
+            bvh: OnceCell::new(),
         }
     }
 
+    pub fn add_entity(&mut self, entity: Box<dyn Entity>) {
+        if entity.can_sample() && !entity.emission().is_black() {
+            self.emitters.push(self.entities.len());
+        }
+        self.entities.push(entity);
+        self.bvh = OnceCell::new();
+    }
+
+    fn bvh(&self) -> &Bvh {
+        self.bvh.get_or_init(|| {
+            let bounds: Vec<Aabb> = self.entities.iter().map(|entity| entity.bounds()).collect();
+            Bvh::new(&bounds)
+        })
+    }
+
+    pub fn emitters(&self) -> &[usize] {
+        &self.emitters
+    }
+
+    // Everything next-event estimation picks from: the lights, the emissive entities and then
+    // the environment
+    pub fn num_lights(&self) -> usize {
+        self.lights.len() + self.emitters.len() + self.environment.is_some() as usize
+    }
+
     pub fn from_toml(table: &toml::Table) -> Self {
+        Self::from_toml_in_dir(table, Path::new(""))
+    }
+
+    // Files referenced by the scene are looked up relative to `scene_dir`
+    pub fn from_toml_in_dir(table: &toml::Table, scene_dir: &Path) -> Self {
         let mut world = Self::new();
 
+        let mut sky = None;
         if let Some(value) = table.get("background") {
-            if let Ok(colour) = toml::Value::try_into::<Colour>(value.clone()) {
-                world.background = colour;
+            let is_sky = matches!(value.get("type"), Some(toml::Value::String(s)) if s.to_lowercase() == "sky");
+            if is_sky {
+                match toml::Value::try_into::<SkySpec>(value.clone()) {
+                    Ok(spec) => sky = Some(Sky::new(&spec)),
+                    Err(_) => eprintln!("Warning: failed to parse sky. Using white."),
+                }
             } else {
-                eprintln!("Warning: failed to parse background colour. Using white.");
+                match Background::from_toml(value, scene_dir) {
+                    Ok(background) => world.background = background,
+                    Err(e) => {
+                        eprintln!("Warning: failed to parse background ({}). Using white.", e)
+                    }
+                }
+            }
+        }
+
+        if let Some(value) = table.get("environment") {
+            match toml::Value::try_into::<EnvironmentSpec>(value.clone()) {
+                Ok(spec) => match Environment::load(&spec, scene_dir) {
+                    Ok(environment) => world.environment = Some(environment),
+                    Err(e) => eprintln!("Warning: failed to load environment image: {}", e),
+                },
+                Err(_) => eprintln!("Warning: failed to parse environment"),
             }
         }
 
         if let Some(value) = table.get("light") {
-            if let Ok(light) = toml::Value::try_into::<Light>(value.clone()) {
-                world.light = light;
+            if let Some(light) = Light::from_toml(value) {
+                world.lights.push(light);
             } else {
                 eprintln!("Warning: failed to parse light");
             }
-        } else {
+        }
+
+        if let Some(toml::Value::Array(array)) = table.get("lights") {
+            for value in array {
+                if let Some(light) = Light::from_toml(value) {
+                    world.lights.push(light);
+                } else {
+                    eprintln!("Warning: failed to parse a light");
+                }
+            }
+        }
+
+        if let Some(sky) = sky {
+            // The sky lights the scene like an environment map, with the sun as a separate light
+            if world.environment.is_some() {
+                eprintln!(
+                    "Warning: both a sky and an environment were given, using the environment"
+                );
+            } else {
+                world.environment = Some(Environment::from_sky(sky));
+            }
+            let (colour, intensity) = sky.sun();
+            if intensity > 0. {
+                world.lights.push(Light::Directional(DirectionalLight {
+                    direction: sky.sun_direction(),
+                    intensity,
+                    colour,
+                }));
+            }
+        } else if !table.contains_key("light") && !table.contains_key("lights") {
             eprintln!("Warning: no light specified, using default");
+            world.lights.push(Light::default());
+        }
+
+        if let Some(value) = table.get("render") {
+            if let Ok(settings) = toml::Value::try_into::<RenderSettings>(value.clone()) {
+                world.settings = settings;
+            } else {
+                eprintln!("Warning: failed to parse render settings, using defaults");
+            }
+        }
+
+        let mut textures = TextureLoader::new(scene_dir);
+        let mut loader = EntityLoader {
+            scene_dir: scene_dir.to_path_buf(),
+            materials: MaterialLibrary::from_toml(table, scene_dir, &mut textures),
+            textures,
+            geometry: HashMap::new(),
+        };
+
+        if let Some(value) = table.get("geometry") {
+            match value.as_table() {
+                Some(geometry) => loader.load_geometry(geometry),
+                None => eprintln!("Warning: geometry must be a table of named entity lists"),
+            }
         }
 
         if let Some(toml::Value::Array(array)) = table.get("entities") {
             for entity in array {
-                if let Some(toml::Value::String(s)) = entity.get("type") {
-                    match s.to_lowercase().as_str() {
-                        "sphere" => {
-                            if let Ok(sphere) = toml::Value::try_into::<Sphere>(entity.clone()) {
-                                world.entities.push(Box::new(sphere));
-                            }
-                        }
-                        _ => {
-                            eprintln!("Warning: missing entity type");
-                        }
-                    }
-                } else {
-                    eprintln!("Warning: failed to parse an entity");
+                if let Some(entity) = loader.load(entity) {
+                    world.add_entity(entity);
                 }
             }
         } else {
@@ -178,52 +1134,77 @@ impl World {
         return world;
     }
 
-    pub fn find_nearest(&self, ray: Vector) -> RaycastResult {
-        let mut dist = f64::INFINITY;
-        let mut closest_entity: &Box<dyn Entity> = &self.entities[0];
-
-        for entity in &self.entities {
-            match entity.intersection(ray) {
-                IntersectionResult::No => {}
-                IntersectionResult::One(t) => {
-                    if t < dist {
-                        dist = t;
-                        closest_entity = entity;
-                    }
-                }
-                IntersectionResult::Two(t1, _) => {
-                    if t1 < dist {
-                        dist = t1;
-                        closest_entity = entity;
-                    }
-                }
-            }
-        }
-
-        let hit = dist < f64::INFINITY;
+    pub fn find_nearest(&self, ray: &Ray) -> RaycastResult {
+        let nearest = self.bvh().nearest(ray, |i| self.entities[i].hit(ray));
 
         let mut result = RaycastResult {
             hit: false,
+            entity: 0,
+            distance: f64::INFINITY,
             position: Vector::zero(),
             normal: Vector::zero(),
+            uv: (0., 0.),
+            dpdu: Vector::zero(),
+            dpdv: Vector::zero(),
+            object_position: Vector::zero(),
+            vertex_colour: None,
             material: Material::default(),
         };
 
-        if hit {
-            let position = ray * dist;
+        if let Some((i, hit)) = nearest {
+            let surface = self.entities[i].surface(ray, hit);
             result.hit = true;
-            result.position = position;
-            result.normal = closest_entity.normal(ray);
-            result.material = closest_entity.material();
+            result.entity = i;
+            result.distance = hit.t;
+            result.position = surface.position;
+            result.normal = surface.normal;
+            result.uv = surface.uv;
+            result.dpdu = surface.dpdu;
+            result.dpdv = surface.dpdv;
+            result.object_position = surface.object_position;
+            result.vertex_colour = surface.vertex_colour;
+            result.material = surface.material.clone();
         }
 
         return result;
     }
+
+    // Closest light surface along the ray: its index, distance and normal
+    pub fn find_nearest_light(&self, ray: &Ray) -> Option<(usize, f64, Vector)> {
+        let mut nearest = None;
+        let mut dist = f64::INFINITY;
+        for (i, light) in self.lights.iter().enumerate() {
+            if let Some((t, normal)) = light.intersection(ray) {
+                if t < dist {
+                    dist = t;
+                    nearest = Some((i, t, normal));
+                }
+            }
+        }
+        nearest
+    }
+
+    // Whether anything blocks the ray before it has travelled `max_distance`
+    pub fn occluded(&self, ray: &Ray, max_distance: f64) -> bool {
+        let blocked_by_entity = self.bvh().any(ray, max_distance, |i| {
+            self.entities[i]
+                .intersection(ray)
+                .nearest()
+                .is_some_and(|t| t < max_distance)
+        });
+        blocked_by_entity
+            || self.lights.iter().any(|light| {
+                light
+                    .intersection(ray)
+                    .is_some_and(|(t, _)| t < max_distance)
+            })
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::vector::Matrix;
 
     #[test]
     fn test_toml_deserialize() {
@@ -234,6 +1215,17 @@ mod tests {
         position = {x = 1, y = 0, z = 0}
         intensity = 0.8
 
+        [[lights]]
+        type = "disk"
+        position = [0, 5, 0]
+        normal = [0, -1, 0]
+        radius = 1
+
+        # should fail
+        [[lights]]
+        type = "rect"
+        position = [0, 5, 0]
+
         [[entities]]
         type = "sphere"
         # testing seq deserialize for Vector
@@ -258,14 +1250,211 @@ mod tests {
         type = "sphere"
         position = {x = 2, y = 0, z = 5}
         material = {colour = {r = 0, g = 0, b = 1}}
+
+        [[entities]]
+        type = "triangle"
+        vertices = [[-1, 2, 3], [1, 2, 3], [0, 2, 5]]
+        material = {colour = [1, 1, 1], emission = [4, 4, 4]}
+
+        [render]
+        samples = 4
         "#;
 
         let table = toml_string.parse::<toml::Table>().unwrap();
         let world = World::from_toml(&table);
 
-        assert_eq!(world.background, Colour::new(1., 0., 0.));
-        assert_eq!(world.light.intensity, 0.8);
-        assert_eq!(world.entities.len(), 2);
-        assert_eq!(world.entities[0].position(), Vector::new(0., 0., 1.))
+        assert_eq!(
+            world.background.colour_at((0.5, 0.5)),
+            Colour::new(1., 0., 0.)
+        );
+        assert_eq!(world.lights.len(), 2);
+        assert_eq!(world.lights[0].intensity(), 0.8);
+        assert_eq!(world.entities.len(), 3);
+        assert_eq!(world.entities[0].position(), Vector::new(0., 0., 1.));
+        assert_eq!(world.emitters(), &[2]);
+        assert_eq!(world.settings.samples, 4);
+    }
+
+    #[test]
+    fn sky_background_adds_environment_and_sun() {
+        let toml_string = r#"
+        background = {type = "sky", sun_elevation = 20, turbidity = 4}
+        "#;
+        let world = World::from_toml(&toml_string.parse::<toml::Table>().unwrap());
+        assert!(world.environment.is_some());
+        assert_eq!(world.lights.len(), 1);
+        assert!(matches!(world.lights[0], Light::Directional(_)));
+    }
+
+    fn sphere(position: Vector, radius: f64) -> Sphere {
+        Sphere {
+            position,
+            radius,
+            material: Material::default(),
+        }
+    }
+
+    #[test]
+    fn sphere_intersection_from_inside() {
+        let s = sphere(Vector::zero(), 1.);
+        let ray = Ray::new(Vector::zero(), Vector::new(0., 0., 1.));
+        assert_eq!(s.intersection(&ray), IntersectionResult::One(1.));
+    }
+
+    #[test]
+    fn sphere_samples_lie_on_surface_with_cone_pdf() {
+        let s = sphere(Vector::new(0., 0., 5.), 1.);
+        let sample = s.sample(Vector::zero(), (0.3, 0.7)).unwrap();
+        assert!(((sample.position - s.position).length() - 1.).abs() < 1e-9);
+        assert_eq!(
+            sample.pdf,
+            s.pdf(Vector::zero(), sample.position, sample.normal)
+        );
+    }
+
+    #[test]
+    fn triangle_intersection() {
+        let triangle = Triangle {
+            vertices: [
+                Vector::new(-1., -1., 2.),
+                Vector::new(1., -1., 2.),
+                Vector::new(0., 1., 2.),
+            ],
+            uvs: default_triangle_uvs(),
+            material: Material::default(),
+        };
+        let hit = Ray::new(Vector::zero(), Vector::new(0., 0., 1.));
+        assert_eq!(triangle.intersection(&hit), IntersectionResult::One(2.));
+        let miss = Ray::new(Vector::zero(), Vector::new(1., 1., 1.));
+        assert_eq!(triangle.intersection(&miss), IntersectionResult::No);
+    }
+
+    #[test]
+    fn sphere_uvs_face_the_camera() {
+        let s = sphere(Vector::zero(), 2.);
+        assert_eq!(s.uv(Vector::new(0., 0., -2.)), (0.5, 0.5));
+        assert_eq!(s.uv(Vector::new(0., -2., 0.)).1, 0.);
+        assert!(s.uv(Vector::new(1., 0., -1.)).0 > 0.5);
+    }
+
+    #[test]
+    fn triangle_interpolates_vertex_uvs() {
+        let toml_string = r#"
+        [[entities]]
+        type = "triangle"
+        vertices = [[0, 0, 1], [2, 0, 1], [0, 2, 1]]
+        uvs = [[0, 1], [1, 1], [0, 0]]
+        material = {colour = {image = "missing.png"}}
+        "#;
+        let world = World::from_toml(&toml_string.parse::<toml::Table>().unwrap());
+        let ray = Ray::new(Vector::new(0.5, 1., 0.), Vector::new(0., 0., 1.));
+        let result = world.find_nearest(&ray);
+        assert!(result.hit);
+        assert!((result.uv.0 - 0.25).abs() < 1e-9 && (result.uv.1 - 0.5).abs() < 1e-9);
+        // A texture that fails to load is left white
+        let up = Vector::new(0., 0., 1.);
+        let f = result
+            .material
+            .bsdf(&result.texture_coords())
+            .evaluate(up, up);
+        assert_eq!(f, Colour::white() / f64::consts::PI as f32);
+    }
+
+    #[test]
+    fn dpduv_follow_the_uv_parameterisation() {
+        let s = sphere(Vector::new(1., 2., 3.), 2.);
+        let triangle = Triangle {
+            vertices: [
+                Vector::new(0., 0., 1.),
+                Vector::new(2., 0., 1.),
+                Vector::new(0., 3., 2.),
+            ],
+            uvs: [(0., 1.), (1., 0.5), (0.2, 0.)],
+            material: Material::default(),
+        };
+        let on_sphere = Vector::new(1., 2., 3.) + Vector::new(0.3, -0.5, -0.8).normalised() * 2.;
+        let check = |uv: &dyn Fn(Vector) -> (f64, f64),
+                     dpduv: &dyn Fn(Vector) -> (Vector, Vector),
+                     at: Vector| {
+            let (u, v) = uv(at);
+            let (dpdu, dpdv) = dpduv(at);
+            let h = 1e-6;
+            let (u2, v2) = uv(at + dpdu * h);
+            assert!(((u2 - u) / h - 1.).abs() < 1e-3 && ((v2 - v) / h).abs() < 1e-3);
+            let (u3, v3) = uv(at + dpdv * h);
+            assert!(((u3 - u) / h).abs() < 1e-3 && ((v3 - v) / h - 1.).abs() < 1e-3);
+        };
+        check(&|p| s.uv(p), &|p| s.dpduv(p), on_sphere);
+        check(
+            &|p| triangle.uv(p),
+            &|p| triangle.dpduv(p),
+            Vector::new(0.5, 0.5, 1. + 0.5 / 3.),
+        );
+    }
+
+    #[test]
+    fn transformed_sphere_is_an_ellipsoid() {
+        let toml_string = r#"
+        [[entities]]
+        type = "sphere"
+        position = [0, 0, 0]
+        radius = 1
+        material = {colour = [1, 1, 1]}
+        transform = {scale = [2, 1, 1], translate = [0, 0, 5]}
+        "#;
+        let world = World::from_toml(&toml_string.parse::<toml::Table>().unwrap());
+        let result = world.find_nearest(&Ray::new(Vector::zero(), Vector::new(0., 0., 1.)));
+        assert!(result.hit && (result.distance - 4.).abs() < 1e-9);
+        let ray = Ray::new(Vector::new(-10., 0., 5.), Vector::new(1., 0., 0.));
+        let result = world.find_nearest(&ray);
+        assert!((result.distance - 8.).abs() < 1e-9);
+        assert!((result.normal - Vector::new(-1., 0., 0.)).length() < 1e-9);
+        assert!((result.object_position - Vector::new(-1., 0., 0.)).length() < 1e-9);
+
+        // Away from the axes the normal follows the ellipsoid, not the sphere
+        let at = Vector::new(2f64.sqrt(), 0.5f64.sqrt(), 5.);
+        let expected = Vector::new(at.x / 4., at.y, 0.).normalised();
+        let result = world.find_nearest(&Ray::new(at + expected * 3., -expected));
+        assert!((result.normal - expected).length() < 1e-9);
+    }
+
+    #[test]
+    fn transformed_emitter_pdf_uses_world_area() {
+        let triangle = Triangle {
+            vertices: [
+                Vector::new(0., 0., 0.),
+                Vector::new(1., 0., 0.),
+                Vector::new(0., 1., 0.),
+            ],
+            uvs: default_triangle_uvs(),
+            material: Material::default(),
+        };
+        let transform = Transform::new(
+            Matrix::translation(Vector::new(0., 0., 3.))
+                * Matrix::rotation(Vector::new(1., 0., 0.), 0.3)
+                * Matrix::scaling(Vector::new(2., 3., 1.)),
+        )
+        .unwrap();
+        let entity = Transformed::new(Box::new(triangle), transform);
+        let reference = Vector::new(0.2, -0.5, -1.);
+        let mut rng = sampling::Rng::new(5);
+        for _ in 0..10 {
+            let sample = entity.sample(reference, rng.next_2d()).unwrap();
+            // The world triangle has six times the area
+            let expected = sampling::area_to_solid_angle_pdf(
+                1. / 3.,
+                reference,
+                sample.position,
+                sample.normal,
+            );
+            assert!((sample.pdf - expected).abs() < 1e-9 * expected);
+        }
+    }
+
+    #[test]
+    fn find_nearest_in_empty_world() {
+        let world = World::new();
+        let ray = Ray::new(Vector::zero(), Vector::new(0., 0., 1.));
+        assert!(!world.find_nearest(&ray).hit);
     }
 }
@@ -0,0 +1,76 @@
+// A material model defined outside the crate, as a downstream crate would add one
+
+use raytrace::bsdf::{Bsdf, Conductor, TrowbridgeReitz};
+use raytrace::colour::Colour;
+use raytrace::material::{Material, MaterialModel};
+use raytrace::ray::Ray;
+use raytrace::texture::TextureCoords;
+use raytrace::vector::Vector;
+use raytrace::world::World;
+use serde::Deserialize;
+use std::sync::Arc;
+
+// A perfect mirror, optionally tinted
+#[derive(Debug, Deserialize)]
+struct Mirror {
+    #[serde(default = "Colour::white")]
+    tint: Colour,
+}
+
+impl MaterialModel for Mirror {
+    fn bsdf(&self, _coords: &TextureCoords) -> Box<dyn Bsdf> {
+        // A conductor with a huge extinction coefficient reflects everything
+        let k = 1e3 / self.tint.max_component().max(1e-3);
+        Box::new(Conductor {
+            eta: Colour::black(),
+            k: Colour::new(k, k, k),
+            distribution: TrowbridgeReitz::from_roughness(0.),
+        })
+    }
+}
+
+fn mirror(value: &toml::Value) -> Result<Arc<dyn MaterialModel>, String> {
+    let mirror = Mirror::deserialize(value.clone()).map_err(|e| e.to_string())?;
+    Ok(Arc::new(mirror))
+}
+
+fn reflects(material: &Material) -> bool {
+    let wo = Vector::new(0.3, 0., 1.).normalised();
+    let coords = TextureCoords {
+        uv: (0.5, 0.5),
+        position: Vector::zero(),
+        object_position: Vector::zero(),
+        vertex_colour: None,
+    };
+    let sample = material.bsdf(&coords).sample(wo, 0.5, (0.5, 0.5));
+    sample.is_some_and(|sample| (sample.wi - Vector::new(-wo.x, -wo.y, wo.z)).length() < 0.05)
+}
+
+#[test]
+fn models_plug_in_directly() {
+    assert!(reflects(&Material::new(Mirror {
+        tint: Colour::white()
+    })));
+}
+
+#[test]
+fn registered_types_load_from_scenes() {
+    let scene: toml::Table = r#"
+    [[entities]]
+    type = "sphere"
+    position = [0, 0, 5]
+    radius = 1
+    material = {type = "Mirror", tint = [1, 0.9, 0.8]}
+    "#
+    .parse()
+    .unwrap();
+    let unknown: toml::Value = "type = \"velvet\"".parse::<toml::Table>().unwrap().into();
+    assert!(Material::try_from(unknown.clone()).is_err());
+
+    Material::register("mirror", mirror);
+    assert!(Material::try_from(unknown).is_err());
+    let world = World::from_toml(&scene);
+    assert_eq!(world.entities.len(), 1);
+    let result = world.find_nearest(&Ray::new(Vector::zero(), Vector::new(0., 0., 1.)));
+    assert!(result.hit && reflects(&result.material));
+}